@@ -25,13 +25,179 @@ stage = Stage
 staged-changes = Staged changes
 unstage = Unstage
 unstaged-changes = Unstaged changes
+git-hunk-original = Original of line {$line}
+git-hunk-revert = Revert
+git-hunk-added = Added lines, reverting removes them
+stage-hunk = Stage hunk
+unstage-hunk = Unstage hunk
+discard-hunk = Discard hunk
+stage-lines = Stage lines
+unstage-lines = Unstage lines
+discard-lines = Discard lines
+git-no-changes = No changes
+git-error = Git operation failed
+error-close = Dismiss
+git-submodule = Submodule
+git-nested-repository = Nested repository
+git-merge-conflicts = Merge conflicts
+git-conflicts = {$count ->
+    [one] 1 merge conflict
+   *[other] {$count} merge conflicts
+}
+git-conflicts-none = All conflicts are resolved
+git-conflict-position = Conflict {$position} of {$count}
+git-conflict-previous = Previous conflict
+git-conflict-next = Next conflict
+git-conflict-accept-ours = Accept ours
+git-conflict-accept-theirs = Accept theirs
+git-conflict-accept-both = Accept both
+git-conflict-mark-resolved = Mark resolved
+git-diff-side-by-side = Side by side
+git-diff-more-context = Show more context
+git-diff-previous-hunk = Previous change
+git-diff-next-hunk = Next change
+git-diff-hunk-position = Change {$position} of {$count}
+git-diff-unchanged-lines = {$count ->
+    [one] 1 unchanged line
+   *[other] {$count} unchanged lines
+}
+commit = Commit
+commit-message = Commit message
+commit-subject-length = Subject: {$length}/50
+commit-amend = Amend
+commit-signoff = Sign off
+committing = Committing...
+recent-commits = Recent commits
+git-blame-uncommitted = Not committed yet
+git-file-history = File history
+git-file-history-description = Commits that changed the file of the active tab. Select a commit to see its changes.
+git-file-history-loading = Loading file history...
+git-file-history-no-file = The active tab has no file.
+local-history = Local history
+local-history-description = Snapshots of the file of the active tab, taken when it is saved or reloaded. Select a snapshot to compare it with the tab.
+local-history-loading = Loading local history...
+local-history-empty = No snapshots have been taken yet.
+local-history-restore = Restore
+problems = Problems
+problems-empty = No problems have been reported by language servers.
+definitions = Definitions
+type-definitions = Type definitions
+references = References
+navigation-empty = Nothing was found.
+document-symbols = Symbols in file
+workspace-symbols = Symbols in project
+symbol-search = Search symbols
+symbol-search-loading = Loading symbols...
+symbol-search-empty = No symbols match.
+rename-symbol = Rename symbol
+rename-new-name = New name
+rename-description = Enter a new name and press Enter to preview the changes.
+rename-loading = Loading changes...
+rename-empty = Nothing would change.
+rename-unsupported = The language server cannot rename symbols.
+rename-apply = Rename
+rename-line = Line {$line}
+code-actions-loading = Loading actions...
+code-actions-empty = No actions are available.
+format-unsupported = The language server cannot format this document.
+lsp-error = Language server edit failed
+formatter-error = Formatter failed
+use-formatters = Use formatters
+edit-file-changed = the file was changed
+git-revision-open = Open
+branches = Branches
+git-head-detached = HEAD detached at {$hash}
+git-branch-name = New branch name
+git-branch-create = Create
+git-branch-delete = Delete
+git-branch-switch = Switch
+git-branch-created = Created and switched to branch {$name}
+git-branch-deleted = Deleted branch {$name}
+git-branch-switched = Switched to branch {$name}
+git-ahead-behind = {$ahead} ahead, {$behind} behind
+git-no-upstream = No upstream branch
+git-pull-rebase = Rebase
+git-fetch = Fetch
+git-pull = Pull
+git-push = Push
+git-fetched = Fetched from remotes
+git-pulled = Pulled from upstream
+git-pushed = Pushed to remote
+git-askpass-submit = Submit
+stashes = Stashes
+git-stash-message = Stash message
+git-stash-untracked = Include untracked files
+git-stash-push = Stash
+git-stash-apply = Apply
+git-stash-pop = Pop
+git-stash-drop = Drop
+git-stash-pushed = Stashed changes
+git-stash-applied = Applied stash
+git-stash-popped = Applied and dropped stash
+git-stash-dropped = Dropped stash
+time-just-now = just now
+time-minutes-ago = {$count ->
+    [one] 1 minute ago
+   *[other] {$count} minutes ago
+}
+time-hours-ago = {$count ->
+    [one] 1 hour ago
+   *[other] {$count} hours ago
+}
+time-days-ago = {$count ->
+    [one] 1 day ago
+   *[other] {$count} days ago
+}
+time-months-ago = {$count ->
+    [one] 1 month ago
+   *[other] {$count} months ago
+}
+time-years-ago = {$count ->
+    [one] 1 year ago
+   *[other] {$count} years ago
+}
 
 ## Project search
 project-search = Project search
+project-search-include = Files to include (e.g. *.rs, src/**)
+project-search-exclude = Files to exclude (e.g. target/**)
+project-search-max-file-size = Maximum file size
+project-search-context-before = Context lines before
+project-search-context-after = Context lines after
+project-search-match-count = {$count ->
+    [one] 1 match
+   *[other] {$count} matches
+}
+project-search-in-progress = Searching...
+project-search-open-tabs-only = Open tabs only
+unsaved = Unsaved
+unlimited = Unlimited
 
 ## Prompt save changes
 prompt-save-changes-title = Save changes before closing?
 prompt-unsaved-changes = You have unsaved changes. If you continue without saving, these changes will be lost.
+prompt-git-checkout-title = Save changes before switching to {$name}?
+prompt-git-checkout-unsaved = These tabs have unsaved changes. If you switch without saving, they will be replaced by the files on the new branch.
+prompt-git-conflict-resolve-title = Mark {$name} resolved?
+prompt-git-conflict-resolve = {$count ->
+    [one] The file still has a merge conflict. Its conflict markers will be committed.
+   *[other] The file still has {$count} merge conflicts. Their conflict markers will be committed.
+}
+prompt-git-stash-push-title = Save changes before stashing?
+prompt-git-stash-apply-title = Save changes before applying the stash?
+prompt-git-stash-unsaved = These tabs have unsaved changes. If you continue without saving, they will be replaced by the files in the working tree.
+prompt-git-stash-drop-title = Drop stash?
+prompt-git-stash-drop = The changes in this stash will be deleted.
+prompt-git-askpass-title = Git needs credentials
+prompt-git-discard-title = Discard changes to {$name}?
+prompt-git-discard-file = Changes that are not staged will be lost. Open tabs of the file are reloaded.
+prompt-git-discard-untracked = This file is not tracked by Git and will be moved to the trash.
+prompt-git-discard-unsaved = These tabs have unsaved changes that will be lost when they are reloaded.
+prompt-git-discard-hunk = The changes of this hunk will be removed from the file. Open tabs of the file are reloaded.
+prompt-git-discard-lines = {$count ->
+    [one] The selected changed line will be removed from the file. Open tabs of the file are reloaded.
+   *[other] The {$count} selected changed lines will be removed from the file. Open tabs of the file are reloaded.
+}
 cancel = Cancel
 discard = Discard changes
 save-all = Save all
@@ -51,6 +217,27 @@ default-font = Default font
 default-font-size = Default font size
 default-zoom-step = Zoom steps
 
+### Local history
+local-history-max-count = Snapshots kept per file
+local-history-max-days = Remove snapshots after
+local-history-days = {$count ->
+    [one] 1 day
+   *[other] {$count} days
+}
+
+### Undo history
+undo-history = Undo history
+undo-history-max-changes = Changes kept per file
+undo-history-max-file-size = Maximum file size
+
+### Formatting
+formatting = Formatting
+format-on-save = Format files on save
+
+### Language servers
+language-servers = Language servers
+enable-language-servers = Enable language servers
+
 
 ### Keyboard shortcuts
 keyboard-shortcuts = Keyboard shortcuts
@@ -65,6 +252,7 @@ replace = Replace
 replace-all = Replace all
 case-sensitive = Case sensitive
 use-regex = Use regex
+whole-word = Whole word
 wrap-around = Wrap around
 
 # Menu
@@ -82,10 +270,20 @@ close-project = Close project
 save = Save
 save-as = Save as...
 revert-all-changes = Revert all changes
+compare = Compare
+compare-select = Select for compare
+compare-with-selected = Compare with selected
+compare-with-saved = Compare with saved file
+compare-with-clipboard = Compare with clipboard
+compare-title = {$old} ↔ {$new}
+clipboard = Clipboard
 menu-document-statistics = Document statistics...
 document-type = Document type...
 encoding = Encoding...
 menu-git-management = Git management...
+menu-git-file-history = File history...
+menu-local-history = Local history...
+menu-problems = Problems...
 print = Print
 quit = Quit
 
@@ -99,6 +297,14 @@ paste = Paste
 select-all = Select all
 find = Find
 find-in-project = Find in project...
+go-to-definition = Go to definition
+go-to-type-definition = Go to type definition
+find-references = Find references
+menu-document-symbols = Symbols in file...
+menu-workspace-symbols = Symbols in project...
+menu-rename = Rename symbol...
+format-document = Format document
+format-selection = Format selection
 spell-check = Spell check...
 
 ## View
@@ -116,6 +322,7 @@ convert-indentation-to-tabs = Convert indentation to tabs
 
 word-wrap = Word wrap
 show-line-numbers = Show line numbers
+show-git-blame = Show git blame
 highlight-current-line = Highlight current line
 syntax-highlighting = Syntax highlighting...
 menu-settings = Settings...
@@ -0,0 +1,649 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Completion of code suggested by language servers. Suggestions are filtered fuzzily by the word
+// typed at the cursor, and snippets are inserted with tab stops that are visited with Tab.
+
+use cosmic_text::Cursor;
+use lsp_types::{
+    CompletionItem, Documentation, ParameterInformation, ParameterLabel, SignatureHelp,
+    SignatureInformation,
+};
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::lsp::LspPositionEncoding;
+
+/// Characters that words are made of, which are typed to filter completions
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Score of `text` matching `pattern` fuzzily, `None` if the characters of `pattern` do not all
+/// occur in order in `text`. Matches at the start of words and consecutive matches score higher,
+/// and shorter texts win ties.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let mut pattern_chars = pattern.chars().peekable();
+    let mut score = 0;
+    let mut prev_opt: Option<char> = None;
+    let mut last_match_opt = None;
+    let mut len = 0;
+    for (i, c) in text.chars().enumerate() {
+        len += 1;
+        if let Some(p) = pattern_chars.peek().copied()
+            && c.to_lowercase().eq(p.to_lowercase())
+        {
+            score += 1;
+            match prev_opt {
+                None => score += 8,
+                Some(prev) if !prev.is_alphanumeric() => score += 6,
+                Some(prev) if prev.is_lowercase() && c.is_uppercase() => score += 6,
+                _ => {}
+            }
+            if i > 0 && last_match_opt == Some(i - 1) {
+                score += 4;
+            }
+            if c == p {
+                score += 1;
+            }
+            last_match_opt = Some(i);
+            pattern_chars.next();
+        }
+        prev_opt = Some(c);
+    }
+    if pattern_chars.peek().is_some() {
+        return None;
+    }
+    Some(score * 16 - len)
+}
+
+/// Completion items of a language server, filtered by the word typed at the cursor
+#[derive(Clone, Debug)]
+pub struct CompletionList {
+    pub items: Vec<CompletionItem>,
+    /// The server has more items, so it must be asked again when the word changes
+    pub is_incomplete: bool,
+    /// Indices of the items matching the word, best match first
+    pub matches: Vec<usize>,
+    /// Index in `matches` of the selected item
+    pub selected: usize,
+}
+
+impl CompletionList {
+    pub fn new(items: Vec<CompletionItem>, is_incomplete: bool) -> Self {
+        Self {
+            items,
+            is_incomplete,
+            matches: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    /// Match the items against `word` and select the best match
+    pub fn filter(&mut self, word: &str) {
+        let mut matches: Vec<(i64, &str, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let text = item.filter_text.as_deref().unwrap_or(&item.label);
+                let sort_text = item.sort_text.as_deref().unwrap_or(&item.label);
+                Some((fuzzy_score(word, text)?, sort_text, i))
+            })
+            .collect();
+        // Without a word the server knows best
+        if !word.is_empty() {
+            matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        } else {
+            matches.sort_by(|a, b| a.1.cmp(b.1));
+        }
+        self.matches = matches.into_iter().map(|(_, _, i)| i).collect();
+        self.selected = if word.is_empty() {
+            self.matches
+                .iter()
+                .position(|i| self.items[*i].preselect == Some(true))
+                .unwrap_or(0)
+        } else {
+            0
+        };
+    }
+
+    pub fn selected_item(&self) -> Option<&CompletionItem> {
+        self.items.get(*self.matches.get(self.selected)?)
+    }
+
+    /// Move the selection by `offset` items, wrapping around at the ends
+    pub fn select(&mut self, offset: isize) {
+        if let Ok(len) = isize::try_from(self.matches.len())
+            && len > 0
+        {
+            self.selected = (self.selected as isize + offset).rem_euclid(len) as usize;
+        }
+    }
+}
+
+/// Documentation as text, with the fences of code blocks in markdown removed
+pub fn documentation_text(documentation: &Documentation) -> String {
+    let value = match documentation {
+        Documentation::String(value) => value,
+        Documentation::MarkupContent(content) => &content.value,
+    };
+    value
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Signature of the call at the cursor
+#[derive(Debug)]
+pub struct ActiveSignature<'a> {
+    pub signature: &'a SignatureInformation,
+    pub parameter_opt: Option<&'a ParameterInformation>,
+    /// Byte range of the parameter in the label of the signature
+    pub range_opt: Option<Range<usize>>,
+}
+
+/// The active signature of signature help with its active parameter
+pub fn active_signature(
+    help: &SignatureHelp,
+    encoding: LspPositionEncoding,
+) -> Option<ActiveSignature<'_>> {
+    let signature = help
+        .signatures
+        .get(help.active_signature.unwrap_or(0) as usize)
+        .or(help.signatures.first())?;
+    let parameter_i = signature
+        .active_parameter
+        .or(help.active_parameter)
+        .unwrap_or(0) as usize;
+    let label = &signature.label;
+    let parameter_opt = signature
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.get(parameter_i));
+    let range_opt = parameter_opt.and_then(|parameter| match &parameter.label {
+        ParameterLabel::Simple(text) => {
+            // Parameters come after the name of the function
+            let offset = label.find('(').unwrap_or(0);
+            let start = offset + label[offset..].find(text.as_str())?;
+            Some(start..start + text.len())
+        }
+        ParameterLabel::LabelOffsets([start, end]) => {
+            Some(encoding.index(label, *start)..encoding.index(label, *end))
+        }
+    });
+    Some(ActiveSignature {
+        signature,
+        parameter_opt,
+        range_opt,
+    })
+}
+
+/// Cursor at the end of `text` inserted at `start`
+pub fn advance_cursor(start: Cursor, text: &str) -> Cursor {
+    match text.rsplit_once('\n') {
+        Some((before, after)) => {
+            Cursor::new(start.line + before.matches('\n').count() + 1, after.len())
+        }
+        None => Cursor::new(start.line, start.index + text.len()),
+    }
+}
+
+/// Where `cursor` ends up when the text from `start` to `end` is replaced with `text`. Cursors
+/// within the replaced text move to its start.
+pub fn shift_cursor(cursor: Cursor, start: Cursor, end: Cursor, text: &str) -> Cursor {
+    if cursor <= start {
+        return cursor;
+    }
+    if cursor < end {
+        return start;
+    }
+    let inserted_end = advance_cursor(start, text);
+    if cursor.line == end.line {
+        Cursor::new(
+            inserted_end.line,
+            inserted_end.index + cursor.index - end.index,
+        )
+    } else {
+        Cursor::new(cursor.line + inserted_end.line - end.line, cursor.index)
+    }
+}
+
+/// Text of a snippet with its tab stops
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte ranges in `text` of the tab stops in the order they are visited, which ends with the
+    /// final tab stop
+    pub tab_stops: Vec<Range<usize>>,
+}
+
+impl Snippet {
+    /// Text without tab stops, after which the cursor is placed
+    pub fn plain(text: &str) -> Self {
+        let len = text.len();
+        Self {
+            text: text.to_string(),
+            tab_stops: std::iter::once(len..len).collect(),
+        }
+    }
+
+    /// Parse the snippet syntax of language servers. Only the first occurrence of each tab stop is
+    /// visited, choices insert their first option and variables insert their default or name.
+    pub fn parse(snippet: &str) -> Self {
+        let mut parser = SnippetParser {
+            chars: snippet.chars().collect(),
+            i: 0,
+            text: String::new(),
+            tab_stops: BTreeMap::new(),
+        };
+        parser.parse(false);
+        let final_opt = parser.tab_stops.remove(&0);
+        let mut tab_stops: Vec<_> = parser.tab_stops.into_values().collect();
+        let len = parser.text.len();
+        tab_stops.push(final_opt.unwrap_or(len..len));
+        Self {
+            text: parser.text,
+            tab_stops,
+        }
+    }
+
+    /// Indent the lines after the first with `indent`, like the line the snippet is inserted in
+    pub fn indent(&mut self, indent: &str) {
+        if indent.is_empty() {
+            return;
+        }
+        let mut text = String::with_capacity(self.text.len());
+        let mut offsets = Vec::new();
+        for (i, c) in self.text.char_indices() {
+            text.push(c);
+            if c == '\n' {
+                text.push_str(indent);
+                offsets.push(i + 1);
+            }
+        }
+        let shift = |index: usize| index + offsets.partition_point(|i| *i <= index) * indent.len();
+        for range in self.tab_stops.iter_mut() {
+            *range = shift(range.start)..shift(range.end);
+        }
+        self.text = text;
+    }
+}
+
+struct SnippetParser {
+    chars: Vec<char>,
+    i: usize,
+    text: String,
+    tab_stops: BTreeMap<u32, Range<usize>>,
+}
+
+impl SnippetParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).copied()
+    }
+
+    /// Parse text until the end, or until the `}` closing a placeholder if `nested`
+    fn parse(&mut self, nested: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => {
+                    self.i += 1;
+                    match self.peek() {
+                        Some(escaped @ ('$' | '}' | '\\')) => {
+                            self.text.push(escaped);
+                            self.i += 1;
+                        }
+                        _ => self.text.push('\\'),
+                    }
+                }
+                '}' if nested => return,
+                '$' => {
+                    if !self.parse_dollar() {
+                        self.text.push('$');
+                        self.i += 1;
+                    }
+                }
+                _ => {
+                    self.text.push(c);
+                    self.i += 1;
+                }
+            }
+        }
+    }
+
+    /// Parse a tab stop or variable at a `$`, false if the `$` is text
+    fn parse_dollar(&mut self) -> bool {
+        let dollar_i = self.i;
+        self.i += 1;
+        let braced = self.peek() == Some('{');
+        if braced {
+            self.i += 1;
+        }
+        let name_start = self.i;
+        let numeric = self.peek().is_some_and(|c| c.is_ascii_digit());
+        while self.peek().is_some_and(|c| {
+            if numeric {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphanumeric() || c == '_'
+            }
+        }) {
+            self.i += 1;
+        }
+        if self.i == name_start {
+            self.i = dollar_i;
+            return false;
+        }
+        let name: String = self.chars[name_start..self.i].iter().collect();
+
+        let start = self.text.len();
+        let mut has_default = false;
+        if braced {
+            match self.peek() {
+                Some(':') => {
+                    self.i += 1;
+                    self.parse(true);
+                    has_default = true;
+                }
+                Some('|') => {
+                    self.i += 1;
+                    self.parse_choice();
+                    has_default = true;
+                }
+                _ => {}
+            }
+            // Skip transformations, which are not supported
+            while self.peek().is_some_and(|c| c != '}') {
+                self.i += 1;
+            }
+            self.i += 1;
+        }
+        match name.parse::<u32>() {
+            Ok(number) => {
+                self.tab_stops
+                    .entry(number)
+                    .or_insert(start..self.text.len());
+            }
+            Err(_) => {
+                if !has_default {
+                    self.text.push_str(&name);
+                }
+            }
+        }
+        true
+    }
+
+    /// Insert the first option of a choice and skip the others
+    fn parse_choice(&mut self) {
+        let mut first = true;
+        while let Some(c) = self.peek() {
+            self.i += 1;
+            match c {
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        self.i += 1;
+                        if first {
+                            self.text.push(escaped);
+                        }
+                    }
+                }
+                ',' => first = false,
+                '|' if self.peek() == Some('}') => return,
+                _ => {
+                    if first {
+                        self.text.push(c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tab stops of a snippet inserted into the buffer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SnippetSession {
+    /// Ranges of the tab stops in the buffer, which ends with the final tab stop
+    pub tab_stops: Vec<(Cursor, Cursor)>,
+    /// Index of the selected tab stop
+    pub current: usize,
+    /// Number of lines of the buffer and length of the line at the end of the selected tab stop
+    /// when it was selected
+    lines: (usize, usize),
+}
+
+impl SnippetSession {
+    /// Tab stops of `snippet` inserted at `start`, `None` if it only has the final tab stop
+    pub fn new(snippet: &Snippet, start: Cursor) -> Option<Self> {
+        if snippet.tab_stops.len() < 2 {
+            return None;
+        }
+        let tab_stops = snippet
+            .tab_stops
+            .iter()
+            .map(|range| {
+                (
+                    advance_cursor(start, &snippet.text[..range.start]),
+                    advance_cursor(start, &snippet.text[..range.end]),
+                )
+            })
+            .collect();
+        Some(Self {
+            tab_stops,
+            current: 0,
+            lines: (0, 0),
+        })
+    }
+
+    /// Remember the buffer around the selected tab stop, `line_len` returns the length of a line
+    pub fn selected(&mut self, line_count: usize, line_len: impl Fn(usize) -> usize) {
+        let end = self.tab_stops[self.current].1;
+        self.lines = (line_count, line_len(end.line));
+    }
+
+    /// Move to the next or previous tab stop, assuming that the buffer was only edited at the
+    /// selected tab stop since it was selected. Returns `None` when moving past the ends.
+    pub fn advance(
+        &mut self,
+        backwards: bool,
+        line_count: usize,
+        line_len: impl Fn(usize) -> usize,
+    ) -> Option<(Cursor, Cursor)> {
+        let (old_line_count, old_len) = self.lines;
+        let end = &mut self.tab_stops[self.current].1;
+        let old_end = *end;
+        let line_delta = line_count as isize - old_line_count as isize;
+        end.line = old_end.line.saturating_add_signed(line_delta);
+        let len_delta = line_len(end.line) as isize - old_len as isize;
+        end.index = old_end.index.saturating_add_signed(len_delta);
+        for (start, end) in self.tab_stops.iter_mut().skip(self.current + 1) {
+            for cursor in [start, end] {
+                if *cursor >= old_end {
+                    if cursor.line == old_end.line {
+                        cursor.index = cursor.index.saturating_add_signed(len_delta);
+                    }
+                    cursor.line = cursor.line.saturating_add_signed(line_delta);
+                }
+            }
+        }
+
+        self.current = if backwards {
+            self.current.checked_sub(1)?
+        } else {
+            Some(self.current + 1).filter(|i| *i < self.tab_stops.len())?
+        };
+        self.selected(line_count, line_len);
+        Some(self.tab_stops[self.current])
+    }
+
+    /// The final tab stop is selected, after which the snippet is done
+    pub fn is_final(&self) -> bool {
+        self.current + 1 == self.tab_stops.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_score("", "anything"), Some(-8));
+        assert_eq!(fuzzy_score("xyz", "xy"), None);
+        assert_eq!(fuzzy_score("ba", "abc"), None);
+        assert!(fuzzy_score("fb", "foo_bar") > fuzzy_score("fb", "fabric"));
+        assert!(fuzzy_score("gc", "getCount") > fuzzy_score("gc", "magic"));
+        assert!(fuzzy_score("len", "len") > fuzzy_score("len", "length"));
+        assert!(fuzzy_score("Len", "Len") > fuzzy_score("Len", "len"));
+    }
+
+    fn item(label: &str) -> CompletionItem {
+        CompletionItem {
+            label: label.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn list() {
+        let mut list = CompletionList::new(
+            vec![
+                item("into_iter"),
+                item("iter"),
+                item("is_empty"),
+                item("len"),
+            ],
+            false,
+        );
+        list.filter("ite");
+        let labels: Vec<_> = list
+            .matches
+            .iter()
+            .map(|i| list.items[*i].label.as_str())
+            .collect();
+        assert_eq!(labels, ["iter", "into_iter"]);
+        list.select(-1);
+        assert_eq!(list.selected_item().unwrap().label, "into_iter");
+        list.select(1);
+        assert_eq!(list.selected_item().unwrap().label, "iter");
+
+        list.items[3].preselect = Some(true);
+        list.filter("");
+        assert_eq!(list.matches.len(), 4);
+        assert_eq!(list.selected_item().unwrap().label, "len");
+        list.filter("zz");
+        assert_eq!(list.selected_item(), None);
+        list.select(1);
+        assert_eq!(list.selected, 0);
+    }
+
+    #[test]
+    fn cursors() {
+        let start = Cursor::new(2, 4);
+        assert_eq!(advance_cursor(start, "abc"), Cursor::new(2, 7));
+        assert_eq!(advance_cursor(start, "a\nbc\n"), Cursor::new(4, 0));
+        assert_eq!(advance_cursor(start, "a\r\nbc"), Cursor::new(3, 2));
+
+        // Replace "bar" in "foo bar baz" with two lines
+        let (start, end) = (Cursor::new(0, 4), Cursor::new(0, 7));
+        let shift = |cursor| shift_cursor(cursor, start, end, "one\ntwo");
+        assert_eq!(shift(Cursor::new(0, 2)), Cursor::new(0, 2));
+        assert_eq!(shift(Cursor::new(0, 5)), Cursor::new(0, 4));
+        assert_eq!(shift(Cursor::new(0, 8)), Cursor::new(1, 4));
+        assert_eq!(shift(Cursor::new(3, 1)), Cursor::new(4, 1));
+    }
+
+    #[test]
+    fn snippet() {
+        let snippet = Snippet::parse("fn ${1:name}(${2:arg: ${3:T}}) {\n\t$0\n}");
+        assert_eq!(snippet.text, "fn name(arg: T) {\n\t\n}");
+        assert_eq!(snippet.tab_stops, [3..7, 8..14, 13..14, 19..19]);
+
+        let snippet = Snippet::parse("println!(\"$1\")");
+        assert_eq!(snippet.text, "println!(\"\")");
+        assert_eq!(snippet.tab_stops, [10..10, 12..12]);
+
+        let snippet = Snippet::parse("${1|one,two|} \\$x \\} ${TM_FILENAME} ${VAR:default} $ {");
+        assert_eq!(snippet.text, "one $x } TM_FILENAME default $ {");
+        assert_eq!(snippet.tab_stops, [0..3, 32..32]);
+
+        let snippet = Snippet::parse("a ${1/(.*)/$1/} $1 b");
+        assert_eq!(snippet.text, "a   b");
+        assert_eq!(snippet.tab_stops, [2..2, 5..5]);
+
+        let mut snippet = Snippet::parse("if $1 {\n\t$0\n}");
+        snippet.indent("  ");
+        assert_eq!(snippet.text, "if  {\n  \t\n  }");
+        assert_eq!(snippet.tab_stops, [3..3, 9..9]);
+        assert_eq!(Snippet::plain("text").tab_stops.first(), Some(&(4..4)));
+    }
+
+    #[test]
+    fn signature() {
+        let mut help = SignatureHelp {
+            signatures: vec![SignatureInformation {
+                label: "fn add(a: i32, b: i32)".to_string(),
+                documentation: None,
+                parameters: Some(vec![
+                    ParameterInformation {
+                        label: ParameterLabel::Simple("a: i32".to_string()),
+                        documentation: None,
+                    },
+                    ParameterInformation {
+                        label: ParameterLabel::LabelOffsets([15, 21]),
+                        documentation: None,
+                    },
+                ]),
+                active_parameter: None,
+            }],
+            active_signature: None,
+            active_parameter: Some(0),
+        };
+        let encoding = LspPositionEncoding::Utf16;
+        assert_eq!(
+            active_signature(&help, encoding).unwrap().range_opt,
+            Some(7..13)
+        );
+        help.active_parameter = Some(1);
+        let active = active_signature(&help, encoding).unwrap();
+        assert!(active.parameter_opt.is_some());
+        assert_eq!(&active.signature.label[active.range_opt.unwrap()], "b: i32");
+        help.active_parameter = Some(2);
+        let active = active_signature(&help, encoding).unwrap();
+        assert!(active.parameter_opt.is_none());
+        assert_eq!(active.range_opt, None);
+        help.signatures.clear();
+        assert!(active_signature(&help, encoding).is_none());
+    }
+
+    #[test]
+    fn session() {
+        // "call(a, b)" inserted at the start of the second of three lines
+        let snippet = Snippet::parse("call(${1:a}, ${2:b})$0");
+        let mut session = SnippetSession::new(&snippet, Cursor::new(1, 0)).unwrap();
+        assert_eq!(session.tab_stops[0], (Cursor::new(1, 5), Cursor::new(1, 6)));
+        let mut lines = vec!["x".to_string(), "call(a, b)".to_string(), "y".to_string()];
+        session.selected(lines.len(), |line_i| lines[line_i].len());
+
+        // Replace the first placeholder with two lines
+        lines.splice(1..2, ["call(first".to_string(), "line, b)".to_string()]);
+        let line_len = |line_i: usize| lines[line_i].len();
+        assert_eq!(
+            session.advance(false, lines.len(), line_len),
+            Some((Cursor::new(2, 6), Cursor::new(2, 7)))
+        );
+        assert_eq!(session.tab_stops[0].1, Cursor::new(2, 4));
+        assert!(!session.is_final());
+        assert_eq!(
+            session.advance(false, lines.len(), line_len),
+            Some((Cursor::new(2, 8), Cursor::new(2, 8)))
+        );
+        assert!(session.is_final());
+        assert_eq!(session.advance(false, lines.len(), line_len), None);
+
+        assert_eq!(
+            SnippetSession::new(&Snippet::parse("text$0"), Cursor::new(0, 0)),
+            None
+        );
+    }
+}
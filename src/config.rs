@@ -6,7 +6,12 @@ use cosmic::{
 };
 use cosmic_text::Metrics;
 use serde::{Deserialize, Serialize};
-use std::{collections::VecDeque, path::PathBuf};
+use std::{collections::VecDeque, path::PathBuf, time::Duration};
+
+use crate::{
+    formatter::FormatterConfig, local_history::LocalHistoryRetention, lsp::LspServerConfig,
+    undo_history::UndoHistoryLimits,
+};
 
 pub const CONFIG_VERSION: u64 = 1;
 
@@ -41,15 +46,32 @@ pub struct Config {
     pub auto_indent: bool,
     pub find_case_sensitive: bool,
     pub find_use_regex: bool,
+    pub find_whole_word: bool,
     pub find_wrap_around: bool,
     pub font_name: String,
     pub font_size: u16,
     pub font_size_zoom_step_mul_100: u16,
+    /// Whether files are formatted with their formatter before they are saved
+    pub format_on_save: bool,
+    /// Projects whose files are not formatted with formatters
+    pub formatter_disabled_projects: Vec<PathBuf>,
+    pub formatters: Vec<FormatterConfig>,
+    pub git_blame: bool,
+    pub git_diff_side_by_side: bool,
     pub highlight_current_line: bool,
     pub line_numbers: bool,
+    pub local_history_max_count: u32,
+    pub local_history_max_days: u32,
+    pub lsp_enabled: bool,
+    pub lsp_servers: Vec<LspServerConfig>,
+    pub project_search_context_after: u16,
+    pub project_search_context_before: u16,
+    pub project_search_max_file_size: u64,
     pub syntax_theme_dark: String,
     pub syntax_theme_light: String,
     pub tab_width: u16,
+    pub undo_history_max_changes: u32,
+    pub undo_history_max_file_size: u64,
     pub vim_bindings: bool,
     pub word_wrap: bool,
 }
@@ -61,15 +83,30 @@ impl Default for Config {
             auto_indent: true,
             find_case_sensitive: false,
             find_use_regex: false,
+            find_whole_word: false,
             find_wrap_around: true,
             font_name: "Noto Sans Mono".to_string(),
             font_size: 14,
             font_size_zoom_step_mul_100: 100,
+            format_on_save: false,
+            formatter_disabled_projects: Vec::new(),
+            formatters: FormatterConfig::defaults(),
+            git_blame: false,
+            git_diff_side_by_side: false,
             highlight_current_line: true,
             line_numbers: true,
+            local_history_max_count: 50,
+            local_history_max_days: 30,
+            lsp_enabled: true,
+            lsp_servers: LspServerConfig::defaults(),
+            project_search_context_after: 0,
+            project_search_context_before: 0,
+            project_search_max_file_size: 8 * 1024 * 1024,
             syntax_theme_dark: "COSMIC Dark".to_string(),
             syntax_theme_light: "COSMIC Light".to_string(),
             tab_width: 4,
+            undo_history_max_changes: 100,
+            undo_history_max_file_size: 1024 * 1024,
             vim_bindings: false,
             word_wrap: true,
         }
@@ -85,10 +122,15 @@ impl Config {
     }
 
     pub fn find_regex(&self, pattern: &str) -> Result<regex::Regex, regex::Error> {
-        let mut builder = if self.find_use_regex {
-            regex::RegexBuilder::new(pattern)
+        let pattern = if self.find_use_regex {
+            pattern.to_string()
         } else {
-            regex::RegexBuilder::new(&regex::escape(pattern))
+            regex::escape(pattern)
+        };
+        let mut builder = if self.find_whole_word {
+            regex::RegexBuilder::new(&format!(r"\b(?:{})\b", pattern))
+        } else {
+            regex::RegexBuilder::new(&pattern)
         };
         builder.case_insensitive(!self.find_case_sensitive);
         builder.build()
@@ -110,6 +152,25 @@ impl Config {
             &self.syntax_theme_light
         }
     }
+
+    // Get how many local history snapshots are kept, zero is used for no limit
+    pub fn local_history_retention(&self) -> LocalHistoryRetention {
+        LocalHistoryRetention {
+            max_count: self.local_history_max_count as usize,
+            max_age_opt: match self.local_history_max_days {
+                0 => None,
+                days => Some(Duration::from_secs(u64::from(days) * 24 * 60 * 60)),
+            },
+        }
+    }
+
+    // Get how much undo history is kept across sessions, zero is used for no limit
+    pub fn undo_history_limits(&self) -> UndoHistoryLimits {
+        UndoHistoryLimits {
+            max_changes: self.undo_history_max_changes as usize,
+            max_file_size: self.undo_history_max_file_size,
+        }
+    }
 }
 
 #[derive(Clone, CosmicConfigEntry, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -0,0 +1,347 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Formatting of files with external commands, which read the text of a file from stdin and write
+// the formatted text to stdout. The text is replaced with as small edits as possible, so the cursor
+// and the rest of the text stay where they are.
+
+use lsp_types::{Position, Range, TextEdit};
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, DiffTag, TextDiff};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, process::Command, time};
+
+use crate::workspace_edit;
+
+/// How long a formatter may run before it is stopped
+pub const FORMATTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long finding the changes of a formatter may take before a larger diff is used
+const DIFF_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Command formatting files with some extensions
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FormatterConfig {
+    pub name: String,
+    pub command: String,
+    /// Arguments, in which `{path}` is replaced with the path of the formatted file
+    pub args: Vec<String>,
+    pub extensions: Vec<String>,
+}
+
+impl FormatterConfig {
+    fn new(name: &str, args: &[&str], extensions: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            command: name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            extensions: extensions
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            // The edition and settings are taken from the Cargo.toml and rustfmt.toml of the project
+            Self::new("rustfmt", &[], &["rs"]),
+            Self::new(
+                "black",
+                &["--quiet", "--stdin-filename", "{path}", "-"],
+                &["py", "pyi"],
+            ),
+            Self::new(
+                "prettier",
+                &["--stdin-filepath", "{path}"],
+                &[
+                    "js", "jsx", "mjs", "cjs", "ts", "tsx", "json", "css", "scss", "less", "html",
+                    "vue", "md", "yaml", "yml", "graphql",
+                ],
+            ),
+            Self::new(
+                "clang-format",
+                &["--assume-filename={path}"],
+                &["c", "h", "cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+            ),
+            Self::new("shfmt", &["--filename", "{path}"], &["sh", "bash"]),
+        ]
+    }
+
+    /// Whether the formatter handles the file at `path`
+    pub fn handles(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| self.extensions.iter().any(|x| x == extension))
+    }
+
+    fn args(&self, path: &Path) -> Vec<String> {
+        let path = path.to_string_lossy();
+        self.args
+            .iter()
+            .map(|arg| arg.replace("{path}", &path))
+            .collect()
+    }
+}
+
+/// Formatter of the file at `path`, the first one handling it
+pub fn formatter_for<'a>(
+    formatters: &'a [FormatterConfig],
+    path: &Path,
+) -> Option<&'a FormatterConfig> {
+    formatters.iter().find(|formatter| formatter.handles(path))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FormatError {
+    /// The command of the formatter is not installed
+    NotFound(String),
+    Failed(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(command) => write!(f, "{} is not installed", command),
+            Self::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Run a formatter on `text` of the file at `path` in the folder of the file, returning the
+/// formatted text. The formatter is stopped after `FORMATTER_TIMEOUT`.
+pub async fn format(
+    config: FormatterConfig,
+    path: PathBuf,
+    text: String,
+) -> Result<String, FormatError> {
+    let mut command = Command::new(&config.command);
+    command
+        .args(config.args(&path))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(dir) = path.parent() {
+        command.current_dir(dir);
+    }
+    let mut child = command.spawn().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => FormatError::NotFound(config.command.clone()),
+        _ => FormatError::Failed(format!("failed to run {}: {}", config.command, err)),
+    })?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input_empty = text.trim().is_empty();
+    let write = async move {
+        // A formatter may exit without reading everything, which its status tells about
+        if let Err(err) = stdin.write_all(text.as_bytes()).await {
+            log::warn!("failed to write to formatter: {}", err);
+        }
+    };
+    let output = match time::timeout(FORMATTER_TIMEOUT, async {
+        tokio::join!(write, child.wait_with_output()).1
+    })
+    .await
+    {
+        Ok(result) => result.map_err(|err| {
+            FormatError::Failed(format!("failed to run {}: {}", config.command, err))
+        })?,
+        Err(_) => {
+            return Err(FormatError::Failed(format!(
+                "{} did not finish within {} seconds",
+                config.name,
+                FORMATTER_TIMEOUT.as_secs()
+            )));
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+        return Err(FormatError::Failed(if stderr.is_empty() {
+            format!("{} failed with {}", config.name, output.status)
+        } else {
+            format!("{}: {}", config.name, stderr)
+        }));
+    }
+    let formatted = String::from_utf8(output.stdout)
+        .map_err(|_| FormatError::Failed(format!("{} did not output UTF-8", config.name)))?;
+    // Formatters that write the file instead of stdout would clear the text
+    if formatted.is_empty() && !input_empty {
+        return Err(FormatError::Failed(format!(
+            "{} did not output anything",
+            config.name
+        )));
+    }
+    Ok(formatted)
+}
+
+/// Edits turning `old` into `new`, found by comparing lines and then the characters of changed
+/// lines. Lines end with `\n`, `\r\n` or `\r` like in the edits applied to tabs, and positions
+/// count UTF-8 bytes.
+pub fn minimal_edits(old: &str, new: &str) -> Vec<TextEdit> {
+    let deadline = Instant::now() + DIFF_TIMEOUT;
+    let old_line_starts = workspace_edit::line_starts(old);
+    let new_line_starts = workspace_edit::line_starts(new);
+    let line_offset = |line_starts: &[usize], text: &str, line: usize| {
+        line_starts.get(line).copied().unwrap_or(text.len())
+    };
+    let position = |offset: usize| {
+        let line = old_line_starts.partition_point(|start| *start <= offset) - 1;
+        Position::new(line as u32, (offset - old_line_starts[line]) as u32)
+    };
+
+    let mut edits = Vec::new();
+    let line_diff = TextDiff::configure()
+        .deadline(deadline)
+        .diff_lines(old, new);
+    for op in line_diff.ops() {
+        let (tag, old_lines, new_lines) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        let old_start = line_offset(&old_line_starts, old, old_lines.start);
+        let old_end = line_offset(&old_line_starts, old, old_lines.end);
+        let new_start = line_offset(&new_line_starts, new, new_lines.start);
+        let new_end = line_offset(&new_line_starts, new, new_lines.end);
+        let (old_text, new_text) = (&old[old_start..old_end], &new[new_start..new_end]);
+
+        let old_chars = char_offsets(old_text);
+        let new_chars = char_offsets(new_text);
+        let char_ops = similar::capture_diff_slices_deadline(
+            Algorithm::Myers,
+            &chars(old_text, &old_chars),
+            &chars(new_text, &new_chars),
+            Some(deadline),
+        );
+        for op in char_ops {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                continue;
+            }
+            let start = old_start + old_chars[old_range.start];
+            let end = old_start + old_chars[old_range.end];
+            edits.push(TextEdit::new(
+                Range::new(position(start), position(end)),
+                new_text[new_chars[new_range.start]..new_chars[new_range.end]].to_string(),
+            ));
+        }
+    }
+    edits
+}
+
+/// Byte offsets of the characters of `text`, followed by its length. A `\r\n` line ending is one
+/// character, so edits never start or end inside it.
+fn char_offsets(text: &str) -> Vec<usize> {
+    let mut offsets: Vec<usize> = text
+        .char_indices()
+        .filter(|(i, c)| !(*c == '\n' && text[..*i].ends_with('\r')))
+        .map(|(i, _)| i)
+        .collect();
+    offsets.push(text.len());
+    offsets
+}
+
+/// Characters of `text` at `offsets`
+fn chars<'a>(text: &'a str, offsets: &[usize]) -> Vec<&'a str> {
+    offsets
+        .windows(2)
+        .map(|pair| &text[pair[0]..pair[1]])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lsp::LspPositionEncoding, test_util::block_on};
+
+    #[test]
+    fn formatter_for_path() {
+        let formatters = FormatterConfig::defaults();
+        let name = |path: &str| formatter_for(&formatters, Path::new(path)).map(|f| &f.name);
+        assert_eq!(name("/src/main.rs").unwrap(), "rustfmt");
+        assert_eq!(name("/src/app.tsx").unwrap(), "prettier");
+        assert_eq!(name("/build.sh").unwrap(), "shfmt");
+        assert_eq!(name("/README"), None);
+        assert_eq!(
+            formatters[1].args(Path::new("/a b.py")),
+            ["--quiet", "--stdin-filename", "/a b.py", "-"]
+        );
+    }
+
+    #[test]
+    fn edits_are_minimal() {
+        let old = "fn main(){\n  let x=1;\n\tlet \u{e9}=2;\n}";
+        let new = "fn main() {\n    let x = 1;\n    let \u{e9} = 2;\n}\n";
+        let edits = minimal_edits(old, new);
+        assert_eq!(
+            workspace_edit::apply(old, LspPositionEncoding::Utf8, &edits).unwrap(),
+            new
+        );
+        // Only whitespace is inserted or replaced
+        for edit in &edits {
+            assert!(edit.new_text.trim().is_empty(), "{:?}", edit);
+        }
+        assert_eq!(
+            edits[0],
+            TextEdit::new(
+                Range::new(Position::new(0, 9), Position::new(0, 9)),
+                " ".into()
+            )
+        );
+        assert!(minimal_edits(new, new).is_empty());
+        for (old, new) in [
+            ("", "a\n"),
+            ("a\nb\n", ""),
+            ("x\n\u{1f600}\n", "\u{1f600}\n"),
+            // Line endings are converted without splitting `\r\n`
+            ("a\r\nb\r\n", "a\nb\n"),
+            ("a\nb\n", "a\r\nb\r\n"),
+            ("a\rb\r\nc", "a\nb \nc\n"),
+            ("a \r\n\r\nb", "a\r\nb"),
+        ] {
+            let edits = minimal_edits(old, new);
+            assert_eq!(
+                workspace_edit::apply(old, LspPositionEncoding::Utf8, &edits).unwrap(),
+                new
+            );
+        }
+    }
+
+    #[test]
+    fn run_formatter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        block_on(async {
+            let mut config = FormatterConfig::new("tr", &["a-z", "A-Z"], &["txt"]);
+            assert_eq!(
+                format(config.clone(), path.clone(), "ab\n".into()).await,
+                Ok("AB\n".to_string())
+            );
+
+            config.command = "sh".to_string();
+            config.args = vec!["-c".into(), "echo bad input >&2; exit 1".into()];
+            assert_eq!(
+                format(config.clone(), path.clone(), "ab\n".into()).await,
+                Err(FormatError::Failed("tr: bad input".to_string()))
+            );
+            config.args = vec!["-c".into(), "cat > /dev/null".into()];
+            assert!(
+                format(config.clone(), path.clone(), "ab\n".into())
+                    .await
+                    .is_err()
+            );
+
+            config.command = "cosmic-edit-missing-formatter".to_string();
+            assert_eq!(
+                format(config, path, "ab\n".into()).await,
+                Err(FormatError::NotFound(
+                    "cosmic-edit-missing-formatter".to_string()
+                ))
+            );
+        });
+    }
+}
@@ -1,10 +1,23 @@
-//TODO: try to use gitoxide
+// Operations use libgit2 when the git2 feature is enabled and it can open the repository, and the
+// git CLI otherwise, or for operations libgit2 does not support
 
+use similar::{DiffTag, TextDiff};
 use std::{
+    ffi::OsString,
+    fmt::Write,
     fs, io,
+    ops::Range,
     path::{Path, PathBuf},
+    pin::Pin,
+    process::{Output, Stdio},
 };
-use tokio::process::Command;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+
+#[cfg(feature = "git2")]
+use crate::git_native::GitNative;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct GitDiff {
@@ -13,6 +26,119 @@ pub struct GitDiff {
     pub hunks: Vec<GitDiffHunk>,
 }
 
+impl GitDiff {
+    /// Diff two texts in process, producing hunks without context lines
+    pub fn from_text<P: AsRef<Path>>(path: P, old: &str, new: &str) -> Self {
+        let text_diff = TextDiff::from_lines(old, new);
+        let old_slices = text_diff.old_slices();
+        let new_slices = text_diff.new_slices();
+
+        let mut hunks = Vec::new();
+        for op in text_diff.ops() {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                continue;
+            }
+
+            let mut lines = Vec::with_capacity(old_range.len() + new_range.len());
+            for old_i in old_range.clone() {
+                lines.push(GitDiffLine::Deleted {
+                    old_line: old_i as u64 + 1,
+                    text: old_slices[old_i].to_string(),
+                });
+            }
+            for new_i in new_range.clone() {
+                lines.push(GitDiffLine::Added {
+                    new_line: new_i as u64 + 1,
+                    text: new_slices[new_i].to_string(),
+                });
+            }
+
+            hunks.push(GitDiffHunk {
+                old_range: unified_range(old_range),
+                new_range: unified_range(new_range),
+                lines,
+            });
+        }
+
+        Self {
+            path: path.as_ref().to_path_buf(),
+            staged: false,
+            hunks,
+        }
+    }
+
+    /// Diff two texts in process, with `context_lines` unchanged lines around changes like git
+    /// diff. Used to compare texts that do not come from git.
+    pub fn compare<P: AsRef<Path>>(path: P, old: &str, new: &str, context_lines: u32) -> Self {
+        let text_diff = TextDiff::from_lines(old, new);
+        let old_slices = text_diff.old_slices();
+        let new_slices = text_diff.new_slices();
+
+        let mut hunks = Vec::new();
+        for group in text_diff.grouped_ops(context_lines as usize) {
+            if group.iter().all(|op| op.tag() == DiffTag::Equal) {
+                continue;
+            }
+            let (Some(first), Some(last)) = (group.first(), group.last()) else {
+                continue;
+            };
+
+            let mut lines = Vec::new();
+            for op in group.iter() {
+                let (tag, old_range, new_range) = op.as_tag_tuple();
+                if tag == DiffTag::Equal {
+                    for (old_i, new_i) in old_range.zip(new_range) {
+                        lines.push(GitDiffLine::Context {
+                            old_line: old_i as u64 + 1,
+                            new_line: new_i as u64 + 1,
+                            text: new_slices[new_i].to_string(),
+                        });
+                    }
+                    continue;
+                }
+                for old_i in old_range {
+                    lines.push(GitDiffLine::Deleted {
+                        old_line: old_i as u64 + 1,
+                        text: old_slices[old_i].to_string(),
+                    });
+                }
+                for new_i in new_range {
+                    lines.push(GitDiffLine::Added {
+                        new_line: new_i as u64 + 1,
+                        text: new_slices[new_i].to_string(),
+                    });
+                }
+            }
+
+            hunks.push(GitDiffHunk {
+                old_range: unified_range(first.old_range().start..last.old_range().end),
+                new_range: unified_range(first.new_range().start..last.new_range().end),
+                lines,
+            });
+        }
+
+        Self {
+            path: path.as_ref().to_path_buf(),
+            staged: false,
+            hunks,
+        }
+    }
+}
+
+/// Range of zero based line indexes as in unified diffs, where empty ranges start at the line
+/// before the change
+fn unified_range(range: Range<usize>) -> patch::Range {
+    patch::Range {
+        start: if range.is_empty() {
+            range.start as u64
+        } else {
+            range.start as u64 + 1
+        },
+        count: range.len() as u64,
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct GitDiffHunk {
     pub old_range: patch::Range,
@@ -20,6 +146,360 @@ pub struct GitDiffHunk {
     pub lines: Vec<GitDiffLine>,
 }
 
+impl GitDiffHunk {
+    pub fn change(&self) -> GitLineChange {
+        if self.old_range.count == 0 {
+            GitLineChange::Added
+        } else if self.new_range.count == 0 {
+            GitLineChange::Deleted
+        } else {
+            GitLineChange::Modified
+        }
+    }
+
+    /// Zero based indexes of the new lines, deleted hunks are an empty range at the line after
+    /// the deletion
+    pub fn new_lines(&self) -> Range<usize> {
+        let start = self.new_range.start as usize;
+        if self.new_range.count == 0 {
+            start..start
+        } else {
+            let start = start.saturating_sub(1);
+            start..start + self.new_range.count as usize
+        }
+    }
+
+    /// Generate a patch containing `lines_opt` of this hunk, or the whole hunk if `None`. Lines
+    /// that are not included are left unchanged in the patch target, which is the old side of
+    /// the diff unless `reverse` is set. Returns `None` if the lines change nothing.
+    pub fn patch(
+        &self,
+        relative_path: &Path,
+        lines_opt: Option<Range<usize>>,
+        reverse: bool,
+    ) -> Option<String> {
+        // Build both sides of the patch, the target side is left as it is
+        let mut old_side = Vec::with_capacity(self.lines.len());
+        let mut new_side = Vec::with_capacity(self.lines.len());
+        for (line_i, line) in self.lines.iter().enumerate() {
+            let selected = lines_opt
+                .as_ref()
+                .is_none_or(|lines| lines.contains(&line_i));
+            match line {
+                GitDiffLine::Context { text, .. } => {
+                    old_side.push(text.as_str());
+                    new_side.push(text.as_str());
+                }
+                GitDiffLine::Added { text, .. } => {
+                    if reverse || selected {
+                        new_side.push(text.as_str());
+                    }
+                    if reverse && !selected {
+                        old_side.push(text.as_str());
+                    }
+                }
+                GitDiffLine::Deleted { text, .. } => {
+                    if !reverse || selected {
+                        old_side.push(text.as_str());
+                    }
+                    if !reverse && !selected {
+                        new_side.push(text.as_str());
+                    }
+                }
+            }
+        }
+
+        // Leaving out lines can move a line without a newline away from the end
+        let with_newlines = |side: Vec<&str>| -> Vec<String> {
+            let last = side.len().saturating_sub(1);
+            side.iter()
+                .enumerate()
+                .map(|(i, text)| {
+                    if i < last && !text.ends_with('\n') {
+                        format!("{}\n", text)
+                    } else {
+                        text.to_string()
+                    }
+                })
+                .collect()
+        };
+        let old_side = with_newlines(old_side);
+        let new_side = with_newlines(new_side);
+
+        // Common lines at the start and end are context, the rest is replaced
+        let prefix = old_side
+            .iter()
+            .zip(new_side.iter())
+            .take_while(|(old, new)| old == new)
+            .count();
+        let suffix = old_side[prefix..]
+            .iter()
+            .rev()
+            .zip(new_side[prefix..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        if prefix == old_side.len() && prefix == new_side.len() {
+            return None;
+        }
+        let mut body = String::new();
+        let mut push_line = |prefix: char, text: &str| {
+            body.push(prefix);
+            body.push_str(text);
+            if !text.ends_with('\n') {
+                body.push_str("\n\\ No newline at end of file\n");
+            }
+        };
+        for text in old_side[..prefix].iter() {
+            push_line(' ', text);
+        }
+        for text in old_side[prefix..old_side.len() - suffix].iter() {
+            push_line('-', text);
+        }
+        for text in new_side[prefix..new_side.len() - suffix].iter() {
+            push_line('+', text);
+        }
+        for text in old_side[old_side.len() - suffix..].iter() {
+            push_line(' ', text);
+        }
+
+        // Both sides start at the first line of the target, or the line before if empty
+        let target = if reverse {
+            &self.new_range
+        } else {
+            &self.old_range
+        };
+        let first = if target.count == 0 {
+            target.start + 1
+        } else {
+            target.start
+        };
+        let start = |count: usize| {
+            if count == 0 {
+                first.saturating_sub(1)
+            } else {
+                first
+            }
+        };
+
+        let path = relative_path.display();
+        let mut patch = String::new();
+        // Writing to a string cannot fail
+        let _ = write!(
+            patch,
+            "diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n@@ -{},{} +{},{} @@\n{body}",
+            start(old_side.len()),
+            old_side.len(),
+            start(new_side.len()),
+            new_side.len(),
+        );
+        Some(patch)
+    }
+
+    /// Check if this hunk has context lines, which is not the case for hunks computed in process
+    pub fn has_context(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|line| matches!(line, GitDiffLine::Context { .. }))
+    }
+
+    /// Rows of a side by side view, as indexes into `lines` for the old and new side. Deleted
+    /// lines are paired with the added lines that follow them.
+    pub fn rows(&self) -> Vec<(Option<usize>, Option<usize>)> {
+        let mut rows = Vec::with_capacity(self.lines.len());
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        let flush = |rows: &mut Vec<_>, deleted: &mut Vec<usize>, added: &mut Vec<usize>| {
+            for row_i in 0..deleted.len().max(added.len()) {
+                rows.push((deleted.get(row_i).copied(), added.get(row_i).copied()));
+            }
+            deleted.clear();
+            added.clear();
+        };
+        for (line_i, line) in self.lines.iter().enumerate() {
+            match line {
+                GitDiffLine::Context { .. } => {
+                    flush(&mut rows, &mut deleted, &mut added);
+                    rows.push((Some(line_i), Some(line_i)));
+                }
+                GitDiffLine::Deleted { .. } => {
+                    if !added.is_empty() {
+                        flush(&mut rows, &mut deleted, &mut added);
+                    }
+                    deleted.push(line_i);
+                }
+                GitDiffLine::Added { .. } => added.push(line_i),
+            }
+        }
+        flush(&mut rows, &mut deleted, &mut added);
+        rows
+    }
+
+    /// Text of the old lines including their line endings, used to revert this hunk
+    pub fn old_text(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            GitDiffLine::Context { text, .. } | GitDiffLine::Deleted { text, .. } => {
+                Some(text.as_str())
+            }
+            GitDiffLine::Added { .. } => None,
+        })
+    }
+}
+
+/// Byte ranges of the words of a line that differ from another line
+pub type WordRanges = Vec<Range<usize>>;
+
+/// Byte ranges of the words that differ between an old and a new line, or `None` if the lines
+/// are too different for this to be useful
+pub fn word_diff(old: &str, new: &str) -> Option<(WordRanges, WordRanges)> {
+    let text_diff = TextDiff::from_words(old, new);
+    if text_diff.ratio() < 0.5 {
+        return None;
+    }
+
+    // Convert ranges of words to ranges of bytes
+    let byte_ranges = |slices: &[&str], ranges: Vec<Range<usize>>| {
+        let mut offsets = Vec::with_capacity(slices.len() + 1);
+        let mut offset = 0;
+        offsets.push(offset);
+        for slice in slices {
+            offset += slice.len();
+            offsets.push(offset);
+        }
+        let mut byte_ranges: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let byte_range = offsets[range.start]..offsets[range.end];
+            match byte_ranges.last_mut() {
+                Some(last) if last.end == byte_range.start => last.end = byte_range.end,
+                _ => byte_ranges.push(byte_range),
+            }
+        }
+        byte_ranges
+    };
+
+    let mut old_ranges = Vec::new();
+    let mut new_ranges = Vec::new();
+    for op in text_diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        if !old_range.is_empty() {
+            old_ranges.push(old_range);
+        }
+        if !new_range.is_empty() {
+            new_ranges.push(new_range);
+        }
+    }
+    Some((
+        byte_ranges(text_diff.old_slices(), old_ranges),
+        byte_ranges(text_diff.new_slices(), new_ranges),
+    ))
+}
+
+/// Region with conflict markers left in a file by a merge, as line indices
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitConflict {
+    /// Line of the `<<<<<<<` marker
+    pub start: usize,
+    /// Lines of our version
+    pub ours: Range<usize>,
+    /// Lines of the common ancestor after a `|||||||` marker, only with the diff3 conflict style
+    pub base_opt: Option<Range<usize>>,
+    /// Lines of their version, after the `=======` marker
+    pub theirs: Range<usize>,
+    /// Line of the `>>>>>>>` marker
+    pub end: usize,
+}
+
+impl GitConflict {
+    /// All lines of the conflict, including markers
+    pub fn lines(&self) -> Range<usize> {
+        self.start..self.end + 1
+    }
+
+    /// Lines kept when resolving the conflict with a choice
+    pub fn resolution(&self, choice: GitConflictChoice) -> Vec<Range<usize>> {
+        match choice {
+            GitConflictChoice::Ours => vec![self.ours.clone()],
+            GitConflictChoice::Theirs => vec![self.theirs.clone()],
+            GitConflictChoice::Both => vec![self.ours.clone(), self.theirs.clone()],
+        }
+    }
+}
+
+/// Find conflict regions in the lines of a file, unterminated regions are ignored
+pub fn conflicts<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<GitConflict> {
+    // Markers are seven characters, optionally followed by a space and a label
+    let is_marker = |line: &str, c: char| {
+        line.len() >= 7
+            && line.bytes().take(7).all(|b| b == c as u8)
+            && line[7..].chars().next().is_none_or(char::is_whitespace)
+    };
+
+    let mut conflicts = Vec::new();
+    let mut start_opt = None;
+    let mut base_start_opt = None;
+    let mut separator_opt = None;
+    for (line_i, line) in lines.enumerate() {
+        if is_marker(line, '<') {
+            // A new conflict starts, any unterminated one is dropped
+            start_opt = Some(line_i);
+            base_start_opt = None;
+            separator_opt = None;
+        } else if let Some(start) = start_opt {
+            if is_marker(line, '|') && base_start_opt.is_none() && separator_opt.is_none() {
+                base_start_opt = Some(line_i);
+            } else if is_marker(line, '=') && separator_opt.is_none() {
+                separator_opt = Some(line_i);
+            } else if is_marker(line, '>') {
+                if let Some(separator) = separator_opt {
+                    let ours_end = base_start_opt.unwrap_or(separator);
+                    conflicts.push(GitConflict {
+                        start,
+                        ours: start + 1..ours_end,
+                        base_opt: base_start_opt.map(|base_start| base_start + 1..separator),
+                        theirs: separator + 1..line_i,
+                        end: line_i,
+                    });
+                }
+                start_opt = None;
+                base_start_opt = None;
+                separator_opt = None;
+            }
+        }
+    }
+    conflicts
+}
+
+/// How to resolve a [`GitConflict`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GitConflictChoice {
+    Ours,
+    Theirs,
+    /// Our version followed by theirs
+    Both,
+}
+
+/// What to do with a hunk of a [`GitDiff`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GitHunkAction {
+    /// Apply unstaged changes to the index
+    Stage,
+    /// Remove staged changes from the index
+    Unstage,
+    /// Remove unstaged changes from the working tree
+    Discard,
+}
+
+/// Change of a line compared to the git index, shown in the editor gutter
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GitLineChange {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A line of a diff, the text includes the original line ending if the line has one
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum GitDiffLine {
     Context {
@@ -37,6 +517,165 @@ pub enum GitDiffLine {
     },
 }
 
+impl GitDiffLine {
+    /// Text of the line without its line ending
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Context { text, .. } | Self::Added { text, .. } | Self::Deleted { text, .. } => {
+                trim_line_ending(text)
+            }
+        }
+    }
+}
+
+/// Remove a trailing LF or CRLF
+pub fn trim_line_ending(text: &str) -> &str {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.strip_suffix('\r').unwrap_or(text)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitCommit {
+    pub hash: String,
+    pub short_hash: String,
+    pub author: String,
+    /// Date relative to now, like "2 days ago"
+    pub date: String,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GitBlameCommit {
+    pub hash: String,
+    pub short_hash: String,
+    pub author: String,
+    /// Author time in seconds since the Unix epoch
+    pub time: i64,
+    pub summary: String,
+    /// Full commit message, empty for changes that are not committed yet
+    pub message: String,
+    /// Path of the file in this commit, which differs from the current path if it was renamed
+    pub path: PathBuf,
+}
+
+impl GitBlameCommit {
+    /// Changes that are not committed yet are blamed on a hash of all zeroes
+    pub fn is_committed(&self) -> bool {
+        self.hash.bytes().any(|b| b != b'0')
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitBlame {
+    pub commits: Vec<GitBlameCommit>,
+    /// Index into `commits` for each line
+    pub lines: Vec<usize>,
+    /// Text of each line without its line ending
+    pub contents: Vec<String>,
+}
+
+impl GitBlame {
+    /// Blame the lines of `text`, a changed version of the blamed text. Lines that are not in
+    /// the blamed text are blamed on changes that are not committed yet.
+    pub fn with_text(&self, text: &str) -> Self {
+        let contents: Vec<String> = text.lines().map(str::to_string).collect();
+        let mut commits = self.commits.clone();
+        let mut uncommitted_i_opt = commits.iter().position(|commit| !commit.is_committed());
+        let mut lines = Vec::with_capacity(contents.len());
+        for op in similar::capture_diff_slices(similar::Algorithm::Myers, &self.contents, &contents)
+        {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            match self.lines.get(old_range) {
+                Some(old_lines) if tag == DiffTag::Equal => lines.extend_from_slice(old_lines),
+                _ => {
+                    let uncommitted_i = *uncommitted_i_opt.get_or_insert_with(|| {
+                        commits.push(GitBlameCommit {
+                            hash: "0".repeat(40),
+                            short_hash: "0".repeat(8),
+                            ..Default::default()
+                        });
+                        commits.len() - 1
+                    });
+                    lines.extend(std::iter::repeat_n(uncommitted_i, new_range.len()));
+                }
+            }
+        }
+        Self {
+            commits,
+            lines,
+            contents,
+        }
+    }
+}
+
+/// A commit that changed a file, with the path of the file at that commit
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitFileRevision {
+    pub commit: GitCommit,
+    pub path: PathBuf,
+}
+
+/// What `HEAD` of a repository points to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GitHead {
+    /// A local branch, which may not have any commits yet
+    Branch(String),
+    /// A commit that is not on a branch, with its short hash
+    Detached(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitBranch {
+    pub name: String,
+    /// The branch is checked out
+    pub current: bool,
+    /// Upstream branch, if one is configured
+    pub upstream_opt: Option<String>,
+}
+
+/// Changes set aside with git stash
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitStash {
+    /// Hash of the stash commit, which stays the same when other stashes are added or removed
+    pub hash: String,
+    /// Message of the stash, like "On main: message"
+    pub message: String,
+    /// Date relative to now, like "2 days ago"
+    pub date: String,
+}
+
+/// How a repository was found
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GitProjectKind {
+    /// Repository of an open project
+    Project,
+    /// Submodule of another repository
+    Submodule,
+    /// Repository inside of another repository's working tree, not tracked by it
+    Nested,
+}
+
+/// State of a project repository shown in git management
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitProject {
+    pub name: String,
+    /// Path of the working tree
+    pub path: PathBuf,
+    pub kind: GitProjectKind,
+    pub status: Vec<GitStatus>,
+    /// Most recent commits, empty if there are none
+    pub commits: Vec<GitCommit>,
+    pub head_opt: Option<GitHead>,
+    /// Local branches, sorted by name
+    pub branches: Vec<GitBranch>,
+    /// Stashes, most recent first
+    pub stashes: Vec<GitStash>,
+    /// Ignored files, and folders instead of their contents
+    pub ignored: Vec<PathBuf>,
+    /// Commits ahead of and behind the upstream of the current branch, if it has one
+    pub ahead_behind_opt: Option<(usize, usize)>,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct GitStatus {
     pub path: PathBuf,
@@ -45,6 +684,20 @@ pub struct GitStatus {
     pub unstaged: GitStatusKind,
 }
 
+impl GitStatus {
+    /// Unmerged paths, which are conflicted until they are staged
+    pub fn is_conflicted(&self) -> bool {
+        // https://git-scm.com/docs/git-status#_short_format
+        matches!(
+            (self.staged, self.unstaged),
+            (GitStatusKind::Updated, _)
+                | (_, GitStatusKind::Updated)
+                | (GitStatusKind::Added, GitStatusKind::Added)
+                | (GitStatusKind::Deleted, GitStatusKind::Deleted)
+        )
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum GitStatusKind {
     Unmodified,
@@ -82,22 +735,98 @@ impl TryFrom<char> for GitStatusKind {
 
 pub struct GitRepository {
     path: PathBuf,
+    /// Operations use libgit2 if it could open the repository
+    #[cfg(feature = "git2")]
+    native_opt: Option<GitNative>,
 }
 
 impl GitRepository {
-    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    /// Find the repository with a working tree containing the folder `path`
+    pub async fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         let path = path.as_ref();
-        if path.join(".git").exists() {
-            let path = fs::canonicalize(path)?;
-            Ok(Self { path })
-        } else {
-            Err(io::Error::new(
+
+        #[cfg(feature = "git2")]
+        if Self::native_enabled() {
+            match GitNative::discover(path.to_path_buf()).await {
+                Ok(native) => {
+                    return Ok(Self {
+                        path: native.path().to_path_buf(),
+                        native_opt: Some(native),
+                    });
+                }
+                Err(err) => {
+                    log::info!("failed to open {:?} with libgit2, using git: {}", path, err);
+                }
+            }
+        }
+
+        // Asking git also finds repositories of subfolders and worktrees, where .git is a file
+        let mut command = Command::new("git");
+        command.arg("-C").arg(path);
+        command.arg("rev-parse").arg("--show-toplevel");
+        let stdout = Self::command_stdout(command).await.map_err(|err| {
+            io::Error::new(
                 io::ErrorKind::NotFound,
-                format!("{:?} is not a git repository", path),
-            ))
+                format!("{:?} is not in a git repository: {}", path, err),
+            )
+        })?;
+        let toplevel = stdout.trim_end_matches(['\r', '\n']);
+        if toplevel.is_empty() {
+            // Bare repositories have no working tree
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} is not in a git working tree", path),
+            ));
+        }
+        let path = fs::canonicalize(toplevel)?;
+        Ok(Self {
+            path,
+            #[cfg(feature = "git2")]
+            native_opt: None,
+        })
+    }
+
+    /// Use of libgit2 can be disabled by setting `COSMIC_EDIT_GIT_BACKEND=cli`
+    #[cfg(feature = "git2")]
+    fn native_enabled() -> bool {
+        std::env::var("COSMIC_EDIT_GIT_BACKEND").as_deref() != Ok("cli")
+    }
+
+    /// Run an operation with libgit2 if it opened the repository, and with the git CLI otherwise
+    /// or if libgit2 fails
+    async fn dispatch<T>(
+        &self,
+        #[cfg_attr(not(feature = "git2"), allow(unused_variables))] what: String,
+        op: impl for<'b> Fn(&'b dyn GitBackendOps) -> GitFuture<'b, T>,
+    ) -> io::Result<T> {
+        #[cfg(feature = "git2")]
+        if let Some(native) = &self.native_opt {
+            match op(native).await {
+                Ok(ok) => return Ok(ok),
+                // Conversions like line endings and filters need the CLI
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+                Err(err) => log::warn!("failed to {} with libgit2, using git: {}", what, err),
+            }
+        }
+        op(&GitCli(self)).await
+    }
+
+    /// Find the repository containing `path`, which may be a file
+    pub async fn containing<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Self::new(path).await
+        } else {
+            // Files may not exist yet, so start from their folder
+            Self::new(path.parent().unwrap_or(path)).await
         }
     }
 
+    /// Path of the working tree
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     fn command(&self) -> Command {
         let mut command = Command::new("git");
         command.arg("-C").arg(&self.path);
@@ -107,6 +836,30 @@ impl GitRepository {
     async fn command_stdout(mut command: Command) -> io::Result<String> {
         log::info!("{:?}", command);
         let output = command.output().await?;
+        Self::output_stdout(output)
+    }
+
+    async fn command_stdin_stdout(mut command: Command, stdin: &str) -> io::Result<String> {
+        log::info!("{:?}", command);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn()?;
+        let mut child_stdin = child.stdin.take().expect("stdin is piped");
+        // Output is read while writing, so git does not block on a full pipe before reading all
+        // of stdin
+        let write = async move {
+            // git may exit without reading everything, which its status tells about
+            if let Err(err) = child_stdin.write_all(stdin.as_bytes()).await {
+                log::warn!("failed to write to git: {}", err);
+            }
+        };
+        let output = tokio::join!(write, child.wait_with_output()).1?;
+        Self::output_stdout(output)
+    }
+
+    fn output_stdout(output: Output) -> io::Result<String> {
         if output.status.success() {
             String::from_utf8(output.stdout).map_err(|err| {
                 io::Error::new(
@@ -128,60 +881,161 @@ impl GitRepository {
         }
     }
 
-    pub async fn diff<P: AsRef<Path>>(&self, path: P, staged: bool) -> io::Result<GitDiff> {
-        let path = path.as_ref();
+    fn relative_path<'a>(&self, path: &'a Path) -> io::Result<&'a Path> {
+        path.strip_prefix(&self.path).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not in {:?}: {}", path, self.path, err),
+            )
+        })
+    }
+
+    /// Stage, unstage, or discard `lines_opt` of a hunk from a diff of `path`, or the whole hunk
+    /// if `None`
+    pub async fn apply_hunk<P: AsRef<Path>>(
+        &self,
+        path: P,
+        hunk: &GitDiffHunk,
+        lines_opt: Option<Range<usize>>,
+        action: GitHunkAction,
+    ) -> io::Result<()> {
+        let relative_path = self.relative_path(path.as_ref())?;
+        let reverse = action != GitHunkAction::Stage;
+        let Some(patch) = hunk.patch(relative_path, lines_opt, reverse) else {
+            return Ok(());
+        };
         let mut command = self.command();
-        command.arg("diff");
-        if staged {
-            command.arg("--staged");
+        command.arg("apply");
+        if action != GitHunkAction::Discard {
+            command.arg("--cached");
         }
-        command.arg("--").arg(path);
-        let diff = Self::command_stdout(command).await?;
-        let patch = patch::Patch::from_single(&diff).map_err(|err| {
+        if reverse {
+            command.arg("--reverse");
+        }
+        // Hunks computed in process have no context lines
+        if !hunk.has_context() {
+            command.arg("--unidiff-zero");
+        }
+        command.arg("-");
+        Self::command_stdin_stdout(command, &patch).await?;
+        Ok(())
+    }
+
+    pub async fn diff<P: AsRef<Path>>(
+        &self,
+        path: P,
+        staged: bool,
+        context_lines: u32,
+    ) -> io::Result<GitDiff> {
+        let path = path.as_ref();
+        let diff = self
+            .dispatch(format!("diff {:?}", path), |backend| {
+                backend.diff(path.to_path_buf(), staged, context_lines)
+            })
+            .await?;
+        Self::parse_diff(path, staged, &diff)
+    }
+
+    /// Parse a unified diff of a single file. Line endings are kept, so lines missing one are
+    /// the ones followed by a `\ No newline at end of file` marker.
+    fn parse_diff(path: &Path, staged: bool, diff: &str) -> io::Result<GitDiff> {
+        let invalid = |msg: String| {
             io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("failed to parse diff: {}", err),
+                format!("failed to parse diff: {}", msg),
             )
-        })?;
+        };
+        let parse_range = |range: &str| -> Option<patch::Range> {
+            let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+            Some(patch::Range {
+                start: start.parse().ok()?,
+                count: count.parse().ok()?,
+            })
+        };
 
-        let mut hunks = Vec::with_capacity(patch.hunks.len());
-        for hunk in patch.hunks.iter() {
-            //TODO: validate range counts
-            let mut old_line = hunk.old_range.start;
-            let mut new_line = hunk.new_range.start;
+        let mut hunks = Vec::new();
+        let mut diff_lines = diff.split_inclusive('\n').peekable();
+        while let Some(header) = diff_lines.next() {
+            // File headers come before the first hunk
+            let Some(header) = header.strip_prefix("@@ -") else {
+                continue;
+            };
+            let ranges_opt = header.split_once(" @@").and_then(|(ranges, _)| {
+                let (old, new) = ranges.split_once(" +")?;
+                Some((parse_range(old)?, parse_range(new)?))
+            });
+            let Some((old_range, new_range)) = ranges_opt else {
+                return Err(invalid(format!("invalid hunk header {:?}", header)));
+            };
 
-            let mut lines = Vec::with_capacity(hunk.lines.len());
-            for line in hunk.lines.iter() {
-                match line {
-                    patch::Line::Context(text) => {
+            let mut old_line = old_range.start;
+            let mut new_line = new_range.start;
+            let mut old_remaining = old_range.count;
+            let mut new_remaining = new_range.count;
+            let mut lines = Vec::new();
+            while old_remaining > 0 || new_remaining > 0 {
+                let Some(diff_line) = diff_lines.next() else {
+                    return Err(invalid(format!(
+                        "hunk at line {} ends early",
+                        old_range.start
+                    )));
+                };
+                // Some tools strip the space of empty context lines
+                let (prefix, text) = match diff_line.chars().next() {
+                    Some(prefix @ (' ' | '+' | '-')) => (prefix, &diff_line[1..]),
+                    _ if trim_line_ending(diff_line).is_empty() => (' ', diff_line),
+                    _ => {
+                        return Err(invalid(format!("invalid hunk line {:?}", diff_line)));
+                    }
+                };
+                let text = text.to_string();
+                match prefix {
+                    '+' if new_remaining > 0 => {
+                        lines.push(GitDiffLine::Added { new_line, text });
+                        new_line += 1;
+                        new_remaining -= 1;
+                    }
+                    '-' if old_remaining > 0 => {
+                        lines.push(GitDiffLine::Deleted { old_line, text });
+                        old_line += 1;
+                        old_remaining -= 1;
+                    }
+                    ' ' if old_remaining > 0 && new_remaining > 0 => {
                         lines.push(GitDiffLine::Context {
                             old_line,
                             new_line,
-                            text: text.to_string(),
+                            text,
                         });
                         old_line += 1;
                         new_line += 1;
+                        old_remaining -= 1;
+                        new_remaining -= 1;
                     }
-                    patch::Line::Add(text) => {
-                        lines.push(GitDiffLine::Added {
-                            new_line,
-                            text: text.to_string(),
-                        });
-                        new_line += 1;
-                    }
-                    patch::Line::Remove(text) => {
-                        lines.push(GitDiffLine::Deleted {
-                            old_line,
-                            text: text.to_string(),
-                        });
-                        old_line += 1;
+                    _ => {
+                        return Err(invalid(format!(
+                            "hunk at line {} does not match its line counts",
+                            old_range.start
+                        )));
                     }
                 }
+
+                // The marker applies to the line before it
+                if diff_lines.next_if(|line| line.starts_with('\\')).is_some()
+                    && let Some(
+                        GitDiffLine::Context { text, .. }
+                        | GitDiffLine::Added { text, .. }
+                        | GitDiffLine::Deleted { text, .. },
+                    ) = lines.last_mut()
+                    // Only the newline was added by the diff, a carriage return is content
+                    && text.ends_with('\n')
+                {
+                    text.pop();
+                }
             }
 
             hunks.push(GitDiffHunk {
-                old_range: hunk.old_range.clone(),
-                new_range: hunk.new_range.clone(),
+                old_range,
+                new_range,
                 lines,
             });
         }
@@ -193,92 +1047,1801 @@ impl GitRepository {
         })
     }
 
-    pub async fn status(&self) -> io::Result<Vec<GitStatus>> {
+    /// Read the indexed version of a file, converted like a checkout would
+    pub async fn index_text<P: AsRef<Path>>(&self, path: P) -> io::Result<String> {
+        let path = fs::canonicalize(path)?;
+        self.dispatch(format!("read {:?}", path), |backend| {
+            backend.index_text(path.clone())
+        })
+        .await
+    }
+
+    /// Blame each line of `path` as committed in the commit `hash`, use [`GitBlame::with_text`]
+    /// to blame changes that are not committed
+    pub async fn blame<P: AsRef<Path>>(&self, hash: &str, path: P) -> io::Result<GitBlame> {
+        let path = fs::canonicalize(path)?;
+        let relative_path = self.relative_path(&path)?;
         let mut command = self.command();
-        command.arg("status").arg("-z");
+        command
+            .arg("blame")
+            .arg("--porcelain")
+            .arg(hash)
+            .arg("--")
+            .arg(relative_path);
         let stdout = Self::command_stdout(command).await?;
+        let mut blame = Self::parse_blame(&self.path, &stdout);
 
-        let mut status = Vec::new();
-        let mut lines = stdout.split('\0');
-        while let Some(line) = lines.next() {
-            macro_rules! invalid_line {
-                () => {{
-                    log::warn!("invalid git status line {:?}", line);
-                    continue;
-                }};
+        // Porcelain output only has the summary, look up full messages
+        let hashes: Vec<&str> = blame
+            .commits
+            .iter()
+            .filter(|commit| commit.is_committed())
+            .map(|commit| commit.hash.as_str())
+            .collect();
+        if !hashes.is_empty() {
+            let mut command = self.command();
+            command
+                .arg("show")
+                .arg("--no-patch")
+                .arg("--format=%H%x00%B%x1e")
+                .args(hashes);
+            let stdout = Self::command_stdout(command).await?;
+            for entry in stdout.split('\x1e') {
+                if let Some((hash, message)) = entry.trim_start().split_once('\0') {
+                    for commit in blame.commits.iter_mut() {
+                        if commit.hash == hash {
+                            commit.message = message.trim_end().to_string();
+                        }
+                    }
+                }
             }
+        }
+
+        Ok(blame)
+    }
 
-            if line.is_empty() {
-                // Ignore empty lines
+    // https://git-scm.com/docs/git-blame#_the_porcelain_format
+    fn parse_blame(repo_path: &Path, porcelain: &str) -> GitBlame {
+        let mut blame = GitBlame {
+            commits: Vec::new(),
+            lines: Vec::new(),
+            contents: Vec::new(),
+        };
+        let mut commit_i_opt = None;
+        for line in porcelain.lines() {
+            if let Some(contents) = line.strip_prefix('\t') {
+                // Line contents end each entry
+                match commit_i_opt.take() {
+                    Some(commit_i) => {
+                        blame.lines.push(commit_i);
+                        blame.contents.push(contents.to_string());
+                    }
+                    None => log::warn!("git blame line without header"),
+                }
                 continue;
             }
 
-            let mut chars = line.chars();
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            if commit_i_opt.is_none() {
+                // Header of a new entry
+                let commit_i = match blame.commits.iter().position(|commit| commit.hash == key) {
+                    Some(commit_i) => commit_i,
+                    None => {
+                        blame.commits.push(GitBlameCommit {
+                            hash: key.to_string(),
+                            short_hash: key.chars().take(8).collect(),
+                            ..Default::default()
+                        });
+                        blame.commits.len() - 1
+                    }
+                };
+                commit_i_opt = Some(commit_i);
+                continue;
+            }
 
-            // Get staged status
-            let staged = match chars.next() {
-                Some(some) => match GitStatusKind::try_from(some) {
-                    Ok(ok) => ok,
-                    Err(_) => invalid_line!(),
-                },
-                None => invalid_line!(),
-            };
+            if let Some(commit) = commit_i_opt.and_then(|commit_i| blame.commits.get_mut(commit_i))
+            {
+                match key {
+                    "author" => commit.author = value.to_string(),
+                    "author-time" => commit.time = value.parse().unwrap_or_default(),
+                    "summary" => commit.summary = value.to_string(),
+                    "filename" => commit.path = repo_path.join(value),
+                    _ => {}
+                }
+            }
+        }
+        blame
+    }
 
-            // Get unstaged status
-            let unstaged = match chars.next() {
-                Some(some) => match GitStatusKind::try_from(some) {
-                    Ok(ok) => ok,
-                    Err(_) => invalid_line!(),
-                },
-                None => invalid_line!(),
-            };
+    /// Diff of `path` in the commit `hash`, with `context_lines` unchanged lines around changes
+    pub async fn commit_diff<P: AsRef<Path>>(
+        &self,
+        hash: &str,
+        path: P,
+        context_lines: u32,
+    ) -> io::Result<GitDiff> {
+        let path = path.as_ref();
 
-            // Skip space
-            match chars.next() {
-                Some(' ') => {}
-                _ => invalid_line!(),
+        // Merges are compared to their first parent, and root commits to an empty tree
+        let mut command = self.command();
+        command
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(format!("{}^", hash));
+        let parent = match Self::command_stdout(command).await {
+            Ok(stdout) => stdout,
+            Err(_) => {
+                let mut command = self.command();
+                command
+                    .arg("hash-object")
+                    .arg("-t")
+                    .arg("tree")
+                    .arg("--stdin");
+                Self::command_stdin_stdout(command, "").await?
             }
+        };
 
-            // The rest of the chars are in the path
-            let relative_path: String = chars.collect();
+        let mut command = self.command();
+        command
+            .arg("diff")
+            .arg(format!("--unified={}", context_lines))
+            .arg(parent.trim_end())
+            .arg(hash)
+            .arg("--")
+            .arg(path);
+        let diff = Self::command_stdout(command).await?;
+        Self::parse_diff(path, false, &diff)
+    }
 
-            let old_path = if staged == GitStatusKind::Renamed || unstaged == GitStatusKind::Renamed
-            {
-                match lines.next() {
-                    Some(old_relative_path) => Some(self.path.join(old_relative_path)),
-                    None => invalid_line!(),
+    /// Commit staged changes, `message` is passed to git unmodified
+    pub async fn commit(&self, message: &str, amend: bool, signoff: bool) -> io::Result<String> {
+        let mut command = self.command();
+        command
+            .arg("commit")
+            .arg("--file=-")
+            .arg("--cleanup=whitespace");
+        if amend {
+            command.arg("--amend");
+        }
+        if signoff {
+            command.arg("--signoff");
+        }
+        Self::command_stdin_stdout(command, message).await
+    }
+
+    /// Full message of the last commit, used when amending
+    pub async fn last_commit_message(&self) -> io::Result<String> {
+        let mut command = self.command();
+        command.arg("log").arg("-1").arg("--format=%B");
+        let stdout = Self::command_stdout(command).await?;
+        Ok(stdout.trim_end().to_string())
+    }
+
+    /// Get up to `count` commits, starting at `HEAD`
+    pub async fn log(&self, count: usize) -> io::Result<Vec<GitCommit>> {
+        let mut command = self.command();
+        command
+            .arg("log")
+            .arg(format!("--max-count={}", count))
+            .arg("--format=%H%x00%h%x00%an%x00%ar%x00%s");
+        let stdout = Self::command_stdout(command).await?;
+
+        let mut commits = Vec::new();
+        for line in stdout.lines() {
+            match Self::parse_commit(&mut line.split('\0')) {
+                Some(commit) => commits.push(commit),
+                None => {
+                    log::warn!("invalid git log line {:?}", line);
                 }
-            } else {
-                None
-            };
+            }
+        }
+        Ok(commits)
+    }
 
-            status.push(GitStatus {
-                path: self.path.join(relative_path),
-                old_path,
-                staged,
-                unstaged,
-            })
+    /// Get all commits that changed `path`, following renames
+    pub async fn file_log<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<GitFileRevision>> {
+        let path = fs::canonicalize(path)?;
+        let relative_path = self.relative_path(&path)?;
+        let mut command = self.command();
+        command
+            .arg("log")
+            .arg("--follow")
+            .arg("--name-only")
+            .arg("-z")
+            .arg("--format=%x1e%H%x00%h%x00%an%x00%ar%x00%s")
+            .arg("--")
+            .arg(relative_path);
+        let stdout = Self::command_stdout(command).await?;
+
+        // Each entry is the commit, followed by a newline and the path at that commit
+        let mut revisions = Vec::new();
+        for entry in stdout.split('\x1e') {
+            if entry.is_empty() {
+                continue;
+            }
+            let mut parts = entry.split('\0');
+            match (Self::parse_commit(&mut parts), parts.next()) {
+                (Some(commit), Some(name)) => {
+                    revisions.push(GitFileRevision {
+                        commit,
+                        path: self.path.join(name.trim_start_matches('\n')),
+                    });
+                }
+                _ => {
+                    log::warn!("invalid git log entry {:?}", entry);
+                }
+            }
         }
+        Ok(revisions)
+    }
 
-        Ok(status)
+    fn parse_commit<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Option<GitCommit> {
+        match (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) {
+            (Some(hash), Some(short_hash), Some(author), Some(date), Some(summary)) => {
+                Some(GitCommit {
+                    hash: hash.to_string(),
+                    short_hash: short_hash.to_string(),
+                    author: author.to_string(),
+                    date: date.to_string(),
+                    summary: summary.to_string(),
+                })
+            }
+            _ => None,
+        }
     }
 
-    pub async fn stage<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    /// Contents of `path` as it was in the commit `hash`
+    pub async fn revision_text<P: AsRef<Path>>(&self, hash: &str, path: P) -> io::Result<String> {
+        let relative_path = self.relative_path(path.as_ref())?;
+        let mut command = self.command();
+        command.arg("cat-file").arg("--filters").arg(format!(
+            "{}:{}",
+            hash,
+            relative_path.display()
+        ));
+        Self::command_stdout(command).await
+    }
+
+    /// Contents of `path` as it was in the revision `rev`, `None` if it did not exist there
+    async fn revision_text_opt(&self, rev: &str, path: &Path) -> io::Result<Option<String>> {
+        let relative_path = self.relative_path(path)?;
+        let mut command = self.command();
+        command
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(format!("{}:{}", rev, relative_path.display()));
+        log::info!("{:?}", command);
+        if !command.output().await?.status.success() {
+            return Ok(None);
+        }
+        self.revision_text(rev, path).await.map(Some)
+    }
+
+    /// Full text of the old and new side of a diff of `path`, which are the parent and the commit
+    /// `hash_opt`, `HEAD` and the index for staged changes, or the index and the working tree.
+    /// Sides that do not exist, like the old side of added files, are empty.
+    pub async fn diff_sides<P: AsRef<Path>>(
+        &self,
+        path: P,
+        staged: bool,
+        hash_opt: Option<&str>,
+    ) -> io::Result<(String, String)> {
         let path = path.as_ref();
+        if let Some(hash) = hash_opt {
+            // Only the parent of the commit may be missing
+            let mut command = self.command();
+            command
+                .arg("rev-parse")
+                .arg("--verify")
+                .arg(format!("{}^{{commit}}", hash));
+            Self::command_stdout(command).await?;
+        }
+        let (old_rev, new_rev_opt) = match hash_opt {
+            Some(hash) => (format!("{}^", hash), Some(hash.to_string())),
+            // An empty revision is the index
+            None if staged => ("HEAD".to_string(), Some(String::new())),
+            None => (String::new(), None),
+        };
+        let old_opt = self.revision_text_opt(&old_rev, path).await?;
+        let new_opt = match new_rev_opt {
+            Some(new_rev) => self.revision_text_opt(&new_rev, path).await?,
+            None => match fs::read_to_string(path) {
+                Ok(text) => Some(text),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            },
+        };
+        Ok((old_opt.unwrap_or_default(), new_opt.unwrap_or_default()))
+    }
+
+    /// Folder with the repository data, which is not `.git` in the working tree for worktrees and
+    /// submodules
+    pub async fn git_dir(&self) -> io::Result<PathBuf> {
         let mut command = self.command();
-        command.arg("stage");
-        command.arg("--").arg(path);
+        command.arg("rev-parse").arg("--absolute-git-dir");
+        let stdout = Self::command_stdout(command).await?;
+        Ok(PathBuf::from(stdout.trim_end_matches(['\r', '\n'])))
+    }
+
+    /// Full hash of the commit `HEAD` points to
+    pub async fn head_hash(&self) -> io::Result<String> {
+        let mut command = self.command();
+        command.arg("rev-parse").arg("--verify").arg("HEAD");
+        let stdout = Self::command_stdout(command).await?;
+        Ok(stdout.trim_end().to_string())
+    }
+
+    /// Check if `HEAD` points to a commit, which is false for new repositories
+    pub async fn has_commits(&self) -> bool {
+        let mut command = self.command();
+        command
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg("HEAD");
+        Self::command_stdout(command).await.is_ok()
+    }
+
+    pub async fn head(&self) -> io::Result<GitHead> {
+        let mut command = self.command();
+        command
+            .arg("symbolic-ref")
+            .arg("--quiet")
+            .arg("--short")
+            .arg("HEAD");
+        match Self::command_stdout(command).await {
+            Ok(stdout) => Ok(GitHead::Branch(stdout.trim_end().to_string())),
+            Err(_) => {
+                // Not a symbolic ref, so HEAD is detached
+                let mut command = self.command();
+                command.arg("rev-parse").arg("--short").arg("HEAD");
+                let stdout = Self::command_stdout(command).await?;
+                Ok(GitHead::Detached(stdout.trim_end().to_string()))
+            }
+        }
+    }
+
+    pub async fn branches(&self) -> io::Result<Vec<GitBranch>> {
+        let mut command = self.command();
+        command
+            .arg("for-each-ref")
+            .arg("--format=%(refname:short)%00%(HEAD)%00%(upstream:short)")
+            .arg("refs/heads");
+        let stdout = Self::command_stdout(command).await?;
+
+        let mut branches = Vec::new();
+        for line in stdout.lines() {
+            let mut parts = line.split('\0');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(head), Some(upstream)) => {
+                    branches.push(GitBranch {
+                        name: name.to_string(),
+                        current: head == "*",
+                        upstream_opt: if upstream.is_empty() {
+                            None
+                        } else {
+                            Some(upstream.to_string())
+                        },
+                    });
+                }
+                _ => {
+                    log::warn!("invalid git for-each-ref line {:?}", line);
+                }
+            }
+        }
+        Ok(branches)
+    }
+
+    /// Fail if `name` is not a valid branch name. Valid names cannot start with `-`, so they are
+    /// not taken as options by commands that do not accept `--` before them.
+    async fn check_branch_name(&self, name: &str) -> io::Result<()> {
+        let mut command = self.command();
+        command.arg("check-ref-format").arg("--branch").arg(name);
+        // Shorthands like `@{-1}` are expanded to other names
+        if Self::command_stdout(command).await?.trim_end() != name {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a valid branch name", name),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check out the local branch `name`, fails if changes in the working tree would be lost
+    pub async fn checkout_branch(&self, name: &str) -> io::Result<()> {
+        self.check_branch_name(name).await?;
+        let mut command = self.command();
+        command.arg("switch").arg("--no-guess").arg(name);
         Self::command_stdout(command).await?;
         Ok(())
     }
 
-    pub async fn unstage<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    /// Create the branch `name` at `HEAD` and check it out
+    pub async fn create_branch(&self, name: &str) -> io::Result<()> {
+        self.check_branch_name(name).await?;
+        let mut command = self.command();
+        command.arg("switch").arg("--create").arg(name);
+        Self::command_stdout(command).await?;
+        Ok(())
+    }
+
+    /// Delete the local branch `name`, fails if it is not merged
+    pub async fn delete_branch(&self, name: &str) -> io::Result<()> {
+        let mut command = self.command();
+        command.arg("branch").arg("--delete").arg("--").arg(name);
+        Self::command_stdout(command).await?;
+        Ok(())
+    }
+
+    /// Number of commits `HEAD` is ahead of and behind its upstream branch, `None` if there is no
+    /// upstream
+    pub async fn ahead_behind(&self) -> io::Result<Option<(usize, usize)>> {
+        let mut command = self.command();
+        command
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg("@{upstream}");
+        if Self::command_stdout(command).await.is_err() {
+            return Ok(None);
+        }
+
+        let mut command = self.command();
+        command
+            .arg("rev-list")
+            .arg("--left-right")
+            .arg("--count")
+            .arg("HEAD...@{upstream}");
+        let stdout = Self::command_stdout(command).await?;
+        let mut parts = stdout.split_whitespace().map(|part| part.parse::<usize>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(ahead)), Some(Ok(behind))) => Ok(Some((ahead, behind))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid git rev-list count {:?}", stdout),
+            )),
+        }
+    }
+
+    /// Remote that branches without upstream are pushed to: `remote.pushDefault`, `origin`, or
+    /// the only remote
+    pub async fn push_remote(&self) -> io::Result<String> {
+        let mut command = self.command();
+        command.arg("config").arg("--get").arg("remote.pushDefault");
+        if let Ok(stdout) = Self::command_stdout(command).await {
+            let remote = stdout.trim_end();
+            if !remote.is_empty() {
+                return Ok(remote.to_string());
+            }
+        }
+
+        let mut command = self.command();
+        command.arg("remote");
+        let stdout = Self::command_stdout(command).await?;
+        let remotes: Vec<&str> = stdout.lines().collect();
+        if remotes.contains(&"origin") {
+            Ok("origin".to_string())
+        } else if remotes.len() == 1 {
+            Ok(remotes[0].to_string())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} has no default remote to push to", self.path),
+            ))
+        }
+    }
+
+    /// Run a command that talks to a remote with extra environment variables `envs`, which are
+    /// used to answer credential prompts. Lines of output are passed to `output` while the command
+    /// runs, with `true` for progress lines that are replaced by the next line.
+    async fn remote_command(
+        mut command: Command,
+        envs: &[(&str, OsString)],
+        mut output: impl FnMut(String, bool),
+    ) -> io::Result<()> {
+        log::info!("{:?}", command);
+        command
+            .envs(envs.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn()?;
+
+        // Read stdout at the same time, so the command does not block when the pipe is full
+        let stdout_task = child.stdout.take().map(|mut stdout| {
+            tokio::spawn(async move {
+                let mut data = Vec::new();
+                stdout.read_to_end(&mut data).await.map(|_| data)
+            })
+        });
+
+        // Progress and messages are printed to stderr, with progress updates ending in '\r'
+        if let Some(mut stderr) = child.stderr.take() {
+            let mut data = Vec::new();
+            let mut chunk = [0; 4096];
+            loop {
+                let count = stderr.read(&mut chunk).await?;
+                if count == 0 {
+                    break;
+                }
+                data.extend_from_slice(&chunk[..count]);
+                while let Some(end) = data.iter().position(|b| *b == b'\n' || *b == b'\r') {
+                    let progress = data[end] == b'\r';
+                    let line: Vec<u8> = data.drain(..=end).collect();
+                    if end > 0 {
+                        output(String::from_utf8_lossy(&line[..end]).into_owned(), progress);
+                    }
+                }
+            }
+            if !data.is_empty() {
+                output(String::from_utf8_lossy(&data).into_owned(), false);
+            }
+        }
+
+        if let Some(stdout_task) = stdout_task {
+            let data = stdout_task.await.map_err(io::Error::other)??;
+            for line in String::from_utf8_lossy(&data).lines() {
+                output(line.to_string(), false);
+            }
+        }
+
+        let status = child.wait().await?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("git exited with {}", status)))
+        }
+    }
+
+    /// Fetch all remotes
+    pub async fn fetch(
+        &self,
+        envs: &[(&str, OsString)],
+        output: impl FnMut(String, bool),
+    ) -> io::Result<()> {
+        let mut command = self.command();
+        command.arg("fetch").arg("--progress").arg("--all");
+        Self::remote_command(command, envs, output).await
+    }
+
+    /// Pull the upstream of the current branch, rebasing local commits if `rebase` is set and
+    /// only fast-forwarding otherwise
+    pub async fn pull(
+        &self,
+        rebase: bool,
+        envs: &[(&str, OsString)],
+        output: impl FnMut(String, bool),
+    ) -> io::Result<()> {
+        let mut command = self.command();
+        command
+            .arg("pull")
+            .arg("--progress")
+            .arg(if rebase { "--rebase" } else { "--ff-only" });
+        Self::remote_command(command, envs, output).await
+    }
+
+    /// Push the current branch, to a branch of the same name on the push remote that is set as
+    /// upstream if `set_upstream` is set
+    pub async fn push(
+        &self,
+        set_upstream: bool,
+        envs: &[(&str, OsString)],
+        output: impl FnMut(String, bool),
+    ) -> io::Result<()> {
+        let mut command = self.command();
+        command.arg("push").arg("--progress");
+        if set_upstream {
+            let remote = self.push_remote().await?;
+            command.arg("--set-upstream").arg(remote).arg("HEAD");
+        }
+        Self::remote_command(command, envs, output).await
+    }
+
+    /// Ignored files in the working tree, folders that only contain ignored files are listed
+    /// instead of their contents
+    pub async fn ignored(&self) -> io::Result<Vec<PathBuf>> {
+        let mut command = self.command();
+        command
+            .arg("ls-files")
+            .arg("-z")
+            .arg("--others")
+            .arg("--ignored")
+            .arg("--exclude-standard")
+            .arg("--directory");
+        let stdout = Self::command_stdout(command).await?;
+        Ok(stdout
+            .split('\0')
+            .filter(|relative_path| !relative_path.is_empty())
+            .map(|relative_path| self.path.join(relative_path))
+            .collect())
+    }
+
+    /// Stashes, most recent first
+    pub async fn stashes(&self) -> io::Result<Vec<GitStash>> {
+        let mut command = self.command();
+        command
+            .arg("stash")
+            .arg("list")
+            .arg("--format=%H%x00%gs%x00%cr");
+        let stdout = Self::command_stdout(command).await?;
+
+        let mut stashes = Vec::new();
+        for line in stdout.lines() {
+            let mut parts = line.split('\0');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(hash), Some(message), Some(date)) => {
+                    stashes.push(GitStash {
+                        hash: hash.to_string(),
+                        message: message.to_string(),
+                        date: date.to_string(),
+                    });
+                }
+                _ => {
+                    log::warn!("invalid git stash list line {:?}", line);
+                }
+            }
+        }
+        Ok(stashes)
+    }
+
+    /// Find the current `stash@{n}` name of a stash, which changes when stashes are added or
+    /// removed
+    async fn stash_ref(&self, hash: &str) -> io::Result<String> {
+        let stashes = self.stashes().await?;
+        match stashes.iter().position(|stash| stash.hash == hash) {
+            Some(stash_i) => Ok(format!("stash@{{{}}}", stash_i)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("stash {} no longer exists", hash),
+            )),
+        }
+    }
+
+    /// Stash changes of tracked files, and of untracked files if `include_untracked` is set
+    pub async fn stash_push(&self, message: &str, include_untracked: bool) -> io::Result<()> {
+        let mut command = self.command();
+        command.arg("stash").arg("push");
+        if include_untracked {
+            command.arg("--include-untracked");
+        }
+        if !message.is_empty() {
+            command.arg("--message").arg(message);
+        }
+        Self::command_stdout(command).await?;
+        Ok(())
+    }
+
+    /// Apply the changes of a stash to the working tree, and remove the stash if `pop` is set
+    /// and there were no conflicts
+    pub async fn stash_apply(&self, hash: &str, pop: bool) -> io::Result<()> {
+        let stash_ref = self.stash_ref(hash).await?;
+        let mut command = self.command();
+        command
+            .arg("stash")
+            .arg(if pop { "pop" } else { "apply" })
+            .arg(stash_ref);
+        Self::command_stdout(command).await?;
+        Ok(())
+    }
+
+    pub async fn stash_drop(&self, hash: &str) -> io::Result<()> {
+        let stash_ref = self.stash_ref(hash).await?;
+        let mut command = self.command();
+        command.arg("stash").arg("drop").arg(stash_ref);
+        Self::command_stdout(command).await?;
+        Ok(())
+    }
+
+    /// Diffs of all files in a stash, with the hash of the commit each diff is from. Untracked
+    /// files are stored in a separate commit without parent.
+    pub async fn stash_diffs(
+        &self,
+        hash: &str,
+        context_lines: u32,
+    ) -> io::Result<Vec<(String, GitDiff)>> {
+        let mut commits = vec![(hash.to_string(), format!("{}^", hash))];
+        let mut command = self.command();
+        command
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(format!("{}^3", hash));
+        if let Ok(stdout) = Self::command_stdout(command).await {
+            let untracked = stdout.trim_end().to_string();
+            // Comparing to the empty tree lists all files of the commit
+            let mut command = self.command();
+            command
+                .arg("hash-object")
+                .arg("-t")
+                .arg("tree")
+                .arg("--stdin");
+            let empty_tree = Self::command_stdin_stdout(command, "").await?;
+            commits.push((untracked, empty_tree.trim_end().to_string()));
+        }
+
+        let mut diffs = Vec::new();
+        for (commit, parent) in commits {
+            let mut command = self.command();
+            command
+                .arg("diff")
+                .arg("--name-only")
+                .arg("-z")
+                .arg(&parent)
+                .arg(&commit);
+            let stdout = Self::command_stdout(command).await?;
+            for relative_path in stdout.split('\0').filter(|path| !path.is_empty()) {
+                let diff = self
+                    .commit_diff(&commit, self.path.join(relative_path), context_lines)
+                    .await?;
+                diffs.push((commit.clone(), diff));
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Initialized submodules and untracked repositories in the working tree, found in `status`
+    pub async fn nested(&self, status: &[GitStatus]) -> Vec<(PathBuf, GitProjectKind)> {
+        let mut nested = Vec::new();
+
+        let gitmodules = self.path.join(".gitmodules");
+        if gitmodules.is_file() {
+            let mut command = self.command();
+            command
+                .arg("config")
+                .arg("-z")
+                .arg("--file")
+                .arg(&gitmodules);
+            command.arg("--get-regexp").arg(r"^submodule\..*\.path$");
+            // Exits with an error if no submodule has a path
+            let stdout = Self::command_stdout(command).await.unwrap_or_default();
+            for entry in stdout.split('\0') {
+                // Entries are the key and value separated by a newline
+                let Some((_key, relative_path)) = entry.split_once('\n') else {
+                    continue;
+                };
+                let path = self.path.join(relative_path);
+                // Submodules that are not initialized have no .git file
+                if path.join(".git").exists() {
+                    nested.push((path, GitProjectKind::Submodule));
+                }
+            }
+        }
+
+        for item in status.iter() {
+            // Untracked repositories are shown as folders
+            if item.unstaged == GitStatusKind::Untracked && item.path.join(".git").exists() {
+                // Untracked folders are listed with a trailing slash, which would not match the
+                // path of the repository (status paths are parsed from UTF-8 output already)
+                let path = PathBuf::from(item.path.to_string_lossy().trim_end_matches('/'));
+                nested.push((path, GitProjectKind::Nested));
+            }
+        }
+
+        nested
+    }
+
+    pub async fn status(&self) -> io::Result<Vec<GitStatus>> {
+        self.dispatch(format!("get status of {:?}", self.path), |backend| {
+            backend.status()
+        })
+        .await
+    }
+
+    /// Restore the working tree version of a file from the index
+    pub async fn discard<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
         let path = path.as_ref();
         let mut command = self.command();
         command.arg("restore");
-        command.arg("--staged");
+        command.arg("--worktree");
         command.arg("--").arg(path);
         Self::command_stdout(command).await?;
         Ok(())
     }
+
+    pub async fn stage<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        self.dispatch(format!("stage {:?}", path), |backend| {
+            backend.stage(path.to_path_buf())
+        })
+        .await
+    }
+
+    pub async fn unstage<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        self.dispatch(format!("unstage {:?}", path), |backend| {
+            backend.unstage(path.to_path_buf())
+        })
+        .await
+    }
+}
+
+/// Future of a [`GitBackendOps`] operation
+pub type GitFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Operations of a [`GitRepository`] that have both a git CLI and a libgit2 implementation. The
+/// libgit2 one fails with [`io::ErrorKind::Unsupported`] for what only the CLI can do, like
+/// running clean and smudge filters.
+pub trait GitBackendOps: Sync {
+    fn status(&self) -> GitFuture<'_, Vec<GitStatus>>;
+
+    /// Unified diff of `path`
+    fn diff(&self, path: PathBuf, staged: bool, context_lines: u32) -> GitFuture<'_, String>;
+
+    /// Indexed version of `path`, converted like a checkout would
+    fn index_text(&self, path: PathBuf) -> GitFuture<'_, String>;
+
+    fn stage(&self, path: PathBuf) -> GitFuture<'_, ()>;
+
+    fn unstage(&self, path: PathBuf) -> GitFuture<'_, ()>;
+}
+
+/// Implementation of [`GitBackendOps`] spawning the git CLI
+struct GitCli<'a>(&'a GitRepository);
+
+impl GitBackendOps for GitCli<'_> {
+    fn status(&self) -> GitFuture<'_, Vec<GitStatus>> {
+        let repo = self.0;
+        Box::pin(async move {
+            let mut command = repo.command();
+            command.arg("status").arg("-z");
+            let stdout = GitRepository::command_stdout(command).await?;
+
+            let mut status = Vec::new();
+            let mut lines = stdout.split('\0');
+            while let Some(line) = lines.next() {
+                macro_rules! invalid_line {
+                    () => {{
+                        log::warn!("invalid git status line {:?}", line);
+                        continue;
+                    }};
+                }
+
+                if line.is_empty() {
+                    // Ignore empty lines
+                    continue;
+                }
+
+                let mut chars = line.chars();
+
+                // Get staged status
+                let staged = match chars.next() {
+                    Some(some) => match GitStatusKind::try_from(some) {
+                        Ok(ok) => ok,
+                        Err(_) => invalid_line!(),
+                    },
+                    None => invalid_line!(),
+                };
+
+                // Get unstaged status
+                let unstaged = match chars.next() {
+                    Some(some) => match GitStatusKind::try_from(some) {
+                        Ok(ok) => ok,
+                        Err(_) => invalid_line!(),
+                    },
+                    None => invalid_line!(),
+                };
+
+                // Skip space
+                match chars.next() {
+                    Some(' ') => {}
+                    _ => invalid_line!(),
+                }
+
+                // The rest of the chars are in the path
+                let relative_path: String = chars.collect();
+
+                // Renamed and copied paths are followed by the path they came from
+                let old_path = if [staged, unstaged]
+                    .iter()
+                    .any(|kind| matches!(kind, GitStatusKind::Renamed | GitStatusKind::Copied))
+                {
+                    match lines.next() {
+                        Some(old_relative_path) => Some(repo.path.join(old_relative_path)),
+                        None => invalid_line!(),
+                    }
+                } else {
+                    None
+                };
+
+                status.push(GitStatus {
+                    path: repo.path.join(relative_path),
+                    old_path,
+                    staged,
+                    unstaged,
+                })
+            }
+
+            Ok(status)
+        })
+    }
+
+    fn diff(&self, path: PathBuf, staged: bool, context_lines: u32) -> GitFuture<'_, String> {
+        let repo = self.0;
+        Box::pin(async move {
+            let mut command = repo.command();
+            command
+                .arg("diff")
+                .arg(format!("--unified={}", context_lines));
+            if staged {
+                command.arg("--staged");
+            }
+            command.arg("--").arg(path);
+            GitRepository::command_stdout(command).await
+        })
+    }
+
+    fn index_text(&self, path: PathBuf) -> GitFuture<'_, String> {
+        let repo = self.0;
+        Box::pin(async move {
+            let relative_path = repo.relative_path(&path)?;
+            let mut command = repo.command();
+            command
+                .arg("cat-file")
+                .arg("--filters")
+                .arg(format!(":{}", relative_path.display()));
+            GitRepository::command_stdout(command).await
+        })
+    }
+
+    fn stage(&self, path: PathBuf) -> GitFuture<'_, ()> {
+        let repo = self.0;
+        Box::pin(async move {
+            let mut command = repo.command();
+            command.arg("stage");
+            command.arg("--").arg(path);
+            GitRepository::command_stdout(command).await?;
+            Ok(())
+        })
+    }
+
+    fn unstage(&self, path: PathBuf) -> GitFuture<'_, ()> {
+        let repo = self.0;
+        Box::pin(async move {
+            let mut command = repo.command();
+            command.arg("restore");
+            command.arg("--staged");
+            command.arg("--").arg(path);
+            GitRepository::command_stdout(command).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{GitFixture, block_on};
+    use std::process;
+
+    /// Apply `patch` to a file containing `target` with git apply and return the result
+    fn apply(target: &str, patch: Option<String>, reverse: bool, unidiff_zero: bool) -> String {
+        let Some(patch) = patch else {
+            return target.to_string();
+        };
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file"), target).unwrap();
+        let mut command = process::Command::new("git");
+        command.arg("-C").arg(dir.path()).arg("apply");
+        if reverse {
+            command.arg("--reverse");
+        }
+        if unidiff_zero {
+            command.arg("--unidiff-zero");
+        }
+        let mut child = command
+            .arg("-")
+            .stdin(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        {
+            use std::io::Write;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(patch.as_bytes())
+                .unwrap();
+        }
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}\n{}",
+            patch,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        fs::read_to_string(dir.path().join("file")).unwrap()
+    }
+
+    /// Apply `lines_opt` of the only hunk of `old` to `new` to the old and new side
+    fn apply_text(old: &str, new: &str, lines_opt: Option<Range<usize>>) -> (String, String) {
+        let diff = GitDiff::from_text("file", old, new);
+        assert_eq!(diff.hunks.len(), 1);
+        let hunk = &diff.hunks[0];
+        let path = Path::new("file");
+        (
+            apply(old, hunk.patch(path, lines_opt.clone(), false), false, true),
+            apply(new, hunk.patch(path, lines_opt, true), true, true),
+        )
+    }
+
+    #[test]
+    fn parse_lines() {
+        let diff = "diff --git a/file b/file\n\
+            index 0000000..1111111 100644\n\
+            --- a/file\n\
+            +++ b/file\n\
+            @@ -1,3 +1,3 @@ fn main() {\n \
+            a\r\n\
+            -b\r\n\
+            +c\r\n\
+            \n";
+        let diff = GitRepository::parse_diff(Path::new("file"), false, diff).unwrap();
+        assert_eq!(diff.hunks.len(), 1);
+        let hunk = &diff.hunks[0];
+        assert_eq!(hunk.old_range, patch::Range { start: 1, count: 3 });
+        assert_eq!(
+            hunk.lines,
+            vec![
+                GitDiffLine::Context {
+                    old_line: 1,
+                    new_line: 1,
+                    text: "a\r\n".to_string(),
+                },
+                GitDiffLine::Deleted {
+                    old_line: 2,
+                    text: "b\r\n".to_string(),
+                },
+                GitDiffLine::Added {
+                    new_line: 2,
+                    text: "c\r\n".to_string(),
+                },
+                // Empty context lines may have lost their space
+                GitDiffLine::Context {
+                    old_line: 3,
+                    new_line: 3,
+                    text: "\n".to_string(),
+                },
+            ]
+        );
+        assert_eq!(hunk.lines[1].text(), "b");
+        assert_eq!(hunk.old_text().collect::<String>(), "a\r\nb\r\n\n");
+    }
+
+    #[test]
+    fn parse_no_newline() {
+        let diff = "@@ -1,2 +1,2 @@\n \
+            a\n\
+            -b\n\
+            \\ No newline at end of file\n\
+            +b\n\
+            @@ -10 +10,0 @@\n\
+            -c\n";
+        let diff = GitRepository::parse_diff(Path::new("file"), false, diff).unwrap();
+        assert_eq!(diff.hunks.len(), 2);
+        assert_eq!(diff.hunks[0].old_text().collect::<String>(), "a\nb");
+        assert_eq!(
+            diff.hunks[0].lines[2],
+            GitDiffLine::Added {
+                new_line: 2,
+                text: "b\n".to_string(),
+            }
+        );
+        assert_eq!(
+            diff.hunks[1].old_range,
+            patch::Range {
+                start: 10,
+                count: 1
+            }
+        );
+        assert_eq!(
+            diff.hunks[1].new_range,
+            patch::Range {
+                start: 10,
+                count: 0
+            }
+        );
+        assert_eq!(diff.hunks[1].change(), GitLineChange::Deleted);
+    }
+
+    #[test]
+    fn compare_like_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_path = dir.path().join("old");
+        let new_path = dir.path().join("new");
+        let lines = |count: usize| {
+            (1..=count)
+                .map(|i| format!("line {}\n", i))
+                .collect::<String>()
+        };
+        let long = lines(20);
+        for (old, new) in [
+            (long.clone(), long.replace("line 2\n", "two\n")),
+            (
+                long.clone(),
+                long.replace("line 3\n", "").replace("line 17\n", ""),
+            ),
+            (long.clone(), long.replace("line 8\n", "line 8\nnew\n")),
+            (long.clone(), long.replace("line 10\n", "ten\n") + "end\n"),
+            (String::new(), "a\n".to_string()),
+            ("a\r\nb".to_string(), "a\r\nc".to_string()),
+        ] {
+            fs::write(&old_path, &old).unwrap();
+            fs::write(&new_path, &new).unwrap();
+            let output = process::Command::new("git")
+                .arg("diff")
+                .arg("--no-index")
+                .arg("--no-color")
+                .arg("--unified=3")
+                .arg(&old_path)
+                .arg(&new_path)
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let expected = GitRepository::parse_diff(Path::new("file"), false, &stdout).unwrap();
+            assert_eq!(
+                GitDiff::compare("file", &old, &new, 3),
+                expected,
+                "{:?} {:?}",
+                old,
+                new
+            );
+        }
+        assert!(GitDiff::compare("file", &long, &long, 3).hunks.is_empty());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for diff in [
+            "@@ -1,2 +1,2 @@\n a\n",
+            "@@ -1 +1 @@\n+a\n+b\n",
+            "@@ -a +1 @@\n a\n",
+            "@@ -1 +1 @@\n!a\n",
+        ] {
+            assert!(
+                GitRepository::parse_diff(Path::new("file"), false, diff).is_err(),
+                "{:?}",
+                diff
+            );
+        }
+    }
+
+    #[test]
+    fn patch_whole_hunk() {
+        for (old, new) in [
+            ("a\nb\nc\n", "a\nB\nc\n"),
+            ("a\nb\nc\n", "a\nc\n"),
+            ("a\nc\n", "a\nb\nc\n"),
+            ("b\n", "a\nb\n"),
+            ("a\n", ""),
+            ("", "a\n"),
+            ("a\r\nb\r\n", "a\r\nc\r\n"),
+            // Lines without a newline at the end of the file
+            ("a", "b"),
+            ("a", "a\nb"),
+            ("a\n", "a\nb"),
+            ("a\nb", "a\n"),
+        ] {
+            assert_eq!(
+                apply_text(old, new, None),
+                (new.to_string(), old.to_string()),
+                "{:?} {:?}",
+                old,
+                new
+            );
+        }
+    }
+
+    #[test]
+    fn patch_lines() {
+        // The hunk is -b, +c, +d
+        let (old, new) = ("a\nb\n", "a\nc\nd\n");
+        assert_eq!(
+            apply_text(old, new, Some(1..2)),
+            ("a\nb\nc\n".to_string(), "a\nd\n".to_string())
+        );
+        assert_eq!(
+            apply_text(old, new, Some(0..1)),
+            ("a\n".to_string(), "a\nb\nc\nd\n".to_string())
+        );
+
+        // The hunk is -a, +a, +b where the old a and b have no newline
+        let (old, new) = ("a", "a\nb");
+        assert_eq!(
+            apply_text(old, new, Some(2..3)),
+            ("a\nb".to_string(), "a\n".to_string())
+        );
+        assert_eq!(
+            apply_text(old, new, Some(0..2)),
+            ("a\n".to_string(), "a\nb".to_string())
+        );
+        // Discarding the added newline has to keep it before b
+        let diff = GitDiff::from_text("file", old, new);
+        assert_eq!(
+            diff.hunks[0].patch(Path::new("file"), Some(0..2), true),
+            None
+        );
+    }
+
+    #[test]
+    fn patch_context() {
+        let old = "1\n2\n3\n4\n5";
+        let new = "1\n2\nthree\n4\n5\nsix";
+        let diff = "@@ -2,4 +2,5 @@\n \
+            2\n\
+            -3\n\
+            +three\n \
+            4\n\
+            -5\n\
+            \\ No newline at end of file\n\
+            +5\n\
+            +six\n\
+            \\ No newline at end of file\n";
+        let diff = GitRepository::parse_diff(Path::new("file"), false, diff).unwrap();
+        let hunk = &diff.hunks[0];
+        assert!(hunk.has_context());
+        let path = Path::new("file");
+        assert_eq!(apply(old, hunk.patch(path, None, false), false, false), new);
+        assert_eq!(apply(new, hunk.patch(path, None, true), true, false), old);
+        // Only the first change
+        assert_eq!(
+            apply(old, hunk.patch(path, Some(1..3), false), false, false),
+            "1\n2\nthree\n4\n5"
+        );
+        assert_eq!(
+            apply(new, hunk.patch(path, Some(1..3), true), true, false),
+            "1\n2\n3\n4\n5\nsix"
+        );
+    }
+
+    #[test]
+    fn conflict_merge_style() {
+        let text = "a\n<<<<<<< HEAD\nours\n=======\ntheirs 1\ntheirs 2\n>>>>>>> branch\nb\n";
+        let found = conflicts(text.lines());
+        assert_eq!(
+            found,
+            vec![GitConflict {
+                start: 1,
+                ours: 2..3,
+                base_opt: None,
+                theirs: 4..6,
+                end: 6,
+            }]
+        );
+        assert_eq!(found[0].lines(), 1..7);
+        assert_eq!(found[0].resolution(GitConflictChoice::Ours), vec![2..3]);
+        assert_eq!(found[0].resolution(GitConflictChoice::Theirs), vec![4..6]);
+        assert_eq!(
+            found[0].resolution(GitConflictChoice::Both),
+            vec![2..3, 4..6]
+        );
+    }
+
+    #[test]
+    fn conflict_diff3_style() {
+        let text = "<<<<<<< ours\n=======\n\
+            >>>>>>> theirs\n\
+            <<<<<<<\none\n||||||| base\nbase\n=======\ntwo\n>>>>>>>";
+        assert_eq!(
+            conflicts(text.lines()),
+            vec![
+                GitConflict {
+                    start: 0,
+                    ours: 1..1,
+                    base_opt: None,
+                    theirs: 2..2,
+                    end: 2,
+                },
+                GitConflict {
+                    start: 3,
+                    ours: 4..5,
+                    base_opt: Some(6..7),
+                    theirs: 8..9,
+                    end: 9,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn conflict_invalid() {
+        // Unterminated conflicts are dropped when another one starts
+        let text = "<<<<<<<\nlost\n<<<<<<<\nours\n=======\ntheirs\n>>>>>>>\n";
+        assert_eq!(conflicts(text.lines()).len(), 1);
+        assert_eq!(conflicts(text.lines())[0].start, 2);
+        // Markers need exactly seven characters followed by whitespace or nothing
+        let text = "<<<<<<<<\nours\n=======\ntheirs\n>>>>>>>\n";
+        assert!(conflicts(text.lines()).is_empty());
+        let text = "<<<<<<<x\nours\n=======\ntheirs\n>>>>>>>\n";
+        assert!(conflicts(text.lines()).is_empty());
+        // A conflict without separator is ignored
+        assert!(conflicts("<<<<<<<\nours\n>>>>>>>\n".lines()).is_empty());
+        // Separators outside of conflicts are text
+        assert!(conflicts("=======\n>>>>>>>\n".lines()).is_empty());
+        // Unterminated conflicts at the end are ignored
+        assert!(conflicts("<<<<<<<\nours\n=======\ntheirs\n".lines()).is_empty());
+    }
+
+    #[test]
+    fn blame_porcelain() {
+        let hash_a = "a".repeat(40);
+        let hash_0 = "0".repeat(40);
+        let porcelain = format!(
+            "{hash_a} 1 1 2\n\
+            author Someone\n\
+            author-time 1700000000\n\
+            summary First line\n\
+            filename old.txt\n\
+            \tone\n\
+            {hash_a} 2 2\n\
+            \ttwo\n\
+            {hash_0} 3 3 1\n\
+            author Not Committed Yet\n\
+            summary Version of new.txt from new.txt\n\
+            filename new.txt\n\
+            \tthree\n"
+        );
+        let blame = GitRepository::parse_blame(Path::new("/repo"), &porcelain);
+        assert_eq!(blame.lines, vec![0, 0, 1]);
+        assert_eq!(blame.contents, vec!["one", "two", "three"]);
+        assert_eq!(blame.commits.len(), 2);
+        assert_eq!(blame.commits[0].short_hash, "aaaaaaaa");
+        assert_eq!(blame.commits[0].author, "Someone");
+        assert_eq!(blame.commits[0].time, 1700000000);
+        assert_eq!(blame.commits[0].summary, "First line");
+        assert_eq!(blame.commits[0].path, Path::new("/repo/old.txt"));
+        assert!(blame.commits[0].is_committed());
+        assert!(!blame.commits[1].is_committed());
+    }
+
+    #[test]
+    fn blame_with_text() {
+        let commit = |hash: &str| GitBlameCommit {
+            hash: hash.repeat(40),
+            ..Default::default()
+        };
+        let blame = GitBlame {
+            commits: vec![commit("a"), commit("b")],
+            lines: vec![0, 1, 0],
+            contents: vec!["one".to_string(), "two".to_string(), "three".to_string()],
+        };
+
+        // Line endings do not matter
+        assert_eq!(blame.with_text("one\r\ntwo\r\nthree").lines, vec![0, 1, 0]);
+
+        let changed = blame.with_text("zero\none\nthree\nfour\n");
+        assert_eq!(changed.commits.len(), 3);
+        assert!(!changed.commits[2].is_committed());
+        assert_eq!(changed.lines, vec![2, 0, 0, 2]);
+        // Uncommitted changes are blamed on the same commit
+        assert_eq!(changed.with_text("zero\n\none\n").lines, vec![2, 2, 0]);
+    }
+
+    #[test]
+    fn large_stdin() {
+        // More output than fits in a pipe is read while stdin is written
+        let text = "line\n".repeat(1024 * 1024);
+        let output = block_on(GitRepository::command_stdin_stdout(
+            Command::new("cat"),
+            &text,
+        ))
+        .unwrap();
+        assert_eq!(output, text);
+    }
+
+    #[test]
+    fn blame_and_commit_diff() {
+        let fixture = GitFixture::new();
+        let git = |args: &[&str]| fixture.git(args);
+        fixture.init(".");
+        fixture.write("old.txt", "one\ntwo\n");
+        git(&["add", "old.txt"]);
+        git(&["commit", "--quiet", "--message=Add"]);
+        let root = git(&["rev-parse", "HEAD"]).trim().to_string();
+        git(&["mv", "old.txt", "new.txt"]);
+        git(&["commit", "--quiet", "--message=Rename"]);
+        git(&["switch", "--quiet", "--create", "branch"]);
+        fixture.write("new.txt", "one\nthree\n");
+        git(&["commit", "--quiet", "--all", "--message=Change"]);
+        git(&["switch", "--quiet", "main"]);
+        fixture.write("other.txt", "other\n");
+        git(&["add", "other.txt"]);
+        git(&["commit", "--quiet", "--message=Other"]);
+        git(&["merge", "--quiet", "--no-edit", "branch"]);
+        let merge = git(&["rev-parse", "HEAD"]).trim().to_string();
+
+        block_on(async {
+            let repo = GitRepository::new(fixture.path()).await.unwrap();
+            let path = repo.path().join("new.txt");
+
+            // Renamed lines are blamed on the commit and path that added them
+            let head = repo.head_hash().await.unwrap();
+            assert_eq!(head, merge);
+            let blame = repo.blame(&head, &path).await.unwrap();
+            let first = &blame.commits[blame.lines[0]];
+            assert_eq!(first.hash, root);
+            assert_eq!(first.message, "Add");
+            assert_eq!(first.path, repo.path().join("old.txt"));
+
+            // Root commits are compared to an empty tree
+            let diff = repo.commit_diff(&root, &first.path, 3).await.unwrap();
+            assert_eq!(diff.hunks.len(), 1);
+            assert_eq!(diff.hunks[0].change(), GitLineChange::Added);
+
+            // Merges are compared to their first parent, not shown as combined diffs
+            let diff = repo.commit_diff(&merge, &path, 3).await.unwrap();
+            assert_eq!(diff.hunks.len(), 1);
+            assert_eq!(diff.hunks[0].old_text().collect::<String>(), "one\ntwo\n");
+        });
+    }
+
+    #[test]
+    fn diff_sides() {
+        let fixture = GitFixture::new();
+        fixture.init(".");
+        fixture.write("file.txt", "one\n");
+        fixture.git(&["add", "file.txt"]);
+        fixture.git(&["commit", "--quiet", "--message=Add"]);
+        let root = fixture.git(&["rev-parse", "HEAD"]).trim().to_string();
+        fixture.write("file.txt", "two\n");
+        fixture.write("added.txt", "added\n");
+        fixture.git(&["add", "added.txt"]);
+
+        block_on(async {
+            let repo = GitRepository::new(fixture.path()).await.unwrap();
+            let file = repo.path().join("file.txt");
+            let added = repo.path().join("added.txt");
+
+            // Sides that do not exist are empty
+            assert_eq!(
+                repo.diff_sides(&file, false, Some(&root)).await.unwrap(),
+                (String::new(), "one\n".to_string())
+            );
+            assert_eq!(
+                repo.diff_sides(&added, true, None).await.unwrap(),
+                (String::new(), "added\n".to_string())
+            );
+            fs::remove_file(&added).unwrap();
+            assert_eq!(
+                repo.diff_sides(&added, false, None).await.unwrap(),
+                ("added\n".to_string(), String::new())
+            );
+            assert_eq!(
+                repo.diff_sides(&file, false, None).await.unwrap(),
+                ("one\n".to_string(), "two\n".to_string())
+            );
+
+            // Other errors are not hidden as empty sides
+            fs::write(&file, [0xff, 0xfe]).unwrap();
+            assert!(repo.diff_sides(&file, false, None).await.is_err());
+            assert!(
+                repo.diff_sides(&file, false, Some("invalid"))
+                    .await
+                    .is_err()
+            );
+        });
+    }
+
+    #[test]
+    fn stash() {
+        let fixture = GitFixture::new();
+        fixture.init(".");
+        fixture.write("file.txt", "one\n");
+        fixture.git(&["add", "file.txt"]);
+        fixture.git(&["commit", "--quiet", "--message=Add"]);
+
+        block_on(async {
+            let repo = GitRepository::new(fixture.path()).await.unwrap();
+            let file = repo.path().join("file.txt");
+            let untracked = repo.path().join("untracked.txt");
+
+            fs::write(&file, "two\n").unwrap();
+            repo.stash_push("first", false).await.unwrap();
+            assert_eq!(fs::read_to_string(&file).unwrap(), "one\n");
+
+            fs::write(&file, "three\n").unwrap();
+            fs::write(&untracked, "untracked\n").unwrap();
+            repo.stash_push("", true).await.unwrap();
+            assert!(!untracked.exists());
+
+            let stashes = repo.stashes().await.unwrap();
+            assert_eq!(stashes.len(), 2);
+            assert_eq!(stashes[1].message, "On main: first");
+            assert!(stashes[0].message.starts_with("WIP on main"));
+
+            // Untracked files are diffed against nothing
+            let diffs = repo.stash_diffs(&stashes[0].hash, 3).await.unwrap();
+            assert_eq!(diffs.len(), 2);
+            assert_eq!(diffs[0].0, stashes[0].hash);
+            assert_eq!(diffs[0].1.path, file);
+            assert_eq!(diffs[1].1.path, untracked);
+            assert_eq!(diffs[1].1.hunks[0].change(), GitLineChange::Added);
+
+            // Stashes are found by hash after others are removed
+            repo.stash_apply(&stashes[0].hash, true).await.unwrap();
+            assert_eq!(fs::read_to_string(&file).unwrap(), "three\n");
+            assert!(untracked.exists());
+            repo.stash_drop(&stashes[1].hash).await.unwrap();
+            assert!(repo.stashes().await.unwrap().is_empty());
+            assert!(repo.stash_apply(&stashes[1].hash, false).await.is_err());
+        });
+    }
+
+    #[test]
+    fn branch_names() {
+        let fixture = GitFixture::new();
+        fixture.init(".");
+        fixture.git(&["commit", "--quiet", "--allow-empty", "--message=Empty"]);
+
+        block_on(async {
+            let repo = GitRepository::new(fixture.path()).await.unwrap();
+            // Names are not taken as options
+            assert!(repo.create_branch("--orphan=other").await.is_err());
+            assert!(repo.create_branch("-b").await.is_err());
+            assert!(repo.create_branch("@{-1}").await.is_err());
+            repo.create_branch("feature").await.unwrap();
+            assert!(repo.checkout_branch("--detach").await.is_err());
+            repo.checkout_branch("main").await.unwrap();
+            let branches = repo.branches().await.unwrap();
+            let names: Vec<_> = branches.iter().map(|branch| branch.name.as_str()).collect();
+            assert_eq!(names, ["feature", "main"]);
+        });
+    }
+
+    #[test]
+    fn remote() {
+        let fixture = GitFixture::new();
+        let git = |args: &[&str]| fixture.git(args);
+        git(&[
+            "init",
+            "--quiet",
+            "--bare",
+            "--initial-branch=main",
+            "remote.git",
+        ]);
+        for name in ["first", "second"] {
+            fixture.init(name);
+            git(&["-C", name, "remote", "add", "origin", "../remote.git"]);
+        }
+        fixture.write("first/file.txt", "one\n");
+        git(&["-C", "first", "add", "file.txt"]);
+        git(&["-C", "first", "commit", "--quiet", "--message=One"]);
+
+        block_on(async {
+            let first = GitRepository::new(fixture.join("first")).await.unwrap();
+            let second = GitRepository::new(fixture.join("second")).await.unwrap();
+            assert_eq!(first.ahead_behind().await.unwrap(), None);
+            assert_eq!(first.push_remote().await.unwrap(), "origin");
+
+            let mut lines = Vec::new();
+            first
+                .push(true, &[], |line, _progress| lines.push(line))
+                .await
+                .unwrap();
+            assert!(lines.iter().any(|line| line.contains("set up to track")));
+            assert_eq!(first.ahead_behind().await.unwrap(), Some((0, 0)));
+
+            // Pulling without upstream fails, fetching does not need one
+            assert!(second.pull(false, &[], |_, _| {}).await.is_err());
+            second.fetch(&[], |_, _| {}).await.unwrap();
+            git(&["-C", "second", "switch", "--quiet", "main"]);
+            assert_eq!(second.ahead_behind().await.unwrap(), Some((0, 0)));
+
+            fixture.write("first/file.txt", "two\n");
+            git(&["-C", "first", "commit", "--quiet", "--all", "--message=Two"]);
+            assert_eq!(first.ahead_behind().await.unwrap(), Some((1, 0)));
+            first.push(false, &[], |_, _| {}).await.unwrap();
+
+            second.fetch(&[], |_, _| {}).await.unwrap();
+            assert_eq!(second.ahead_behind().await.unwrap(), Some((0, 1)));
+            second.pull(false, &[], |_, _| {}).await.unwrap();
+            assert_eq!(
+                fs::read_to_string(fixture.join("second/file.txt")).unwrap(),
+                "two\n"
+            );
+
+            // Diverged branches are rebased, or refused when only fast-forwarding
+            fixture.write("first/other.txt", "first\n");
+            git(&["-C", "first", "add", "other.txt"]);
+            git(&["-C", "first", "commit", "--quiet", "--message=First"]);
+            first.push(false, &[], |_, _| {}).await.unwrap();
+            fixture.write("second/file.txt", "three\n");
+            git(&[
+                "-C",
+                "second",
+                "commit",
+                "--quiet",
+                "--all",
+                "--message=Three",
+            ]);
+            assert!(second.pull(false, &[], |_, _| {}).await.is_err());
+            assert_eq!(second.ahead_behind().await.unwrap(), Some((1, 1)));
+            second.pull(true, &[], |_, _| {}).await.unwrap();
+            assert_eq!(second.ahead_behind().await.unwrap(), Some((1, 0)));
+            assert!(fixture.join("second/other.txt").exists());
+        });
+    }
+
+    #[cfg(feature = "git2")]
+    #[test]
+    fn native_parity() {
+        let fixture = GitFixture::new();
+        let git = |args: &[&str]| fixture.git(args);
+        let write = |name: &str, text: &str| fixture.write(name, text);
+        fixture.init(".");
+        write("both.txt", "one\ntwo\n");
+        write("crlf.txt", "one\r\ntwo\r\n");
+        write("eol.txt", "one\ntwo");
+        write("old.txt", "moved\n");
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "--message=Add"]);
+
+        write("both.txt", "one\nthree\n");
+        git(&["add", "both.txt"]);
+        write("both.txt", "zero\none\nthree\n");
+        write("crlf.txt", "one\r\nthree\r\n");
+        write("eol.txt", "one\ntwo\nthree");
+        write("added.txt", "added");
+        git(&["add", "added.txt"]);
+        git(&["mv", "old.txt", "renamed.txt"]);
+        write("untracked.txt", "untracked\n");
+        write("folder/untracked.txt", "untracked\n");
+
+        block_on(async {
+            let native = GitNative::discover(fixture.path().to_path_buf())
+                .await
+                .unwrap();
+            let repo = GitRepository {
+                path: native.path().to_path_buf(),
+                native_opt: None,
+            };
+            let cli = GitCli(&repo);
+
+            let mut status = cli.status().await.unwrap();
+            let mut native_status = native.status().await.unwrap();
+            status.sort_by(|a, b| a.path.cmp(&b.path));
+            native_status.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(status, native_status);
+            assert_eq!(status.len(), 7);
+
+            for item in status.iter() {
+                for staged in [false, true] {
+                    let diff = cli.diff(item.path.clone(), staged, 3).await.unwrap();
+                    let native_diff = native.diff(item.path.clone(), staged, 3).await.unwrap();
+                    assert_eq!(
+                        GitRepository::parse_diff(&item.path, staged, &diff).unwrap(),
+                        GitRepository::parse_diff(&item.path, staged, &native_diff).unwrap(),
+                        "diff of {:?}, staged {}",
+                        item.path,
+                        staged
+                    );
+                }
+                if item.unstaged != GitStatusKind::Untracked {
+                    assert_eq!(
+                        cli.index_text(item.path.clone()).await.unwrap(),
+                        native.index_text(item.path.clone()).await.unwrap(),
+                        "index of {:?}",
+                        item.path
+                    );
+                }
+            }
+
+            native.stage(repo.path.join("eol.txt")).await.unwrap();
+            native.unstage(repo.path.join("both.txt")).await.unwrap();
+            let mut native_status = native.status().await.unwrap();
+            native_status.sort_by(|a, b| a.path.cmp(&b.path));
+            let mut status = cli.status().await.unwrap();
+            status.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(status, native_status);
+            let eol = status.iter().find(|item| item.path.ends_with("eol.txt"));
+            assert_eq!(eol.unwrap().unstaged, GitStatusKind::Unmodified);
+            let both = status.iter().find(|item| item.path.ends_with("both.txt"));
+            assert_eq!(both.unwrap().staged, GitStatusKind::Unmodified);
+
+            // Line ending conversions and filters are left to the CLI
+            git(&["config", "core.autocrlf", "true"]);
+            let path = repo.path.join("crlf.txt");
+            let unsupported = |err: io::Error| err.kind() == io::ErrorKind::Unsupported;
+            assert!(unsupported(
+                native.index_text(path.clone()).await.unwrap_err()
+            ));
+            assert!(unsupported(
+                native.diff(path.clone(), false, 3).await.unwrap_err()
+            ));
+            assert!(unsupported(native.stage(path.clone()).await.unwrap_err()));
+            git(&["config", "filter.lfs.clean", "git-lfs clean -- %f"]);
+            assert!(unsupported(native.status().await.unwrap_err()));
+        });
+    }
+
+    /// Compare the git CLI and libgit2 on a large repository, run with
+    /// `cargo test --release git::tests::native_timing -- --ignored --nocapture`
+    #[cfg(feature = "git2")]
+    #[ignore]
+    #[test]
+    fn native_timing() {
+        use std::time::Instant;
+
+        const FILES: usize = 20_000;
+        const LINES: usize = 100;
+        const CHANGED: usize = 100;
+        const RUNS: u32 = 5;
+
+        let fixture = GitFixture::new();
+        fixture.init(".");
+        let name = |i: usize| format!("{}/{}.txt", i % 100, i);
+        let text = |i: usize, changed: bool| {
+            (0..LINES)
+                .map(|line| {
+                    if changed && line == LINES / 2 {
+                        format!("changed line {} of file {}\n", line, i)
+                    } else {
+                        format!("line {} of file {}\n", line, i)
+                    }
+                })
+                .collect::<String>()
+        };
+        for i in 0..FILES {
+            fixture.write(&name(i), &text(i, false));
+        }
+        fixture.git(&["add", "."]);
+        fixture.git(&["commit", "--quiet", "--message=Add"]);
+        for i in (0..FILES).step_by(FILES / CHANGED) {
+            fixture.write(&name(i), &text(i, true));
+            fixture.write(&format!("untracked/{}.txt", i), "untracked\n");
+        }
+
+        block_on(async {
+            let native = GitNative::discover(fixture.path().to_path_buf())
+                .await
+                .unwrap();
+            let repo = GitRepository {
+                path: native.path().to_path_buf(),
+                native_opt: None,
+            };
+            let cli = GitCli(&repo);
+            let backends: [(&str, &dyn GitBackendOps); 2] = [("cli", &cli), ("native", &native)];
+
+            let status = cli.status().await.unwrap();
+            let modified: Vec<_> = status
+                .iter()
+                .filter(|item| item.unstaged == GitStatusKind::Modified)
+                .map(|item| item.path.clone())
+                .collect();
+            assert_eq!(modified.len(), CHANGED);
+
+            for (name, backend) in backends {
+                let start = Instant::now();
+                for _ in 0..RUNS {
+                    backend.status().await.unwrap();
+                }
+                let status_time = start.elapsed() / RUNS;
+
+                let start = Instant::now();
+                for path in modified.iter() {
+                    backend.diff(path.clone(), false, 3).await.unwrap();
+                }
+                let diff_time = start.elapsed() / CHANGED as u32;
+
+                let start = Instant::now();
+                for path in modified.iter() {
+                    backend.index_text(path.clone()).await.unwrap();
+                }
+                let index_text_time = start.elapsed() / CHANGED as u32;
+
+                println!(
+                    "{}: status {:?}, diff {:?} and index text {:?} per file",
+                    name, status_time, diff_time, index_text_time
+                );
+            }
+        });
+    }
 }
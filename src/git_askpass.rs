@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Credential prompts of git commands are answered in the UI. Git runs cosmic-edit again as its
+// askpass program, which passes the prompt to the running application over a Unix socket and
+// prints the answer it gets back.
+
+use std::{
+    env,
+    ffi::OsString,
+    fs::Permissions,
+    io::{self, Read, Write},
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    path::{Path, PathBuf},
+    process,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixListener,
+};
+
+/// Set to the socket path when cosmic-edit is run as askpass program
+const SOCKET_ENV: &str = "COSMIC_EDIT_ASKPASS";
+
+/// Answer a prompt and exit if cosmic-edit was run as askpass program
+pub fn helper() {
+    let Some(socket) = env::var_os(SOCKET_ENV) else {
+        return;
+    };
+    let prompt = env::args().nth(1).unwrap_or_default();
+    match ask(Path::new(&socket), &prompt) {
+        Ok(answer) => {
+            println!("{}", answer);
+            process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("failed to answer {:?}: {}", prompt, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn ask(socket: &Path, prompt: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(prompt.as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    // Nothing is sent back if the prompt was cancelled
+    if answer.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+    }
+    Ok(answer)
+}
+
+/// Socket that answers the credential prompts of one git command, removed when dropped
+pub struct GitAskpass {
+    path: PathBuf,
+    listener: UnixListener,
+    // Dropped last so the socket is removed with it
+    _dir: TempDir,
+}
+
+impl GitAskpass {
+    pub fn new() -> io::Result<Self> {
+        // The socket is put in a new folder that only the user can access, as the temporary
+        // folder is shared when there is no runtime folder
+        let dir = tempfile::Builder::new()
+            .prefix("cosmic-edit-askpass-")
+            .permissions(Permissions::from_mode(0o700))
+            .tempdir_in(dirs::runtime_dir().unwrap_or_else(env::temp_dir))?;
+        let path = dir.path().join("socket");
+        let listener = UnixListener::bind(&path)?;
+        Ok(Self {
+            path,
+            listener,
+            _dir: dir,
+        })
+    }
+
+    /// Environment variables that make git and ssh ask for credentials through this socket
+    /// instead of the terminal
+    pub fn envs(&self) -> io::Result<Vec<(&'static str, OsString)>> {
+        let exe = env::current_exe()?;
+        Ok(vec![
+            ("GIT_ASKPASS", exe.clone().into()),
+            ("SSH_ASKPASS", exe.into()),
+            ("SSH_ASKPASS_REQUIRE", "force".into()),
+            ("GIT_TERMINAL_PROMPT", "0".into()),
+            (SOCKET_ENV, self.path.clone().into()),
+        ])
+    }
+
+    /// Answer prompts with `answer`, which returns `None` if a prompt is cancelled. Runs until an
+    /// error occurs, so it has to be stopped when the git command exits.
+    pub async fn serve<F: Future<Output = Option<String>>>(
+        &self,
+        mut answer: impl FnMut(String) -> F,
+    ) -> io::Result<()> {
+        loop {
+            let (mut stream, _) = self.listener.accept().await?;
+            let mut prompt = String::new();
+            stream.read_to_string(&mut prompt).await?;
+            if let Some(text) = answer(prompt).await {
+                stream.write_all(text.as_bytes()).await?;
+            }
+            stream.shutdown().await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::block_on;
+
+    #[test]
+    fn answer() {
+        block_on(async {
+            let askpass = GitAskpass::new().unwrap();
+            let path = askpass.path.clone();
+            let dir = std::fs::metadata(path.parent().unwrap()).unwrap();
+            assert_eq!(dir.permissions().mode() & 0o777, 0o700);
+            let prompts = tokio::task::spawn_blocking(move || {
+                (
+                    ask(&path, "Username for 'https://example.com': ").unwrap(),
+                    ask(&path, "Password for 'https://user@example.com': ").is_err(),
+                )
+            });
+            let server = tokio::spawn(async move {
+                askpass
+                    .serve(|prompt| async move {
+                        if prompt.starts_with("Username") {
+                            Some("user".to_string())
+                        } else {
+                            None
+                        }
+                    })
+                    .await
+            });
+            assert_eq!(prompts.await.unwrap(), ("user".to_string(), true));
+            assert!(!server.is_finished());
+            server.abort();
+        });
+    }
+}
@@ -0,0 +1,327 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Library backed implementation of GitRepository operations, used instead of spawning the git CLI
+// when libgit2 can open a repository
+
+use git2::{
+    AttrCheckFlags, AttrValue, DiffFormat, DiffOptions, Error, Repository, Status, StatusOptions,
+};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use crate::git::{GitBackendOps, GitFuture, GitStatus, GitStatusKind};
+
+/// Opened repositories by working tree, reused so that large indexes are only parsed again after
+/// they change on disk
+static REPOSITORIES: LazyLock<Mutex<HashMap<PathBuf, Arc<Mutex<Repository>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Close cached repositories that `keep` returns false for, like those of closed projects
+pub fn retain(keep: impl Fn(&Path) -> bool) {
+    let mut repositories = REPOSITORIES.lock().unwrap();
+    repositories.retain(|repo_path, _| keep(repo_path));
+}
+
+/// Implementation of [`GitBackendOps`] with libgit2
+pub struct GitNative {
+    repo_path: PathBuf,
+}
+
+impl GitNative {
+    /// Open the repository with a working tree containing the folder `path`
+    pub async fn discover(path: PathBuf) -> io::Result<Self> {
+        let repo_path = blocking(move || discover(&path).map(Some)).await?;
+        Ok(Self { repo_path })
+    }
+
+    /// Path of the working tree
+    pub fn path(&self) -> &Path {
+        &self.repo_path
+    }
+}
+
+impl GitBackendOps for GitNative {
+    fn status(&self) -> GitFuture<'_, Vec<GitStatus>> {
+        let repo_path = self.repo_path.clone();
+        blocking(move || status(&repo_path))
+    }
+
+    fn diff(&self, path: PathBuf, staged: bool, context_lines: u32) -> GitFuture<'_, String> {
+        let repo_path = self.repo_path.clone();
+        blocking(move || diff(&repo_path, &path, staged, context_lines))
+    }
+
+    fn index_text(&self, path: PathBuf) -> GitFuture<'_, String> {
+        let repo_path = self.repo_path.clone();
+        blocking(move || index_text(&repo_path, &path))
+    }
+
+    fn stage(&self, path: PathBuf) -> GitFuture<'_, ()> {
+        let repo_path = self.repo_path.clone();
+        blocking(move || stage(&repo_path, &path))
+    }
+
+    fn unstage(&self, path: PathBuf) -> GitFuture<'_, ()> {
+        let repo_path = self.repo_path.clone();
+        blocking(move || unstage(&repo_path, &path))
+    }
+}
+
+/// Run a libgit2 operation on the blocking thread pool, `None` results are unsupported
+fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<Option<T>, Error> + Send + 'static,
+) -> GitFuture<'static, T> {
+    Box::pin(async move {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(Ok(Some(ok))) => Ok(ok),
+            Ok(Ok(None)) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only supported by the git CLI",
+            )),
+            Ok(Err(err)) => Err(io::Error::other(err)),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    })
+}
+
+fn with_repository<T>(
+    repo_path: &Path,
+    f: impl FnOnce(&Repository) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let repo_lock = {
+        let mut repositories = REPOSITORIES.lock().unwrap();
+        match repositories.get(repo_path) {
+            Some(repo_lock) => repo_lock.clone(),
+            None => {
+                let repo_lock = Arc::new(Mutex::new(Repository::open(repo_path)?));
+                repositories.insert(repo_path.to_path_buf(), repo_lock.clone());
+                repo_lock
+            }
+        }
+    };
+    let repo = repo_lock.lock().unwrap();
+    // The index may have been changed by other programs, this only reads it if it did
+    repo.index()?.read(false)?;
+    f(&repo)
+}
+
+fn relative_path<'a>(repo_path: &Path, path: &'a Path) -> Result<&'a Path, Error> {
+    path.strip_prefix(repo_path)
+        .map_err(|err| Error::from_str(&format!("{:?} is not in {:?}: {}", path, repo_path, err)))
+}
+
+/// Find the working tree of the repository containing the folder `path`
+fn discover(path: &Path) -> Result<PathBuf, Error> {
+    let repo = Repository::discover(path)?;
+    let Some(workdir) = repo.workdir() else {
+        return Err(Error::from_str("bare repositories have no working tree"));
+    };
+    fs::canonicalize(workdir).map_err(|err| Error::from_str(&err.to_string()))
+}
+
+/// Whether git converts `relative_path` between the index and the working tree in ways libgit2
+/// does not, like line endings, clean and smudge filters, and encodings
+fn needs_conversion(repo: &Repository, relative_path: &Path) -> Result<bool, Error> {
+    let config = repo.config()?;
+    let autocrlf = match config.get_string("core.autocrlf") {
+        Ok(value) => !value.eq_ignore_ascii_case("false"),
+        Err(_) => false,
+    };
+    if autocrlf || config.get_entry("core.eol").is_ok() {
+        return Ok(true);
+    }
+    for name in ["eol", "filter", "ident", "text", "working-tree-encoding"] {
+        let value = repo.get_attr(relative_path, name, AttrCheckFlags::default())?;
+        if !matches!(
+            AttrValue::from_string(value),
+            AttrValue::Unspecified | AttrValue::False
+        ) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether filter drivers like Git LFS are configured, which change how files compare to the
+/// index
+fn has_filter_drivers(repo: &Repository) -> Result<bool, Error> {
+    let config = repo.config()?;
+    let mut entries = config.entries(Some(r"^filter\..*\.(clean|process)$"))?;
+    Ok(entries.next().is_some())
+}
+
+fn status(repo_path: &Path) -> Result<Option<Vec<GitStatus>>, Error> {
+    with_repository(repo_path, |repo| {
+        if has_filter_drivers(repo)? {
+            return Ok(None);
+        }
+
+        // Match the defaults of git status
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(false)
+            .renames_head_to_index(true);
+        let statuses = repo.statuses(Some(&mut options))?;
+
+        let mut status = Vec::with_capacity(statuses.len());
+        for entry in statuses.iter() {
+            let flags = entry.status();
+            if flags.is_ignored() {
+                continue;
+            }
+            // The path of the entry is the old path of renames, so use the new path of the delta
+            let delta_opt = entry.head_to_index().or_else(|| entry.index_to_workdir());
+            let Some(relative_path) = delta_opt.and_then(|delta| delta.new_file().path()) else {
+                log::warn!("invalid git status path {:?}", entry.path_bytes());
+                continue;
+            };
+
+            let (staged, unstaged) = if flags.is_conflicted() {
+                (GitStatusKind::Updated, GitStatusKind::Updated)
+            } else if flags.is_wt_new() {
+                (GitStatusKind::Untracked, GitStatusKind::Untracked)
+            } else {
+                let staged = if flags.is_index_new() {
+                    GitStatusKind::Added
+                } else if flags.is_index_deleted() {
+                    GitStatusKind::Deleted
+                } else if flags.is_index_renamed() {
+                    GitStatusKind::Renamed
+                } else if flags.is_index_typechange() {
+                    GitStatusKind::FileTypeChanged
+                } else if flags.is_index_modified() {
+                    GitStatusKind::Modified
+                } else {
+                    GitStatusKind::Unmodified
+                };
+                let unstaged = if flags.is_wt_deleted() {
+                    GitStatusKind::Deleted
+                } else if flags.is_wt_renamed() {
+                    GitStatusKind::Renamed
+                } else if flags.is_wt_typechange() {
+                    GitStatusKind::FileTypeChanged
+                } else if flags.is_wt_modified() {
+                    GitStatusKind::Modified
+                } else {
+                    GitStatusKind::Unmodified
+                };
+                (staged, unstaged)
+            };
+
+            let old_path = if flags.intersects(Status::INDEX_RENAMED) {
+                entry
+                    .head_to_index()
+                    .and_then(|delta| delta.old_file().path().map(|path| repo_path.join(path)))
+            } else {
+                None
+            };
+
+            status.push(GitStatus {
+                path: repo_path.join(relative_path),
+                old_path,
+                staged,
+                unstaged,
+            });
+        }
+        Ok(Some(status))
+    })
+}
+
+/// Unified diff of `path`, formatted like the output of git diff
+fn diff(
+    repo_path: &Path,
+    path: &Path,
+    staged: bool,
+    context_lines: u32,
+) -> Result<Option<String>, Error> {
+    with_repository(repo_path, |repo| {
+        let relative_path = relative_path(repo_path, path)?;
+        // The working tree has to be converted to compare it to the index
+        if !staged && needs_conversion(repo, relative_path)? {
+            return Ok(None);
+        }
+        let mut options = DiffOptions::new();
+        options
+            .pathspec(relative_path)
+            .disable_pathspec_match(true)
+            .context_lines(context_lines);
+        let index = repo.index()?;
+        let diff = if staged {
+            // Repositories without commits have no tree to compare to
+            let tree_opt = match repo.head() {
+                Ok(head) => Some(head.peel_to_tree()?),
+                Err(_) => None,
+            };
+            repo.diff_tree_to_index(tree_opt.as_ref(), Some(&index), Some(&mut options))?
+        } else {
+            repo.diff_index_to_workdir(Some(&index), Some(&mut options))?
+        };
+
+        let mut patch = String::new();
+        diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+            if matches!(line.origin(), ' ' | '+' | '-') {
+                patch.push(line.origin());
+            }
+            patch.push_str(&String::from_utf8_lossy(line.content()));
+            true
+        })?;
+        Ok(Some(patch))
+    })
+}
+
+fn stage(repo_path: &Path, path: &Path) -> Result<Option<()>, Error> {
+    with_repository(repo_path, |repo| {
+        let relative_path = relative_path(repo_path, path)?;
+        let mut index = repo.index()?;
+        match fs::symlink_metadata(path) {
+            // Files of folders would each need to be checked for conversions
+            Ok(metadata) if metadata.is_dir() => return Ok(None),
+            // Adding files does not run clean filters
+            Ok(_) if needs_conversion(repo, relative_path)? => return Ok(None),
+            Ok(_) => index.add_path(relative_path)?,
+            Err(_) => index.remove_all([relative_path], None)?,
+        }
+        index.write().map(Some)
+    })
+}
+
+fn unstage(repo_path: &Path, path: &Path) -> Result<Option<()>, Error> {
+    with_repository(repo_path, |repo| {
+        let relative_path = relative_path(repo_path, path)?;
+        // Without commits, entries are removed from the index
+        let head_opt = match repo.head() {
+            Ok(head) => Some(head.peel(git2::ObjectType::Commit)?),
+            Err(_) => None,
+        };
+        repo.reset_default(head_opt.as_ref(), [relative_path])
+            .map(Some)
+    })
+}
+
+/// Read the indexed version of a file, or `None` if a checkout would convert it in ways only the
+/// git CLI supports, like line endings and filters
+fn index_text(repo_path: &Path, path: &Path) -> Result<Option<String>, Error> {
+    with_repository(repo_path, |repo| {
+        let relative_path = relative_path(repo_path, path)?;
+        if needs_conversion(repo, relative_path)? {
+            return Ok(None);
+        }
+
+        let index = repo.index()?;
+        let Some(entry) = index.get_path(relative_path, 0) else {
+            return Err(Error::from_str(&format!(
+                "{:?} is not in the index",
+                relative_path
+            )));
+        };
+        let blob = repo.find_blob(entry.id)?;
+        String::from_utf8(blob.content().to_vec())
+            .map(Some)
+            .map_err(|err| Error::from_str(&format!("failed to parse blob: {}", err)))
+    })
+}
@@ -9,10 +9,10 @@ pub fn key_binds() -> HashMap<KeyBind, Action> {
     let mut key_binds = HashMap::new();
 
     macro_rules! bind {
-        ([$($modifier:ident),+ $(,)?], $key:expr, $action:ident) => {{
+        ([$($modifier:ident),* $(,)?], $key:expr, $action:ident) => {{
             key_binds.insert(
                 KeyBind {
-                    modifiers: vec![$(Modifier::$modifier),+],
+                    modifiers: vec![$(Modifier::$modifier),*],
                     key: $key,
                 },
                 Action::$action,
@@ -38,6 +38,12 @@ pub fn key_binds() -> HashMap<KeyBind, Action> {
     bind!([Ctrl], Key::Character("s".into()), Save);
     bind!([Ctrl, Shift], Key::Character("S".into()), SaveAsDialog);
     bind!([Ctrl], Key::Character("a".into()), SelectAll);
+    bind!([], Key::Named(Named::F12), GoToDefinition);
+    bind!([Shift], Key::Named(Named::F12), FindReferences);
+    bind!([Ctrl], Key::Character("r".into()), DocumentSymbols);
+    bind!([Ctrl, Shift], Key::Character("R".into()), WorkspaceSymbols);
+    bind!([], Key::Named(Named::F2), Rename);
+    bind!([Ctrl, Shift], Key::Character("I".into()), FormatDocument);
     // Ctrl+0, Ctrl+-, and Ctrl+= are not special keys for terminals and are free to use
     bind!([Ctrl], Key::Character("0".into()), ZoomReset);
     bind!([Ctrl], Key::Character("-".into()), ZoomOut);
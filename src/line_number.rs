@@ -27,27 +27,32 @@ impl LineNumberCache {
 
     pub fn get(&mut self, font_system: &mut FontSystem, key: LineNumberKey) -> &Vec<LayoutLine> {
         self.cache.entry(key).or_insert_with(|| {
-            let attrs = crate::monospace_attrs();
             let text = format!("{:width$}", key.number, width = key.width);
-            let mut buffer_line = BufferLine::new(
-                text,
-                LineEnding::default(),
-                AttrsList::new(&attrs),
-                Shaping::Advanced,
-            );
-            buffer_line.set_align(Some(Align::Left));
-            buffer_line
-                .layout(
-                    font_system,
-                    1.0, /* font size adjusted later */
-                    None,
-                    Wrap::None,
-                    Ellipsize::None,
-                    None,
-                    8, /* default tab width */
-                    Default::default(),
-                )
-                .to_vec()
+            layout_monospace(font_system, text)
         })
     }
 }
+
+/// Lay out a single line of monospace text at font size 1.0, to be scaled when drawn
+pub fn layout_monospace(font_system: &mut FontSystem, text: String) -> Vec<LayoutLine> {
+    let attrs = crate::monospace_attrs();
+    let mut buffer_line = BufferLine::new(
+        text,
+        LineEnding::default(),
+        AttrsList::new(&attrs),
+        Shaping::Advanced,
+    );
+    buffer_line.set_align(Some(Align::Left));
+    buffer_line
+        .layout(
+            font_system,
+            1.0, /* font size adjusted later */
+            None,
+            Wrap::None,
+            Ellipsize::None,
+            None,
+            8, /* default tab width */
+            Default::default(),
+        )
+        .to_vec()
+}
@@ -0,0 +1,330 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Snapshots of files taken on save and before reloads, so older versions can be restored even for
+// files outside of git repositories. Each file has a folder named after a hash of its path, with
+// one snapshot per file named after the time it was taken in milliseconds since the Unix epoch.
+// Large files are not kept, and the oldest snapshots of all files are removed once the history
+// grows too large.
+
+use std::{
+    cmp::Reverse,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Maximum size in bytes of a snapshot
+const MAX_SNAPSHOT_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum size in bytes of the snapshots of all files
+const MAX_TOTAL_SIZE: u64 = 256 * 1024 * 1024;
+
+/// FNV-1a hash, which is stable across versions unlike the hasher of the standard library
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// How many snapshots of a file are kept
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LocalHistoryRetention {
+    /// Maximum number of snapshots, zero for no limit
+    pub max_count: usize,
+    /// Maximum age of snapshots, `None` for no limit
+    pub max_age_opt: Option<Duration>,
+}
+
+/// A snapshot of a file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LocalSnapshot {
+    pub path: PathBuf,
+    /// Milliseconds since the Unix epoch
+    pub time_ms: i64,
+}
+
+impl LocalSnapshot {
+    /// Seconds since the Unix epoch
+    pub fn time(&self) -> i64 {
+        self.time_ms / 1000
+    }
+
+    pub fn read(&self) -> io::Result<String> {
+        fs::read_to_string(&self.path)
+    }
+}
+
+/// Text to keep in the local history of a file, written in the background
+pub struct LocalHistorySave {
+    pub path: PathBuf,
+    pub text: String,
+    pub retention: LocalHistoryRetention,
+}
+
+impl LocalHistorySave {
+    /// Take the snapshot
+    pub fn write(&self) {
+        // Snapshots taken at the same time would get the same name
+        static WRITE_LOCK: Mutex<()> = Mutex::new(());
+        let Some(local_history) = LocalHistory::new() else {
+            log::warn!("failed to find local history folder");
+            return;
+        };
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = local_history.snapshot(&self.path, &self.text, self.retention) {
+            log::warn!("failed to take snapshot of {:?}: {}", self.path, err);
+        }
+    }
+}
+
+pub struct LocalHistory {
+    root: PathBuf,
+    max_snapshot_size: usize,
+    max_total_size: u64,
+}
+
+impl LocalHistory {
+    /// History in the data folder of the user
+    pub fn new() -> Option<Self> {
+        let root = dirs::data_dir()?.join("cosmic-edit").join("history");
+        Some(Self {
+            root,
+            max_snapshot_size: MAX_SNAPSHOT_SIZE,
+            max_total_size: MAX_TOTAL_SIZE,
+        })
+    }
+
+    fn file_dir(&self, path: &Path) -> PathBuf {
+        self.root.join(format!(
+            "{:016x}",
+            stable_hash(path.as_os_str().as_encoded_bytes())
+        ))
+    }
+
+    /// Snapshots of the file at `path`, newest first
+    pub fn snapshots(&self, path: &Path) -> io::Result<Vec<LocalSnapshot>> {
+        let dir = self.file_dir(path);
+        let mut snapshots = Vec::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(ok) => ok,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(snapshots),
+            Err(err) => return Err(err),
+        };
+        for entry_res in entries {
+            let entry = entry_res?;
+            let Some(time_ms) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<i64>().ok())
+            else {
+                continue;
+            };
+            snapshots.push(LocalSnapshot {
+                path: entry.path(),
+                time_ms,
+            });
+        }
+        snapshots.sort_by_key(|snapshot| Reverse(snapshot.time_ms));
+        Ok(snapshots)
+    }
+
+    /// Take a snapshot of `text` as the contents of `path` unless it is too large or matches the
+    /// newest snapshot, then remove snapshots that are not retained. Returns true if a snapshot
+    /// was taken.
+    pub fn snapshot(
+        &self,
+        path: &Path,
+        text: &str,
+        retention: LocalHistoryRetention,
+    ) -> io::Result<bool> {
+        if text.len() > self.max_snapshot_size {
+            return Ok(false);
+        }
+        let dir = self.file_dir(path);
+        let mut snapshots = self.snapshots(path)?;
+        if snapshots
+            .first()
+            .is_some_and(|newest| newest.read().is_ok_and(|newest_text| newest_text == text))
+        {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(&dir)?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as i64);
+        // Snapshots taken within the same millisecond must not overwrite each other
+        let time_ms = snapshots
+            .first()
+            .map_or(now, |newest| now.max(newest.time_ms + 1));
+        let snapshot = LocalSnapshot {
+            path: dir.join(time_ms.to_string()),
+            time_ms,
+        };
+        fs::write(&snapshot.path, text)?;
+        snapshots.insert(0, snapshot);
+
+        // The new snapshot is always kept
+        let mut keep = snapshots.len();
+        if retention.max_count > 0 {
+            keep = keep.min(retention.max_count.max(1));
+        }
+        if let Some(max_age) = retention.max_age_opt {
+            let oldest = now.saturating_sub(max_age.as_millis() as i64);
+            keep = keep.min(
+                snapshots
+                    .iter()
+                    .skip(1)
+                    .position(|snapshot| snapshot.time_ms < oldest)
+                    .map_or(snapshots.len(), |i| i + 1),
+            );
+        }
+        for snapshot in snapshots.drain(keep..) {
+            if let Err(err) = fs::remove_file(&snapshot.path) {
+                log::warn!("failed to remove snapshot {:?}: {}", snapshot.path, err);
+            }
+        }
+        self.limit_total_size(&snapshots[0].path)?;
+        Ok(true)
+    }
+
+    /// Remove the oldest snapshots of all files until they fit in the maximum total size, except
+    /// for the snapshot at `keep_path`
+    fn limit_total_size(&self, keep_path: &Path) -> io::Result<()> {
+        let mut snapshots = Vec::new();
+        let mut total_size = 0;
+        for dir_entry_res in fs::read_dir(&self.root)? {
+            let dir_entry = dir_entry_res?;
+            if !dir_entry.file_type()?.is_dir() {
+                continue;
+            }
+            for entry_res in fs::read_dir(dir_entry.path())? {
+                let entry = entry_res?;
+                let Some(time_ms) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse::<i64>().ok())
+                else {
+                    continue;
+                };
+                let size = entry.metadata()?.len();
+                total_size += size;
+                snapshots.push((time_ms, size, entry.path()));
+            }
+        }
+        if total_size <= self.max_total_size {
+            return Ok(());
+        }
+
+        snapshots.sort_by_key(|(time_ms, _, _)| *time_ms);
+        for (_, size, path) in snapshots {
+            if total_size <= self.max_total_size {
+                break;
+            }
+            if path == keep_path {
+                continue;
+            }
+            if let Err(err) = fs::remove_file(&path) {
+                log::warn!("failed to remove snapshot {:?}: {}", path, err);
+                continue;
+            }
+            total_size -= size;
+            // Folders of files without snapshots are removed with them
+            if let Some(dir) = path.parent()
+                && fs::read_dir(dir)?.next().is_none()
+            {
+                fs::remove_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(root: &Path) -> LocalHistory {
+        LocalHistory {
+            root: root.to_path_buf(),
+            max_snapshot_size: MAX_SNAPSHOT_SIZE,
+            max_total_size: MAX_TOTAL_SIZE,
+        }
+    }
+
+    #[test]
+    fn snapshot() {
+        let root = tempfile::tempdir().unwrap();
+        let history = history(root.path());
+        let path = Path::new("/home/user/notes.txt");
+        let retention = LocalHistoryRetention {
+            max_count: 3,
+            max_age_opt: None,
+        };
+        assert!(history.snapshots(path).unwrap().is_empty());
+        for text in ["one", "two", "two", "three", "four"] {
+            history.snapshot(path, text, retention).unwrap();
+        }
+        let texts: Vec<String> = history
+            .snapshots(path)
+            .unwrap()
+            .iter()
+            .map(|snapshot| snapshot.read().unwrap())
+            .collect();
+        assert_eq!(texts, ["four", "three", "two"]);
+        assert!(
+            history
+                .snapshots(Path::new("/home/user/other.txt"))
+                .unwrap()
+                .is_empty()
+        );
+
+        // Only the new snapshot is younger than the maximum age
+        let retention = LocalHistoryRetention {
+            max_count: 0,
+            max_age_opt: Some(Duration::ZERO),
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        history.snapshot(path, "five", retention).unwrap();
+        let snapshots = history.snapshots(path).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].read().unwrap(), "five");
+    }
+
+    #[test]
+    fn size_limits() {
+        let root = tempfile::tempdir().unwrap();
+        let history = LocalHistory {
+            max_snapshot_size: 4,
+            max_total_size: 10,
+            ..history(root.path())
+        };
+        let retention = LocalHistoryRetention {
+            max_count: 0,
+            max_age_opt: None,
+        };
+        let (path, other_path) = (Path::new("/home/user/a.txt"), Path::new("/home/user/b.txt"));
+        assert!(!history.snapshot(path, "large", retention).unwrap());
+        for text in ["one", "two", "six"] {
+            assert!(history.snapshot(path, text, retention).unwrap());
+        }
+        // Snapshots of other files are named after the current time only
+        std::thread::sleep(Duration::from_millis(10));
+        // The oldest snapshots of all files are removed, including the folder of `path`
+        assert!(history.snapshot(other_path, "four", retention).unwrap());
+        assert!(history.snapshot(other_path, "five", retention).unwrap());
+        assert!(history.snapshots(path).unwrap().is_empty());
+        assert!(!history.file_dir(path).exists());
+        let texts: Vec<String> = history
+            .snapshots(other_path)
+            .unwrap()
+            .iter()
+            .map(|snapshot| snapshot.read().unwrap())
+            .collect();
+        assert_eq!(texts, ["five", "four"]);
+    }
+}
@@ -0,0 +1,1463 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Client for language servers, which run as child processes and talk JSON-RPC over their standard
+// input and output. A server is started for each workspace root and server configuration, and is
+// kept in sync with the contents of the open files it handles.
+
+use lsp_types::{
+    ApplyWorkspaceEditResponse, ClientCapabilities, ClientInfo, CodeAction,
+    CodeActionCapabilityResolveSupport, CodeActionClientCapabilities, CodeActionContext,
+    CodeActionKind, CodeActionKindLiteralSupport, CodeActionLiteralSupport, CodeActionOrCommand,
+    CodeActionParams, CodeActionProviderCapability, CodeActionTriggerKind,
+    CompletionClientCapabilities, CompletionContext, CompletionItem, CompletionItemCapability,
+    CompletionItemCapabilityResolveSupport, CompletionParams, CompletionResponse,
+    CompletionTriggerKind, Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    DocumentFormattingParams, DocumentRangeFormattingParams, DocumentSymbolClientCapabilities,
+    DocumentSymbolParams, DocumentSymbolResponse, DynamicRegistrationClientCapabilities,
+    ExecuteCommandParams, FormattingOptions, GeneralClientCapabilities, GotoCapability,
+    GotoDefinitionParams, GotoDefinitionResponse, InitializeParams, InitializeResult,
+    InitializedParams, Location, MarkupKind, OneOf, ParameterInformationSettings, Position,
+    PositionEncodingKind, PublishDiagnosticsClientCapabilities, PublishDiagnosticsParams, Range,
+    ReferenceClientCapabilities, ReferenceContext, ReferenceParams, RenameClientCapabilities,
+    RenameParams, ServerCapabilities, SignatureHelp, SignatureHelpClientCapabilities,
+    SignatureHelpContext, SignatureHelpParams, SignatureHelpTriggerKind,
+    SignatureInformationSettings, TextDocumentClientCapabilities, TextDocumentContentChangeEvent,
+    TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams,
+    TextDocumentSyncCapability, TextDocumentSyncClientCapabilities, TextDocumentSyncKind,
+    TextDocumentSyncSaveOptions, TextEdit, TypeDefinitionProviderCapability, Uri,
+    VersionedTextDocumentIdentifier, WorkspaceClientCapabilities, WorkspaceEdit,
+    WorkspaceEditClientCapabilities, WorkspaceFolder, WorkspaceSymbolClientCapabilities,
+    WorkspaceSymbolParams, WorkspaceSymbolResponse,
+    notification::{self, Notification},
+    request::{self, Request},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    process::{Child, Command},
+    sync::{mpsc, oneshot},
+    time,
+};
+
+/// Time a server has to answer the initialize request before it is killed
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time a server has to answer the shutdown request and then exit before it is killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Language server and the files it handles
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LspServerConfig {
+    /// Name of the server, shown in logs
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    /// Language identifiers of the handled files by extension
+    pub languages: BTreeMap<String, String>,
+    /// Files marking the root of a workspace, used for files outside of open projects
+    pub root_markers: Vec<String>,
+}
+
+impl LspServerConfig {
+    fn new(name: &str, args: &[&str], languages: &[(&str, &str)], root_markers: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            command: name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            languages: languages
+                .iter()
+                .map(|(extension, language_id)| (extension.to_string(), language_id.to_string()))
+                .collect(),
+            root_markers: root_markers
+                .iter()
+                .map(|marker| marker.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn defaults() -> Vec<Self> {
+        let mut pyright = Self::new(
+            "pyright",
+            &["--stdio"],
+            &[("py", "python"), ("pyi", "python")],
+            &[
+                "pyproject.toml",
+                "setup.py",
+                "setup.cfg",
+                "requirements.txt",
+            ],
+        );
+        pyright.command = "pyright-langserver".to_string();
+        let mut typescript = Self::new(
+            "typescript-language-server",
+            &["--stdio"],
+            &[
+                ("js", "javascript"),
+                ("jsx", "javascriptreact"),
+                ("ts", "typescript"),
+                ("tsx", "typescriptreact"),
+            ],
+            &["package.json", "tsconfig.json", "jsconfig.json"],
+        );
+        typescript.name = "typescript".to_string();
+        vec![
+            Self::new("rust-analyzer", &[], &[("rs", "rust")], &["Cargo.toml"]),
+            Self::new(
+                "clangd",
+                &[],
+                &[
+                    ("c", "c"),
+                    ("h", "c"),
+                    ("cc", "cpp"),
+                    ("cpp", "cpp"),
+                    ("cxx", "cpp"),
+                    ("hh", "cpp"),
+                    ("hpp", "cpp"),
+                ],
+                &["compile_commands.json", "compile_flags.txt", ".clangd"],
+            ),
+            pyright,
+            typescript,
+            Self::new("gopls", &[], &[("go", "go")], &["go.mod"]),
+        ]
+    }
+
+    /// Language identifier of the file at `path`, `None` if the server does not handle it
+    pub fn language_id(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?;
+        self.languages.get(extension).map(String::as_str)
+    }
+
+    /// Workspace root of the file at `path`: the project containing it if there is one, otherwise
+    /// the closest folder with a root marker, otherwise the folder of the file
+    pub fn root(&self, path: &Path, project_opt: Option<&Path>) -> PathBuf {
+        if let Some(project) = project_opt {
+            return project.to_path_buf();
+        }
+        let parent = path.parent().unwrap_or(path);
+        parent
+            .ancestors()
+            .find(|dir| {
+                self.root_markers
+                    .iter()
+                    .any(|marker| dir.join(marker).exists())
+            })
+            .unwrap_or(parent)
+            .to_path_buf()
+    }
+}
+
+/// A language server is started for each server configuration and workspace root
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LspServerKey {
+    pub name: String,
+    pub root: PathBuf,
+}
+
+/// File opened with a language server
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LspDocument {
+    pub key: LspServerKey,
+    pub path: PathBuf,
+    pub language_id: String,
+}
+
+/// How columns of positions are counted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LspPositionEncoding {
+    Utf8,
+    Utf16,
+}
+
+impl LspPositionEncoding {
+    /// Byte index of `column` of `line`, clamped to the line and character boundaries
+    pub fn index(self, line: &str, column: u32) -> usize {
+        let column = column as usize;
+        match self {
+            Self::Utf8 => {
+                let mut index = column.min(line.len());
+                while !line.is_char_boundary(index) {
+                    index -= 1;
+                }
+                index
+            }
+            Self::Utf16 => {
+                let mut units = 0;
+                for (i, c) in line.char_indices() {
+                    units += c.len_utf16();
+                    if units > column {
+                        return i;
+                    }
+                }
+                line.len()
+            }
+        }
+    }
+
+    /// Column of the byte `index` of `line`
+    pub fn column(self, line: &str, index: usize) -> u32 {
+        let prefix = line.get(..index).unwrap_or(line);
+        let column = match self {
+            Self::Utf8 => prefix.len(),
+            Self::Utf16 => prefix.chars().map(char::len_utf16).sum(),
+        };
+        column as u32
+    }
+}
+
+/// Diagnostic with its range as cursors in the text
+#[derive(Clone, Debug)]
+pub struct LspDiagnostic {
+    pub start: cosmic_text::Cursor,
+    pub end: cosmic_text::Cursor,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+}
+
+/// Events sent by language servers
+#[derive(Clone, Debug)]
+pub enum LspEvent {
+    Diagnostics(PublishDiagnosticsParams),
+    /// Edits to apply, usually made by running a command
+    ApplyEdit(WorkspaceEdit),
+}
+
+pub fn path_uri(path: &Path) -> Option<Uri> {
+    url::Url::from_file_path(path).ok()?.as_str().parse().ok()
+}
+
+pub fn uri_path(uri: &Uri) -> Option<PathBuf> {
+    url::Url::parse(uri.as_str()).ok()?.to_file_path().ok()
+}
+
+type Pending = Mutex<HashMap<i64, oneshot::Sender<Result<Value, String>>>>;
+
+struct LspClientInner {
+    name: String,
+    sender: mpsc::UnboundedSender<Value>,
+    next_id: AtomicI64,
+    pending: Arc<Pending>,
+    capabilities: OnceLock<ServerCapabilities>,
+    /// Process of the server until it exits, killed when dropped
+    process: Mutex<Option<Child>>,
+}
+
+/// Connection to a running language server
+#[derive(Clone)]
+pub struct LspClient(Arc<LspClientInner>);
+
+impl fmt::Debug for LspClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LspClient").field(&self.0.name).finish()
+    }
+}
+
+impl LspClient {
+    /// Start the server of `config` for the workspace at `root`. Events are received until the
+    /// server exits.
+    pub async fn start(
+        config: &LspServerConfig,
+        root: &Path,
+    ) -> io::Result<(Self, mpsc::UnboundedReceiver<LspEvent>)> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(io::Error::other("failed to open standard streams"));
+        };
+
+        let name = config.name.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                log::debug!("{}: {}", name, line);
+            }
+        });
+
+        let (client, events) = Self::connect(&config.name, stdout, stdin, Some(child), || {});
+        match time::timeout(INITIALIZE_TIMEOUT, client.initialize(root)).await {
+            Ok(Ok(())) => Ok((client, events)),
+            Ok(Err(err)) => {
+                client.notify::<notification::Exit>(());
+                Err(err)
+            }
+            Err(_) => {
+                client.kill();
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "{} did not initialize within {} seconds",
+                        config.name,
+                        INITIALIZE_TIMEOUT.as_secs()
+                    ),
+                ))
+            }
+        }
+    }
+
+    /// Talk to a server reading from `reader` and writing to `writer`, calling `on_exit` once the
+    /// server closes its output. The server `process_opt` is killed if it does not shut down.
+    fn connect(
+        name: &str,
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        mut writer: impl AsyncWrite + Send + Unpin + 'static,
+        process_opt: Option<Child>,
+        on_exit: impl FnOnce() + Send + 'static,
+    ) -> (Self, mpsc::UnboundedReceiver<LspEvent>) {
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Value>();
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let pending = Arc::new(Pending::default());
+        let inner = Arc::new(LspClientInner {
+            name: name.to_string(),
+            sender: sender.clone(),
+            next_id: AtomicI64::new(0),
+            pending: pending.clone(),
+            capabilities: OnceLock::new(),
+            process: Mutex::new(process_opt),
+        });
+
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                if let Err(err) = writer.write_all(&frame(&message)).await {
+                    log::warn!("failed to write to language server: {}", err);
+                    break;
+                }
+                let _ = writer.flush().await;
+            }
+        });
+
+        {
+            let name = name.to_string();
+            // The client is not kept alive by its own server
+            let inner = Arc::downgrade(&inner);
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(reader);
+                loop {
+                    match read_message(&mut reader).await {
+                        Ok(Some(message)) => {
+                            handle_message(&name, message, &sender, &pending, &event_sender);
+                        }
+                        Ok(None) => break,
+                        Err(err) => {
+                            log::warn!("failed to read from {}: {}", name, err);
+                            break;
+                        }
+                    }
+                }
+                // Requests still waiting fail as their senders are dropped
+                pending.lock().unwrap().clear();
+                // The process exited once the server closes its output
+                if let Some(inner) = inner.upgrade() {
+                    inner.process.lock().unwrap().take();
+                }
+                on_exit();
+            });
+        }
+
+        (Self(inner), events)
+    }
+
+    async fn initialize(&self, root: &Path) -> io::Result<()> {
+        let uri = path_uri(root).ok_or_else(|| io::Error::other("invalid workspace root"))?;
+        #[allow(deprecated)]
+        let params = InitializeParams {
+            process_id: Some(std::process::id()),
+            // Some servers only support the deprecated root
+            root_uri: Some(uri.clone()),
+            capabilities: ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: Some(vec![
+                        PositionEncodingKind::UTF8,
+                        PositionEncodingKind::UTF16,
+                    ]),
+                    ..Default::default()
+                }),
+                text_document: Some(TextDocumentClientCapabilities {
+                    synchronization: Some(TextDocumentSyncClientCapabilities {
+                        did_save: Some(true),
+                        ..Default::default()
+                    }),
+                    publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+                        version_support: Some(true),
+                        ..Default::default()
+                    }),
+                    completion: Some(CompletionClientCapabilities {
+                        completion_item: Some(CompletionItemCapability {
+                            snippet_support: Some(true),
+                            documentation_format: Some(vec![
+                                MarkupKind::Markdown,
+                                MarkupKind::PlainText,
+                            ]),
+                            preselect_support: Some(true),
+                            resolve_support: Some(CompletionItemCapabilityResolveSupport {
+                                properties: vec![
+                                    "documentation".to_string(),
+                                    "detail".to_string(),
+                                    "additionalTextEdits".to_string(),
+                                ],
+                            }),
+                            ..Default::default()
+                        }),
+                        context_support: Some(true),
+                        ..Default::default()
+                    }),
+                    signature_help: Some(SignatureHelpClientCapabilities {
+                        signature_information: Some(SignatureInformationSettings {
+                            documentation_format: Some(vec![
+                                MarkupKind::Markdown,
+                                MarkupKind::PlainText,
+                            ]),
+                            parameter_information: Some(ParameterInformationSettings {
+                                label_offset_support: Some(true),
+                            }),
+                            active_parameter_support: Some(true),
+                        }),
+                        context_support: Some(true),
+                        ..Default::default()
+                    }),
+                    definition: Some(GotoCapability {
+                        link_support: Some(true),
+                        ..Default::default()
+                    }),
+                    type_definition: Some(GotoCapability {
+                        link_support: Some(true),
+                        ..Default::default()
+                    }),
+                    references: Some(ReferenceClientCapabilities::default()),
+                    document_symbol: Some(DocumentSymbolClientCapabilities {
+                        hierarchical_document_symbol_support: Some(true),
+                        ..Default::default()
+                    }),
+                    code_action: Some(CodeActionClientCapabilities {
+                        code_action_literal_support: Some(CodeActionLiteralSupport {
+                            code_action_kind: CodeActionKindLiteralSupport {
+                                value_set: [
+                                    CodeActionKind::EMPTY,
+                                    CodeActionKind::QUICKFIX,
+                                    CodeActionKind::REFACTOR,
+                                    CodeActionKind::REFACTOR_EXTRACT,
+                                    CodeActionKind::REFACTOR_INLINE,
+                                    CodeActionKind::REFACTOR_REWRITE,
+                                    CodeActionKind::SOURCE,
+                                    CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                                    CodeActionKind::SOURCE_FIX_ALL,
+                                ]
+                                .iter()
+                                .map(|kind| kind.as_str().to_string())
+                                .collect(),
+                            },
+                        }),
+                        is_preferred_support: Some(true),
+                        data_support: Some(true),
+                        resolve_support: Some(CodeActionCapabilityResolveSupport {
+                            properties: vec!["edit".to_string()],
+                        }),
+                        ..Default::default()
+                    }),
+                    formatting: Some(DynamicRegistrationClientCapabilities::default()),
+                    range_formatting: Some(DynamicRegistrationClientCapabilities::default()),
+                    rename: Some(RenameClientCapabilities::default()),
+                    ..Default::default()
+                }),
+                workspace: Some(WorkspaceClientCapabilities {
+                    apply_edit: Some(true),
+                    workspace_edit: Some(WorkspaceEditClientCapabilities {
+                        document_changes: Some(true),
+                        ..Default::default()
+                    }),
+                    symbol: Some(WorkspaceSymbolClientCapabilities::default()),
+                    execute_command: Some(DynamicRegistrationClientCapabilities::default()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            workspace_folders: Some(vec![WorkspaceFolder {
+                uri,
+                name: root.file_name().map_or_else(
+                    || root.display().to_string(),
+                    |name| name.to_string_lossy().to_string(),
+                ),
+            }]),
+            client_info: Some(ClientInfo {
+                name: "cosmic-edit".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+            ..Default::default()
+        };
+        let result = self
+            .request::<request::Initialize>(params)
+            .await
+            .map_err(io::Error::other)?;
+        self.notify::<notification::Initialized>(InitializedParams {});
+        log_server_info(&self.0.name, &result);
+        let _ = self.0.capabilities.set(result.capabilities);
+        Ok(())
+    }
+
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        static DEFAULT: OnceLock<ServerCapabilities> = OnceLock::new();
+        self.0
+            .capabilities
+            .get()
+            .unwrap_or_else(|| DEFAULT.get_or_init(ServerCapabilities::default))
+    }
+
+    pub fn position_encoding(&self) -> LspPositionEncoding {
+        if self.capabilities().position_encoding == Some(PositionEncodingKind::UTF8) {
+            LspPositionEncoding::Utf8
+        } else {
+            LspPositionEncoding::Utf16
+        }
+    }
+
+    fn send(&self, message: Value) {
+        // Fails only if the server exited, which is handled by the owner of the client
+        let _ = self.0.sender.send(message);
+    }
+
+    pub fn notify<N: Notification>(&self, params: N::Params) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": N::METHOD,
+            "params": params,
+        }));
+    }
+
+    pub async fn request<R: Request>(&self, params: R::Params) -> Result<R::Result, String> {
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.0.pending.lock().unwrap().insert(id, sender);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": R::METHOD,
+            "params": params,
+        }));
+        let result = receiver
+            .await
+            .map_err(|_| format!("{} exited", self.0.name))??;
+        serde_json::from_value(result).map_err(|err| err.to_string())
+    }
+
+    /// Ask the server to exit, killing it if it does not in time
+    pub async fn shutdown(&self) {
+        match time::timeout(SHUTDOWN_TIMEOUT, self.request::<request::Shutdown>(())).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                log::warn!("failed to shut down {}: {}", self.0.name, err);
+            }
+            Err(_) => {
+                log::warn!(
+                    "{} did not shut down within {} seconds, killing it",
+                    self.0.name,
+                    SHUTDOWN_TIMEOUT.as_secs()
+                );
+                self.kill();
+                return;
+            }
+        }
+        self.notify::<notification::Exit>(());
+        let process_opt = self.0.process.lock().unwrap().take();
+        if let Some(mut process) = process_opt
+            && time::timeout(SHUTDOWN_TIMEOUT, process.wait())
+                .await
+                .is_err()
+        {
+            log::warn!("{} did not exit, killing it", self.0.name);
+        }
+    }
+
+    /// Kill the server process, requests still waiting fail once its output is closed
+    fn kill(&self) {
+        if let Some(mut process) = self.0.process.lock().unwrap().take()
+            && let Err(err) = process.start_kill()
+        {
+            log::warn!("failed to kill {}: {}", self.0.name, err);
+        }
+    }
+
+    fn sync_kind(&self) -> TextDocumentSyncKind {
+        match &self.capabilities().text_document_sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+            Some(TextDocumentSyncCapability::Options(options)) => {
+                options.change.unwrap_or(TextDocumentSyncKind::NONE)
+            }
+            None => TextDocumentSyncKind::NONE,
+        }
+    }
+
+    pub fn did_open(&self, uri: Uri, language_id: &str, version: i32, text: String) {
+        self.notify::<notification::DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri,
+                language_id: language_id.to_string(),
+                version,
+                text,
+            },
+        });
+    }
+
+    /// Send the full text of a document, which every server that takes changes supports
+    pub fn did_change(&self, uri: Uri, version: i32, text: String) {
+        if self.sync_kind() == TextDocumentSyncKind::NONE {
+            return;
+        }
+        self.notify::<notification::DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier { uri, version },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text,
+            }],
+        });
+    }
+
+    pub fn did_save(&self, uri: Uri, text: &str) {
+        let include_text = match &self.capabilities().text_document_sync {
+            Some(TextDocumentSyncCapability::Options(options)) => match &options.save {
+                Some(TextDocumentSyncSaveOptions::SaveOptions(save_options)) => {
+                    save_options.include_text.unwrap_or(false)
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        self.notify::<notification::DidSaveTextDocument>(DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+            text: include_text.then(|| text.to_string()),
+        });
+    }
+
+    pub fn did_close(&self, uri: Uri) {
+        self.notify::<notification::DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+        });
+    }
+
+    /// Characters that trigger completion when typed
+    pub fn completion_trigger_characters(&self) -> &[String] {
+        self.capabilities()
+            .completion_provider
+            .as_ref()
+            .and_then(|options| options.trigger_characters.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Characters that trigger signature help when typed, and characters that update it when
+    /// typed while it is shown
+    pub fn signature_help_trigger_characters(&self) -> (&[String], &[String]) {
+        match &self.capabilities().signature_help_provider {
+            Some(options) => (
+                options.trigger_characters.as_deref().unwrap_or_default(),
+                options.retrigger_characters.as_deref().unwrap_or_default(),
+            ),
+            None => (&[], &[]),
+        }
+    }
+
+    /// Completions at `position`, `trigger_character_opt` is the typed character that triggered
+    /// completion
+    pub async fn completion(
+        &self,
+        uri: Uri,
+        position: Position,
+        trigger_character_opt: Option<String>,
+    ) -> Result<Option<CompletionResponse>, String> {
+        if self.capabilities().completion_provider.is_none() {
+            return Ok(None);
+        }
+        let trigger_kind = if trigger_character_opt.is_some() {
+            CompletionTriggerKind::TRIGGER_CHARACTER
+        } else {
+            CompletionTriggerKind::INVOKED
+        };
+        self.request::<request::Completion>(CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: Some(CompletionContext {
+                trigger_kind,
+                trigger_character: trigger_character_opt,
+            }),
+        })
+        .await
+    }
+
+    /// Fill in the documentation and edits of a completion item if the server leaves them out of
+    /// completions
+    pub async fn resolve_completion_item(
+        &self,
+        item: CompletionItem,
+    ) -> Result<CompletionItem, String> {
+        let resolves = self
+            .capabilities()
+            .completion_provider
+            .as_ref()
+            .and_then(|options| options.resolve_provider)
+            .unwrap_or(false);
+        if !resolves {
+            return Ok(item);
+        }
+        self.request::<request::ResolveCompletionItem>(item).await
+    }
+
+    /// Signatures of the call at `position`, `active_opt` is the signature help shown before
+    pub async fn signature_help(
+        &self,
+        uri: Uri,
+        position: Position,
+        trigger_character_opt: Option<String>,
+        active_opt: Option<SignatureHelp>,
+    ) -> Result<Option<SignatureHelp>, String> {
+        if self.capabilities().signature_help_provider.is_none() {
+            return Ok(None);
+        }
+        let trigger_kind = if trigger_character_opt.is_some() {
+            SignatureHelpTriggerKind::TRIGGER_CHARACTER
+        } else {
+            SignatureHelpTriggerKind::CONTENT_CHANGE
+        };
+        self.request::<request::SignatureHelpRequest>(SignatureHelpParams {
+            context: Some(SignatureHelpContext {
+                trigger_kind,
+                trigger_character: trigger_character_opt,
+                is_retrigger: active_opt.is_some(),
+                active_signature_help: active_opt,
+            }),
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: Default::default(),
+        })
+        .await
+    }
+
+    /// Definitions of the symbol at `position`, `None` if the server does not find definitions
+    pub async fn definition(
+        &self,
+        uri: Uri,
+        position: Position,
+    ) -> Result<Option<Vec<Location>>, String> {
+        if !provides(&self.capabilities().definition_provider) {
+            return Ok(None);
+        }
+        self.request::<request::GotoDefinition>(goto_params(uri, position))
+            .await
+            .map(|response_opt| Some(goto_locations(response_opt)))
+    }
+
+    /// Definitions of the type of the symbol at `position`, `None` if the server does not find
+    /// type definitions
+    pub async fn type_definition(
+        &self,
+        uri: Uri,
+        position: Position,
+    ) -> Result<Option<Vec<Location>>, String> {
+        match self.capabilities().type_definition_provider {
+            Some(TypeDefinitionProviderCapability::Simple(true))
+            | Some(TypeDefinitionProviderCapability::Options(_)) => {}
+            _ => return Ok(None),
+        }
+        self.request::<request::GotoTypeDefinition>(goto_params(uri, position))
+            .await
+            .map(|response_opt| Some(goto_locations(response_opt)))
+    }
+
+    /// References to the symbol at `position` including its declaration, `None` if the server
+    /// does not find references
+    pub async fn references(
+        &self,
+        uri: Uri,
+        position: Position,
+    ) -> Result<Option<Vec<Location>>, String> {
+        if !provides(&self.capabilities().references_provider) {
+            return Ok(None);
+        }
+        self.request::<request::References>(ReferenceParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration: true,
+            },
+        })
+        .await
+        .map(|locations_opt| Some(locations_opt.unwrap_or_default()))
+    }
+
+    /// Symbols of a document, `None` if the server does not list symbols
+    pub async fn document_symbols(
+        &self,
+        uri: Uri,
+    ) -> Result<Option<DocumentSymbolResponse>, String> {
+        if !provides(&self.capabilities().document_symbol_provider) {
+            return Ok(None);
+        }
+        self.request::<request::DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .await
+        .map(|response_opt| Some(response_opt.unwrap_or(DocumentSymbolResponse::Flat(Vec::new()))))
+    }
+
+    /// Symbols of the workspace matching `query`, `None` if the server does not search symbols
+    pub async fn workspace_symbols(
+        &self,
+        query: String,
+    ) -> Result<Option<WorkspaceSymbolResponse>, String> {
+        if !provides(&self.capabilities().workspace_symbol_provider) {
+            return Ok(None);
+        }
+        self.request::<request::WorkspaceSymbolRequest>(WorkspaceSymbolParams {
+            query,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .await
+        .map(|response_opt| Some(response_opt.unwrap_or(WorkspaceSymbolResponse::Flat(Vec::new()))))
+    }
+
+    /// Edits renaming the symbol at `position` to `new_name`, `None` if the server does not rename
+    /// symbols
+    pub async fn rename(
+        &self,
+        uri: Uri,
+        position: Position,
+        new_name: String,
+    ) -> Result<Option<WorkspaceEdit>, String> {
+        if !provides(&self.capabilities().rename_provider) {
+            return Ok(None);
+        }
+        self.request::<request::Rename>(RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            new_name,
+            work_done_progress_params: Default::default(),
+        })
+        .await
+        .map(|edit_opt| Some(edit_opt.unwrap_or_default()))
+    }
+
+    /// Code actions for `range`, which fix `diagnostics` among others, `None` if the server has no
+    /// code actions
+    pub async fn code_actions(
+        &self,
+        uri: Uri,
+        range: Range,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Result<Option<Vec<CodeActionOrCommand>>, String> {
+        match self.capabilities().code_action_provider {
+            Some(CodeActionProviderCapability::Simple(true))
+            | Some(CodeActionProviderCapability::Options(_)) => {}
+            _ => return Ok(None),
+        }
+        self.request::<request::CodeActionRequest>(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri },
+            range,
+            context: CodeActionContext {
+                diagnostics,
+                only: None,
+                trigger_kind: Some(CodeActionTriggerKind::INVOKED),
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .await
+        .map(|actions_opt| Some(actions_opt.unwrap_or_default()))
+    }
+
+    /// Fill in the edit of a code action if the server leaves it out of code actions
+    pub async fn resolve_code_action(&self, action: CodeAction) -> Result<CodeAction, String> {
+        let resolves = match &self.capabilities().code_action_provider {
+            Some(CodeActionProviderCapability::Options(options)) => {
+                options.resolve_provider.unwrap_or(false)
+            }
+            _ => false,
+        };
+        if action.edit.is_some() || !resolves {
+            return Ok(action);
+        }
+        self.request::<request::CodeActionResolveRequest>(action)
+            .await
+    }
+
+    /// Run a command on the server, which may send edits to apply while it runs
+    pub async fn execute_command(&self, command: lsp_types::Command) -> Result<(), String> {
+        self.request::<request::ExecuteCommand>(ExecuteCommandParams {
+            command: command.command,
+            arguments: command.arguments.unwrap_or_default(),
+            work_done_progress_params: Default::default(),
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Edits formatting a document, or only `range_opt` of it, `None` if the server does not
+    /// format documents
+    pub async fn formatting(
+        &self,
+        uri: Uri,
+        range_opt: Option<Range>,
+        options: FormattingOptions,
+    ) -> Result<Option<Vec<TextEdit>>, String> {
+        let text_document = TextDocumentIdentifier { uri };
+        let work_done_progress_params = Default::default();
+        let result = match range_opt {
+            Some(range) => {
+                if !provides(&self.capabilities().document_range_formatting_provider) {
+                    return Ok(None);
+                }
+                self.request::<request::RangeFormatting>(DocumentRangeFormattingParams {
+                    text_document,
+                    range,
+                    options,
+                    work_done_progress_params,
+                })
+                .await
+            }
+            None => {
+                if !provides(&self.capabilities().document_formatting_provider) {
+                    return Ok(None);
+                }
+                self.request::<request::Formatting>(DocumentFormattingParams {
+                    text_document,
+                    options,
+                    work_done_progress_params,
+                })
+                .await
+            }
+        };
+        result.map(|edits_opt| Some(edits_opt.unwrap_or_default()))
+    }
+}
+
+/// Check if a server capability is enabled
+fn provides<T>(capability: &Option<OneOf<bool, T>>) -> bool {
+    matches!(capability, Some(OneOf::Left(true)) | Some(OneOf::Right(_)))
+}
+
+fn goto_params(uri: Uri, position: Position) -> GotoDefinitionParams {
+    GotoDefinitionParams {
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position,
+        },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    }
+}
+
+/// Locations of a definition response, which may be links
+fn goto_locations(response_opt: Option<GotoDefinitionResponse>) -> Vec<Location> {
+    match response_opt {
+        Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+        Some(GotoDefinitionResponse::Array(locations)) => locations,
+        Some(GotoDefinitionResponse::Link(links)) => links
+            .into_iter()
+            .map(|link| Location::new(link.target_uri, link.target_selection_range))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn log_server_info(name: &str, result: &InitializeResult) {
+    match &result.server_info {
+        Some(info) => log::info!(
+            "started {}: {} {}",
+            name,
+            info.name,
+            info.version.as_deref().unwrap_or_default()
+        ),
+        None => log::info!("started {}", name),
+    }
+}
+
+fn frame(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    let mut data = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    data.extend_from_slice(body.as_bytes());
+    data
+}
+
+/// Read one message, `None` at the end of the stream
+async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length_opt = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            content_length_opt = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(content_length) = content_length_opt else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing content length",
+        ));
+    };
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn handle_message(
+    name: &str,
+    message: Value,
+    sender: &mpsc::UnboundedSender<Value>,
+    pending: &Pending,
+    event_sender: &mpsc::UnboundedSender<LspEvent>,
+) {
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        // Response to a request of the client
+        let Some(id) = message.get("id").and_then(Value::as_i64) else {
+            return;
+        };
+        if let Some(reply) = pending.lock().unwrap().remove(&id) {
+            let result = match message.get("error") {
+                Some(error) => Err(error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown error")
+                    .to_string()),
+                None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+            };
+            let _ = reply.send(result);
+        }
+        return;
+    };
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    if let Some(id) = message.get("id") {
+        // Requests of the server are answered with empty results, as the client registers no
+        // capabilities dynamically and has no settings. Edits are reported as applied once they
+        // are passed on, failures are shown by the app.
+        let reply = match method {
+            request::ApplyWorkspaceEdit::METHOD => {
+                let response = match serde_json::from_value::<
+                    <request::ApplyWorkspaceEdit as Request>::Params,
+                >(params)
+                {
+                    Ok(params) => ApplyWorkspaceEditResponse {
+                        applied: event_sender.send(LspEvent::ApplyEdit(params.edit)).is_ok(),
+                        failure_reason: None,
+                        failed_change: None,
+                    },
+                    Err(err) => ApplyWorkspaceEditResponse {
+                        applied: false,
+                        failure_reason: Some(err.to_string()),
+                        failed_change: None,
+                    },
+                };
+                json!({ "jsonrpc": "2.0", "id": id, "result": response })
+            }
+            request::WorkspaceConfiguration::METHOD => {
+                let len = params
+                    .get("items")
+                    .and_then(Value::as_array)
+                    .map_or(0, Vec::len);
+                json!({ "jsonrpc": "2.0", "id": id, "result": vec![Value::Null; len] })
+            }
+            request::RegisterCapability::METHOD
+            | request::UnregisterCapability::METHOD
+            | request::WorkDoneProgressCreate::METHOD
+            | request::WorkspaceDiagnosticRefresh::METHOD => {
+                json!({ "jsonrpc": "2.0", "id": id, "result": null })
+            }
+            _ => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("{} is not supported", method) },
+            }),
+        };
+        let _ = sender.send(reply);
+        return;
+    }
+
+    match method {
+        notification::PublishDiagnostics::METHOD => match serde_json::from_value(params) {
+            Ok(params) => {
+                let _ = event_sender.send(LspEvent::Diagnostics(params));
+            }
+            Err(err) => log::warn!("invalid diagnostics from {}: {}", name, err),
+        },
+        notification::LogMessage::METHOD | notification::ShowMessage::METHOD => {
+            if let Some(text) = params.get("message").and_then(Value::as_str) {
+                log::info!("{}: {}", name, text);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::block_on;
+    use lsp_types::{Diagnostic, Range};
+
+    #[test]
+    fn position_encoding() {
+        let line = "a\u{e9}\u{1f600}b";
+        for (index, utf16) in [(0, 0), (1, 1), (3, 2), (7, 4), (8, 5)] {
+            assert_eq!(LspPositionEncoding::Utf16.index(line, utf16), index);
+            assert_eq!(LspPositionEncoding::Utf8.index(line, index as u32), index);
+            assert_eq!(LspPositionEncoding::Utf16.column(line, index), utf16);
+            assert_eq!(LspPositionEncoding::Utf8.column(line, index), index as u32);
+        }
+        // Columns within characters and past the end are clamped
+        assert_eq!(LspPositionEncoding::Utf16.index(line, 3), 3);
+        assert_eq!(LspPositionEncoding::Utf8.index(line, 2), 1);
+        assert_eq!(LspPositionEncoding::Utf16.index(line, 100), line.len());
+    }
+
+    #[test]
+    fn root() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(project.join("Cargo.toml"), "").unwrap();
+        let config = &LspServerConfig::defaults()[0];
+        let file = project.join("src").join("main.rs");
+        assert_eq!(config.language_id(&file), Some("rust"));
+        assert_eq!(config.language_id(&project.join("README.md")), None);
+        assert_eq!(config.root(&file, None), project);
+        assert_eq!(config.root(&file, Some(dir.path())), dir.path());
+        let other = dir.path().join("other.rs");
+        assert_eq!(config.root(&other, None), dir.path());
+    }
+
+    /// Stub server that answers the initialize request, publishes an error at the start of every
+    /// opened document, completes `main`, finds its definition, renames it and formats documents
+    async fn stub_server(stream: tokio::io::DuplexStream) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        while let Ok(Some(message)) = read_message(&mut reader).await {
+            let reply = match message["method"].as_str() {
+                Some(request::Initialize::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": {
+                        "capabilities": {
+                            "positionEncoding": "utf-8",
+                            "textDocumentSync": 1,
+                            "completionProvider": { "triggerCharacters": ["."], "resolveProvider": true },
+                            "signatureHelpProvider": { "triggerCharacters": ["(", ","] },
+                            "definitionProvider": true,
+                            "referencesProvider": true,
+                            "renameProvider": true,
+                            "codeActionProvider": { "resolveProvider": true },
+                            "documentFormattingProvider": true,
+                        },
+                    },
+                }),
+                Some(request::Completion::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": {
+                        "isIncomplete": false,
+                        "items": [{ "label": "main", "insertText": "main($1)$0", "insertTextFormat": 2 }],
+                    },
+                }),
+                Some(request::ResolveCompletionItem::METHOD) => {
+                    let mut item = message["params"].clone();
+                    item["documentation"] = json!("The entry point");
+                    json!({ "jsonrpc": "2.0", "id": message["id"], "result": item })
+                }
+                Some(request::SignatureHelpRequest::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": {
+                        "signatures": [{ "label": "fn main()", "parameters": [] }],
+                        "activeParameter": message["params"]["position"]["character"],
+                    },
+                }),
+                Some(request::GotoDefinition::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": [{
+                        "targetUri": message["params"]["textDocument"]["uri"],
+                        "targetRange": Range::new(Position::new(0, 0), Position::new(0, 12)),
+                        "targetSelectionRange": Range::new(Position::new(0, 3), Position::new(0, 7)),
+                    }],
+                }),
+                Some(request::References::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": null,
+                }),
+                Some(request::Rename::METHOD) => {
+                    let uri = message["params"]["textDocument"]["uri"].as_str().unwrap();
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "result": {
+                            "changes": {
+                                uri: [{
+                                    "range": Range::new(Position::new(0, 3), Position::new(0, 7)),
+                                    "newText": message["params"]["newName"],
+                                }],
+                            },
+                        },
+                    })
+                }
+                Some(request::CodeActionRequest::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": [{ "title": "Fix", "kind": "quickfix", "data": 1 }],
+                }),
+                Some(request::CodeActionResolveRequest::METHOD) => {
+                    let mut action = message["params"].clone();
+                    action["command"] = json!({ "title": "Fix", "command": "fix" });
+                    json!({ "jsonrpc": "2.0", "id": message["id"], "result": action })
+                }
+                Some(request::ExecuteCommand::METHOD) => {
+                    // The command edits through the client before it finishes
+                    writer
+                        .write_all(&frame(&json!({
+                            "jsonrpc": "2.0",
+                            "id": "edit",
+                            "method": request::ApplyWorkspaceEdit::METHOD,
+                            "params": { "edit": { "changes": {} } },
+                        })))
+                        .await
+                        .unwrap();
+                    json!({ "jsonrpc": "2.0", "id": message["id"], "result": null })
+                }
+                Some(request::Formatting::METHOD) => json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": [{
+                        "range": Range::new(Position::new(0, 0), Position::new(0, 0)),
+                        "newText": "// main\n",
+                    }],
+                }),
+                Some(notification::DidOpenTextDocument::METHOD) => {
+                    let params = PublishDiagnosticsParams {
+                        uri: message["params"]["textDocument"]["uri"]
+                            .as_str()
+                            .unwrap()
+                            .parse()
+                            .unwrap(),
+                        diagnostics: vec![Diagnostic {
+                            range: Range::new(Position::new(0, 0), Position::new(0, 1)),
+                            severity: Some(DiagnosticSeverity::ERROR),
+                            message: "stub error".to_string(),
+                            ..Default::default()
+                        }],
+                        version: None,
+                    };
+                    // Configuration is requested before publishing, like many servers do
+                    writer
+                        .write_all(&frame(&json!({
+                            "jsonrpc": "2.0",
+                            "id": "config",
+                            "method": request::WorkspaceConfiguration::METHOD,
+                            "params": { "items": [{}, {}] },
+                        })))
+                        .await
+                        .unwrap();
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": notification::PublishDiagnostics::METHOD,
+                        "params": params,
+                    })
+                }
+                Some(request::Shutdown::METHOD) => {
+                    json!({ "jsonrpc": "2.0", "id": message["id"], "result": null })
+                }
+                Some(notification::Exit::METHOD) => break,
+                _ => continue,
+            };
+            writer.write_all(&frame(&reply)).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn client() {
+        block_on(async {
+            let (client_stream, server_stream) = tokio::io::duplex(4096);
+            let server = tokio::spawn(stub_server(server_stream));
+            let (reader, writer) = tokio::io::split(client_stream);
+            let (exit_sender, exit_receiver) = oneshot::channel();
+            let (client, mut events) =
+                LspClient::connect("stub", reader, writer, None, move || {
+                    let _ = exit_sender.send(());
+                });
+            client.initialize(Path::new("/tmp")).await.unwrap();
+            assert_eq!(client.position_encoding(), LspPositionEncoding::Utf8);
+
+            let uri = path_uri(Path::new("/tmp/main.rs")).unwrap();
+            assert_eq!(uri_path(&uri).unwrap(), Path::new("/tmp/main.rs"));
+            client.did_open(uri.clone(), "rust", 1, "fn main() {}\n".to_string());
+            let Some(LspEvent::Diagnostics(params)) = events.recv().await else {
+                panic!("no diagnostics published");
+            };
+            assert_eq!(params.uri, uri);
+            assert_eq!(params.diagnostics[0].message, "stub error");
+
+            assert_eq!(client.completion_trigger_characters(), ["."]);
+            let Some(CompletionResponse::List(list)) = client
+                .completion(uri.clone(), Position::new(0, 3), None)
+                .await
+                .unwrap()
+            else {
+                panic!("no completion list");
+            };
+            assert_eq!(list.items[0].label, "main");
+            let item = client
+                .resolve_completion_item(list.items[0].clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                item.documentation,
+                Some(lsp_types::Documentation::String(
+                    "The entry point".to_string()
+                ))
+            );
+            assert_eq!(client.signature_help_trigger_characters().0, ["(", ","]);
+            let help = client
+                .signature_help(
+                    uri.clone(),
+                    Position::new(0, 8),
+                    Some("(".to_string()),
+                    None,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(help.signatures[0].label, "fn main()");
+            assert_eq!(help.active_parameter, Some(8));
+
+            assert_eq!(
+                client.definition(uri.clone(), Position::new(0, 4)).await,
+                Ok(Some(vec![Location::new(
+                    uri.clone(),
+                    Range::new(Position::new(0, 3), Position::new(0, 7))
+                )]))
+            );
+            assert_eq!(
+                client.references(uri.clone(), Position::new(0, 4)).await,
+                Ok(Some(Vec::new()))
+            );
+            assert_eq!(
+                client
+                    .type_definition(uri.clone(), Position::new(0, 4))
+                    .await,
+                Ok(None)
+            );
+            assert_eq!(client.workspace_symbols("main".to_string()).await, Ok(None));
+
+            let edit = client
+                .rename(uri.clone(), Position::new(0, 4), "start".to_string())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(edit.changes.unwrap()[&uri][0].new_text, "start");
+            let range = Range::new(Position::new(0, 0), Position::new(0, 1));
+            let actions = client
+                .code_actions(uri.clone(), range, params.diagnostics.clone())
+                .await
+                .unwrap()
+                .unwrap();
+            let [CodeActionOrCommand::CodeAction(action)] = actions.as_slice() else {
+                panic!("no code action");
+            };
+            let action = client.resolve_code_action(action.clone()).await.unwrap();
+            assert_eq!(action.title, "Fix");
+            client
+                .execute_command(action.command.unwrap())
+                .await
+                .unwrap();
+            let Some(LspEvent::ApplyEdit(edit)) = events.recv().await else {
+                panic!("no edit applied");
+            };
+            assert_eq!(edit.changes.unwrap().len(), 0);
+            let edits = client
+                .formatting(uri.clone(), None, FormattingOptions::default())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(edits[0].new_text, "// main\n");
+            assert_eq!(
+                client
+                    .formatting(uri.clone(), Some(range), FormattingOptions::default())
+                    .await,
+                Ok(None)
+            );
+
+            client.shutdown().await;
+            server.await.unwrap();
+            exit_receiver.await.unwrap();
+            assert!(events.recv().await.is_none());
+        });
+    }
+
+    #[test]
+    fn unresponsive_server() {
+        block_on(async {
+            let mut child = Command::new("sleep")
+                .arg("60")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .unwrap();
+            let (stdin, stdout) = (child.stdin.take().unwrap(), child.stdout.take().unwrap());
+            let (exit_sender, exit_receiver) = oneshot::channel();
+            let (client, _events) = LspClient::connect("sleep", stdout, stdin, Some(child), || {
+                let _ = exit_sender.send(());
+            });
+
+            // Servers that do not answer are killed
+            client.shutdown().await;
+            time::timeout(Duration::from_secs(5), exit_receiver)
+                .await
+                .unwrap()
+                .unwrap();
+        });
+    }
+}
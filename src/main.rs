@@ -11,12 +11,14 @@ use cosmic::{
     cosmic_theme, executor,
     font::Font,
     iced::{
-        self, Alignment, Background, Color, Length, Limits, Point, Subscription,
+        self, Alignment, Background, Border, Color, Length, Limits, Point, Subscription,
         advanced::graphics::text::font_system,
         clipboard, event,
-        futures::{self, SinkExt},
+        futures::{self, SinkExt, StreamExt},
         keyboard::{self, Modifiers},
-        stream, window,
+        stream,
+        widget::text_editor,
+        window,
     },
     style, theme,
     widget::{self, about::About, button, icon, nav_bar, segmented_button},
@@ -31,20 +33,38 @@ use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 use std::{
     any::TypeId,
-    collections::{HashMap, HashSet},
-    env, fs, io,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    env, fs, io, mem,
     path::{self, Path, PathBuf},
     process,
-    sync::{Mutex, OnceLock},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::SystemTime,
 };
 use tokio::time;
 use unicode_segmentation::UnicodeSegmentation;
 
+use completion::{CompletionList, active_signature, documentation_text, is_word_char};
+mod completion;
+
 use config::{AppTheme, CONFIG_VERSION, Config, ConfigState};
 mod config;
 
-use git::{GitDiff, GitDiffLine, GitRepository, GitStatus, GitStatusKind};
+use formatter::{FormatError, FormatterConfig};
+mod formatter;
+
+use git::{
+    GitBlame, GitBlameCommit, GitConflictChoice, GitDiff, GitDiffHunk, GitDiffLine,
+    GitFileRevision, GitHead, GitHunkAction, GitProject, GitProjectKind, GitRepository, GitStash,
+    GitStatusKind, conflicts, trim_line_ending,
+};
 mod git;
+#[cfg(unix)]
+mod git_askpass;
+#[cfg(feature = "git2")]
+mod git_native;
 
 use icon_cache::IconCache;
 mod icon_cache;
@@ -55,23 +75,47 @@ mod key_bind;
 use line_number::LineNumberCache;
 mod line_number;
 
+use local_history::{LocalHistory, LocalHistorySave, LocalSnapshot, stable_hash};
+mod local_history;
+
 mod localize;
 
+use lsp::{LspClient, LspDocument, LspEvent, LspPositionEncoding, LspServerConfig, LspServerKey};
+mod lsp;
+
 use self::menu::menu_bar;
 mod menu;
 
-use self::project::ProjectNode;
+use navigation::{NavItem, NavRequest, TagsCache};
+mod navigation;
+
+use self::project::{ProjectGitStatus, ProjectGitStatuses, ProjectNode};
 mod project;
 
-use self::search::ProjectSearchResult;
+use self::search::{
+    BufferSearchInput, FileSearchKey, FileSearchResult, LineSearchResult, ProjectSearchOptions,
+    ProjectSearchResult,
+};
 mod search;
 
-use self::tab::{EditorTab, GitDiffTab, Tab};
+use self::tab::{
+    EditorRevision, EditorTab, GIT_DIFF_CONTEXT_LINES, GitDiffSpan, GitDiffTab, Tab, file_title,
+    git_diff_spans,
+};
 mod tab;
 
-use self::text_box::text_box;
+#[cfg(test)]
+mod test_util;
+
+use self::text_box::{CompletionKey, CompletionTrigger, text_box};
 mod text_box;
 
+use undo_history::UndoHistorySave;
+mod undo_history;
+
+use workspace_edit::FileChange;
+mod workspace_edit;
+
 static ICON_CACHE: OnceLock<Mutex<IconCache>> = OnceLock::new();
 static LINE_NUMBER_CACHE: OnceLock<Mutex<LineNumberCache>> = OnceLock::new();
 static SWASH_CACHE: OnceLock<Mutex<SwashCache>> = OnceLock::new();
@@ -88,7 +132,180 @@ pub fn monospace_attrs() -> cosmic_text::Attrs<'static> {
     cosmic_text::Attrs::new().family(Family::Monospace)
 }
 
+/// Highlight of the selected item of a popup at the cursor
+fn popup_selected_style(theme: &cosmic::Theme) -> widget::container::Style {
+    let mut color: Color = theme.cosmic().accent_color().into();
+    color.a = 0.3;
+    widget::container::Style {
+        background: Some(Background::Color(color)),
+        ..Default::default()
+    }
+}
+
+/// Describe how long ago `time`, in seconds since the Unix epoch, was
+fn relative_time(time: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let minutes = now.saturating_sub(time) / 60;
+    let hours = minutes / 60;
+    let days = hours / 24;
+    if days >= 365 {
+        fl!("time-years-ago", count = days / 365)
+    } else if days >= 30 {
+        fl!("time-months-ago", count = days / 30)
+    } else if days > 0 {
+        fl!("time-days-ago", count = days)
+    } else if hours > 0 {
+        fl!("time-hours-ago", count = hours)
+    } else if minutes > 0 {
+        fl!("time-minutes-ago", count = minutes)
+    } else {
+        fl!("time-just-now")
+    }
+}
+
+/// Log a failed git operation and show it in the UI
+fn git_error(context: String, err: impl std::fmt::Display) -> action::Action<Message> {
+    let msg = format!("{}: {}", context, err);
+    log::error!("{}", msg);
+    action::app(Message::GitError(msg))
+}
+
+/// Run a git operation on the repository containing `path` in the background, showing an error
+/// if there is no repository
+fn git_task<F, Fut>(path: PathBuf, f: F) -> Task<Message>
+where
+    F: FnOnce(GitRepository) -> Fut + Send + 'static,
+    Fut: Future<Output = action::Action<Message>> + Send + 'static,
+{
+    Task::perform(
+        async move {
+            match GitRepository::containing(&path).await {
+                Ok(repo) => f(repo).await,
+                Err(err) => git_error(format!("failed to open repository of {:?}", path), err),
+            }
+        },
+        |x| x,
+    )
+}
+
+/// Store the undo histories of closed tabs in the background
+async fn write_undo_histories(undo_history_saves: Vec<UndoHistorySave>) {
+    if undo_history_saves.is_empty() {
+        return;
+    }
+    let result = tokio::task::spawn_blocking(move || {
+        for undo_history_save in undo_history_saves {
+            undo_history_save.write();
+        }
+    })
+    .await;
+    if let Err(err) = result {
+        log::warn!("failed to save undo histories: {}", err);
+    }
+}
+
+/// Load everything shown for a repository in git management
+async fn git_project(
+    name: String,
+    repo: &GitRepository,
+    kind: GitProjectKind,
+) -> Result<GitProject, String> {
+    let path = repo.path();
+    let status = repo
+        .status()
+        .await
+        .map_err(|err| format!("failed to get status of {:?}: {}", path, err))?;
+    let commits = if repo.has_commits().await {
+        match repo.log(5).await {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::error!("failed to get log of {:?}: {}", path, err);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let head_opt = match repo.head().await {
+        Ok(ok) => Some(ok),
+        Err(err) => {
+            log::error!("failed to get HEAD of {:?}: {}", path, err);
+            None
+        }
+    };
+    let branches = match repo.branches().await {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::error!("failed to get branches of {:?}: {}", path, err);
+            Vec::new()
+        }
+    };
+    let stashes = match repo.stashes().await {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::error!("failed to get stashes of {:?}: {}", path, err);
+            Vec::new()
+        }
+    };
+    let ignored = match repo.ignored().await {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::error!("failed to get ignored files of {:?}: {}", path, err);
+            Vec::new()
+        }
+    };
+    let ahead_behind_opt = match repo.ahead_behind().await {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::error!("failed to compare {:?} with its upstream: {}", path, err);
+            None
+        }
+    };
+    Ok(GitProject {
+        name,
+        path: path.to_path_buf(),
+        kind,
+        status,
+        commits,
+        head_opt,
+        branches,
+        stashes,
+        ignored,
+        ahead_behind_opt,
+    })
+}
+
+/// Label the first line of each run of lines from the same commit for the blame gutter
+fn git_blame_labels(blame: &GitBlame) -> Vec<String> {
+    let mut labels = Vec::with_capacity(blame.lines.len());
+    let mut last_commit_i_opt = None;
+    for commit_i in blame.lines.iter() {
+        if last_commit_i_opt == Some(commit_i) {
+            labels.push(String::new());
+            continue;
+        }
+        last_commit_i_opt = Some(commit_i);
+
+        labels.push(match blame.commits.get(*commit_i) {
+            Some(commit) if commit.is_committed() => format!(
+                "{} {} {}",
+                commit.short_hash,
+                relative_time(commit.time),
+                commit.author
+            ),
+            _ => fl!("git-blame-uncommitted"),
+        });
+    }
+    labels
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Git runs cosmic-edit to ask for credentials, which has to happen before daemonizing
+    #[cfg(unix)]
+    git_askpass::helper();
+
     #[cfg(all(unix, not(target_os = "redox")))]
     match fork::daemon(true, true) {
         Ok(fork::Fork::Child) => (),
@@ -197,10 +414,20 @@ pub enum Action {
     About,
     CloseFile,
     CloseProject(usize),
+    CompareSelect,
+    CompareWithClipboard,
+    CompareWithSaved,
+    CompareWithSelected,
     Copy,
     Cut,
+    DocumentSymbols,
     Find,
     FindAndReplace,
+    FindReferences,
+    FormatDocument,
+    FormatSelection,
+    GoToDefinition,
+    GoToTypeDefinition,
     NewFile,
     NewWindow,
     OpenFileDialog,
@@ -210,6 +437,7 @@ pub enum Action {
     Paste,
     Quit,
     Redo,
+    Rename,
     RevertAllChanges,
     Save,
     SaveAsDialog,
@@ -228,13 +456,18 @@ pub enum Action {
     TabWidth(u16),
     ToggleAutoIndent,
     ToggleDocumentStatistics,
+    ToggleGitBlame,
+    ToggleGitFileHistory,
     ToggleGitManagement,
     ToggleHighlightCurrentLine,
     ToggleLineNumbers,
+    ToggleLocalHistory,
+    ToggleProblems,
     ToggleProjectSearch,
     ToggleSettingsPage,
     ToggleWordWrap,
     Undo,
+    WorkspaceSymbols,
     ZoomIn,
     ZoomOut,
     ZoomReset,
@@ -247,10 +480,20 @@ impl Action {
             Self::About => Message::ToggleContextPage(ContextPage::About),
             Self::CloseFile => Message::CloseFile,
             Self::CloseProject(project_i) => Message::CloseProject(*project_i),
+            Self::CompareSelect => Message::CompareSelect,
+            Self::CompareWithClipboard => Message::CompareWithClipboard,
+            Self::CompareWithSaved => Message::CompareWithSaved,
+            Self::CompareWithSelected => Message::CompareWithSelected,
             Self::Copy => Message::Copy,
             Self::Cut => Message::Cut,
+            Self::DocumentSymbols => Message::SymbolSearchOpen(false),
             Self::Find => Message::Find(Some(false)),
             Self::FindAndReplace => Message::Find(Some(true)),
+            Self::FindReferences => Message::Navigate(NavRequest::References),
+            Self::FormatDocument => Message::LspFormat(false),
+            Self::FormatSelection => Message::LspFormat(true),
+            Self::GoToDefinition => Message::Navigate(NavRequest::Definition),
+            Self::GoToTypeDefinition => Message::Navigate(NavRequest::TypeDefinition),
             Self::NewFile => Message::NewFile,
             Self::NewWindow => Message::NewWindow,
             Self::OpenFileDialog => Message::OpenFileDialog,
@@ -260,6 +503,7 @@ impl Action {
             Self::Paste => Message::Paste,
             Self::Quit => Message::Quit,
             Self::Redo => Message::Redo,
+            Self::Rename => Message::RenameOpen,
             Self::RevertAllChanges => Message::RevertAllChanges,
             Self::Save => Message::Save(entity_opt),
             Self::SaveAsDialog => Message::SaveAsDialog(entity_opt),
@@ -280,13 +524,18 @@ impl Action {
             Self::ToggleDocumentStatistics => {
                 Message::ToggleContextPage(ContextPage::DocumentStatistics)
             }
+            Self::ToggleGitBlame => Message::ToggleGitBlame,
+            Self::ToggleGitFileHistory => Message::ToggleContextPage(ContextPage::GitFileHistory),
             Self::ToggleGitManagement => Message::ToggleContextPage(ContextPage::GitManagement),
             Self::ToggleHighlightCurrentLine => Message::ToggleHighlightCurrentLine,
             Self::ToggleLineNumbers => Message::ToggleLineNumbers,
+            Self::ToggleLocalHistory => Message::ToggleContextPage(ContextPage::LocalHistory),
+            Self::ToggleProblems => Message::ToggleContextPage(ContextPage::Problems),
             Self::ToggleProjectSearch => Message::ToggleContextPage(ContextPage::ProjectSearch),
             Self::ToggleSettingsPage => Message::ToggleContextPage(ContextPage::Settings),
             Self::ToggleWordWrap => Message::ToggleWordWrap,
             Self::Undo => Message::Undo,
+            Self::WorkspaceSymbols => Message::SymbolSearchOpen(true),
             Self::ZoomIn => Message::ZoomIn,
             Self::ZoomOut => Message::ZoomOut,
             Self::ZoomReset => Message::ZoomReset,
@@ -301,6 +550,33 @@ impl MenuAction for Action {
     }
 }
 
+/// Actions of the project tree context menu
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NavMenuAction {
+    CompareSelect(nav_bar::Id),
+    CompareWithSelected(nav_bar::Id),
+    ToggleFormatters(nav_bar::Id),
+}
+
+impl MenuAction for NavMenuAction {
+    type Message = action::Action<Message>;
+    fn message(&self) -> Self::Message {
+        action::app(Message::NavMenuAction(*self))
+    }
+}
+
+/// One side of a compare
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CompareSide {
+    /// Text of an editor tab, including unsaved changes
+    Tab(segmented_button::Entity),
+    /// Saved text of a file
+    File(PathBuf),
+    Clipboard(String),
+    /// Snapshot from the local history
+    Snapshot(LocalSnapshot),
+}
+
 #[derive(Clone, Debug)]
 pub struct Flags {
     config_handler: Option<cosmic_config::Config>,
@@ -341,6 +617,12 @@ pub enum Message {
     CloseFile,
     CloseProject(usize),
     CloseWindow(window::Id),
+    Compare(CompareSide, CompareSide),
+    CompareOpen(String, GitDiff, String, String),
+    CompareSelect,
+    CompareWithClipboard,
+    CompareWithSaved,
+    CompareWithSelected,
     Copy,
     Cut,
     DefaultFont(usize),
@@ -361,37 +643,167 @@ pub enum Message {
     FindReplaceValueChanged(String),
     FindSearchValueChanged(String),
     FindUseRegex(bool),
+    FindWholeWord(bool),
     FindWrapAround(bool),
     Focus(window::Id),
-    GitProjectStatus(Vec<(String, PathBuf, Vec<GitStatus>)>),
+    FormatOnSave(bool),
+    FormatterErrorClose,
+    FormatterResult(
+        segmented_button::Entity,
+        u64,
+        bool,
+        Result<String, FormatError>,
+    ),
+    GitAskpass(String, GitAskpassReply),
+    GitAskpassInput(String),
+    GitAskpassSubmit,
+    GitBlame(
+        segmented_button::Entity,
+        u64,
+        (String, Arc<GitBlame>),
+        GitBlame,
+    ),
+    GitBlameHover(segmented_button::Entity, Option<(usize, Point)>),
+    GitBlameOpenCommit(segmented_button::Entity, usize),
+    GitBranchCheckout(PathBuf, String),
+    GitBranchCreate(PathBuf),
+    GitBranchDelete(PathBuf, String),
+    GitBranchFinished(PathBuf, Result<String, String>, bool),
+    GitBranchName(PathBuf, String),
+    GitBranchPicker(PathBuf),
+    GitBranchSwitch(PathBuf, String),
+    GitBufferDiff(segmented_button::Entity, u64, GitDiff),
+    GitBufferDiffUpdate(segmented_button::Entity, u64),
+    GitDiffApply(segmented_button::Entity, usize, GitHunkAction),
+    GitDiffContext(segmented_button::Entity),
+    GitDiffDiscard(segmented_button::Entity, usize),
+    GitDiffDiscarded(segmented_button::Entity, GitDiff),
+    GitDiffHunk(segmented_button::Entity, usize),
+    GitDiffSelect(segmented_button::Entity, usize, usize),
+    GitDiffSideBySide(bool),
+    GitDiffSpans(segmented_button::Entity, u64, Vec<Vec<Vec<GitDiffSpan>>>),
+    GitDiffUpdate(segmented_button::Entity, GitDiff),
+    GitDir(PathBuf, PathBuf),
+    GitDiscard(PathBuf, PathBuf, bool),
+    GitDiscardFinished(PathBuf),
+    GitDiscardForce(PathBuf, PathBuf, bool),
+    GitError(String),
+    GitErrorClose,
+    GitFileHistory(PathBuf, Result<(PathBuf, Vec<GitFileRevision>), String>),
+    GitFileHistoryDiff(usize),
+    GitFileHistoryOpen(usize),
+    GitHunkClose(segmented_button::Entity),
+    GitHunkRevert(segmented_button::Entity, usize),
+    GitIndexText(segmented_button::Entity, PathBuf, Option<String>),
+    GitMarker(segmented_button::Entity, usize),
+    GitCommit(PathBuf),
+    GitCommitAction(PathBuf, text_editor::Action),
+    GitCommitAmend(PathBuf, bool),
+    GitCommitAmendMessage(PathBuf, Result<String, String>),
+    GitCommitFinished(PathBuf, Result<String, String>),
+    GitCommitSignoff(PathBuf, bool),
+    GitConflictAccept(segmented_button::Entity, usize, GitConflictChoice),
+    GitConflictMarkResolved(PathBuf, PathBuf),
+    GitConflictMarkResolvedForce(PathBuf, PathBuf),
+    GitConflictMarkers(PathBuf, PathBuf, usize),
+    GitConflictResolve(segmented_button::Entity),
+    GitConflictResolved(segmented_button::Entity),
+    GitConflictsUpdate(segmented_button::Entity, u64),
+    GitProjectStatus(u64, Vec<GitProject>, ProjectGitStatuses, Vec<String>),
+    GitProjectStatusUpdate(u64),
+    GitRemote(PathBuf, GitRemoteAction),
+    GitRemoteFinished(PathBuf, Result<String, String>),
+    GitRemoteOutput(PathBuf, String, bool),
+    GitRemoteRebase(PathBuf, bool),
     GitStage(PathBuf, PathBuf),
+    GitStash(PathBuf, GitStashAction),
+    GitStashDiff(PathBuf, String),
+    GitStashDiffs(PathBuf, Vec<(String, GitDiff)>),
+    GitStashFinished(PathBuf, GitStashAction, Result<String, String>),
+    GitStashForce(PathBuf, GitStashAction),
+    GitStashMessage(PathBuf, String),
+    GitStashUntracked(PathBuf, bool),
     GitUnstage(PathBuf, PathBuf),
     Key(Modifiers, keyboard::key::Physical, keyboard::Key),
     LaunchUrl(String),
+    LocalHistory(PathBuf, Result<Vec<LocalSnapshot>, String>),
+    LocalHistoryDiff(usize),
+    LocalHistoryMaxCount(usize),
+    LocalHistoryMaxDays(usize),
+    LocalHistoryRestore(usize),
+    LocalHistoryRestoreText(segmented_button::Entity, String),
+    LocalHistoryUpdate,
+    LspChange(segmented_button::Entity, u64),
+    LspCodeActionApply(usize),
+    LspCodeActionKey(segmented_button::Entity, CompletionKey),
+    LspCodeActionsOpen(segmented_button::Entity, Option<usize>, Point),
+    LspCodeActionsResult(u64, Result<Vec<lsp_types::CodeActionOrCommand>, String>),
+    LspCompletionAccept(usize),
+    LspCompletionKey(segmented_button::Entity, CompletionKey),
+    LspCompletionResolved(u64, usize, lsp_types::CompletionItem, bool),
+    LspCompletionResult(u64, LspCompletion),
+    LspCompletionTrigger(segmented_button::Entity, CompletionTrigger, Point),
+    LspEdit(LspServerKey, LspEdit),
+    LspEnabled(bool),
+    LspError(String),
+    LspErrorClose,
+    LspEvent(LspServerKey, u64, LspEvent),
+    LspExited(LspServerKey, u64),
+    LspFormat(bool),
+    LspFormatResult(
+        segmented_button::Entity,
+        u64,
+        LspPositionEncoding,
+        Result<Option<Vec<lsp_types::TextEdit>>, String>,
+    ),
+    LspProblemOpen(PathBuf, lsp_types::Position),
+    LspSignatureHelp(u64, Option<LspSignatureHelp>),
+    LspStable(LspServerKey, u64),
+    LspStarted(LspServerKey, u64, LspClient),
     Modifiers(Modifiers),
     NewFile,
+    NavMenuAction(NavMenuAction),
+    Navigate(NavRequest),
+    NavigationOpen(usize),
+    NavigationResult(u64, NavResults),
     NewWindow,
     NotifyEvent(notify::Event),
     NotifyWatcher(WatcherWrapper),
     OpenFile(PathBuf),
     OpenFileDialog,
     OpenFileResult(DialogResult),
-    OpenGitDiff(PathBuf, GitDiff),
+    OpenGitDiff(PathBuf, GitDiff, Option<String>),
+    OpenGitRevision(PathBuf, String, String),
     OpenProjectDialog,
     OpenProjectResult(DialogResult),
     OpenRecentFile(usize),
     OpenRecentProject(usize),
-    OpenSearchResult(usize, usize),
+    OpenSearchResult(usize, usize, usize),
     Paste,
     PasteValue(String),
     PrepareGitDiff(PathBuf, PathBuf, bool),
-    ProjectSearchResult(ProjectSearchResult),
+    ProjectSearchCancel,
+    ProjectSearchContextAfter(usize),
+    ProjectSearchContextBefore(usize),
+    ProjectSearchExclude(String),
+    ProjectSearchFile(u64, FileSearchResult),
+    ProjectSearchFinished(u64, Option<String>),
+    ProjectSearchInclude(String),
+    ProjectSearchMaxFileSize(usize),
+    ProjectSearchOpenTabsOnly(bool),
     ProjectSearchSubmit,
+    ProjectSearchToggleFile(FileSearchKey),
     ProjectSearchValue(String),
+    ProjectSearchWord(String),
     PromptSaveChanges(segmented_button::Entity),
     Quit,
     QuitForce,
     Redo,
+    RenameApply,
+    RenameInput(String),
+    RenameOpen,
+    RenameResult(u64, Result<Vec<FileChange>, String>),
+    RenameSubmit,
     ReorderTab(ReorderEvent),
     RevertAllChanges,
     Save(Option<segmented_button::Entity>),
@@ -400,7 +812,13 @@ pub enum Message {
     SaveAsResult(segmented_button::Entity, DialogResult),
     Scroll(f32),
     SelectAll,
+    SnippetKey(segmented_button::Entity, CompletionKey),
     Surface(surface::Action),
+    SymbolSearchOpen(bool),
+    SymbolSearchOpenItem(usize),
+    SymbolSearchQuery(String),
+    SymbolSearchResult(u64, bool, Result<Vec<NavItem>, String>),
+    SymbolSearchSubmit,
     SystemThemeModeChange(cosmic_theme::ThemeMode),
     SyntaxTheme(usize, bool),
     TabActivate(segmented_button::Entity),
@@ -417,10 +835,13 @@ pub enum Message {
     Todo,
     ToggleAutoIndent,
     ToggleContextPage(ContextPage),
+    ToggleGitBlame,
     ToggleHighlightCurrentLine,
     ToggleLineNumbers,
     ToggleWordWrap,
     Undo,
+    UndoHistoryMaxChanges(usize),
+    UndoHistoryMaxFileSize(usize),
     UpdateGitProjectStatus,
     VimBindings(bool),
 }
@@ -429,14 +850,26 @@ pub enum Message {
 pub enum ContextPage {
     About,
     DocumentStatistics,
+    GitFileHistory,
     GitManagement,
+    LocalHistory,
+    Navigation,
+    Problems,
     //TODO: Move search to pop-up
     ProjectSearch,
+    Rename,
     Settings,
+    Symbols,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DialogPage {
+    PromptGitAskpass,
+    PromptGitCheckout(PathBuf, String),
+    PromptGitConflictResolve(PathBuf, PathBuf, usize),
+    PromptGitDiscard(PathBuf, PathBuf, bool),
+    PromptGitDiscardHunk(segmented_button::Entity, usize),
+    PromptGitStash(PathBuf, GitStashAction),
     PromptSaveClose(segmented_button::Entity),
     PromptSaveQuit(Vec<segmented_button::Entity>),
 }
@@ -448,6 +881,254 @@ pub enum Find {
     FindAndReplace,
 }
 
+/// Commits that changed the file of the active tab
+pub struct GitFileHistory {
+    path: PathBuf,
+    /// Path of the repository and revisions of the file, once loaded
+    result_opt: Option<Result<(PathBuf, Vec<GitFileRevision>), String>>,
+}
+
+/// Local history snapshots of the file of the active tab
+pub struct LocalFileHistory {
+    path: PathBuf,
+    /// Snapshots of the file, newest first, once loaded
+    result_opt: Option<Result<Vec<LocalSnapshot>, String>>,
+}
+
+/// Restarts of a language server after it crashed, before giving up
+const LSP_MAX_RESTARTS: u32 = 3;
+
+/// Time a restarted language server has to run before its crashes are forgotten
+const LSP_STABLE_TIME: time::Duration = time::Duration::from_secs(60);
+
+enum LspServerState {
+    Starting,
+    Running(LspClient),
+    /// The server could not be started or crashed too often
+    Failed,
+}
+
+/// Language server for a workspace root
+struct LspServer {
+    config: LspServerConfig,
+    state: LspServerState,
+    /// Incremented on every start, to ignore events of previous processes
+    generation: u64,
+    /// Restarts after recent crashes
+    restarts: u32,
+}
+
+/// Completion items shown at the cursor at once
+const LSP_COMPLETION_ITEMS: usize = 10;
+
+/// Completions shown at the cursor of an editor tab
+#[derive(Clone, Debug)]
+pub struct LspCompletion {
+    entity: segmented_button::Entity,
+    /// Position below the cursor in the text box
+    point: Point,
+    /// Start of the completed word
+    word_start: Cursor,
+    /// Where the cursor was when completions were requested
+    request_cursor: Cursor,
+    list: CompletionList,
+    /// Items resolved with the server, which may add details on request
+    resolved: BTreeSet<usize>,
+}
+
+/// Signatures of the call at the cursor of an editor tab
+#[derive(Clone, Debug)]
+pub struct LspSignatureHelp {
+    entity: segmented_button::Entity,
+    /// Position below the cursor in the text box
+    point: Point,
+    help: lsp_types::SignatureHelp,
+    encoding: LspPositionEncoding,
+}
+
+/// Code actions shown at the cursor or at a diagnostic marker of an editor tab
+pub struct LspCodeActions {
+    entity: segmented_button::Entity,
+    /// Position below the cursor or the marker in the text box
+    point: Point,
+    /// Code actions, once loaded
+    result_opt: Option<Result<Vec<lsp_types::CodeActionOrCommand>, String>>,
+    selected: usize,
+}
+
+/// Edits of a language server to apply, with a command to run on the server afterwards
+#[derive(Clone, Debug)]
+pub struct LspEdit {
+    encoding: LspPositionEncoding,
+    result: Result<Vec<FileChange>, String>,
+    command_opt: Option<lsp_types::Command>,
+}
+
+/// Rename of the symbol at the cursor of an editor tab, with a preview of its edits
+pub struct Rename {
+    entity: segmented_button::Entity,
+    encoding: LspPositionEncoding,
+    /// Position of the symbol in the tab
+    position: lsp_types::Position,
+    old_name: String,
+    name: String,
+    /// Edits of the rename to `name`, once loaded
+    changes_opt: Option<Result<Vec<FileChange>, String>>,
+    loading: bool,
+}
+
+/// Definitions or references of the symbol at the cursor
+#[derive(Clone, Debug)]
+pub struct NavResults {
+    request: NavRequest,
+    /// Word at the cursor
+    word: String,
+    encoding: LspPositionEncoding,
+    result: Result<Vec<NavItem>, String>,
+}
+
+/// Search for symbols of the active tab or of the whole workspace
+pub struct SymbolSearch {
+    entity: segmented_button::Entity,
+    workspace: bool,
+    query: String,
+    encoding: LspPositionEncoding,
+    /// The language server searches workspace symbols itself, so it is asked again as the query
+    /// changes
+    from_server: bool,
+    /// Symbols, once loaded
+    result_opt: Option<Result<Vec<NavItem>, String>>,
+    /// Indexes of the symbols matching the query, best first
+    matches: Vec<usize>,
+}
+
+/// Symbols shown at once in the symbol search
+const SYMBOL_SEARCH_ITEMS: usize = 200;
+
+/// Branch picker for a project in git management, shown while it exists
+#[derive(Default)]
+pub struct GitBranchPicker {
+    /// Name of the branch to create
+    name: String,
+    in_progress: bool,
+    result_opt: Option<Result<String, String>>,
+}
+
+/// Operations that talk to the remote of a project in git management
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GitRemoteAction {
+    Fetch,
+    Pull,
+    Push,
+}
+
+/// Remote operations and their output for a project in git management
+pub struct GitRemote {
+    /// Pull rebases local commits instead of only fast-forwarding
+    rebase: bool,
+    in_progress: bool,
+    /// Output of the last operation
+    log: Vec<String>,
+    /// The last line of the log shows progress and is replaced by the next line
+    log_progress: bool,
+    log_scroll_id: widget::Id,
+    result_opt: Option<Result<String, String>>,
+}
+
+impl Default for GitRemote {
+    fn default() -> Self {
+        Self {
+            rebase: false,
+            in_progress: false,
+            log: Vec::new(),
+            log_progress: false,
+            log_scroll_id: widget::Id::unique(),
+            result_opt: None,
+        }
+    }
+}
+
+/// Answer to a credential prompt of a git command, which is cancelled if it is dropped without
+/// being sent
+#[derive(Clone)]
+pub struct GitAskpassReply(Arc<Mutex<Option<futures::channel::oneshot::Sender<String>>>>);
+
+impl GitAskpassReply {
+    fn send(&self, answer: String) {
+        if let Some(sender) = self.0.lock().unwrap().take() {
+            // Fails only if the git command already exited
+            let _ = sender.send(answer);
+        }
+    }
+}
+
+impl std::fmt::Debug for GitAskpassReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("GitAskpassReply")
+    }
+}
+
+/// Credential prompt of a git command, shown in a dialog
+pub struct GitAskpassPrompt {
+    prompt: String,
+    value: String,
+    reply: GitAskpassReply,
+}
+
+/// Stash operations in git management
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GitStashAction {
+    /// Stash changes with a message, including untracked files if set
+    Push(String, bool),
+    /// Apply the stash with a hash, removing it if set
+    Apply(String, bool),
+    Drop(String),
+}
+
+impl GitStashAction {
+    /// Files in the working tree are replaced, so tabs must be reloaded
+    fn changes_files(&self) -> bool {
+        !matches!(self, Self::Drop(_))
+    }
+}
+
+/// Stash message and options for a project in git management
+#[derive(Default)]
+pub struct GitStashEditor {
+    message: String,
+    include_untracked: bool,
+    in_progress: bool,
+    result_opt: Option<Result<String, String>>,
+}
+
+/// Commit message and options for a project in git management
+pub struct GitCommitEditor {
+    content: text_editor::Content,
+    amend: bool,
+    /// Message from before amend was enabled, restored when it is disabled
+    message_before_amend: Option<String>,
+    signoff: bool,
+    in_progress: bool,
+    result_opt: Option<Result<String, String>>,
+}
+
+impl GitCommitEditor {
+    fn new() -> Self {
+        Self {
+            content: text_editor::Content::new(),
+            amend: false,
+            message_before_amend: None,
+            signoff: false,
+            in_progress: false,
+            result_opt: None,
+        }
+    }
+
+    fn message(&self) -> String {
+        self.content.text().trim_end().to_string()
+    }
+}
+
 pub struct App {
     core: Core,
     about: About,
@@ -459,6 +1140,8 @@ pub struct App {
     config_state: ConfigState,
     zoom_step_names: Vec<String>,
     zoom_steps: Vec<u16>,
+    max_file_size_names: Vec<String>,
+    max_file_sizes: Vec<u64>,
     key_binds: HashMap<KeyBind, Action>,
     app_themes: Vec<String>,
     font_names: Vec<String>,
@@ -468,6 +1151,8 @@ pub struct App {
     context_page: ContextPage,
     text_box_id: widget::Id,
     auto_scroll: Option<(f32, u32)>,
+    /// First side of the next compare
+    compare_selection_opt: Option<CompareSide>,
     dialog_opt: Option<Dialog<Message>>,
     dialog_page_opt: Option<DialogPage>,
     find_opt: Option<FindField>,
@@ -475,12 +1160,77 @@ pub struct App {
     find_replace_value: String,
     find_search_id: widget::Id,
     find_search_value: String,
-    git_project_status: Option<Vec<(String, PathBuf, Vec<GitStatus>)>>,
+    /// Last failed formatter run, shown until closed
+    formatter_error_opt: Option<String>,
+    git_askpass_id: widget::Id,
+    /// Credential prompts of git commands, the first one is shown
+    git_askpass_prompts: VecDeque<GitAskpassPrompt>,
+    git_blame_hover_opt: Option<(segmented_button::Entity, usize, Point)>,
+    git_branch_pickers: HashMap<PathBuf, GitBranchPicker>,
+    git_diff_scroll_id: widget::Id,
+    /// Last failed git operation, shown until closed
+    git_error_opt: Option<String>,
+    git_file_history_opt: Option<GitFileHistory>,
+    git_project_status: Option<Vec<GitProject>>,
+    /// Incremented when loading the status, to ignore results of older loads
+    git_project_status_generation: u64,
+    /// Incremented on changes to files in projects, to debounce loading the status
+    git_project_status_update: u64,
+    /// A git folder changed since the status was loaded, so everything else is loaded again too
+    git_project_status_full: bool,
+    /// Status of files and folders in the project tree
+    git_tree_status: ProjectGitStatuses,
+    git_commit_editors: HashMap<PathBuf, GitCommitEditor>,
+    git_stash_editors: HashMap<PathBuf, GitStashEditor>,
+    git_remotes: HashMap<PathBuf, GitRemote>,
+    local_history_opt: Option<LocalFileHistory>,
+    local_history_max_count_names: Vec<String>,
+    local_history_max_counts: Vec<u32>,
+    local_history_max_days_names: Vec<String>,
+    local_history_max_days: Vec<u32>,
+    undo_history_max_changes_names: Vec<String>,
+    undo_history_max_changes: Vec<u32>,
+    lsp_servers: BTreeMap<LspServerKey, LspServer>,
+    /// Diagnostics by file and the server that published them
+    lsp_diagnostics: BTreeMap<PathBuf, (LspServerKey, Vec<lsp_types::Diagnostic>)>,
+    lsp_completion_opt: Option<LspCompletion>,
+    /// Incremented on every completion request, to ignore results of older requests
+    lsp_completion_generation: u64,
+    lsp_signature_help_opt: Option<LspSignatureHelp>,
+    /// Incremented on every signature help request, to ignore results of older requests
+    lsp_signature_help_generation: u64,
+    lsp_code_actions_opt: Option<LspCodeActions>,
+    /// Incremented on every code action request, to ignore results of older requests
+    lsp_code_actions_generation: u64,
+    /// Last edit of a language server that failed, shown until closed
+    lsp_error_opt: Option<String>,
+    navigation_opt: Option<NavResults>,
+    /// Incremented on every navigation, to ignore results of older requests
+    navigation_generation: u64,
+    /// Tags of projects found with ctags
+    tags_cache: TagsCache,
     projects: Vec<(String, PathBuf)>,
+    /// Git folders of projects, watched to update git change markers
+    project_git_dirs: HashMap<PathBuf, PathBuf>,
     project_search_id: widget::Id,
     project_search_value: String,
     project_search_result: Option<ProjectSearchResult>,
     project_search_has_focus: bool,
+    project_search_include_value: String,
+    project_search_exclude_value: String,
+    project_search_context_names: Vec<String>,
+    project_search_collapsed: HashSet<FileSearchKey>,
+    project_search_open_tabs_only: bool,
+    project_search_generation: u64,
+    project_search_cancel_opt: Option<Arc<AtomicBool>>,
+    rename_id: widget::Id,
+    rename_opt: Option<Rename>,
+    /// Incremented on every rename request, to ignore results of older requests
+    rename_generation: u64,
+    symbol_search_id: widget::Id,
+    symbol_search_opt: Option<SymbolSearch>,
+    /// Incremented on every symbol search, to ignore results of older searches
+    symbol_search_generation: u64,
     watcher_opt: Option<(
         notify::RecommendedWatcher,
         HashSet<(PathBuf, RecursiveMode)>,
@@ -544,11 +1294,12 @@ impl App {
         nodes.sort();
 
         for node in nodes {
+            let icon = self.project_node_icon(&node);
             self.nav_model
                 .insert()
                 .position(position)
                 .indent(indent)
-                .icon(node.icon(16))
+                .icon(icon)
                 .text(node.name().to_string())
                 .data(node);
 
@@ -556,7 +1307,27 @@ impl App {
         }
     }
 
-    pub fn open_project<P: AsRef<Path>>(&mut self, path: P) {
+    /// Icon of a node in the project tree, files and folders with a git status show the status
+    /// instead of their type or whether they are open
+    fn project_node_icon(&self, node: &ProjectNode) -> icon::Icon {
+        match self.git_tree_status.get(node.path()) {
+            Some(status) => icon_cache_get(status.icon_name(), 16),
+            None => node.icon(16),
+        }
+    }
+
+    /// Show the git status of all files and folders in the project tree after it was loaded
+    fn update_project_git_status(&mut self) {
+        let entities: Vec<_> = self.nav_model.iter().collect();
+        for entity in entities {
+            if let Some(node) = self.nav_model.data::<ProjectNode>(entity) {
+                let icon = self.project_node_icon(node);
+                self.nav_model.icon_set(entity, icon);
+            }
+        }
+    }
+
+    pub fn open_project<P: AsRef<Path>>(&mut self, path: P) -> Task<Message> {
         let path = path.as_ref();
         let node = match ProjectNode::new(path) {
             Ok(mut node) => {
@@ -573,7 +1344,7 @@ impl App {
                         for (_project_name, project_path) in self.projects.iter() {
                             if project_path == path {
                                 // Project already open
-                                return;
+                                return Task::none();
                             }
                         }
 
@@ -594,21 +1365,22 @@ impl App {
                     }
                     _ => {
                         log::error!("failed to open project {:?}: not a directory", path);
-                        return;
+                        return Task::none();
                     }
                 }
                 node
             }
             Err(err) => {
                 log::error!("failed to open project {:?}: {}", path, err);
-                return;
+                return Task::none();
             }
         };
 
+        let icon = self.project_node_icon(&node);
         let id = self
             .nav_model
             .insert()
-            .icon(node.icon(16))
+            .icon(icon)
             .text(node.name().to_string())
             .data(node)
             .id();
@@ -616,6 +1388,25 @@ impl App {
 
         let position = self.nav_model.position(id).unwrap_or(0);
         self.open_folder(path, position + 1, 1);
+
+        // Find the git folder, which may be outside of the project
+        let project_path = path.to_path_buf();
+        Task::perform(
+            async move {
+                let repo = match GitRepository::new(&project_path).await {
+                    Ok(ok) => ok,
+                    Err(_) => return action::none(),
+                };
+                match repo.git_dir().await {
+                    Ok(git_dir) => action::app(Message::GitDir(project_path, git_dir)),
+                    Err(err) => {
+                        log::warn!("failed to find git folder of {:?}: {}", project_path, err);
+                        action::none()
+                    }
+                }
+            },
+            |x| x,
+        )
     }
 
     pub fn open_tab(&mut self, path_opt: Option<PathBuf>) -> Option<segmented_button::Entity> {
@@ -712,13 +1503,18 @@ impl App {
 
     fn update_config(&mut self) -> Task<Message> {
         //TODO: provide iterator over data
+        let mut tasks = Vec::new();
         let entities: Vec<_> = self.tab_model.iter().collect();
         for entity in entities {
-            if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
-                tab.set_config(&self.config);
+            match self.tab_model.data_mut::<Tab>(entity) {
+                Some(Tab::Editor(tab)) => tab.set_config(&self.config),
+                Some(Tab::GitDiff(_)) => tasks.push(self.update_git_diff_spans(entity)),
+                None => {}
             }
         }
-        cosmic::command::set_theme(self.config.app_theme.theme())
+        tasks.push(cosmic::command::set_theme(self.config.app_theme.theme()));
+        tasks.push(self.update_lsp());
+        Task::batch(tasks)
     }
 
     fn update_render_active_tab_zoom(&mut self, zoom_message: Message) -> Task<Message> {
@@ -760,6 +1556,27 @@ impl App {
 
     fn update_dialogs(&mut self) -> Task<Message> {
         match self.dialog_page_opt {
+            Some(DialogPage::PromptGitCheckout(ref project_path, ref name)) => {
+                if self.git_unsaved_tabs(project_path).is_empty() {
+                    // Tabs have been saved, check out the branch (which also closes this dialog)
+                    let message = Message::GitBranchSwitch(project_path.clone(), name.clone());
+                    return self.update(message);
+                }
+            }
+            Some(DialogPage::PromptGitStash(ref project_path, ref stash_action)) => {
+                if stash_action.changes_files() && self.git_unsaved_tabs(project_path).is_empty() {
+                    // Tabs have been saved, continue (which also closes this dialog)
+                    let message =
+                        Message::GitStashForce(project_path.clone(), stash_action.clone());
+                    return self.update(message);
+                }
+            }
+            Some(DialogPage::PromptGitDiscardHunk(entity, _)) => {
+                if self.tab_model.data::<Tab>(entity).is_none() {
+                    // Tab no longer found, close dialog
+                    self.dialog_page_opt = None;
+                }
+            }
             Some(DialogPage::PromptSaveClose(entity)) => {
                 if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
                     if !tab.changed() {
@@ -788,7 +1605,7 @@ impl App {
                     self.dialog_page_opt = Some(DialogPage::PromptSaveQuit(unsaved));
                 }
             }
-            None => {}
+            Some(DialogPage::PromptGitDiscard(..)) | None => {}
         }
         Task::none()
     }
@@ -799,6 +1616,8 @@ impl App {
                 ContextPage::ProjectSearch if self.project_search_has_focus => {
                     widget::text_input::focus(self.project_search_id.clone())
                 }
+                ContextPage::Rename => widget::text_input::focus(self.rename_id.clone()),
+                ContextPage::Symbols => widget::text_input::focus(self.symbol_search_id.clone()),
                 _ => Task::none(),
             }
         } else if self.find_opt.is_some_and(
@@ -885,6 +1704,20 @@ impl App {
     pub fn update_tab(&mut self) -> Task<Message> {
         self.update_nav_bar_active();
 
+        let git_index_task = self.update_git_index(self.tab_model.active());
+        let git_file_history_task = self.update_git_file_history();
+        let local_history_task = self.update_local_history();
+        let lsp_task = self.update_lsp();
+        let git_blame_stale = matches!(
+            self.active_tab(),
+            Some(Tab::Editor(tab)) if tab.git_blame_head_opt.is_none() || tab.git_blame_head_stale
+        );
+        let git_blame_task = if git_blame_stale {
+            self.update_git_blame(self.tab_model.active())
+        } else {
+            Task::none()
+        };
+
         let title = match self.active_tab() {
             Some(tab) => {
                 if let Tab::Editor(inner) = tab {
@@ -904,1249 +1737,6358 @@ impl App {
                 Task::none()
             },
             self.update_focus(),
+            git_index_task,
+            git_blame_task,
+            git_file_history_task,
+            local_history_task,
+            lsp_task,
         ])
     }
 
-    fn update_watcher(&mut self) {
-        if let Some((mut watcher, old_paths)) = self.watcher_opt.take() {
-            let mut new_paths = HashSet::new();
+    /// Load the indexed version of a tab's file if it is stale, used for git change markers
+    fn update_git_index(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        if !tab.git_index_stale {
+            return Task::none();
+        }
+        let Some(path) = tab.path_opt.clone() else {
+            return Task::none();
+        };
+        tab.git_index_stale = false;
+        Task::perform(
+            async move {
+                let text_opt = match GitRepository::containing(&path).await {
+                    Ok(repo) => match repo.index_text(&path).await {
+                        Ok(text) => Some(text),
+                        Err(err) => {
+                            // Untracked files have no indexed version
+                            log::debug!("failed to read index of {:?}: {}", path, err);
+                            None
+                        }
+                    },
+                    Err(_) => None,
+                };
+                action::app(Message::GitIndexText(entity, path, text_opt))
+            },
+            |x| x,
+        )
+    }
 
-            for (_, project_path) in self.projects.iter() {
-                new_paths.insert((project_path.clone(), RecursiveMode::Recursive));
-            }
+    /// Repository path and a revision from the loaded file history
+    fn git_file_history_revision(&self, revision_i: usize) -> Option<(PathBuf, GitFileRevision)> {
+        match &self.git_file_history_opt.as_ref()?.result_opt {
+            Some(Ok((repo_path, revisions))) => revisions
+                .get(revision_i)
+                .map(|revision| (repo_path.clone(), revision.clone())),
+            _ => None,
+        }
+    }
 
-            'tabs: for entity in self.tab_model.iter() {
+    /// Close repositories opened by libgit2 that no project or tab uses anymore
+    fn forget_git_repositories(&self) {
+        #[cfg(feature = "git2")]
+        {
+            let mut paths: Vec<&Path> = self
+                .projects
+                .iter()
+                .map(|(_, project_path)| project_path.as_path())
+                .collect();
+            for entity in self.tab_model.iter() {
                 if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
                     if let Some(path) = &tab.path_opt {
-                        for (_, project_path) in self.projects.iter() {
-                            if path.starts_with(&project_path) {
-                                // Do not watch tabs inside of already watched projects
-                                continue 'tabs;
-                            }
-                        }
-                        new_paths.insert((path.to_path_buf(), RecursiveMode::NonRecursive));
+                        paths.push(path);
                     }
                 }
             }
+            // Projects may contain repositories or be inside of one
+            git_native::retain(|repo_path| {
+                paths
+                    .iter()
+                    .any(|path| path.starts_with(repo_path) || repo_path.starts_with(path))
+            });
+        }
+    }
 
-            // Unwatch paths no longer used
-            for path_mode in old_paths.iter() {
-                if !new_paths.contains(path_mode) {
-                    let (path, _) = path_mode;
-                    match watcher.unwatch(path) {
-                        Ok(()) => {
-                            log::debug!("unwatching {:?}", path);
-                        }
-                        Err(err) => {
-                            log::debug!("failed to unwatch {:?}: {}", path, err);
-                        }
-                    }
+    /// Tabs with unsaved changes to files in a project, or to a single file
+    fn git_unsaved_tabs(&self, project_path: &Path) -> Vec<segmented_button::Entity> {
+        let mut unsaved = Vec::new();
+        for entity in self.tab_model.iter() {
+            if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                if tab.changed()
+                    && tab
+                        .path_opt
+                        .as_ref()
+                        .is_some_and(|path| path.starts_with(project_path))
+                {
+                    unsaved.push(entity);
                 }
             }
+        }
+        unsaved
+    }
 
-            // Watch new paths
-            for path_mode in new_paths.iter() {
-                if !old_paths.contains(path_mode) {
-                    let (path, mode) = path_mode;
-                    match watcher.watch(path, *mode) {
-                        Ok(()) => {
-                            log::debug!("watching {:?} {:?}", path, mode);
-                        }
-                        Err(err) => {
-                            log::debug!("failed to watch {:?} {:?}: {}", path, mode, err);
-                        }
-                    }
+    /// Warn that discarding changes to a file replaces the unsaved text of its tabs
+    fn git_discard_unsaved(&self, path: &Path) -> Element<'_, Message> {
+        let cosmic_theme::Spacing { space_xxs, .. } = self.core().system_theme().cosmic().spacing;
+
+        let unsaved = self.git_unsaved_tabs(path);
+        let mut column = widget::column::with_capacity(unsaved.len() + 1).spacing(space_xxs);
+        if !unsaved.is_empty() {
+            column = column.push(widget::text::heading(fl!("prompt-git-discard-unsaved")));
+            for entity in unsaved {
+                if let Some(tab) = self.tab_model.data::<Tab>(entity) {
+                    column = column.push(widget::text(tab.title()));
                 }
             }
+        }
+        column.into()
+    }
 
-            self.watcher_opt = Some((watcher, new_paths));
+    /// Reload all tabs with files in a project, after the working tree was replaced
+    fn git_reload_tabs(&mut self, project_path: &Path) -> Task<Message> {
+        let mut tasks = Vec::new();
+        let mut local_history_saves = Vec::new();
+        let entities: Vec<_> = self.tab_model.iter().collect();
+        for entity in entities {
+            if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                if !tab
+                    .path_opt
+                    .as_ref()
+                    .is_some_and(|path| path.starts_with(project_path))
+                {
+                    continue;
+                }
+                // Unsaved changes are replaced, but can still be restored with undo
+                local_history_saves.extend(tab.reload());
+                tab.git_index_stale = true;
+                tab.git_blame_opt = None;
+                tab.git_blame_head_opt = None;
+                tab.git_blame_labels.clear();
+            } else {
+                continue;
+            }
+            tasks.push(self.update_git_index(entity));
+            tasks.push(self.update_git_blame(entity));
         }
+        tasks.push(self.write_local_history(local_history_saves));
+        Task::batch(tasks)
     }
 
-    fn document_statistics(&self) -> Element<'_, Message> {
-        //TODO: calculate in the background
-        let mut character_count = 0;
-        let mut character_count_no_spaces = 0;
-        let mut line_count = 0;
-        let mut word_count = 0;
+    /// Load the history of the active tab's file if it is shown and not loaded yet
+    fn update_git_file_history(&mut self) -> Task<Message> {
+        if !self.core.window.show_context || self.context_page != ContextPage::GitFileHistory {
+            return Task::none();
+        }
+        let Some(Tab::Editor(tab)) = self.active_tab() else {
+            return Task::none();
+        };
+        let Some(path) = tab.path_opt.clone() else {
+            return Task::none();
+        };
+        if self
+            .git_file_history_opt
+            .as_ref()
+            .is_some_and(|history| history.path == path)
+        {
+            return Task::none();
+        }
+        self.git_file_history_opt = Some(GitFileHistory {
+            path: path.clone(),
+            result_opt: None,
+        });
+        Task::perform(
+            async move {
+                let result = match GitRepository::containing(&path).await {
+                    Ok(repo) => repo
+                        .file_log(&path)
+                        .await
+                        .map(|revisions| (repo.path().to_path_buf(), revisions))
+                        .map_err(|err| err.to_string()),
+                    Err(err) => Err(err.to_string()),
+                };
+                action::app(Message::GitFileHistory(path, result))
+            },
+            |x| x,
+        )
+    }
 
-        if let Some(Tab::Editor(tab)) = self.active_tab() {
-            let editor = tab.editor.lock().unwrap();
-            editor.with_buffer(|buffer| {
-                line_count = buffer.lines.len();
-                for line in buffer.lines.iter() {
-                    let text = line.text();
-                    let mut last_whitespace = true;
-
-                    // Count graphemes instead of Unicode scalar values for accurate character count
-                    for grapheme in text.graphemes(true) {
-                        character_count += 1;
-                        let is_whitespace = grapheme.chars().all(|c| c.is_whitespace());
-                        if !is_whitespace {
-                            character_count_no_spaces += 1;
-                            if last_whitespace {
-                                word_count += 1;
-                            }
-                        }
-                        last_whitespace = is_whitespace;
-                    }
-                }
+    /// Load the local history of the active tab's file if it is shown
+    fn update_local_history(&mut self) -> Task<Message> {
+        if !self.core.window.show_context || self.context_page != ContextPage::LocalHistory {
+            return Task::none();
+        }
+        let path_opt = match self.active_tab() {
+            Some(Tab::Editor(tab)) => tab.path_opt.clone(),
+            _ => None,
+        };
+        let Some(path) = path_opt else {
+            self.local_history_opt = None;
+            return Task::none();
+        };
+        // Snapshots are taken often, so the previous result is kept until loading is done
+        if !self
+            .local_history_opt
+            .as_ref()
+            .is_some_and(|history| history.path == path)
+        {
+            self.local_history_opt = Some(LocalFileHistory {
+                path: path.clone(),
+                result_opt: None,
             });
         }
-
-        widget::settings::view_column(vec![
-            widget::settings::section()
-                .add(
-                    widget::settings::item::builder(fl!("word-count"))
-                        .control(widget::text(word_count.to_string())),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("character-count"))
-                        .control(widget::text(character_count.to_string())),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("character-count-no-spaces"))
-                        .control(widget::text(character_count_no_spaces.to_string())),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("line-count"))
-                        .control(widget::text(line_count.to_string())),
-                )
-                .into(),
-        ])
-        .into()
+        Task::perform(
+            async move {
+                let result = match LocalHistory::new() {
+                    Some(local_history) => local_history
+                        .snapshots(&path)
+                        .map_err(|err| err.to_string()),
+                    None => Ok(Vec::new()),
+                };
+                action::app(Message::LocalHistory(path, result))
+            },
+            |x| x,
+        )
     }
 
-    fn git_management(&self) -> Element<'_, Message> {
-        let spacing = self.core().system_theme().cosmic().spacing;
-
-        if let Some(project_status) = &self.git_project_status {
-            let (success_color, destructive_color, warning_color) = {
-                let cosmic_theme = self.core().system_theme().cosmic();
-                (
-                    cosmic_theme.success_color(),
-                    cosmic_theme.destructive_color(),
-                    cosmic_theme.warning_color(),
-                )
-            };
-            let added = || widget::text("[+]").class(theme::Text::Color(success_color.into()));
-            let deleted =
-                || widget::text("[-]").class(theme::Text::Color(destructive_color.into()));
-            let modified = || widget::text("[*]").class(theme::Text::Color(warning_color.into()));
-
-            let mut items =
-                Vec::with_capacity(project_status.len().saturating_mul(3).saturating_add(1));
-            items.push(widget::text(fl!("git-management-description")).into());
+    /// Take snapshots for the local history in the background, then load the shown history again
+    fn write_local_history(
+        &self,
+        local_history_saves: impl IntoIterator<Item = LocalHistorySave>,
+    ) -> Task<Message> {
+        let local_history_saves: Vec<_> = local_history_saves.into_iter().collect();
+        if local_history_saves.is_empty() {
+            return Task::none();
+        }
+        Task::perform(
+            async move {
+                let result = tokio::task::spawn_blocking(move || {
+                    for local_history_save in local_history_saves {
+                        local_history_save.write();
+                    }
+                })
+                .await;
+                if let Err(err) = result {
+                    log::warn!("failed to take snapshots: {}", err);
+                }
+                action::app(Message::LocalHistoryUpdate)
+            },
+            |x| x,
+        )
+    }
 
-            for (project_name, project_path, status) in project_status.iter() {
-                let mut unstaged_items = Vec::with_capacity(status.len());
-                let mut staged_items = Vec::with_capacity(status.len());
-                for item in status.iter() {
-                    let relative_path = match item.path.strip_prefix(project_path) {
-                        Ok(ok) => ok,
-                        Err(err) => {
-                            log::warn!(
-                                "failed to find relative path of {:?} in project {:?}: {}",
-                                item.path,
-                                project_path,
-                                err
-                            );
-                            &item.path
-                        }
-                    };
+    /// A snapshot from the loaded local history
+    fn local_history_snapshot(&self, snapshot_i: usize) -> Option<LocalSnapshot> {
+        match &self.local_history_opt.as_ref()?.result_opt {
+            Some(Ok(snapshots)) => snapshots.get(snapshot_i).cloned(),
+            _ => None,
+        }
+    }
 
-                    let text = match &item.old_path {
-                        Some(old_path) => {
-                            let old_relative_path = match old_path.strip_prefix(project_path) {
-                                Ok(ok) => ok,
+    /// Blame the buffer of a tab if the blame gutter is shown
+    /// Blame the buffer, only asking git to blame the file again if `HEAD` changed
+    fn update_git_blame(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        if !self.config.git_blame {
+            return Task::none();
+        }
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        let Some(path) = tab.path_opt.clone() else {
+            return Task::none();
+        };
+        tab.git_blame_generation = tab.git_blame_generation.wrapping_add(1);
+        let generation = tab.git_blame_generation;
+        let head_opt = tab.git_blame_head_opt.clone();
+        let head_stale = tab.git_blame_head_stale;
+        let text = tab.text();
+        Task::perform(
+            async move {
+                let (hash, head) = match head_opt {
+                    Some(head) if !head_stale => head,
+                    _ => {
+                        let repo = match GitRepository::containing(&path).await {
+                            Ok(repo) => repo,
+                            Err(_) => return action::none(),
+                        };
+                        let hash = match repo.head_hash().await {
+                            Ok(hash) => hash,
+                            Err(err) => {
+                                log::debug!("failed to get HEAD of {:?}: {}", repo.path(), err);
+                                return action::none();
+                            }
+                        };
+                        match head_opt {
+                            Some((head_hash, head)) if head_hash == hash => (hash, head),
+                            _ => match repo.blame(&hash, &path).await {
+                                Ok(blame) => (hash, Arc::new(blame)),
                                 Err(err) => {
-                                    log::warn!(
-                                        "failed to find relative path of {:?} in project {:?}: {}",
-                                        old_path,
-                                        project_path,
-                                        err
-                                    );
-                                    old_path
+                                    // Files that are not committed cannot be blamed
+                                    log::debug!("failed to blame {:?}: {}", path, err);
+                                    return action::none();
                                 }
-                            };
-                            format!(
-                                "{} -> {}",
-                                old_relative_path.display(),
-                                relative_path.display()
-                            )
+                            },
                         }
-                        None => format!("{}", relative_path.display()),
-                    };
-
-                    let unstaged_opt = match item.unstaged {
-                        GitStatusKind::Unmodified => None,
-                        GitStatusKind::Modified => Some(modified()),
-                        GitStatusKind::FileTypeChanged => Some(modified()),
-                        GitStatusKind::Added => Some(added()),
-                        GitStatusKind::Deleted => Some(deleted()),
-                        GitStatusKind::Renamed => Some(modified()), //TODO
-                        GitStatusKind::Copied => Some(modified()),  // TODO
-                        GitStatusKind::Updated => Some(modified()),
-                        GitStatusKind::Untracked => Some(added()),
-                        GitStatusKind::SubmoduleModified => Some(modified()),
-                    };
-
-                    if let Some(icon) = unstaged_opt {
-                        unstaged_items.push(
-                            widget::button::custom(
-                                widget::row::with_children(vec![
-                                    icon.into(),
-                                    widget::text(text.clone()).into(),
-                                    widget::space::horizontal().into(),
-                                    widget::button::standard(fl!("stage"))
-                                        .on_press(Message::GitStage(
-                                            project_path.clone(),
-                                            item.path.clone(),
-                                        ))
-                                        .into(),
-                                ])
-                                .align_y(Alignment::Center)
-                                .spacing(spacing.space_xs),
-                            )
-                            .on_press(Message::PrepareGitDiff(
-                                project_path.clone(),
-                                item.path.clone(),
-                                false,
-                            ))
-                            .class(theme::Button::AppletMenu)
-                            .width(Length::Fill)
-                            .into(),
-                        );
                     }
-
-                    let staged_opt = match item.staged {
-                        GitStatusKind::Unmodified => None,
-                        GitStatusKind::Modified => Some(modified()),
-                        GitStatusKind::FileTypeChanged => Some(modified()),
-                        GitStatusKind::Added => Some(added()),
-                        GitStatusKind::Deleted => Some(deleted()),
-                        GitStatusKind::Renamed => Some(modified()), //TODO
-                        GitStatusKind::Copied => Some(modified()),  // TODO
-                        GitStatusKind::Updated => Some(modified()),
-                        GitStatusKind::Untracked => None,
-                        GitStatusKind::SubmoduleModified => Some(modified()),
-                    };
-
-                    if let Some(icon) = staged_opt {
-                        staged_items.push(
-                            widget::button::custom(
-                                widget::row::with_children(vec![
-                                    icon.into(),
-                                    widget::text(text.clone()).into(),
-                                    widget::space::horizontal().into(),
-                                    widget::button::standard(fl!("unstage"))
-                                        .on_press(Message::GitUnstage(
-                                            project_path.clone(),
-                                            item.path.clone(),
-                                        ))
-                                        .into(),
-                                ])
-                                .align_y(Alignment::Center)
-                                .spacing(spacing.space_xs),
-                            )
-                            .on_press(Message::PrepareGitDiff(
-                                project_path.clone(),
-                                item.path.clone(),
-                                true,
-                            ))
-                            .class(theme::Button::AppletMenu)
-                            .width(Length::Fill)
-                            .into(),
-                        );
+                };
+                let blame_head = head.clone();
+                match tokio::task::spawn_blocking(move || blame_head.with_text(&text)).await {
+                    Ok(blame) => {
+                        action::app(Message::GitBlame(entity, generation, (hash, head), blame))
+                    }
+                    Err(err) => {
+                        log::error!("failed to blame buffer: {}", err);
+                        action::none()
                     }
                 }
+            },
+            |x| x,
+        )
+    }
 
-                items.push(widget::text::heading(project_name.clone()).into());
-
-                if !unstaged_items.is_empty() {
-                    items.push(
-                        widget::settings::section()
-                            .title(fl!("unstaged-changes"))
-                            .add(widget::column::with_children(unstaged_items))
-                            .into(),
-                    );
+    /// Highlight a diff tab in the background
+    fn update_git_diff_spans(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        let syntax_theme = self.config.syntax_theme().to_string();
+        let Some(Tab::GitDiff(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        tab.spans_generation = tab.spans_generation.wrapping_add(1);
+        let generation = tab.spans_generation;
+        let project_path = tab.project_path.clone();
+        let diff = tab.diff.clone();
+        let commit_opt = tab.commit_opt.clone();
+        let compare_opt = tab
+            .compare_opt
+            .as_ref()
+            .map(|compare| (compare.old_text.clone(), compare.new_text.clone()));
+        Task::perform(
+            async move {
+                // Files are highlighted from the start, so hunks inside of comments look right
+                let (old, new) = match compare_opt {
+                    Some(texts) => texts,
+                    None => {
+                        let result = match GitRepository::new(&project_path).await {
+                            Ok(repo) => {
+                                repo.diff_sides(&diff.path, diff.staged, commit_opt.as_deref())
+                                    .await
+                            }
+                            Err(err) => Err(err),
+                        };
+                        match result {
+                            Ok(texts) => texts,
+                            Err(err) => {
+                                return git_error(
+                                    format!("failed to read both sides of {:?}", diff.path),
+                                    err,
+                                );
+                            }
+                        }
+                    }
+                };
+                match tokio::task::spawn_blocking(move || {
+                    git_diff_spans(&diff, &old, &new, &syntax_theme)
+                })
+                .await
+                {
+                    Ok(spans) => action::app(Message::GitDiffSpans(entity, generation, spans)),
+                    Err(err) => {
+                        log::error!("failed to highlight diff: {}", err);
+                        action::none()
+                    }
                 }
+            },
+            |x| x,
+        )
+    }
 
-                if !staged_items.is_empty() {
-                    items.push(
-                        widget::settings::section()
-                            .title(fl!("staged-changes"))
-                            .add(widget::column::with_children(staged_items))
-                            .into(),
-                    );
-                }
-            }
+    /// Open a diff tab and highlight it in the background
+    fn open_diff_tab(&mut self, tab: GitDiffTab) -> Task<Message> {
+        let icon = icon::icon(mime_icon(mime_for_path(&tab.diff.path, None, false), 16)).size(16);
+        let tab = Tab::GitDiff(tab);
+        let entity = self
+            .tab_model
+            .insert()
+            .text(tab.title())
+            .icon(icon)
+            .data::<Tab>(tab)
+            .closable()
+            .activate()
+            .id();
+        Task::batch([self.update_git_diff_spans(entity), self.update_tab()])
+    }
 
-            widget::column::with_children(items)
-                .spacing(spacing.space_s)
-                .padding([spacing.space_xxs, spacing.space_none])
-                .into()
-        } else {
-            widget::column::with_children(vec![
-                widget::text(fl!("git-management-description")).into(),
-                widget::text(fl!("git-management-loading")).into(),
-            ])
-            .spacing(spacing.space_s)
-            .padding([spacing.space_xxs, spacing.space_none])
-            .into()
+    /// Update git change markers after edits have stopped for a moment
+    fn update_git_diff(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        if tab.git_index_opt.is_none() {
+            return Task::none();
         }
+        tab.git_diff_generation = tab.git_diff_generation.wrapping_add(1);
+        let generation = tab.git_diff_generation;
+        Task::perform(
+            async move {
+                time::sleep(time::Duration::from_millis(250)).await;
+                action::app(Message::GitBufferDiffUpdate(entity, generation))
+            },
+            |x| x,
+        )
     }
 
-    fn project_search(&self) -> Element<'_, Message> {
-        let spacing = self.core().system_theme().cosmic().spacing;
-
-        let search_input = widget::text_input::search_input(
-            fl!("project-search"),
-            self.project_search_value.clone(),
+    /// Reload the git status after file changes have stopped for a moment. Everything shown in git
+    /// management is loaded again if `full` is set, for changes in git folders.
+    fn update_git_project_status(&mut self, full: bool) -> Task<Message> {
+        self.git_project_status_full |= full;
+        self.git_project_status_update = self.git_project_status_update.wrapping_add(1);
+        let update = self.git_project_status_update;
+        Task::perform(
+            async move {
+                time::sleep(time::Duration::from_millis(500)).await;
+                action::app(Message::GitProjectStatusUpdate(update))
+            },
+            |x| x,
         )
-        .id(self.project_search_id.clone());
-
-        let items = match &self.project_search_result {
-            Some(project_search_result) => {
-                let mut items =
-                    Vec::with_capacity(project_search_result.files.len().saturating_add(1));
+    }
 
-                if project_search_result.in_progress {
-                    items.push(search_input.into());
-                } else {
-                    items.push(
-                        search_input
-                            .on_input(Message::ProjectSearchValue)
-                            .on_submit(|_| Message::ProjectSearchSubmit)
-                            .into(),
+    /// Load only the status of the repositories shown, for changes of files in their working trees
+    fn update_git_status(&mut self) -> Task<Message> {
+        let Some(mut projects) = self.git_project_status.clone() else {
+            return Task::none();
+        };
+        let generation = self.git_project_status_generation;
+        Task::perform(
+            async move {
+                let mut errors = Vec::new();
+                for project in projects.iter_mut() {
+                    let result = match GitRepository::new(&project.path).await {
+                        Ok(repo) => repo.status().await,
+                        Err(err) => Err(err),
+                    };
+                    match result {
+                        Ok(status) => project.status = status,
+                        Err(err) => {
+                            let err =
+                                format!("failed to get status of {:?}: {}", project.path, err);
+                            log::error!("{}", err);
+                            errors.push(err);
+                        }
+                    }
+                }
+                let tree_status = ProjectGitStatuses::new(&projects);
+                action::app(Message::GitProjectStatus(
+                    generation,
+                    projects,
+                    tree_status,
+                    errors,
+                ))
+            },
+            |x| x,
+        )
+    }
+
+    /// Update merge conflict regions after edits have stopped for a moment
+    fn update_git_conflicts(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        tab.git_conflicts_generation = tab.git_conflicts_generation.wrapping_add(1);
+        let generation = tab.git_conflicts_generation;
+        Task::perform(
+            async move {
+                time::sleep(time::Duration::from_millis(250)).await;
+                action::app(Message::GitConflictsUpdate(entity, generation))
+            },
+            |x| x,
+        )
+    }
+
+    /// Open the files of editor tabs with their language servers, starting servers as needed and
+    /// stopping servers that are no longer used
+    fn update_lsp(&mut self) -> Task<Message> {
+        let mut documents = Vec::new();
+        if self.config.lsp_enabled {
+            for entity in self.tab_model.iter() {
+                let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+                    continue;
+                };
+                let Some(path) = tab.path_opt.as_ref().filter(|_| tab.revision_opt.is_none())
+                else {
+                    continue;
+                };
+                let Some((config, language_id)) = self
+                    .config
+                    .lsp_servers
+                    .iter()
+                    .find_map(|config| Some((config, config.language_id(path)?)))
+                else {
+                    continue;
+                };
+                let project_opt = self
+                    .projects
+                    .iter()
+                    .map(|(_, project_path)| project_path.as_path())
+                    .filter(|project_path| path.starts_with(project_path))
+                    .max_by_key(|project_path| project_path.as_os_str().len());
+                let document = LspDocument {
+                    key: LspServerKey {
+                        name: config.name.clone(),
+                        root: config.root(path, project_opt),
+                    },
+                    path: path.clone(),
+                    language_id: language_id.to_string(),
+                };
+                documents.push((entity, config.clone(), document));
+            }
+        }
+
+        let mut tasks = Vec::new();
+        let unused: Vec<_> = self
+            .lsp_servers
+            .iter()
+            .filter(|(key, server)| {
+                !documents
+                    .iter()
+                    .any(|(_, config, document)| &document.key == *key && config == &server.config)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in unused {
+            tasks.push(self.stop_lsp_server(&key));
+        }
+
+        for (entity, config, document) in documents {
+            let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+                continue;
+            };
+            if tab.lsp_document_opt.as_ref() == Some(&document) {
+                continue;
+            }
+            // The file was saved under another name or belongs to another workspace now
+            if let Some(old_document) = tab.lsp_document_opt.take() {
+                if let Some(LspServer {
+                    state: LspServerState::Running(client),
+                    ..
+                }) = self.lsp_servers.get(&old_document.key)
+                    && let Some(uri) = lsp::path_uri(&old_document.path)
+                {
+                    client.did_close(uri);
+                }
+                tab.clear_lsp();
+            }
+            match self.lsp_servers.get(&document.key) {
+                Some(LspServer {
+                    state: LspServerState::Running(client),
+                    ..
+                }) => {
+                    let text = tab.text();
+                    tab.lsp_version = 1;
+                    tab.lsp_text_hash = stable_hash(text.as_bytes());
+                    if let Some(uri) = lsp::path_uri(&document.path) {
+                        client.did_open(uri, &document.language_id, tab.lsp_version, text);
+                    }
+                    if let Some((_, diagnostics)) = self.lsp_diagnostics.get(&document.path) {
+                        tab.set_lsp_diagnostics(client.position_encoding(), diagnostics);
+                    }
+                    tab.lsp_document_opt = Some(document);
+                }
+                // Files are opened once the server is running
+                Some(_) => {}
+                None => {
+                    self.lsp_servers.insert(
+                        document.key.clone(),
+                        LspServer {
+                            config,
+                            state: LspServerState::Starting,
+                            generation: 0,
+                            restarts: 0,
+                        },
                     );
+                    tasks.push(self.start_lsp_server(document.key, time::Duration::ZERO));
                 }
+            }
+        }
+        Task::batch(tasks)
+    }
 
-                for (file_i, file_search_result) in project_search_result.files.iter().enumerate() {
-                    let mut column = widget::column::with_capacity(file_search_result.lines.len());
-                    let mut line_number_width = 1;
-                    if let Some(line_search_result) = file_search_result.lines.last() {
-                        let mut number = line_search_result.number;
-                        while number >= 10 {
-                            number /= 10;
-                            line_number_width += 1;
+    /// Start a language server after `delay`, its events are received until it exits
+    fn start_lsp_server(&mut self, key: LspServerKey, delay: time::Duration) -> Task<Message> {
+        let Some(server) = self.lsp_servers.get_mut(&key) else {
+            return Task::none();
+        };
+        server.state = LspServerState::Starting;
+        server.generation = server.generation.wrapping_add(1);
+        let generation = server.generation;
+        let config = server.config.clone();
+        Task::stream(stream::channel(
+            16,
+            move |mut output: futures::channel::mpsc::Sender<cosmic::Action<Message>>| async move {
+                time::sleep(delay).await;
+                match LspClient::start(&config, &key.root).await {
+                    Ok((client, mut events)) => {
+                        let _ = output
+                            .send(action::app(Message::LspStarted(
+                                key.clone(),
+                                generation,
+                                client,
+                            )))
+                            .await;
+                        while let Some(event) = events.recv().await {
+                            let message = Message::LspEvent(key.clone(), generation, event);
+                            if output.send(action::app(message)).await.is_err() {
+                                break;
+                            }
                         }
                     }
-                    for (line_i, line_search_result) in file_search_result.lines.iter().enumerate()
-                    {
-                        column = column.push(
-                            widget::button::custom(
-                                widget::row::with_children(vec![
-                                    widget::text(format!(
-                                        "{:width$}",
-                                        line_search_result.number,
-                                        width = line_number_width,
-                                    ))
-                                    .font(Font::MONOSPACE)
-                                    .into(),
-                                    widget::text(line_search_result.text.to_string())
-                                        .font(Font::MONOSPACE)
-                                        .into(),
-                                ])
-                                .spacing(spacing.space_xs),
-                            )
-                            .on_press(Message::OpenSearchResult(file_i, line_i))
-                            .width(Length::Fill)
-                            .class(theme::Button::AppletMenu),
+                    Err(err) => {
+                        log::warn!(
+                            "failed to start {} in {:?}: {}",
+                            config.command,
+                            key.root,
+                            err
                         );
                     }
-
-                    items.push(
-                        widget::settings::section()
-                            .title(format!("{}", file_search_result.path.display(),))
-                            .add(column)
-                            .into(),
-                    );
                 }
+                let _ = output
+                    .send(action::app(Message::LspExited(key, generation)))
+                    .await;
+            },
+        ))
+    }
 
-                items
-            }
-            None => {
-                vec![
-                    search_input
-                        .on_input(Message::ProjectSearchValue)
-                        .on_submit(|_| Message::ProjectSearchSubmit)
-                        .into(),
-                ]
-            }
+    /// Shut down a language server and forget the files opened with it
+    fn stop_lsp_server(&mut self, key: &LspServerKey) -> Task<Message> {
+        let Some(server) = self.lsp_servers.remove(key) else {
+            return Task::none();
         };
+        self.forget_lsp_server(key);
+        match server.state {
+            LspServerState::Running(client) => Task::perform(
+                async move {
+                    client.shutdown().await;
+                    action::none()
+                },
+                |x| x,
+            ),
+            LspServerState::Starting | LspServerState::Failed => Task::none(),
+        }
+    }
 
-        widget::column::with_children(items)
-            .spacing(spacing.space_s)
-            .padding([spacing.space_xxs, spacing.space_none])
-            .into()
+    /// Forget the files opened with a language server and the diagnostics it published
+    fn forget_lsp_server(&mut self, key: &LspServerKey) {
+        let entities: Vec<_> = self.tab_model.iter().collect();
+        for entity in entities {
+            if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity)
+                && tab
+                    .lsp_document_opt
+                    .as_ref()
+                    .is_some_and(|document| &document.key == key)
+            {
+                tab.clear_lsp();
+            }
+        }
+        self.lsp_diagnostics
+            .retain(|_, (server_key, _)| server_key != key);
     }
 
-    fn settings(&self) -> Element<'_, Message> {
-        let app_theme_selected = match self.config.app_theme {
-            AppTheme::Dark => 1,
-            AppTheme::Light => 2,
-            AppTheme::System => 0,
-        };
-        let dark_selected = self
-            .theme_names
-            .iter()
-            .position(|theme_name| theme_name == &self.config.syntax_theme_dark);
-        let light_selected = self
-            .theme_names
-            .iter()
-            .position(|theme_name| theme_name == &self.config.syntax_theme_light);
-        let font_selected = {
-            let mut font_system = font_system().write().unwrap();
-            let current_font_name = font_system.raw().db().family_name(&Family::Monospace);
-            self.font_names
-                .iter()
-                .position(|font_name| font_name == current_font_name)
+    /// The client of the language server a tab's file is opened with
+    fn lsp_client(&self, entity: segmented_button::Entity) -> Option<(&LspClient, &LspDocument)> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return None;
         };
-        let font_size_selected = self
-            .font_sizes
-            .iter()
-            .position(|font_size| font_size == &self.config.font_size);
-        let zoom_step_selected = self
-            .zoom_steps
-            .iter()
-            .position(|zoom_step| zoom_step == &self.config.font_size_zoom_step_mul_100);
-        widget::settings::view_column(vec![
-            widget::settings::section()
-                .title(fl!("appearance"))
-                .add(
-                    widget::settings::item::builder(fl!("theme")).control(widget::dropdown(
-                        &self.app_themes,
-                        Some(app_theme_selected),
-                        move |index| {
-                            Message::AppTheme(match index {
-                                1 => AppTheme::Dark,
-                                2 => AppTheme::Light,
-                                _ => AppTheme::System,
-                            })
-                        },
-                    )),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("syntax-dark")).control(widget::dropdown(
-                        &self.theme_names,
-                        dark_selected,
-                        move |index| Message::SyntaxTheme(index, true),
-                    )),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("syntax-light")).control(widget::dropdown(
-                        &self.theme_names,
-                        light_selected,
-                        move |index| Message::SyntaxTheme(index, false),
-                    )),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("default-font")).control(widget::dropdown(
-                        &self.font_names,
-                        font_selected,
-                        Message::DefaultFont,
-                    )),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("default-font-size")).control(
-                        widget::dropdown(&self.font_size_names, font_size_selected, |index| {
-                            Message::DefaultFontSize(index)
-                        }),
-                    ),
-                )
-                .add(
-                    widget::settings::item::builder(fl!("default-zoom-step")).control(
-                        widget::dropdown(&self.zoom_step_names, zoom_step_selected, |index| {
-                            Message::DefaultZoomStep(index)
-                        }),
-                    ),
-                )
-                .into(),
-            widget::settings::section()
-                .title(fl!("keyboard-shortcuts"))
-                .add(
-                    widget::settings::item::builder(fl!("enable-vim-bindings"))
-                        .toggler(self.config.vim_bindings, Message::VimBindings),
-                )
-                .into(),
-        ])
-        .into()
+        let document = tab.lsp_document_opt.as_ref()?;
+        match &self.lsp_servers.get(&document.key)?.state {
+            LspServerState::Running(client) => Some((client, document)),
+            LspServerState::Starting | LspServerState::Failed => None,
+        }
     }
-}
-
-/// Implement [`cosmic::Application`] to integrate with COSMIC.
-impl Application for App {
-    /// Default async executor to use with the app.
-    type Executor = executor::Default;
-
-    /// Argument received [`cosmic::Application::new`].
-    type Flags = Flags;
-
-    /// Message type specific to our [`App`].
-    type Message = Message;
 
-    /// The unique application ID to supply to the window manager.
-    const APP_ID: &'static str = "com.system76.CosmicEdit";
-
-    fn core(&self) -> &Core {
-        &self.core
+    /// Send changes of a tab to its language server after edits have stopped for a moment
+    fn update_lsp_change(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        if tab.lsp_document_opt.is_none() {
+            return Task::none();
+        }
+        tab.lsp_generation = tab.lsp_generation.wrapping_add(1);
+        let generation = tab.lsp_generation;
+        Task::perform(
+            async move {
+                time::sleep(time::Duration::from_millis(250)).await;
+                action::app(Message::LspChange(entity, generation))
+            },
+            |x| x,
+        )
     }
 
-    fn core_mut(&mut self) -> &mut Core {
-        &mut self.core
+    /// Send the text of a tab to its language server if it changed since it was last sent
+    fn lsp_sync(&mut self, entity: segmented_button::Entity) {
+        let Some((client, document)) = self.lsp_client(entity) else {
+            return;
+        };
+        let (client, path) = (client.clone(), document.path.clone());
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return;
+        };
+        let text = tab.text();
+        let text_hash = stable_hash(text.as_bytes());
+        if text_hash == tab.lsp_text_hash {
+            return;
+        }
+        tab.lsp_text_hash = text_hash;
+        tab.lsp_version = tab.lsp_version.wrapping_add(1);
+        if let Some(uri) = lsp::path_uri(&path) {
+            client.did_change(uri, tab.lsp_version, text);
+        }
     }
 
-    /// Creates the application, and optionally emits command on initialize.
-    fn init(mut core: Core, flags: Self::Flags) -> (Self, Task<Self::Message>) {
-        core.window.context_is_overlay = false;
+    /// Tell the language server of a tab that its file was saved
+    fn lsp_did_save(&mut self, entity: segmented_button::Entity) {
+        self.lsp_sync(entity);
+        if let Some((client, document)) = self.lsp_client(entity)
+            && let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity)
+            && !tab.changed()
+            && let Some(uri) = lsp::path_uri(&document.path)
+        {
+            client.did_save(uri, &tab.text());
+        }
+    }
 
-        // Update font name from config
+    /// Close the file of a tab with its language server
+    fn lsp_close(&mut self, entity: segmented_button::Entity) {
+        if let Some((client, document)) = self.lsp_client(entity)
+            && let Some(uri) = lsp::path_uri(&document.path)
         {
-            let mut font_system = font_system().write().unwrap();
-            font_system
-                .raw()
-                .db_mut()
-                .set_monospace_family(&flags.config.font_name);
+            client.did_close(uri);
+        }
+        if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+            tab.clear_lsp();
         }
+    }
 
-        let app_themes = vec![fl!("match-desktop"), fl!("dark"), fl!("light")];
+    /// Update completions and signature help of a tab after typing or deleting, or when they are
+    /// asked for
+    fn lsp_complete(
+        &mut self,
+        entity: segmented_button::Entity,
+        trigger: CompletionTrigger,
+        point: Point,
+    ) -> Task<Message> {
+        // Code actions are for the text before it was edited
+        self.lsp_code_actions_opt = None;
+        if trigger == CompletionTrigger::Cancelled {
+            self.lsp_completion_opt = None;
+            self.lsp_signature_help_opt = None;
+            if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                tab.snippet_opt = None;
+            }
+            return Task::none();
+        }
+        let Some((client, document)) = self.lsp_client(entity) else {
+            return Task::none();
+        };
+        let (client, path) = (client.clone(), document.path.clone());
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return Task::none();
+        };
+        let (cursor, word_start, word) = tab.completion_word();
+        let encoding = client.position_encoding();
+        let position = tab.lsp_position(encoding);
+        let typed_opt = match trigger {
+            CompletionTrigger::Typed(c) => Some(c.to_string()),
+            _ => None,
+        };
+        let triggered_by = |characters: &[String]| {
+            typed_opt
+                .as_ref()
+                .filter(|typed| characters.contains(typed))
+                .cloned()
+        };
 
-        let font_names = {
-            let mut font_names = Vec::new();
-            let mut font_system = font_system().write().unwrap();
-            for face in font_system.raw().db().faces() {
-                if face.monospaced {
-                    //TODO: get localized name if possible
-                    let font_name = face
-                        .families
-                        .first()
-                        .map_or_else(|| face.post_script_name.to_string(), |x| x.0.to_string());
-                    if !font_names.contains(&font_name) {
-                        font_names.push(font_name);
+        // Completions are kept and filtered while typing the same word
+        let completion_trigger_opt = triggered_by(client.completion_trigger_characters());
+        let completion_opt = self.lsp_completion_opt.as_mut().filter(|completion| {
+            completion.entity == entity && completion.word_start == word_start
+        });
+        let request_completion = match trigger {
+            _ if completion_trigger_opt.is_some() => true,
+            CompletionTrigger::Invoked => true,
+            CompletionTrigger::Typed(c) if is_word_char(c) => completion_opt
+                .as_ref()
+                .map_or(word.chars().count() == 1, |completion| {
+                    completion.list.is_incomplete
+                }),
+            CompletionTrigger::Deleted => {
+                !word.is_empty()
+                    && completion_opt
+                        .as_ref()
+                        .is_some_and(|completion| completion.list.is_incomplete)
+            }
+            _ => false,
+        };
+        let mut tasks = Vec::new();
+        if !request_completion {
+            match completion_opt {
+                Some(completion) if !word.is_empty() => {
+                    completion.list.filter(&word);
+                    if completion.list.matches.is_empty() {
+                        self.lsp_completion_opt = None;
+                    } else {
+                        tasks.push(self.lsp_resolve_completion(false));
                     }
                 }
+                _ => self.lsp_completion_opt = None,
             }
-            font_names.sort();
-            font_names
-        };
-
-        let mut font_size_names = Vec::new();
-        let mut font_sizes = Vec::new();
-        for font_size in 4..=32 {
-            font_size_names.push(format!("{}px", font_size));
-            font_sizes.push(font_size);
         }
 
-        let mut theme_names =
-            Vec::with_capacity(SYNTAX_SYSTEM.get().unwrap().theme_set.themes.len());
-        for (theme_name, _theme) in SYNTAX_SYSTEM.get().unwrap().theme_set.themes.iter() {
-            theme_names.push(theme_name.to_string());
+        // Signature help is updated while it is shown
+        let (triggers, retriggers) = client.signature_help_trigger_characters();
+        let signature_help_trigger_opt =
+            triggered_by(triggers).or_else(|| triggered_by(retriggers));
+        let active_opt = self
+            .lsp_signature_help_opt
+            .as_ref()
+            .filter(|signature_help| signature_help.entity == entity)
+            .map(|signature_help| signature_help.help.clone());
+        let request_signature_help = triggered_by(triggers).is_some() || active_opt.is_some();
+
+        if !request_completion && !request_signature_help {
+            return Task::batch(tasks);
         }
-
-        let mut zoom_step_names = Vec::new();
-        let mut zoom_steps = Vec::new();
-        for zoom_step in [25, 50, 75, 100, 150, 200] {
-            zoom_step_names.push(format!("{}px", f32::from(zoom_step) / 100.0));
-            zoom_steps.push(zoom_step);
+        let Some(uri) = lsp::path_uri(&path) else {
+            return Task::batch(tasks);
+        };
+        // The server must know what was typed
+        self.lsp_sync(entity);
+
+        if request_completion {
+            self.lsp_completion_generation = self.lsp_completion_generation.wrapping_add(1);
+            let generation = self.lsp_completion_generation;
+            let (client, uri) = (client.clone(), uri.clone());
+            tasks.push(Task::perform(
+                async move {
+                    match client
+                        .completion(uri, position, completion_trigger_opt)
+                        .await
+                    {
+                        Ok(response_opt) => {
+                            let (items, is_incomplete) = match response_opt {
+                                Some(lsp_types::CompletionResponse::Array(items)) => (items, false),
+                                Some(lsp_types::CompletionResponse::List(list)) => {
+                                    (list.items, list.is_incomplete)
+                                }
+                                None => (Vec::new(), false),
+                            };
+                            action::app(Message::LspCompletionResult(
+                                generation,
+                                LspCompletion {
+                                    entity,
+                                    point,
+                                    word_start,
+                                    request_cursor: cursor,
+                                    list: CompletionList::new(items, is_incomplete),
+                                    resolved: BTreeSet::new(),
+                                },
+                            ))
+                        }
+                        Err(err) => {
+                            log::warn!("failed to get completions: {}", err);
+                            action::none()
+                        }
+                    }
+                },
+                |x| x,
+            ));
         }
+        if request_signature_help {
+            self.lsp_signature_help_generation = self.lsp_signature_help_generation.wrapping_add(1);
+            let generation = self.lsp_signature_help_generation;
+            tasks.push(Task::perform(
+                async move {
+                    match client
+                        .signature_help(uri, position, signature_help_trigger_opt, active_opt)
+                        .await
+                    {
+                        Ok(help_opt) => action::app(Message::LspSignatureHelp(
+                            generation,
+                            help_opt.map(|help| LspSignatureHelp {
+                                entity,
+                                point,
+                                help,
+                                encoding,
+                            }),
+                        )),
+                        Err(err) => {
+                            log::warn!("failed to get signature help: {}", err);
+                            action::none()
+                        }
+                    }
+                },
+                |x| x,
+            ));
+        }
+        Task::batch(tasks)
+    }
 
-        let about = About::default()
-            .name(fl!("cosmic-text-editor"))
-            .icon(icon::from_name(Self::APP_ID))
-            .version(env!("CARGO_PKG_VERSION"))
-            .author("System76")
-            .comments(fl!("comment"))
-            .license("GPL-3.0-only")
-            .developers([("Jeremy Soller", "jeremy@system76.com")])
-            .links([
-                (fl!("repository"), "https://github.com/pop-os/cosmic-edit"),
-                (
-                    fl!("support"),
-                    "https://github.com/pop-os/cosmic-edit/issues",
-                ),
-            ]);
-
-        let mut app = App {
-            core,
-            about,
-            nav_model: nav_bar::Model::builder().build(),
-            tab_model: segmented_button::Model::builder().build(),
-            config_handler: flags.config_handler,
-            config: flags.config,
-            config_state_handler: flags.config_state_handler,
-            config_state: flags.config_state,
-            key_binds: key_binds(),
-            zoom_step_names,
-            zoom_steps,
-            app_themes,
-            font_names,
-            font_size_names,
-            font_sizes,
-            theme_names,
-            context_page: ContextPage::Settings,
-            text_box_id: widget::Id::unique(),
-            auto_scroll: None,
-            dialog_opt: None,
-            dialog_page_opt: None,
-            find_opt: None,
-            find_replace_id: widget::Id::unique(),
-            find_replace_value: String::new(),
-            find_search_id: widget::Id::unique(),
-            find_search_value: String::new(),
-            git_project_status: None,
-            projects: Vec::new(),
-            project_search_id: widget::Id::unique(),
-            project_search_value: String::new(),
-            project_search_result: None,
-            project_search_has_focus: false,
-            watcher_opt: None,
-            modifiers: Modifiers::empty(),
+    /// Resolve the selected completion item if the server leaves out details, and insert it
+    /// afterwards if `insert`
+    fn lsp_resolve_completion(&mut self, insert: bool) -> Task<Message> {
+        let Some(completion) = &self.lsp_completion_opt else {
+            return Task::none();
         };
-
-        // Do not show nav bar by default. Will be opened by open_project if needed
-        app.core.nav_bar_set_toggled(false);
-        for arg in env::args().skip(1) {
-            let path = PathBuf::from(arg);
-            if path.is_dir() {
-                app.open_project(path);
+        let Some(item_i) = completion
+            .list
+            .matches
+            .get(completion.list.selected)
+            .copied()
+        else {
+            return Task::none();
+        };
+        if completion.resolved.contains(&item_i) {
+            return if insert {
+                self.lsp_insert_completion(item_i)
             } else {
-                app.open_tab(Some(path));
-            }
+                Task::none()
+            };
         }
+        let Some((client, _)) = self.lsp_client(completion.entity) else {
+            return Task::none();
+        };
+        let client = client.clone();
+        let item = completion.list.items[item_i].clone();
+        let generation = self.lsp_completion_generation;
+        Task::perform(
+            async move {
+                let item = match client.resolve_completion_item(item.clone()).await {
+                    Ok(ok) => ok,
+                    Err(err) => {
+                        log::warn!("failed to resolve completion {:?}: {}", item.label, err);
+                        item
+                    }
+                };
+                action::app(Message::LspCompletionResolved(
+                    generation, item_i, item, insert,
+                ))
+            },
+            |x| x,
+        )
+    }
 
-        app.update_nav_bar_placeholder();
-
-        // Open an empty file if no arguments provided
-        if app.tab_model.iter().next().is_none() {
-            app.open_tab(None);
+    /// Insert a completion item into its tab and close completions
+    fn lsp_insert_completion(&mut self, item_i: usize) -> Task<Message> {
+        let Some(completion) = self.lsp_completion_opt.take() else {
+            return Task::none();
+        };
+        let Some(item) = completion.list.items.get(item_i) else {
+            return Task::none();
+        };
+        let Some((client, _)) = self.lsp_client(completion.entity) else {
+            return Task::none();
+        };
+        let encoding = client.position_encoding();
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(completion.entity) else {
+            return Task::none();
+        };
+        // The cursor may have been moved out of the word
+        let (cursor, _, _) = tab.completion_word();
+        if cursor.line != completion.word_start.line || cursor < completion.word_start {
+            return Task::none();
         }
-
-        //TODO: try update_config here? It breaks loading system theme by default
-        let command = app.update_tab();
-        (app, command)
+        tab.insert_lsp_completion(
+            encoding,
+            item,
+            completion.word_start,
+            completion.request_cursor,
+        );
+        self.update(Message::TabChanged(completion.entity))
     }
 
-    // The default nav_bar widget needs to be condensed for cosmic-edit
-    fn nav_bar(&self) -> Option<Element<'_, action::Action<Self::Message>>> {
-        if !self.core().nav_bar_active() {
-            return None;
+    /// Options of the project search page
+    fn project_search_options(&self) -> ProjectSearchOptions {
+        ProjectSearchOptions {
+            use_regex: self.config.find_use_regex,
+            case_sensitive: self.config.find_case_sensitive,
+            whole_word: self.config.find_whole_word,
+            include: self.project_search_include_value.clone(),
+            exclude: self.project_search_exclude_value.clone(),
+            max_file_size: match self.config.project_search_max_file_size {
+                0 => None,
+                max_file_size => Some(max_file_size),
+            },
+            context_before: self.config.project_search_context_before.into(),
+            context_after: self.config.project_search_context_after.into(),
+            open_tabs_only: self.project_search_open_tabs_only,
         }
+    }
 
-        let nav_model = self.nav_model()?;
-
-        let cosmic_theme::Spacing {
-            space_none,
-            space_s,
-            space_xxxs,
-            ..
-        } = self.core().system_theme().cosmic().spacing;
-
-        let mut nav = segmented_button::vertical(nav_model)
-            .button_height(space_xxxs + 20 /* line height */ + space_xxxs)
-            .button_padding([space_s, space_xxxs, space_s, space_xxxs])
-            .button_spacing(space_xxxs)
-            .on_activate(|entity| action::cosmic(cosmic::app::Action::NavBar(entity)))
-            .spacing(space_none)
-            .style(theme::SegmentedButton::FileNav)
-            .apply(widget::container)
-            .padding(space_s)
-            .width(Length::Shrink);
-
-        if !self.core().is_condensed() {
-            nav = nav.max_width(280);
+    /// Search the projects for the value of the project search page
+    fn start_project_search(&mut self, options: ProjectSearchOptions) -> Task<Message> {
+        // A new query cancels any running search
+        if let Some(cancel) = self.project_search_cancel_opt.take() {
+            cancel.store(true, Ordering::Relaxed);
         }
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.project_search_cancel_opt = Some(cancel.clone());
+
+        self.project_search_generation = self.project_search_generation.wrapping_add(1);
+        let id = self.project_search_generation;
+        let project_search_result =
+            ProjectSearchResult::new(id, self.project_search_value.clone(), options);
+        self.project_search_result = Some(project_search_result.clone());
+
+        let projects = self.projects.clone();
+        // Open tabs are searched instead of their files on disk
+        let mut buffers = Vec::new();
+        for entity in self.tab_model.iter() {
+            if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                buffers.push(BufferSearchInput {
+                    entity,
+                    path_opt: tab.path_opt.clone(),
+                    title: tab.title(),
+                    text: tab.text(),
+                    changed: tab.changed(),
+                });
+            }
+        }
+        Task::stream(stream::channel(
+            16,
+            move |mut output: futures::channel::mpsc::Sender<cosmic::Action<Message>>| async move {
+                let (file_tx, mut file_rx) = futures::channel::mpsc::unbounded();
+                let task = tokio::task::spawn_blocking(move || {
+                    project_search_result.search_projects(projects, buffers, &cancel, |file| {
+                        // Fails only if the search was abandoned
+                        let _ = file_tx.unbounded_send(file);
+                    })
+                });
 
-        Some(
-            nav.apply(widget::scrollable)
-                .apply(widget::container)
-                .height(Length::Fill)
-                .class(theme::Container::custom(nav_bar::nav_bar_style))
-                .into(),
-        )
+                // Stream results to the UI as files complete
+                while let Some(file_search_result) = file_rx.next().await {
+                    if let Err(err) = output
+                        .send(action::app(Message::ProjectSearchFile(
+                            id,
+                            file_search_result,
+                        )))
+                        .await
+                    {
+                        log::warn!("failed to send search result: {:?}", err);
+                        break;
+                    }
+                }
+
+                let error_opt = match task.await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(err)) => Some(err),
+                    Err(err) => Some(format!("failed to run search task: {}", err)),
+                };
+                if let Err(err) = output
+                    .send(action::app(Message::ProjectSearchFinished(id, error_opt)))
+                    .await
+                {
+                    log::warn!("failed to send search finished: {:?}", err);
+                }
+            },
+        ))
     }
 
-    fn nav_model(&self) -> Option<&nav_bar::Model> {
-        Some(&self.nav_model)
+    /// Open a file at a position of its language server, or at the start of a line for
+    /// positions from ctags
+    fn open_lsp_position(
+        &mut self,
+        path: PathBuf,
+        encoding: LspPositionEncoding,
+        position: lsp_types::Position,
+    ) -> Task<Message> {
+        if let Some(entity) = self.open_tab(Some(path))
+            && let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity)
+        {
+            let cursor = tab.lsp_cursor(encoding, position);
+            return Task::batch([
+                //TODO: why must this be done in a command?
+                Task::perform(
+                    async move { action::app(Message::TabSetCursor(entity, cursor)) },
+                    |x| x,
+                ),
+                self.update_tab(),
+            ]);
+        }
+        Task::none()
     }
 
-    fn on_app_exit(&mut self) -> Option<Message> {
-        Some(Message::Quit)
+    /// Folder that ctags indexes for a file, the project containing it or else its folder
+    fn ctags_root(&self, path_opt: Option<&Path>) -> Option<PathBuf> {
+        match path_opt {
+            Some(path) => self
+                .projects
+                .iter()
+                .map(|(_, project_path)| project_path)
+                .find(|project_path| path.starts_with(project_path))
+                .cloned()
+                .or_else(|| path.parent().map(Path::to_path_buf)),
+            None => self
+                .projects
+                .first()
+                .map(|(_, project_path)| project_path.clone()),
+        }
     }
 
-    fn on_context_drawer(&mut self) -> Task<Message> {
-        // Focus correct widget
-        self.update_focus()
+    /// Cache of the tags of the folder at `root` if it is a project, whose changes are watched
+    fn tags_cache(&self, root: &Path) -> Option<TagsCache> {
+        self.projects
+            .iter()
+            .any(|(_, project_path)| project_path == root)
+            .then(|| self.tags_cache.clone())
     }
 
-    //TODO: currently the first escape unfocuses, and the second calls this function
-    fn on_escape(&mut self) -> Task<Message> {
-        if self.core.window.show_context {
-            // Close context drawer if open
-            self.core.window.show_context = false;
-        } else if self.find_opt.is_some() {
-            // Close find if open
-            self.find_opt = None;
+    /// Find definitions or references of the symbol at the cursor of the active tab. Without a
+    /// language server, definitions are looked up with ctags and references are searched as
+    /// text.
+    fn navigate(&mut self, request: NavRequest) -> Task<Message> {
+        let entity = self.tab_model.active();
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return Task::none();
+        };
+        let word = tab.cursor_word();
+        let root_opt = self
+            .ctags_root(tab.path_opt.as_deref())
+            .map(|root| (self.tags_cache(&root), root));
+        let path_opt = tab.path_opt.clone();
+        let lsp_opt = self.lsp_client(entity).and_then(|(client, document)| {
+            let uri = lsp::path_uri(&document.path)?;
+            let encoding = client.position_encoding();
+            Some((client.clone(), uri, tab.lsp_position(encoding)))
+        });
+        if lsp_opt.is_none() && word.is_empty() {
+            return Task::none();
         }
+        let encoding = match &lsp_opt {
+            Some((client, _, _)) => {
+                // The server must know the text the position is in
+                let encoding = client.position_encoding();
+                self.lsp_sync(entity);
+                encoding
+            }
+            None => LspPositionEncoding::Utf16,
+        };
 
-        // Focus correct widget
-        self.update_focus()
+        self.navigation_generation = self.navigation_generation.wrapping_add(1);
+        let generation = self.navigation_generation;
+        Task::perform(
+            async move {
+                let lsp_result = match lsp_opt {
+                    Some((client, uri, position)) => match request {
+                        NavRequest::Definition => client.definition(uri, position).await,
+                        NavRequest::TypeDefinition => client.type_definition(uri, position).await,
+                        NavRequest::References => client.references(uri, position).await,
+                    },
+                    None => Ok(None),
+                };
+                let result = match lsp_result {
+                    Ok(Some(locations)) => Ok(navigation::location_items(locations)),
+                    Err(err) => Err(err),
+                    Ok(None) if request == NavRequest::References => {
+                        return action::app(Message::ProjectSearchWord(word));
+                    }
+                    Ok(None) => match root_opt {
+                        Some((cache_opt, root)) => {
+                            let tags_result = navigation::load_tags(cache_opt, root, None).await;
+                            tags_result.map(|tags| {
+                                let mut tags: Vec<_> = tags
+                                    .iter()
+                                    .filter(|tag| {
+                                        tag.name == word
+                                            && (request == NavRequest::Definition || tag.is_type())
+                                    })
+                                    .collect();
+                                // Definitions in the same file come first
+                                tags.sort_by_key(|tag| Some(&tag.path) != path_opt.as_ref());
+                                navigation::tag_items(tags)
+                            })
+                        }
+                        None => Ok(Vec::new()),
+                    },
+                };
+                action::app(Message::NavigationResult(
+                    generation,
+                    NavResults {
+                        request,
+                        word,
+                        encoding,
+                        result,
+                    },
+                ))
+            },
+            |x| x,
+        )
     }
 
-    fn on_nav_select(&mut self, id: nav_bar::Id) -> Task<Message> {
-        // Toggle open state and get clone of node data
-        let node_opt = match self.nav_model.data_mut::<ProjectNode>(id) {
-            Some(node) => {
-                if let ProjectNode::Folder { open, .. } = node {
-                    *open = !*open;
-                }
-                Some(node.clone())
-            }
-            None => None,
+    /// Load symbols of the symbol search, from the language server of its tab or from ctags
+    fn update_symbol_search(&mut self) -> Task<Message> {
+        let Some(search) = &self.symbol_search_opt else {
+            return Task::none();
         };
+        let (entity, workspace, query) = (search.entity, search.workspace, search.query.clone());
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return Task::none();
+        };
+        let path_opt = tab.path_opt.clone();
+        let roots: Vec<PathBuf> = if workspace && !self.projects.is_empty() {
+            self.projects
+                .iter()
+                .map(|(_, project_path)| project_path.clone())
+                .collect()
+        } else {
+            self.ctags_root(path_opt.as_deref()).into_iter().collect()
+        };
+        let roots: Vec<_> = roots
+            .into_iter()
+            .map(|root| (self.tags_cache(&root), root))
+            .collect();
+        let lsp_opt = self
+            .lsp_client(entity)
+            .map(|(client, document)| (client.clone(), document.path.clone()));
+        if lsp_opt.is_some() && !workspace {
+            self.lsp_sync(entity);
+        }
 
-        match node_opt {
-            Some(node) => {
-                // Update icon
-                self.nav_model.icon_set(id, node.icon(16));
-
-                match node {
-                    ProjectNode::Folder { path, open, .. } => {
-                        let position = self.nav_model.position(id).unwrap_or(0);
-                        let indent = self.nav_model.indent(id).unwrap_or(0);
-                        if open {
-                            // Open folder
-                            self.open_folder(path, position + 1, indent + 1);
-                        } else {
-                            // Close folder
-                            while let Some(child_id) = self.nav_model.entity_at(position + 1) {
-                                if self.nav_model.indent(child_id).unwrap_or(0) > indent {
-                                    self.nav_model.remove(child_id);
-                                } else {
+        self.symbol_search_generation = self.symbol_search_generation.wrapping_add(1);
+        let generation = self.symbol_search_generation;
+        Task::perform(
+            async move {
+                let lsp_result = match lsp_opt {
+                    Some((client, _)) if workspace => client
+                        .workspace_symbols(query)
+                        .await
+                        .map(|response_opt| response_opt.map(navigation::workspace_symbol_items)),
+                    Some((client, path)) => match lsp::path_uri(&path) {
+                        Some(uri) => client.document_symbols(uri).await.map(|response_opt| {
+                            response_opt
+                                .map(|response| navigation::document_symbol_items(&path, response))
+                        }),
+                        None => Ok(None),
+                    },
+                    None => Ok(None),
+                };
+                let (from_server, result) = match lsp_result {
+                    Ok(Some(items)) => (true, Ok(items)),
+                    Err(err) => (true, Err(err)),
+                    Ok(None) => {
+                        let mut result = Ok(Vec::new());
+                        for (cache_opt, root) in roots {
+                            // Only the tags of the file are needed for its symbols
+                            let file_opt = if workspace { None } else { path_opt.clone() };
+                            match navigation::load_tags(cache_opt, root, file_opt).await {
+                                Ok(tags) => {
+                                    if let Ok(items) = &mut result {
+                                        items.extend(navigation::tag_items(tags.iter()));
+                                    }
+                                }
+                                Err(err) => {
+                                    result = Err(err);
                                     break;
                                 }
                             }
                         }
-
-                        // Prevent nav bar from closing when selecting a
-                        // folder in condensed mode.
-                        self.core_mut().nav_bar_set_toggled(true);
-
-                        Task::none()
-                    }
-                    ProjectNode::File { path, .. } => {
-                        //TODO: go to already open file if possible
-                        self.update(Message::OpenFile(path))
+                        (false, result)
                     }
-                }
-            }
-            None => {
-                // Open project
-                self.update(Message::OpenProjectDialog)
-            }
-        }
+                };
+                action::app(Message::SymbolSearchResult(generation, from_server, result))
+            },
+            |x| x,
+        )
     }
 
-    fn dialog(&self) -> Option<Element<'_, Self::Message>> {
-        let Some(ref dialog) = self.dialog_page_opt else {
-            return None;
+    /// Open a symbol of the symbol search and close it
+    fn open_symbol(&mut self, item_i: usize) -> Task<Message> {
+        let Some(search) = &self.symbol_search_opt else {
+            return Task::none();
         };
+        let Some(Ok(items)) = &search.result_opt else {
+            return Task::none();
+        };
+        let Some(item) = items.get(item_i) else {
+            return Task::none();
+        };
+        let (path, encoding, position) = (item.path.clone(), search.encoding, item.position);
+        self.core.window.show_context = false;
+        self.open_lsp_position(path, encoding, position)
+    }
 
-        let cosmic_theme::Spacing { space_xxs, .. } = self.core().system_theme().cosmic().spacing;
+    /// Text of editor tabs by the path of their file
+    fn open_texts(&self) -> HashMap<PathBuf, String> {
+        self.tab_model
+            .iter()
+            .filter_map(|entity| match self.tab_model.data::<Tab>(entity) {
+                Some(Tab::Editor(tab)) => Some((tab.path_opt.clone()?, tab.text())),
+                _ => None,
+            })
+            .collect()
+    }
 
-        match dialog {
-            DialogPage::PromptSaveClose(entity) => {
-                let save_button =
-                    widget::button::suggested(fl!("save")).on_press(Message::Save(Some(*entity)));
-                let discard_button = widget::button::destructive(fl!("discard"))
-                    .on_press(Message::TabCloseForce(*entity));
-                let cancel_button =
-                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
-                let dialog = widget::dialog()
-                    .title(fl!("prompt-save-changes-title"))
-                    .body(fl!("prompt-unsaved-changes"))
-                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
-                    .primary_action(save_button)
-                    .secondary_action(discard_button)
-                    .tertiary_action(cancel_button);
-                Some(dialog.into())
+    /// Apply edits of a language server as single changes for undo in open tabs and on disk for
+    /// other files. Files that changed since the edits were made are left alone.
+    fn apply_file_changes(
+        &mut self,
+        encoding: LspPositionEncoding,
+        changes: Vec<FileChange>,
+    ) -> Task<Message> {
+        let retention = self.config.local_history_retention();
+        let mut errors = Vec::new();
+        let mut tasks = Vec::new();
+        let mut local_history_saves = Vec::new();
+        for change in changes {
+            let entity_opt = self.tab_model.iter().find(|entity| {
+                matches!(
+                    self.tab_model.data::<Tab>(*entity),
+                    Some(Tab::Editor(tab)) if tab.path_opt.as_ref() == Some(&change.path)
+                )
+            });
+            let result = match entity_opt {
+                Some(entity) => match self.tab_model.data_mut::<Tab>(entity) {
+                    Some(Tab::Editor(tab)) if tab.text() == change.text => {
+                        let result = tab.apply_lsp_edits(encoding, &change.edits);
+                        if result.is_ok() {
+                            // The server must know the new text before it is asked again
+                            self.lsp_sync(entity);
+                            tasks.push(self.update(Message::TabChanged(entity)));
+                        }
+                        result
+                    }
+                    _ => Err(fl!("edit-file-changed")),
+                },
+                None => match workspace_edit::apply(&change.text, encoding, &change.edits) {
+                    Ok(text) => {
+                        let result = workspace_edit::write_file(&change.path, &change.text, &text)
+                            .map_err(|err| err.to_string());
+                        // The old text is kept in the local history, so the edit can be undone
+                        if result.is_ok() {
+                            local_history_saves.push(LocalHistorySave {
+                                path: change.path.clone(),
+                                text: change.text,
+                                retention,
+                            });
+                        }
+                        result
+                    }
+                    Err(err) => Err(err),
+                },
+            };
+            if let Err(err) = result {
+                errors.push(format!("{}: {}", change.path.display(), err));
             }
-            DialogPage::PromptSaveQuit(entities) => {
-                let mut can_save_all = true;
-                let mut column = widget::column::with_capacity(entities.len()).spacing(space_xxs);
-                for entity in entities.iter() {
-                    if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(*entity) {
-                        let mut row = widget::row::with_capacity(3).align_y(Alignment::Center);
-                        row = row.push(widget::text(tab.title()));
-                        row = row.push(widget::space::horizontal());
-                        if let Some(_path) = &tab.path_opt {
-                            row = row.push(
-                                widget::button::standard(fl!("save"))
-                                    .on_press(Message::Save(Some(*entity))),
-                            );
-                            //TODO row = row.push(widget::text(format!("{}", path.display())));
-                        } else {
-                            row = row.push(
-                                widget::button::standard(fl!("save-as"))
-                                    .on_press(Message::SaveAsDialog(Some(*entity))),
-                            );
-                            can_save_all = false;
+        }
+        if !errors.is_empty() {
+            self.lsp_error_opt = Some(errors.join("\n"));
+        }
+        tasks.push(self.write_local_history(local_history_saves));
+        Task::batch(tasks)
+    }
+
+    /// Load the edits of the rename, to be previewed before they are applied
+    fn update_rename(&mut self) -> Task<Message> {
+        let Some(rename) = &self.rename_opt else {
+            return Task::none();
+        };
+        let (entity, encoding, position, name) = (
+            rename.entity,
+            rename.encoding,
+            rename.position,
+            rename.name.clone(),
+        );
+        if name.is_empty() || name == rename.old_name {
+            return Task::none();
+        }
+        let Some((client, document)) = self.lsp_client(entity) else {
+            return Task::none();
+        };
+        let (client, path) = (client.clone(), document.path.clone());
+        let Some(uri) = lsp::path_uri(&path) else {
+            return Task::none();
+        };
+        self.lsp_sync(entity);
+        let open_texts = self.open_texts();
+        if let Some(rename) = &mut self.rename_opt {
+            rename.loading = true;
+        }
+        self.rename_generation = self.rename_generation.wrapping_add(1);
+        let generation = self.rename_generation;
+        Task::perform(
+            async move {
+                let result = match client.rename(uri, position, name).await {
+                    Ok(Some(edit)) => workspace_edit::load(edit, encoding, &open_texts),
+                    Ok(None) => Err(fl!("rename-unsupported")),
+                    Err(err) => Err(err),
+                };
+                action::app(Message::RenameResult(generation, result))
+            },
+            |x| x,
+        )
+    }
+
+    /// Ask the language server of a tab for code actions at the selection, or at the diagnostics
+    /// of line `line_opt` when its marker was clicked
+    fn lsp_code_actions(
+        &mut self,
+        entity: segmented_button::Entity,
+        line_opt: Option<usize>,
+        point: Point,
+    ) -> Task<Message> {
+        let Some((client, document)) = self.lsp_client(entity) else {
+            return Task::none();
+        };
+        let (client, path) = (client.clone(), document.path.clone());
+        let Some(uri) = lsp::path_uri(&path) else {
+            return Task::none();
+        };
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        tab.context_menu = None;
+        let encoding = client.position_encoding();
+        let range = match line_opt {
+            Some(line_i) => tab.lsp_line_range(encoding, line_i),
+            None => tab.lsp_selection(encoding),
+        };
+        // Diagnostics on the lines of the range are sent along, so they can be fixed
+        let diagnostics: Vec<lsp_types::Diagnostic> = self
+            .lsp_diagnostics
+            .get(&path)
+            .map(|(_, diagnostics)| {
+                diagnostics
+                    .iter()
+                    .filter(|diagnostic| {
+                        diagnostic.range.start.line <= range.end.line
+                            && diagnostic.range.end.line >= range.start.line
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.lsp_sync(entity);
+
+        self.lsp_completion_opt = None;
+        self.lsp_signature_help_opt = None;
+        self.lsp_code_actions_opt = Some(LspCodeActions {
+            entity,
+            point,
+            result_opt: None,
+            selected: 0,
+        });
+        self.lsp_code_actions_generation = self.lsp_code_actions_generation.wrapping_add(1);
+        let generation = self.lsp_code_actions_generation;
+        Task::perform(
+            async move {
+                let mut result = client
+                    .code_actions(uri, range, diagnostics)
+                    .await
+                    .map(Option::unwrap_or_default);
+                // Preferred fixes come first
+                if let Ok(actions) = &mut result {
+                    actions.sort_by_key(|action| match action {
+                        lsp_types::CodeActionOrCommand::CodeAction(action) => {
+                            action.is_preferred != Some(true)
                         }
+                        lsp_types::CodeActionOrCommand::Command(_) => true,
+                    });
+                }
+                action::app(Message::LspCodeActionsResult(generation, result))
+            },
+            |x| x,
+        )
+    }
 
-                        column = column.push(row);
+    /// Apply a code action and close the code actions. The edit of the action is loaded first, as
+    /// servers may leave it out until an action is picked.
+    fn lsp_apply_code_action(&mut self, action_i: usize) -> Task<Message> {
+        let Some(code_actions) = self.lsp_code_actions_opt.take() else {
+            return Task::none();
+        };
+        let Some(Ok(actions)) = code_actions.result_opt else {
+            return Task::none();
+        };
+        let Some(code_action) = actions.into_iter().nth(action_i) else {
+            return Task::none();
+        };
+        let Some((client, document)) = self.lsp_client(code_actions.entity) else {
+            return Task::none();
+        };
+        let (client, key) = (client.clone(), document.key.clone());
+        let encoding = client.position_encoding();
+        let open_texts = self.open_texts();
+        Task::perform(
+            async move {
+                let (edit_opt, command_opt) = match code_action {
+                    lsp_types::CodeActionOrCommand::Command(command) => (None, Some(command)),
+                    lsp_types::CodeActionOrCommand::CodeAction(code_action) => {
+                        match client.resolve_code_action(code_action).await {
+                            Ok(code_action) => (code_action.edit, code_action.command),
+                            Err(err) => return action::app(Message::LspError(err)),
+                        }
                     }
-                }
+                };
+                let result = match edit_opt {
+                    Some(edit) => workspace_edit::load(edit, encoding, &open_texts),
+                    None => Ok(Vec::new()),
+                };
+                action::app(Message::LspEdit(
+                    key,
+                    LspEdit {
+                        encoding,
+                        result,
+                        command_opt,
+                    },
+                ))
+            },
+            |x| x,
+        )
+    }
 
-                let mut save_button = widget::button::suggested(fl!("save-all"));
-                if can_save_all {
-                    save_button = save_button.on_press(Message::SaveAll);
-                }
-                let discard_button =
-                    widget::button::destructive(fl!("discard")).on_press(Message::QuitForce);
-                let cancel_button =
-                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
-                let dialog = widget::dialog()
-                    .title(fl!("prompt-save-changes-title"))
-                    .body(fl!("prompt-unsaved-changes"))
-                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
-                    .control(column)
-                    .primary_action(save_button)
-                    .secondary_action(discard_button)
-                    .tertiary_action(cancel_button);
+    /// Formatter of the file of an editor tab, unless formatters are turned off for its project
+    fn formatter(&self, entity: segmented_button::Entity) -> Option<(FormatterConfig, PathBuf)> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return None;
+        };
+        let path = tab.path_opt.as_ref()?;
+        if self
+            .config
+            .formatter_disabled_projects
+            .iter()
+            .any(|project| path.starts_with(project))
+        {
+            return None;
+        }
+        let config = formatter::formatter_for(&self.config.formatters, path)?;
+        Some((config.clone(), path.clone()))
+    }
 
-                Some(dialog.into())
+    /// Store the undo histories of closed tabs and every open tab, then exit
+    fn quit(&mut self, mut undo_history_saves: Vec<UndoHistorySave>) -> Task<Message> {
+        let entities: Vec<_> = self.tab_model.iter().collect();
+        for entity in entities {
+            if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                undo_history_saves.extend(tab.undo_history_save());
             }
         }
+        Task::perform(
+            async move {
+                write_undo_histories(undo_history_saves).await;
+                process::exit(0)
+            },
+            |()| action::none(),
+        )
     }
 
-    fn update(&mut self, message: Message) -> Task<Message> {
-        // Helper for updating config values efficiently
-        macro_rules! config_set {
-            ($name: ident, $value: expr) => {
-                match &self.config_handler {
-                    Some(config_handler) => {
-                        if let Err(err) =
-                            paste::paste! { self.config.[<set_ $name>](config_handler, $value) }
-                        {
-                            log::warn!("failed to save config {:?}: {}", stringify!($name), err);
+    /// Whether an editor tab is formatted before it is saved
+    fn format_on_save(&self, entity: segmented_button::Entity) -> bool {
+        self.config.format_on_save && self.formatter(entity).is_some()
+    }
+
+    /// Write an editor tab to its file, keeping a snapshot in the local history
+    fn save_editor_tab(&mut self, entity: segmented_button::Entity) -> Task<Message> {
+        let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+            return Task::none();
+        };
+        let Some(path) = tab.path_opt.clone() else {
+            return Task::none();
+        };
+        let local_history_save_opt = tab.save();
+        if let Ok(canonical) = fs::canonicalize(&path) {
+            self.add_to_recents(&canonical);
+        }
+        self.lsp_did_save(entity);
+        self.write_local_history(local_history_save_opt)
+    }
+
+    /// Run the formatter of an editor tab in the background, saving the tab once it is done if
+    /// `save` is set
+    fn run_formatter(&mut self, entity: segmented_button::Entity, save: bool) -> Task<Message> {
+        let Some((config, path)) = self.formatter(entity) else {
+            return Task::none();
+        };
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return Task::none();
+        };
+        let text = tab.text();
+        let text_hash = stable_hash(text.as_bytes());
+        Task::perform(
+            async move {
+                let result = formatter::format(config, path, text).await;
+                action::app(Message::FormatterResult(entity, text_hash, save, result))
+            },
+            |x| x,
+        )
+    }
+
+    /// Format the document of the active tab with its language server, or only the selection
+    fn lsp_format(&mut self, selection: bool) -> Task<Message> {
+        let entity = self.tab_model.active();
+        let Some((client, document)) = self.lsp_client(entity) else {
+            return Task::none();
+        };
+        let (client, path) = (client.clone(), document.path.clone());
+        let Some(uri) = lsp::path_uri(&path) else {
+            return Task::none();
+        };
+        let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+            return Task::none();
+        };
+        let encoding = client.position_encoding();
+        let range_opt = selection.then(|| tab.lsp_selection(encoding));
+        let options = lsp_types::FormattingOptions {
+            tab_size: self.config.tab_width.into(),
+            insert_spaces: true,
+            ..Default::default()
+        };
+        // Edits are only applied to the text they were made for
+        let text_hash = stable_hash(tab.text().as_bytes());
+        self.lsp_sync(entity);
+        Task::perform(
+            async move {
+                let result = client.formatting(uri, range_opt, options).await;
+                action::app(Message::LspFormatResult(
+                    entity, text_hash, encoding, result,
+                ))
+            },
+            |x| x,
+        )
+    }
+
+    fn update_watcher(&mut self) {
+        if let Some((mut watcher, old_paths)) = self.watcher_opt.take() {
+            let mut new_paths = HashSet::new();
+
+            for (_, project_path) in self.projects.iter() {
+                new_paths.insert((project_path.clone(), RecursiveMode::Recursive));
+            }
+
+            for git_dir in self.project_git_dirs.values() {
+                if !self
+                    .projects
+                    .iter()
+                    .any(|(_, project_path)| git_dir.starts_with(project_path))
+                {
+                    // Changes to the index and HEAD are enough to update git change markers
+                    new_paths.insert((git_dir.clone(), RecursiveMode::NonRecursive));
+                }
+            }
+
+            'tabs: for entity in self.tab_model.iter() {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                    if let Some(path) = &tab.path_opt {
+                        for (_, project_path) in self.projects.iter() {
+                            if path.starts_with(&project_path) {
+                                // Do not watch tabs inside of already watched projects
+                                continue 'tabs;
+                            }
                         }
+                        new_paths.insert((path.to_path_buf(), RecursiveMode::NonRecursive));
                     }
-                    None => {
-                        self.config.$name = $value;
-                        log::warn!(
-                            "failed to save config {:?}: no config handler",
-                            stringify!($name)
-                        );
+                }
+            }
+
+            // Unwatch paths no longer used
+            for path_mode in old_paths.iter() {
+                if !new_paths.contains(path_mode) {
+                    let (path, _) = path_mode;
+                    match watcher.unwatch(path) {
+                        Ok(()) => {
+                            log::debug!("unwatching {:?}", path);
+                        }
+                        Err(err) => {
+                            log::debug!("failed to unwatch {:?}: {}", path, err);
+                        }
                     }
                 }
-            };
-        }
-        match message {
-            Message::AppTheme(app_theme) => {
-                config_set!(app_theme, app_theme);
-                return self.update_config();
             }
-            Message::AutoScroll(auto_scroll) => {
-                self.auto_scroll = auto_scroll.map(|new| {
-                    (
-                        new,
-                        self.auto_scroll
-                            .map(|old| old.1.wrapping_add(1))
-                            .unwrap_or_default(),
-                    )
-                });
+
+            // Watch new paths
+            for path_mode in new_paths.iter() {
+                if !old_paths.contains(path_mode) {
+                    let (path, mode) = path_mode;
+                    match watcher.watch(path, *mode) {
+                        Ok(()) => {
+                            log::debug!("watching {:?} {:?}", path, mode);
+                        }
+                        Err(err) => {
+                            log::debug!("failed to watch {:?} {:?}: {}", path, mode, err);
+                        }
+                    }
+                }
             }
-            Message::Config(config) => {
-                if config != self.config {
-                    log::info!("update config");
-                    //TODO: update syntax theme by clearing tabs, only if needed
-                    self.config = config;
-                    return self.update_config();
+
+            self.watcher_opt = Some((watcher, new_paths));
+        }
+    }
+
+    fn document_statistics(&self) -> Element<'_, Message> {
+        //TODO: calculate in the background
+        let mut character_count = 0;
+        let mut character_count_no_spaces = 0;
+        let mut line_count = 0;
+        let mut word_count = 0;
+
+        if let Some(Tab::Editor(tab)) = self.active_tab() {
+            let editor = tab.editor.lock().unwrap();
+            editor.with_buffer(|buffer| {
+                line_count = buffer.lines.len();
+                for line in buffer.lines.iter() {
+                    let text = line.text();
+                    let mut last_whitespace = true;
+
+                    // Count graphemes instead of Unicode scalar values for accurate character count
+                    for grapheme in text.graphemes(true) {
+                        character_count += 1;
+                        let is_whitespace = grapheme.chars().all(|c| c.is_whitespace());
+                        if !is_whitespace {
+                            character_count_no_spaces += 1;
+                            if last_whitespace {
+                                word_count += 1;
+                            }
+                        }
+                        last_whitespace = is_whitespace;
+                    }
+                }
+            });
+        }
+
+        widget::settings::view_column(vec![
+            widget::settings::section()
+                .add(
+                    widget::settings::item::builder(fl!("word-count"))
+                        .control(widget::text(word_count.to_string())),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("character-count"))
+                        .control(widget::text(character_count.to_string())),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("character-count-no-spaces"))
+                        .control(widget::text(character_count_no_spaces.to_string())),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("line-count"))
+                        .control(widget::text(line_count.to_string())),
+                )
+                .into(),
+        ])
+        .into()
+    }
+
+    fn git_management(&self) -> Element<'_, Message> {
+        let spacing = self.core().system_theme().cosmic().spacing;
+
+        if let Some(project_status) = &self.git_project_status {
+            let (success_color, destructive_color, warning_color, accent_color) = {
+                let cosmic_theme = self.core().system_theme().cosmic();
+                (
+                    cosmic_theme.success_color(),
+                    cosmic_theme.destructive_color(),
+                    cosmic_theme.warning_color(),
+                    cosmic_theme.accent_color(),
+                )
+            };
+            let added = || widget::text("[+]").class(theme::Text::Color(success_color.into()));
+            let deleted =
+                || widget::text("[-]").class(theme::Text::Color(destructive_color.into()));
+            let modified = || widget::text("[*]").class(theme::Text::Color(warning_color.into()));
+            let renamed = || widget::text("[R]").class(theme::Text::Color(accent_color.into()));
+            let copied = || widget::text("[C]").class(theme::Text::Color(accent_color.into()));
+            let conflicted =
+                || widget::text("[!]").class(theme::Text::Color(destructive_color.into()));
+
+            let mut items =
+                Vec::with_capacity(project_status.len().saturating_mul(3).saturating_add(1));
+            items.push(widget::text(fl!("git-management-description")).into());
+
+            for project in project_status.iter() {
+                let project_path = &project.path;
+                let mut conflict_items = Vec::new();
+                let mut unstaged_items = Vec::with_capacity(project.status.len());
+                let mut staged_items = Vec::with_capacity(project.status.len());
+                for item in project.status.iter() {
+                    let relative_path = match item.path.strip_prefix(project_path) {
+                        Ok(ok) => ok,
+                        Err(err) => {
+                            log::warn!(
+                                "failed to find relative path of {:?} in project {:?}: {}",
+                                item.path,
+                                project_path,
+                                err
+                            );
+                            &item.path
+                        }
+                    };
+
+                    let text = match &item.old_path {
+                        Some(old_path) => {
+                            let old_relative_path = match old_path.strip_prefix(project_path) {
+                                Ok(ok) => ok,
+                                Err(err) => {
+                                    log::warn!(
+                                        "failed to find relative path of {:?} in project {:?}: {}",
+                                        old_path,
+                                        project_path,
+                                        err
+                                    );
+                                    old_path
+                                }
+                            };
+                            format!(
+                                "{} -> {}",
+                                old_relative_path.display(),
+                                relative_path.display()
+                            )
+                        }
+                        None => format!("{}", relative_path.display()),
+                    };
+
+                    if item.is_conflicted() {
+                        conflict_items.push(
+                            widget::button::custom(
+                                widget::row::with_children(vec![
+                                    conflicted().into(),
+                                    widget::text(text).into(),
+                                    widget::space::horizontal().into(),
+                                    widget::button::standard(fl!("git-conflict-mark-resolved"))
+                                        .on_press(Message::GitConflictMarkResolved(
+                                            project_path.clone(),
+                                            item.path.clone(),
+                                        ))
+                                        .into(),
+                                ])
+                                .align_y(Alignment::Center)
+                                .spacing(spacing.space_xs),
+                            )
+                            .on_press(Message::OpenFile(item.path.clone()))
+                            .class(theme::Button::AppletMenu)
+                            .width(Length::Fill)
+                            .into(),
+                        );
+                        continue;
+                    }
+
+                    let unstaged_opt = match item.unstaged {
+                        GitStatusKind::Unmodified => None,
+                        GitStatusKind::Modified => Some(modified()),
+                        GitStatusKind::FileTypeChanged => Some(modified()),
+                        GitStatusKind::Added => Some(added()),
+                        GitStatusKind::Deleted => Some(deleted()),
+                        GitStatusKind::Renamed => Some(renamed()),
+                        GitStatusKind::Copied => Some(copied()),
+                        GitStatusKind::Updated => Some(modified()),
+                        GitStatusKind::Untracked => Some(added()),
+                        GitStatusKind::SubmoduleModified => Some(modified()),
+                    };
+
+                    if let Some(icon) = unstaged_opt {
+                        unstaged_items.push(
+                            widget::button::custom(
+                                widget::row::with_children(vec![
+                                    icon.into(),
+                                    widget::text(text.clone()).into(),
+                                    widget::space::horizontal().into(),
+                                    widget::button::destructive(fl!("discard"))
+                                        .on_press(Message::GitDiscard(
+                                            project_path.clone(),
+                                            item.path.clone(),
+                                            item.unstaged == GitStatusKind::Untracked,
+                                        ))
+                                        .into(),
+                                    widget::button::standard(fl!("stage"))
+                                        .on_press(Message::GitStage(
+                                            project_path.clone(),
+                                            item.path.clone(),
+                                        ))
+                                        .into(),
+                                ])
+                                .align_y(Alignment::Center)
+                                .spacing(spacing.space_xs),
+                            )
+                            .on_press(Message::PrepareGitDiff(
+                                project_path.clone(),
+                                item.path.clone(),
+                                false,
+                            ))
+                            .class(theme::Button::AppletMenu)
+                            .width(Length::Fill)
+                            .into(),
+                        );
+                    }
+
+                    let staged_opt = match item.staged {
+                        GitStatusKind::Unmodified => None,
+                        GitStatusKind::Modified => Some(modified()),
+                        GitStatusKind::FileTypeChanged => Some(modified()),
+                        GitStatusKind::Added => Some(added()),
+                        GitStatusKind::Deleted => Some(deleted()),
+                        GitStatusKind::Renamed => Some(renamed()),
+                        GitStatusKind::Copied => Some(copied()),
+                        GitStatusKind::Updated => Some(modified()),
+                        GitStatusKind::Untracked => None,
+                        GitStatusKind::SubmoduleModified => Some(modified()),
+                    };
+
+                    if let Some(icon) = staged_opt {
+                        staged_items.push(
+                            widget::button::custom(
+                                widget::row::with_children(vec![
+                                    icon.into(),
+                                    widget::text(text.clone()).into(),
+                                    widget::space::horizontal().into(),
+                                    widget::button::standard(fl!("unstage"))
+                                        .on_press(Message::GitUnstage(
+                                            project_path.clone(),
+                                            item.path.clone(),
+                                        ))
+                                        .into(),
+                                ])
+                                .align_y(Alignment::Center)
+                                .spacing(spacing.space_xs),
+                            )
+                            .on_press(Message::PrepareGitDiff(
+                                project_path.clone(),
+                                item.path.clone(),
+                                true,
+                            ))
+                            .class(theme::Button::AppletMenu)
+                            .width(Length::Fill)
+                            .into(),
+                        );
+                    }
+                }
+
+                let mut title = widget::column::with_capacity(2)
+                    .push(widget::text::heading(project.name.clone()));
+                match project.kind {
+                    GitProjectKind::Project => {}
+                    GitProjectKind::Submodule => {
+                        title = title.push(widget::text::caption(fl!("git-submodule")));
+                    }
+                    GitProjectKind::Nested => {
+                        title = title.push(widget::text::caption(fl!("git-nested-repository")));
+                    }
+                }
+                let mut header = widget::row::with_capacity(4)
+                    .push(title)
+                    .push(widget::space::horizontal())
+                    .align_y(Alignment::Center)
+                    .spacing(spacing.space_xs);
+                if let Some((ahead, behind)) = project.ahead_behind_opt {
+                    header = header.push(widget::text::caption(fl!(
+                        "git-ahead-behind",
+                        ahead = ahead,
+                        behind = behind
+                    )));
+                }
+                if let Some(head) = &project.head_opt {
+                    let label = match head {
+                        GitHead::Branch(name) => name.clone(),
+                        GitHead::Detached(short_hash) => {
+                            fl!("git-head-detached", hash = short_hash.as_str())
+                        }
+                    };
+                    header = header.push(
+                        widget::button::standard(label)
+                            .on_press(Message::GitBranchPicker(project_path.clone())),
+                    );
+                }
+                items.push(header.into());
+
+                if let Some(picker) = self.git_branch_pickers.get(project_path) {
+                    items.push(self.git_branch_picker(project, picker));
+                }
+
+                if let Some(remote) = self.git_remotes.get(project_path) {
+                    items.push(self.git_remote(project, remote));
+                }
+
+                if project.status.is_empty() {
+                    items.push(widget::text(fl!("git-no-changes")).into());
+                }
+
+                if !conflict_items.is_empty() {
+                    items.push(
+                        widget::settings::section()
+                            .title(fl!("git-merge-conflicts"))
+                            .add(widget::column::with_children(conflict_items))
+                            .into(),
+                    );
+                }
+
+                if !unstaged_items.is_empty() {
+                    items.push(
+                        widget::settings::section()
+                            .title(fl!("unstaged-changes"))
+                            .add(widget::column::with_children(unstaged_items))
+                            .into(),
+                    );
+                }
+
+                let has_staged = !staged_items.is_empty();
+                if has_staged {
+                    items.push(
+                        widget::settings::section()
+                            .title(fl!("staged-changes"))
+                            .add(widget::column::with_children(staged_items))
+                            .into(),
+                    );
+                }
+
+                if let Some(editor) = self.git_commit_editors.get(project_path) {
+                    items.push(self.git_commit_editor(project, editor, has_staged));
+                }
+
+                if let Some(editor) = self.git_stash_editors.get(project_path) {
+                    items.push(self.git_stashes(project, editor));
+                }
+
+                if !project.commits.is_empty() {
+                    let mut section = widget::settings::section().title(fl!("recent-commits"));
+                    for commit in project.commits.iter() {
+                        section = section.add(
+                            widget::column::with_capacity(2)
+                                .push(widget::text(commit.summary.clone()))
+                                .push(widget::text::caption(format!(
+                                    "{} \u{2022} {} \u{2022} {}",
+                                    commit.short_hash, commit.author, commit.date
+                                ))),
+                        );
+                    }
+                    items.push(section.into());
+                }
+            }
+
+            widget::column::with_children(items)
+                .spacing(spacing.space_s)
+                .padding([spacing.space_xxs, spacing.space_none])
+                .into()
+        } else {
+            widget::column::with_children(vec![
+                widget::text(fl!("git-management-description")).into(),
+                widget::text(fl!("git-management-loading")).into(),
+            ])
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+        }
+    }
+
+    fn git_file_history(&self) -> Element<'_, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+
+        let mut items = Vec::with_capacity(3);
+        items.push(widget::text(fl!("git-file-history-description")).into());
+        match &self.git_file_history_opt {
+            Some(history) => {
+                items.push(widget::text::heading(format!("{}", history.path.display())).into());
+                match &history.result_opt {
+                    Some(Ok((_, revisions))) => {
+                        let mut section = widget::settings::section();
+                        for (revision_i, revision) in revisions.iter().enumerate() {
+                            let commit = &revision.commit;
+                            section = section.add(
+                                widget::button::custom(
+                                    widget::row::with_capacity(3)
+                                        .push(
+                                            widget::column::with_capacity(2)
+                                                .push(widget::text(commit.summary.clone()))
+                                                .push(widget::text::caption(format!(
+                                                    "{} \u{2022} {} \u{2022} {}",
+                                                    commit.short_hash, commit.author, commit.date
+                                                ))),
+                                        )
+                                        .push(widget::space::horizontal())
+                                        .push(
+                                            widget::button::standard(fl!("git-revision-open"))
+                                                .on_press(Message::GitFileHistoryOpen(revision_i)),
+                                        )
+                                        .align_y(Alignment::Center)
+                                        .spacing(spacing.space_xs),
+                                )
+                                .on_press(Message::GitFileHistoryDiff(revision_i))
+                                .class(theme::Button::AppletMenu)
+                                .width(Length::Fill),
+                            );
+                        }
+                        items.push(section.into());
+                    }
+                    Some(Err(err)) => {
+                        items.push(
+                            widget::text(err.clone())
+                                .class(theme::Text::Color(cosmic_theme.destructive_color().into()))
+                                .into(),
+                        );
+                    }
+                    None => {
+                        items.push(widget::text(fl!("git-file-history-loading")).into());
+                    }
+                }
+            }
+            None => {
+                items.push(widget::text(fl!("git-file-history-no-file")).into());
+            }
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    fn problems(&self) -> Element<'_, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+
+        let mut items = Vec::with_capacity(self.lsp_diagnostics.len() * 2 + 1);
+        if self.lsp_diagnostics.is_empty() {
+            items.push(widget::text(fl!("problems-empty")).into());
+        }
+        for (path, (_, diagnostics)) in self.lsp_diagnostics.iter() {
+            let title = self
+                .projects
+                .iter()
+                .find_map(|(_, project_path)| path.strip_prefix(project_path).ok())
+                .unwrap_or(path.as_path());
+            items.push(widget::text::heading(format!("{}", title.display())).into());
+            let mut section = widget::settings::section();
+            for diagnostic in diagnostics.iter() {
+                let icon_name = match diagnostic.severity {
+                    Some(lsp_types::DiagnosticSeverity::WARNING) => "dialog-warning-symbolic",
+                    Some(lsp_types::DiagnosticSeverity::INFORMATION)
+                    | Some(lsp_types::DiagnosticSeverity::HINT) => "dialog-information-symbolic",
+                    _ => "dialog-error-symbolic",
+                };
+                let position = diagnostic.range.start;
+                section = section.add(
+                    widget::button::custom(
+                        widget::row::with_capacity(3)
+                            .push(icon_cache_get(icon_name, 16))
+                            .push(widget::text(format!(
+                                "{}:{}",
+                                position.line + 1,
+                                position.character + 1
+                            )))
+                            .push(widget::text(diagnostic.message.clone()).width(Length::Fill))
+                            .spacing(spacing.space_xs),
+                    )
+                    .on_press(Message::LspProblemOpen(path.clone(), position))
+                    .class(theme::Button::AppletMenu)
+                    .width(Length::Fill),
+                );
+            }
+            items.push(section.into());
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    fn navigation(&self) -> Element<'_, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+
+        let Some(results) = &self.navigation_opt else {
+            return widget::column::with_capacity(0).into();
+        };
+        let mut items = Vec::new();
+        items.push(widget::text::heading(results.word.clone()).into());
+        match &results.result {
+            Ok(nav_items) if nav_items.is_empty() => {
+                items.push(widget::text(fl!("navigation-empty")).into());
+            }
+            Ok(nav_items) => {
+                // Items are sorted by file, each file gets a section
+                let mut item_i = 0;
+                for file_items in nav_items.chunk_by(|a, b| a.path == b.path) {
+                    let path = &file_items[0].path;
+                    let title = self
+                        .projects
+                        .iter()
+                        .find_map(|(_, project_path)| path.strip_prefix(project_path).ok())
+                        .unwrap_or(path.as_path());
+                    items.push(widget::text::heading(format!("{}", title.display())).into());
+                    let mut section = widget::settings::section();
+                    for item in file_items {
+                        let mut row = widget::row::with_capacity(3)
+                            .push(
+                                widget::text(format!("{}", item.position.line + 1))
+                                    .font(Font::MONOSPACE),
+                            )
+                            .push(
+                                widget::text(item.text.clone())
+                                    .font(Font::MONOSPACE)
+                                    .width(Length::Fill),
+                            )
+                            .spacing(spacing.space_xs);
+                        if !item.detail.is_empty() {
+                            row = row.push(widget::text::caption(item.detail.clone()));
+                        }
+                        section = section.add(
+                            widget::button::custom(row)
+                                .on_press(Message::NavigationOpen(item_i))
+                                .class(theme::Button::AppletMenu)
+                                .width(Length::Fill),
+                        );
+                        item_i += 1;
+                    }
+                    items.push(section.into());
+                }
+            }
+            Err(err) => {
+                items.push(
+                    widget::text(err.clone())
+                        .class(theme::Text::Color(cosmic_theme.destructive_color().into()))
+                        .into(),
+                );
+            }
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    fn symbol_search(&self) -> Element<'_, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+
+        let Some(search) = &self.symbol_search_opt else {
+            return widget::column::with_capacity(0).into();
+        };
+        let mut items = Vec::with_capacity(2);
+        items.push(
+            widget::text_input::search_input(fl!("symbol-search"), search.query.clone())
+                .id(self.symbol_search_id.clone())
+                .on_input(Message::SymbolSearchQuery)
+                .on_submit(|_| Message::SymbolSearchSubmit)
+                .into(),
+        );
+        match &search.result_opt {
+            None => {
+                items.push(widget::text(fl!("symbol-search-loading")).into());
+            }
+            Some(Ok(_)) if search.matches.is_empty() => {
+                items.push(widget::text(fl!("symbol-search-empty")).into());
+            }
+            Some(Ok(symbols)) => {
+                let mut section = widget::settings::section();
+                for item_i in search.matches.iter().take(SYMBOL_SEARCH_ITEMS) {
+                    let symbol = &symbols[*item_i];
+                    let mut column = widget::column::with_capacity(2)
+                        .push(widget::text(symbol.text.clone()).font(Font::MONOSPACE));
+                    let mut detail = symbol.detail.clone();
+                    if search.workspace {
+                        let path = self
+                            .projects
+                            .iter()
+                            .find_map(|(_, project_path)| {
+                                symbol.path.strip_prefix(project_path).ok()
+                            })
+                            .unwrap_or(symbol.path.as_path());
+                        detail = format!(
+                            "{} \u{2022} {}:{}",
+                            detail,
+                            path.display(),
+                            symbol.position.line + 1
+                        );
+                    }
+                    column = column.push(widget::text::caption(detail));
+                    section = section.add(
+                        widget::button::custom(column)
+                            .on_press(Message::SymbolSearchOpenItem(*item_i))
+                            .class(theme::Button::AppletMenu)
+                            .width(Length::Fill),
+                    );
+                }
+                items.push(section.into());
+            }
+            Some(Err(err)) => {
+                items.push(
+                    widget::text(err.clone())
+                        .class(theme::Text::Color(cosmic_theme.destructive_color().into()))
+                        .into(),
+                );
+            }
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    fn rename(&self) -> Element<'_, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+
+        let Some(rename) = &self.rename_opt else {
+            return widget::column::with_capacity(0).into();
+        };
+        let mut items = Vec::with_capacity(4);
+        items.push(widget::text::heading(rename.old_name.clone()).into());
+        items.push(
+            widget::text_input(fl!("rename-new-name"), rename.name.clone())
+                .id(self.rename_id.clone())
+                .on_input(Message::RenameInput)
+                .on_submit(|_| Message::RenameSubmit)
+                .into(),
+        );
+        match &rename.changes_opt {
+            _ if rename.loading => {
+                items.push(widget::text(fl!("rename-loading")).into());
+            }
+            None => {
+                items.push(widget::text(fl!("rename-description")).into());
+            }
+            Some(Ok(changes)) if changes.is_empty() => {
+                items.push(widget::text(fl!("rename-empty")).into());
+            }
+            Some(Ok(changes)) => {
+                let destructive_color = cosmic_theme.destructive_color();
+                let success_color = cosmic_theme.success_color();
+                items.push(
+                    widget::button::suggested(fl!("rename-apply"))
+                        .on_press(Message::RenameApply)
+                        .into(),
+                );
+                for change in changes {
+                    let path = self
+                        .projects
+                        .iter()
+                        .find_map(|(_, project_path)| change.path.strip_prefix(project_path).ok())
+                        .unwrap_or(change.path.as_path());
+                    let mut section = widget::settings::section().title(path.display().to_string());
+                    for lines in &change.lines {
+                        section = section.add(
+                            widget::column::with_capacity(3)
+                                .push(widget::text::caption(fl!(
+                                    "rename-line",
+                                    line = (lines.line + 1)
+                                )))
+                                .push(
+                                    widget::text::monotext(lines.old.clone())
+                                        .class(theme::Text::Color(destructive_color.into())),
+                                )
+                                .push(
+                                    widget::text::monotext(lines.new.clone())
+                                        .class(theme::Text::Color(success_color.into())),
+                                ),
+                        );
+                    }
+                    items.push(section.into());
+                }
+            }
+            Some(Err(err)) => {
+                items.push(
+                    widget::text(err.clone())
+                        .class(theme::Text::Color(cosmic_theme.destructive_color().into()))
+                        .into(),
+                );
+            }
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    fn local_history(&self) -> Element<'_, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+
+        let mut items = Vec::with_capacity(3);
+        items.push(widget::text(fl!("local-history-description")).into());
+        match &self.local_history_opt {
+            Some(history) => {
+                items.push(widget::text::heading(format!("{}", history.path.display())).into());
+                match &history.result_opt {
+                    Some(Ok(snapshots)) if snapshots.is_empty() => {
+                        items.push(widget::text(fl!("local-history-empty")).into());
+                    }
+                    Some(Ok(snapshots)) => {
+                        let mut section = widget::settings::section();
+                        for (snapshot_i, snapshot) in snapshots.iter().enumerate() {
+                            section = section.add(
+                                widget::button::custom(
+                                    widget::row::with_capacity(3)
+                                        .push(widget::text(relative_time(snapshot.time())))
+                                        .push(widget::space::horizontal())
+                                        .push(
+                                            widget::button::standard(fl!("local-history-restore"))
+                                                .on_press(Message::LocalHistoryRestore(snapshot_i)),
+                                        )
+                                        .align_y(Alignment::Center)
+                                        .spacing(spacing.space_xs),
+                                )
+                                .on_press(Message::LocalHistoryDiff(snapshot_i))
+                                .class(theme::Button::AppletMenu)
+                                .width(Length::Fill),
+                            );
+                        }
+                        items.push(section.into());
+                    }
+                    Some(Err(err)) => {
+                        items.push(
+                            widget::text(err.clone())
+                                .class(theme::Text::Color(cosmic_theme.destructive_color().into()))
+                                .into(),
+                        );
+                    }
+                    None => {
+                        items.push(widget::text(fl!("local-history-loading")).into());
+                    }
+                }
+            }
+            None => {
+                items.push(widget::text(fl!("git-file-history-no-file")).into());
+            }
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    fn git_branch_picker<'a>(
+        &'a self,
+        project: &'a GitProject,
+        picker: &'a GitBranchPicker,
+    ) -> Element<'a, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+        let project_path = &project.path;
+
+        let mut section = widget::settings::section().title(fl!("branches"));
+        for branch in project.branches.iter() {
+            let mut name_column = widget::column::with_capacity(2).push(if branch.current {
+                widget::text::heading(branch.name.clone())
+            } else {
+                widget::text(branch.name.clone())
+            });
+            if let Some(upstream) = &branch.upstream_opt {
+                name_column = name_column.push(widget::text::caption(upstream.clone()));
+            }
+            let mut row = widget::row::with_capacity(4)
+                .push(name_column)
+                .push(widget::space::horizontal())
+                .align_y(Alignment::Center)
+                .spacing(spacing.space_xs);
+            if !branch.current {
+                row = row
+                    .push(
+                        widget::button::destructive(fl!("git-branch-delete")).on_press_maybe(
+                            (!picker.in_progress).then(|| {
+                                Message::GitBranchDelete(project_path.clone(), branch.name.clone())
+                            }),
+                        ),
+                    )
+                    .push(
+                        widget::button::standard(fl!("git-branch-switch")).on_press_maybe(
+                            (!picker.in_progress).then(|| {
+                                Message::GitBranchCheckout(
+                                    project_path.clone(),
+                                    branch.name.clone(),
+                                )
+                            }),
+                        ),
+                    );
+            }
+            section = section.add(row);
+        }
+
+        let can_create = !picker.in_progress && !picker.name.trim().is_empty();
+        section = section.add(
+            widget::row::with_capacity(2)
+                .push(
+                    widget::text_input::text_input(fl!("git-branch-name"), &picker.name)
+                        .on_input(|name| Message::GitBranchName(project_path.clone(), name))
+                        .on_submit(|_| Message::GitBranchCreate(project_path.clone())),
+                )
+                .push(
+                    widget::button::suggested(fl!("git-branch-create")).on_press_maybe(
+                        can_create.then(|| Message::GitBranchCreate(project_path.clone())),
+                    ),
+                )
+                .align_y(Alignment::Center)
+                .spacing(spacing.space_xs),
+        );
+
+        let mut column = widget::column::with_capacity(2)
+            .push(section)
+            .spacing(spacing.space_xxs);
+        if let Some(result) = &picker.result_opt {
+            column = column.push(match result {
+                Ok(message) => widget::text::caption(message.clone()),
+                Err(err) => widget::text::caption(err.clone())
+                    .class(theme::Text::Color(cosmic_theme.destructive_color().into())),
+            });
+        }
+        column.into()
+    }
+
+    fn git_remote<'a>(
+        &'a self,
+        project: &'a GitProject,
+        remote: &'a GitRemote,
+    ) -> Element<'a, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+        let project_path = &project.path;
+
+        // Pulling needs an upstream, pushing without one sets it
+        let on_branch = matches!(project.head_opt, Some(GitHead::Branch(_)));
+        let has_upstream = project.ahead_behind_opt.is_some();
+        let action = |remote_action: GitRemoteAction, enabled: bool| {
+            (enabled && !remote.in_progress)
+                .then(|| Message::GitRemote(project_path.clone(), remote_action))
+        };
+        let mut row = widget::row::with_capacity(6)
+            .align_y(Alignment::Center)
+            .spacing(spacing.space_xs);
+        if on_branch && !has_upstream {
+            row = row.push(widget::text::caption(fl!("git-no-upstream")));
+        }
+        row = row
+            .push(widget::space::horizontal())
+            .push(
+                widget::checkbox(remote.rebase)
+                    .label(fl!("git-pull-rebase"))
+                    .on_toggle(|rebase| Message::GitRemoteRebase(project_path.clone(), rebase)),
+            )
+            .push(
+                widget::button::standard(fl!("git-fetch"))
+                    .on_press_maybe(action(GitRemoteAction::Fetch, true)),
+            )
+            .push(
+                widget::button::standard(fl!("git-pull"))
+                    .on_press_maybe(action(GitRemoteAction::Pull, on_branch && has_upstream)),
+            )
+            .push(
+                widget::button::standard(fl!("git-push"))
+                    .on_press_maybe(action(GitRemoteAction::Push, on_branch)),
+            );
+
+        let mut column = widget::column::with_capacity(3)
+            .push(row)
+            .spacing(spacing.space_xxs);
+        if !remote.log.is_empty() {
+            let mut log = widget::column::with_capacity(remote.log.len());
+            for line in remote.log.iter() {
+                log = log.push(widget::text::monotext(line.clone()));
+            }
+            column = column.push(
+                widget::layer_container(
+                    widget::container(
+                        widget::scrollable(log.width(Length::Fill))
+                            .id(remote.log_scroll_id.clone())
+                            .height(Length::Shrink),
+                    )
+                    .padding(spacing.space_xs)
+                    .max_height(160.0),
+                )
+                .layer(cosmic_theme::Layer::Primary),
+            );
+        }
+        if let Some(result) = &remote.result_opt {
+            column = column.push(match result {
+                Ok(message) => widget::text::caption(message.clone()),
+                Err(err) => widget::text::caption(err.clone())
+                    .class(theme::Text::Color(cosmic_theme.destructive_color().into())),
+            });
+        }
+        column.into()
+    }
+
+    fn git_stashes<'a>(
+        &'a self,
+        project: &'a GitProject,
+        editor: &'a GitStashEditor,
+    ) -> Element<'a, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+        let project_path = &project.path;
+
+        let mut section = widget::settings::section().title(fl!("stashes"));
+        // Stashes contain tracked changes, or untracked files if they are included
+        let has_changes = project
+            .status
+            .iter()
+            .any(|item| editor.include_untracked || item.unstaged != GitStatusKind::Untracked);
+        let push_message = (has_changes && !editor.in_progress).then(|| {
+            Message::GitStash(
+                project_path.clone(),
+                GitStashAction::Push(editor.message.trim().to_string(), editor.include_untracked),
+            )
+        });
+        section = section.add(
+            widget::row::with_capacity(3)
+                .push(
+                    widget::text_input::text_input(fl!("git-stash-message"), &editor.message)
+                        .on_input(|message| {
+                            Message::GitStashMessage(project_path.clone(), message)
+                        }),
+                )
+                .push(
+                    widget::checkbox(editor.include_untracked)
+                        .label(fl!("git-stash-untracked"))
+                        .on_toggle(|include_untracked| {
+                            Message::GitStashUntracked(project_path.clone(), include_untracked)
+                        }),
+                )
+                .push(widget::button::suggested(fl!("git-stash-push")).on_press_maybe(push_message))
+                .align_y(Alignment::Center)
+                .spacing(spacing.space_xs),
+        );
+
+        for stash in project.stashes.iter() {
+            let action = |action: GitStashAction| {
+                (!editor.in_progress).then(|| Message::GitStash(project_path.clone(), action))
+            };
+            section = section.add(
+                widget::button::custom(
+                    widget::row::with_capacity(5)
+                        .push(
+                            widget::column::with_capacity(2)
+                                .push(widget::text(stash.message.clone()))
+                                .push(widget::text::caption(stash.date.clone())),
+                        )
+                        .push(widget::space::horizontal())
+                        .push(
+                            widget::button::destructive(fl!("git-stash-drop"))
+                                .on_press_maybe(action(GitStashAction::Drop(stash.hash.clone()))),
+                        )
+                        .push(
+                            widget::button::standard(fl!("git-stash-apply")).on_press_maybe(
+                                action(GitStashAction::Apply(stash.hash.clone(), false)),
+                            ),
+                        )
+                        .push(
+                            widget::button::standard(fl!("git-stash-pop")).on_press_maybe(action(
+                                GitStashAction::Apply(stash.hash.clone(), true),
+                            )),
+                        )
+                        .align_y(Alignment::Center)
+                        .spacing(spacing.space_xs),
+                )
+                .on_press(Message::GitStashDiff(
+                    project_path.clone(),
+                    stash.hash.clone(),
+                ))
+                .class(theme::Button::AppletMenu)
+                .width(Length::Fill),
+            );
+        }
+
+        let mut column = widget::column::with_capacity(2)
+            .push(section)
+            .spacing(spacing.space_xxs);
+        if let Some(result) = &editor.result_opt {
+            column = column.push(match result {
+                Ok(message) => widget::text::caption(message.clone()),
+                Err(err) => widget::text::caption(err.clone())
+                    .class(theme::Text::Color(cosmic_theme.destructive_color().into())),
+            });
+        }
+        column.into()
+    }
+
+    fn git_commit_editor<'a>(
+        &'a self,
+        project: &'a GitProject,
+        editor: &'a GitCommitEditor,
+        has_staged: bool,
+    ) -> Element<'a, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let spacing = cosmic_theme.spacing;
+        let project_path = &project.path;
+
+        // Subjects should fit in 50 characters and must not exceed 72
+        let message = editor.message();
+        let subject_len = message.lines().next().unwrap_or_default().chars().count();
+        let subject_color = if subject_len > 72 {
+            cosmic_theme.destructive_color()
+        } else if subject_len > 50 {
+            cosmic_theme.warning_color()
+        } else {
+            cosmic_theme.palette.neutral_6
+        };
+
+        let mut column = widget::column::with_capacity(4)
+            .push(
+                text_editor(&editor.content)
+                    .placeholder(fl!("commit-message"))
+                    .on_action({
+                        let project_path = project_path.clone();
+                        move |text_editor_action| {
+                            Message::GitCommitAction(project_path.clone(), text_editor_action)
+                        }
+                    })
+                    .font(Font::MONOSPACE)
+                    .height(Length::Fixed(120.0)),
+            )
+            .push(
+                widget::row::with_capacity(4)
+                    .push(
+                        widget::text(fl!("commit-subject-length", length = subject_len))
+                            .class(theme::Text::Color(subject_color.into())),
+                    )
+                    .push(widget::space::horizontal())
+                    .push(
+                        widget::checkbox(editor.amend)
+                            .label(fl!("commit-amend"))
+                            .on_toggle(|amend| {
+                                Message::GitCommitAmend(project_path.clone(), amend)
+                            }),
+                    )
+                    .push(
+                        widget::checkbox(editor.signoff)
+                            .label(fl!("commit-signoff"))
+                            .on_toggle(|signoff| {
+                                Message::GitCommitSignoff(project_path.clone(), signoff)
+                            }),
+                    )
+                    .align_y(Alignment::Center)
+                    .spacing(spacing.space_xs),
+            )
+            .spacing(spacing.space_xs);
+
+        match &editor.result_opt {
+            Some(Ok(output)) => {
+                if let Some(line) = output.lines().next() {
+                    column = column.push(
+                        widget::text(line.to_string())
+                            .class(theme::Text::Color(cosmic_theme.success_color().into())),
+                    );
+                }
+            }
+            Some(Err(err)) => {
+                // Includes output of failed hooks
+                column = column.push(
+                    widget::text::monotext(err.clone())
+                        .class(theme::Text::Color(cosmic_theme.destructive_color().into())),
+                );
+            }
+            None => {}
+        }
+
+        let can_commit = !message.is_empty() && !editor.in_progress && (has_staged || editor.amend);
+        column = column.push(
+            widget::row::with_capacity(2)
+                .push(widget::space::horizontal())
+                .push(
+                    widget::button::suggested(if editor.in_progress {
+                        fl!("committing")
+                    } else if editor.amend {
+                        fl!("commit-amend")
+                    } else {
+                        fl!("commit")
+                    })
+                    .on_press_maybe(can_commit.then(|| Message::GitCommit(project_path.clone()))),
+                ),
+        );
+
+        widget::settings::section()
+            .title(fl!("commit"))
+            .add(column)
+            .into()
+    }
+
+    fn project_search(&self) -> Element<'_, Message> {
+        let spacing = self.core().system_theme().cosmic().spacing;
+
+        let mut search_row = widget::row::with_capacity(2)
+            .align_y(Alignment::Center)
+            .spacing(spacing.space_xs)
+            .push(
+                widget::text_input::search_input(
+                    fl!("project-search"),
+                    self.project_search_value.clone(),
+                )
+                .id(self.project_search_id.clone())
+                .on_input(Message::ProjectSearchValue)
+                .on_submit(|_| Message::ProjectSearchSubmit),
+            );
+        if self
+            .project_search_result
+            .as_ref()
+            .is_some_and(|x| x.in_progress)
+        {
+            search_row = search_row.push(
+                widget::button::standard(fl!("cancel")).on_press(Message::ProjectSearchCancel),
+            );
+        }
+
+        let max_file_size_selected = self
+            .max_file_sizes
+            .iter()
+            .position(|max_file_size| max_file_size == &self.config.project_search_max_file_size);
+        let options = widget::column::with_children(vec![
+            widget::row::with_children(vec![
+                widget::checkbox(self.config.find_case_sensitive)
+                    .label(fl!("case-sensitive"))
+                    .on_toggle(Message::FindCaseSensitive)
+                    .into(),
+                widget::checkbox(self.config.find_use_regex)
+                    .label(fl!("use-regex"))
+                    .on_toggle(Message::FindUseRegex)
+                    .into(),
+                widget::checkbox(self.config.find_whole_word)
+                    .label(fl!("whole-word"))
+                    .on_toggle(Message::FindWholeWord)
+                    .into(),
+            ])
+            .align_y(Alignment::Center)
+            .spacing(spacing.space_xs)
+            .into(),
+            widget::checkbox(self.project_search_open_tabs_only)
+                .label(fl!("project-search-open-tabs-only"))
+                .on_toggle(Message::ProjectSearchOpenTabsOnly)
+                .into(),
+            widget::text_input::text_input(
+                fl!("project-search-include"),
+                &self.project_search_include_value,
+            )
+            .on_input(Message::ProjectSearchInclude)
+            .on_submit(|_| Message::ProjectSearchSubmit)
+            .into(),
+            widget::text_input::text_input(
+                fl!("project-search-exclude"),
+                &self.project_search_exclude_value,
+            )
+            .on_input(Message::ProjectSearchExclude)
+            .on_submit(|_| Message::ProjectSearchSubmit)
+            .into(),
+            widget::row::with_children(vec![
+                widget::text(fl!("project-search-context-before")).into(),
+                widget::space::horizontal().into(),
+                widget::dropdown(
+                    &self.project_search_context_names,
+                    Some(self.config.project_search_context_before.into()),
+                    Message::ProjectSearchContextBefore,
+                )
+                .into(),
+            ])
+            .align_y(Alignment::Center)
+            .spacing(spacing.space_xs)
+            .into(),
+            widget::row::with_children(vec![
+                widget::text(fl!("project-search-context-after")).into(),
+                widget::space::horizontal().into(),
+                widget::dropdown(
+                    &self.project_search_context_names,
+                    Some(self.config.project_search_context_after.into()),
+                    Message::ProjectSearchContextAfter,
+                )
+                .into(),
+            ])
+            .align_y(Alignment::Center)
+            .spacing(spacing.space_xs)
+            .into(),
+            widget::row::with_children(vec![
+                widget::text(fl!("project-search-max-file-size")).into(),
+                widget::space::horizontal().into(),
+                widget::dropdown(
+                    &self.max_file_size_names,
+                    max_file_size_selected,
+                    Message::ProjectSearchMaxFileSize,
+                )
+                .into(),
+            ])
+            .align_y(Alignment::Center)
+            .spacing(spacing.space_xs)
+            .into(),
+        ])
+        .spacing(spacing.space_xs);
+
+        let mut items = vec![search_row.into(), options.into()];
+
+        if let Some(project_search_result) = &self.project_search_result {
+            items.reserve(project_search_result.files.len().saturating_add(2));
+
+            if project_search_result.in_progress {
+                items.push(widget::text(fl!("project-search-in-progress")).into());
+            }
+
+            if let Some(err) = &project_search_result.error_opt {
+                let destructive_color = self.core().system_theme().cosmic().destructive_color();
+                items.push(
+                    widget::text(err.clone())
+                        .class(theme::Text::Color(destructive_color.into()))
+                        .into(),
+                );
+            }
+
+            let warning_color = self.core().system_theme().cosmic().warning_color();
+            for (file_i, file_search_result) in project_search_result.sorted_files() {
+                let key = file_search_result.key();
+                let collapsed = self.project_search_collapsed.contains(&key);
+
+                let mut header = widget::row::with_capacity(5)
+                    .push(icon_cache_get(
+                        if collapsed {
+                            "go-next-symbolic"
+                        } else {
+                            "go-down-symbolic"
+                        },
+                        16,
+                    ))
+                    .push(widget::text::heading(format!(
+                        "{}",
+                        file_search_result.path.display()
+                    )));
+                // Results from tabs with unsaved changes may not match the file on disk
+                if file_search_result.unsaved {
+                    header = header.push(
+                        widget::text(fl!("unsaved"))
+                            .class(theme::Text::Color(warning_color.into())),
+                    );
+                }
+                header = header
+                    .push(widget::space::horizontal())
+                    .push(widget::text(fl!(
+                        "project-search-match-count",
+                        count = file_search_result.match_count()
+                    )));
+
+                items.push(
+                    widget::button::custom(
+                        header.align_y(Alignment::Center).spacing(spacing.space_xs),
+                    )
+                    .on_press(Message::ProjectSearchToggleFile(key))
+                    .class(theme::Button::AppletMenu)
+                    .width(Length::Fill)
+                    .into(),
+                );
+
+                if collapsed {
+                    continue;
+                }
+
+                let mut column = widget::column::with_capacity(file_search_result.lines.len());
+                let mut line_number_width = 1;
+                if let Some(line_search_result) = file_search_result.lines.last() {
+                    let mut number = line_search_result.number;
+                    while number >= 10 {
+                        number /= 10;
+                        line_number_width += 1;
+                    }
+                }
+                let mut last_number_opt = None;
+                for (line_i, line_search_result) in file_search_result.lines.iter().enumerate() {
+                    // Separate groups of lines that are not contiguous
+                    if let Some(last_number) = last_number_opt {
+                        if line_search_result.number > last_number + 1 {
+                            column = column.push(widget::divider::horizontal::light());
+                        }
+                    }
+                    last_number_opt = Some(line_search_result.number);
+
+                    column = column.push(
+                        widget::button::custom(
+                            widget::row::with_children(vec![
+                                widget::text(format!(
+                                    "{:width$}",
+                                    line_search_result.number,
+                                    width = line_number_width,
+                                ))
+                                .font(Font::MONOSPACE)
+                                .into(),
+                                self.project_search_line(file_i, line_i, line_search_result),
+                            ])
+                            .spacing(spacing.space_xs),
+                        )
+                        .on_press(Message::OpenSearchResult(file_i, line_i, 0))
+                        .width(Length::Fill)
+                        .class(theme::Button::AppletMenu),
+                    );
+                }
+
+                items.push(widget::settings::section().add(column).into());
+            }
+        }
+
+        widget::column::with_children(items)
+            .spacing(spacing.space_s)
+            .padding([spacing.space_xxs, spacing.space_none])
+            .into()
+    }
+
+    /// Original version of a changed hunk, shown below the editor when its marker is clicked
+    fn git_hunk<'a>(
+        &self,
+        entity: segmented_button::Entity,
+        hunk_i: usize,
+        hunk: &'a GitDiffHunk,
+    ) -> Element<'a, Message> {
+        let cosmic_theme::Spacing {
+            space_xxs, space_s, ..
+        } = self.core().system_theme().cosmic().spacing;
+
+        let mut column = widget::column::with_capacity(hunk.lines.len() + 1);
+        column = column.push(
+            widget::row::with_capacity(3)
+                .push(widget::text::heading(fl!(
+                    "git-hunk-original",
+                    line = hunk.new_lines().start + 1
+                )))
+                .push(widget::space::horizontal())
+                .push(
+                    widget::button::standard(fl!("git-hunk-revert"))
+                        .on_press(Message::GitHunkRevert(entity, hunk_i)),
+                )
+                .push(
+                    button::custom(icon_cache_get("window-close-symbolic", 16))
+                        .on_press(Message::GitHunkClose(entity))
+                        .padding(space_xxs)
+                        .class(style::Button::Icon),
+                )
+                .align_y(Alignment::Center)
+                .spacing(space_xxs),
+        );
+        let mut has_old_lines = false;
+        for text in hunk.old_text() {
+            has_old_lines = true;
+            column = column.push(
+                widget::container(widget::text::monotext(trim_line_ending(text).to_string()))
+                    .style(|theme| {
+                        let mut color: Color = theme.cosmic().destructive_color().into();
+                        color.a = 0.2;
+                        widget::container::Style {
+                            background: Some(Background::Color(color)),
+                            ..Default::default()
+                        }
+                    })
+                    .width(Length::Fill),
+            );
+        }
+        if !has_old_lines {
+            column = column.push(widget::text(fl!("git-hunk-added")));
+        }
+
+        widget::layer_container(
+            widget::container(widget::scrollable(column).height(Length::Shrink))
+                .padding(space_s)
+                .max_height(240.0),
+        )
+        .layer(cosmic_theme::Layer::Primary)
+        .into()
+    }
+
+    fn git_blame_popup<'a>(&self, commit: &'a GitBlameCommit) -> Element<'a, Message> {
+        let cosmic_theme::Spacing {
+            space_xxs, space_s, ..
+        } = self.core().system_theme().cosmic().spacing;
+
+        let mut column = widget::column::with_capacity(2).spacing(space_xxs);
+        if commit.is_committed() {
+            column = column
+                .push(widget::text::caption(format!(
+                    "{} • {} • {}",
+                    commit.short_hash,
+                    commit.author,
+                    relative_time(commit.time)
+                )))
+                .push(widget::text(&commit.message));
+        } else {
+            column = column.push(widget::text(fl!("git-blame-uncommitted")));
+        }
+
+        widget::layer_container(widget::container(column).padding(space_s).max_width(480.0))
+            .layer(cosmic_theme::Layer::Primary)
+            .into()
+    }
+
+    /// Completions of a language server next to the documentation of the selected one, below
+    /// the signature of the call being typed
+    fn lsp_popup<'a>(
+        &self,
+        completion_opt: Option<&'a LspCompletion>,
+        signature_help_opt: Option<&'a LspSignatureHelp>,
+    ) -> Element<'a, Message> {
+        let cosmic_theme::Spacing {
+            space_xxs, space_s, ..
+        } = self.core().system_theme().cosmic().spacing;
+
+        let mut column = widget::column::with_capacity(2).spacing(space_xxs);
+        if let Some(signature_help) = signature_help_opt
+            && let Some(active) = active_signature(&signature_help.help, signature_help.encoding)
+        {
+            let label = &active.signature.label;
+            let mut signature_column = widget::column::with_capacity(3).spacing(space_xxs);
+            signature_column = signature_column.push(match active.range_opt {
+                Some(range) => Element::from(
+                    widget::row::with_capacity(3)
+                        .push(widget::text::monotext(&label[..range.start]))
+                        .push(
+                            widget::container(widget::text::monotext(&label[range.clone()]))
+                                .style(popup_selected_style),
+                        )
+                        .push(widget::text::monotext(&label[range.end..])),
+                ),
+                None => widget::text::monotext(label).into(),
+            });
+            if let Some(documentation) = active
+                .parameter_opt
+                .and_then(|parameter| parameter.documentation.as_ref())
+            {
+                signature_column =
+                    signature_column.push(widget::text::caption(documentation_text(documentation)));
+            }
+            if let Some(documentation) = &active.signature.documentation {
+                signature_column =
+                    signature_column.push(widget::text::caption(documentation_text(documentation)));
+            }
+            column = column.push(signature_column);
+        }
+
+        if let Some(completion) = completion_opt {
+            let list = &completion.list;
+            // Only a page of completions around the selected one is shown
+            let start = list
+                .selected
+                .saturating_sub(LSP_COMPLETION_ITEMS / 2)
+                .min(list.matches.len().saturating_sub(LSP_COMPLETION_ITEMS));
+            let mut items = widget::column::with_capacity(LSP_COMPLETION_ITEMS);
+            for (match_i, item_i) in list
+                .matches
+                .iter()
+                .enumerate()
+                .skip(start)
+                .take(LSP_COMPLETION_ITEMS)
+            {
+                let item = &list.items[*item_i];
+                let mut row = widget::row::with_capacity(2)
+                    .push(widget::text::monotext(&item.label).width(Length::Fill))
+                    .align_y(Alignment::Center)
+                    .spacing(space_s);
+                if let Some(detail) = &item.detail {
+                    row = row.push(widget::text::caption(detail));
+                }
+                let mut item_container = widget::container(
+                    button::custom(row)
+                        .on_press(Message::LspCompletionAccept(match_i))
+                        .class(theme::Button::AppletMenu)
+                        .width(Length::Fill),
+                );
+                if match_i == list.selected {
+                    item_container = item_container.style(popup_selected_style);
+                }
+                items = items.push(item_container);
+            }
+
+            let mut row = widget::row::with_capacity(2)
+                .push(widget::container(items).max_width(360.0))
+                .spacing(space_s);
+            if let Some(item) = list.selected_item()
+                && let Some(documentation) = &item.documentation
+            {
+                row = row.push(
+                    widget::container(widget::scrollable(widget::text::body(documentation_text(
+                        documentation,
+                    ))))
+                    .max_width(360.0)
+                    .max_height(240.0),
+                );
+            }
+            column = column.push(row);
+        }
+
+        widget::layer_container(widget::container(column).padding(space_s).max_width(760.0))
+            .layer(cosmic_theme::Layer::Primary)
+            .into()
+    }
+
+    /// Code actions at the cursor or at a diagnostic marker, while loading and once loaded
+    fn lsp_code_actions_popup<'a>(&self, code_actions: &'a LspCodeActions) -> Element<'a, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let space_s = cosmic_theme.spacing.space_s;
+
+        let content: Element<_> = match &code_actions.result_opt {
+            None => widget::text::body(fl!("code-actions-loading")).into(),
+            Some(Ok(actions)) if actions.is_empty() => {
+                widget::text::body(fl!("code-actions-empty")).into()
+            }
+            Some(Ok(actions)) => {
+                let mut items = widget::column::with_capacity(actions.len());
+                for (action_i, action) in actions.iter().enumerate() {
+                    let title = match action {
+                        lsp_types::CodeActionOrCommand::Command(command) => &command.title,
+                        lsp_types::CodeActionOrCommand::CodeAction(action) => &action.title,
+                    };
+                    let mut item_container = widget::container(
+                        button::custom(widget::text::body(title))
+                            .on_press(Message::LspCodeActionApply(action_i))
+                            .class(theme::Button::AppletMenu)
+                            .width(Length::Fill),
+                    );
+                    if action_i == code_actions.selected {
+                        item_container = item_container.style(popup_selected_style);
+                    }
+                    items = items.push(item_container);
+                }
+                widget::scrollable(items).into()
+            }
+            Some(Err(err)) => widget::text::body(err)
+                .class(theme::Text::Color(cosmic_theme.destructive_color().into()))
+                .into(),
+        };
+
+        widget::layer_container(
+            widget::container(content)
+                .padding(space_s)
+                .max_width(480.0)
+                .max_height(320.0),
+        )
+        .layer(cosmic_theme::Layer::Primary)
+        .into()
+    }
+
+    /// Failed operation above the tabs, shown until closed
+    fn error_bar<'a>(
+        &self,
+        heading: String,
+        err: &'a str,
+        on_close: Message,
+    ) -> Element<'a, Message> {
+        let cosmic_theme::Spacing { space_xxs, .. } = self.core().system_theme().cosmic().spacing;
+        let destructive_color = self.core().system_theme().cosmic().destructive_color();
+
+        widget::row::with_capacity(3)
+            .push(
+                widget::column::with_capacity(2)
+                    .push(widget::text::heading(heading))
+                    .push(
+                        widget::text::body(err).class(theme::Text::Color(destructive_color.into())),
+                    )
+                    .width(Length::Fill),
+            )
+            .push(widget::tooltip(
+                button::custom(icon_cache_get("window-close-symbolic", 16))
+                    .on_press(on_close)
+                    .padding(space_xxs)
+                    .class(style::Button::Icon),
+                widget::text::body(fl!("error-close")),
+                widget::tooltip::Position::Bottom,
+            ))
+            .align_y(Alignment::Center)
+            .spacing(space_xxs)
+            .padding(space_xxs)
+            .into()
+    }
+
+    fn git_conflict_bar<'a>(
+        &self,
+        entity: segmented_button::Entity,
+        tab: &'a EditorTab,
+    ) -> Element<'a, Message> {
+        let cosmic_theme::Spacing { space_xxs, .. } = self.core().system_theme().cosmic().spacing;
+
+        let cursor_line = tab.editor.lock().unwrap().cursor().line;
+        let conflicts = &tab.git_conflicts;
+        let current_opt = conflicts
+            .iter()
+            .position(|conflict| conflict.lines().contains(&cursor_line));
+        let previous_opt = conflicts
+            .iter()
+            .rposition(|conflict| conflict.end < cursor_line);
+        let next_opt = conflicts
+            .iter()
+            .position(|conflict| conflict.start > cursor_line);
+        let goto = |conflict_i_opt: Option<usize>| {
+            conflict_i_opt.map(|conflict_i| {
+                Message::TabSetCursor(entity, Cursor::new(conflicts[conflict_i].start, 0))
+            })
+        };
+
+        let label = match current_opt {
+            _ if conflicts.is_empty() => fl!("git-conflicts-none"),
+            Some(conflict_i) => fl!(
+                "git-conflict-position",
+                position = conflict_i + 1,
+                count = conflicts.len()
+            ),
+            None => fl!("git-conflicts", count = conflicts.len()),
+        };
+        let mut row = widget::row::with_capacity(8)
+            .push(widget::tooltip(
+                button::custom(icon_cache_get("go-up-symbolic", 16))
+                    .on_press_maybe(goto(previous_opt))
+                    .padding(space_xxs)
+                    .class(style::Button::Icon),
+                widget::text::body(fl!("git-conflict-previous")),
+                widget::tooltip::Position::Bottom,
+            ))
+            .push(widget::tooltip(
+                button::custom(icon_cache_get("go-down-symbolic", 16))
+                    .on_press_maybe(goto(next_opt))
+                    .padding(space_xxs)
+                    .class(style::Button::Icon),
+                widget::text::body(fl!("git-conflict-next")),
+                widget::tooltip::Position::Bottom,
+            ))
+            .push(widget::text::body(label))
+            .push(widget::space::horizontal())
+            .align_y(Alignment::Center)
+            .spacing(space_xxs)
+            .padding(space_xxs);
+        if conflicts.is_empty() {
+            // Files must be saved before they are staged
+            row = row.push(
+                widget::button::suggested(fl!("git-conflict-mark-resolved")).on_press_maybe(
+                    (!tab.changed()).then_some(Message::GitConflictResolve(entity)),
+                ),
+            );
+        } else {
+            let accept = |choice| {
+                current_opt.map(|conflict_i| Message::GitConflictAccept(entity, conflict_i, choice))
+            };
+            row = row
+                .push(
+                    widget::button::standard(fl!("git-conflict-accept-ours"))
+                        .on_press_maybe(accept(GitConflictChoice::Ours)),
+                )
+                .push(
+                    widget::button::standard(fl!("git-conflict-accept-theirs"))
+                        .on_press_maybe(accept(GitConflictChoice::Theirs)),
+                )
+                .push(
+                    widget::button::standard(fl!("git-conflict-accept-both"))
+                        .on_press_maybe(accept(GitConflictChoice::Both)),
+                );
+        }
+        row.into()
+    }
+
+    /// One line of a diff tab, `side_opt` picks the old or new column for side-by-side rows
+    fn git_diff_line<'a>(
+        &self,
+        tab_id: segmented_button::Entity,
+        tab: &'a GitDiffTab,
+        hunk_i: usize,
+        line_i: usize,
+        side_opt: Option<bool>,
+    ) -> Element<'a, Message> {
+        let cosmic_theme = self.core().system_theme().cosmic();
+        let line = &tab.diff.hunks[hunk_i].lines[line_i];
+        let text = line.text();
+        let (prefix, line_color_opt) = match line {
+            GitDiffLine::Context {
+                old_line, new_line, ..
+            } => {
+                let prefix = match side_opt {
+                    None => format!("{:4} {:4}   ", old_line, new_line),
+                    Some(false) => format!("{:4}   ", old_line),
+                    Some(true) => format!("{:4}   ", new_line),
+                };
+                (prefix, None)
+            }
+            GitDiffLine::Added { new_line, .. } => {
+                let prefix = match side_opt {
+                    None => format!("{:4} {:4} + ", "", new_line),
+                    _ => format!("{:4} + ", new_line),
+                };
+                (prefix, Some(cosmic_theme.success_color()))
+            }
+            GitDiffLine::Deleted { old_line, .. } => {
+                let prefix = match side_opt {
+                    None => format!("{:4} {:4} - ", old_line, ""),
+                    _ => format!("{:4} - ", old_line),
+                };
+                (prefix, Some(cosmic_theme.destructive_color()))
+            }
+        };
+
+        let mut spans: Vec<iced::widget::text::Span<'static>> = vec![
+            iced::widget::span(prefix)
+                .font(Font::MONOSPACE)
+                .color(cosmic_theme.palette.neutral_6),
+        ];
+        let line_spans = tab
+            .spans
+            .get(hunk_i)
+            .and_then(|hunk_spans| hunk_spans.get(line_i))
+            .map_or(&[][..], |line_spans| line_spans.as_slice());
+        for line_span in line_spans {
+            let Some(span_text) = text.get(line_span.range.clone()) else {
+                continue;
+            };
+            let mut span = iced::widget::span(span_text.to_string())
+                .font(Font::MONOSPACE)
+                .color_maybe(line_span.color);
+            if let Some(line_color) = line_color_opt.filter(|_| line_span.changed) {
+                let mut background = Color::from(line_color);
+                background.a = 0.35;
+                span = span.background(background);
+            }
+            spans.push(span);
+        }
+        if line_spans.is_empty() {
+            spans.push(iced::widget::span(text.to_string()).font(Font::MONOSPACE));
+        }
+
+        let selected = tab
+            .selection_opt
+            .as_ref()
+            .is_some_and(|(selection_hunk_i, lines)| {
+                *selection_hunk_i == hunk_i && lines.contains(&line_i)
+            });
+        let line_widget = widget::container(iced::widget::rich_text(spans))
+            .width(Length::Fill)
+            .style(move |theme| widget::container::Style {
+                background: line_color_opt.map(|line_color| {
+                    let mut background = Color::from(line_color);
+                    background.a = 0.15;
+                    Background::Color(background)
+                }),
+                // Selected lines are outlined on top of their diff color
+                border: if selected {
+                    Border {
+                        color: theme.cosmic().accent_color().into(),
+                        width: 1.0,
+                        radius: 0.0.into(),
+                    }
+                } else {
+                    Border::default()
+                },
+                ..Default::default()
+            });
+        button::custom(line_widget)
+            .on_press(Message::GitDiffSelect(tab_id, hunk_i, line_i))
+            .padding(0)
+            .class(theme::Button::Text)
+            .width(Length::Fill)
+            .into()
+    }
+
+    /// Line text with every match highlighted, clicking a match opens it
+    fn project_search_line<'a>(
+        &self,
+        file_i: usize,
+        line_i: usize,
+        line_search_result: &'a LineSearchResult,
+    ) -> Element<'a, Message> {
+        let text = &line_search_result.text;
+        if line_search_result.is_context() {
+            let context_color = self.core().system_theme().cosmic().palette.neutral_6;
+            return widget::text(text.clone())
+                .font(Font::MONOSPACE)
+                .class(theme::Text::Color(context_color.into()))
+                .into();
+        }
+
+        let mut row = widget::row::with_capacity(line_search_result.matches.len() * 2 + 1);
+        let mut last_end = 0;
+        for (match_i, m) in line_search_result.matches.iter().enumerate() {
+            // Matches may extend into trimmed whitespace
+            let start = m.start().min(text.len()).max(last_end);
+            let end = m.end().min(text.len()).max(start);
+            if let Some(before) = text.get(last_end..start) {
+                if !before.is_empty() {
+                    row = row.push(widget::text(before.to_string()).font(Font::MONOSPACE));
+                }
+            }
+            if let Some(matched) = text.get(start..end) {
+                row = row.push(
+                    widget::button::custom(
+                        widget::container(widget::text(matched.to_string()).font(Font::MONOSPACE))
+                            .style(|theme| {
+                                let mut color: Color = theme.cosmic().accent_color().into();
+                                color.a = 0.3;
+                                widget::container::Style {
+                                    background: Some(Background::Color(color)),
+                                    ..Default::default()
+                                }
+                            }),
+                    )
+                    .on_press(Message::OpenSearchResult(file_i, line_i, match_i))
+                    .padding(0)
+                    .class(theme::Button::Text),
+                );
+            }
+            last_end = end;
+        }
+        if let Some(after) = text.get(last_end..) {
+            if !after.is_empty() {
+                row = row.push(widget::text(after.to_string()).font(Font::MONOSPACE));
+            }
+        }
+        row.into()
+    }
+
+    fn settings(&self) -> Element<'_, Message> {
+        let app_theme_selected = match self.config.app_theme {
+            AppTheme::Dark => 1,
+            AppTheme::Light => 2,
+            AppTheme::System => 0,
+        };
+        let dark_selected = self
+            .theme_names
+            .iter()
+            .position(|theme_name| theme_name == &self.config.syntax_theme_dark);
+        let light_selected = self
+            .theme_names
+            .iter()
+            .position(|theme_name| theme_name == &self.config.syntax_theme_light);
+        let font_selected = {
+            let mut font_system = font_system().write().unwrap();
+            let current_font_name = font_system.raw().db().family_name(&Family::Monospace);
+            self.font_names
+                .iter()
+                .position(|font_name| font_name == current_font_name)
+        };
+        let font_size_selected = self
+            .font_sizes
+            .iter()
+            .position(|font_size| font_size == &self.config.font_size);
+        let zoom_step_selected = self
+            .zoom_steps
+            .iter()
+            .position(|zoom_step| zoom_step == &self.config.font_size_zoom_step_mul_100);
+        let local_history_max_count_selected = self
+            .local_history_max_counts
+            .iter()
+            .position(|max_count| max_count == &self.config.local_history_max_count);
+        let undo_history_max_changes_selected = self
+            .undo_history_max_changes
+            .iter()
+            .position(|max_changes| max_changes == &self.config.undo_history_max_changes);
+        let undo_history_max_file_size_selected = self
+            .max_file_sizes
+            .iter()
+            .position(|max_file_size| max_file_size == &self.config.undo_history_max_file_size);
+        let local_history_max_days_selected = self
+            .local_history_max_days
+            .iter()
+            .position(|max_days| max_days == &self.config.local_history_max_days);
+        widget::settings::view_column(vec![
+            widget::settings::section()
+                .title(fl!("appearance"))
+                .add(
+                    widget::settings::item::builder(fl!("theme")).control(widget::dropdown(
+                        &self.app_themes,
+                        Some(app_theme_selected),
+                        move |index| {
+                            Message::AppTheme(match index {
+                                1 => AppTheme::Dark,
+                                2 => AppTheme::Light,
+                                _ => AppTheme::System,
+                            })
+                        },
+                    )),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("syntax-dark")).control(widget::dropdown(
+                        &self.theme_names,
+                        dark_selected,
+                        move |index| Message::SyntaxTheme(index, true),
+                    )),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("syntax-light")).control(widget::dropdown(
+                        &self.theme_names,
+                        light_selected,
+                        move |index| Message::SyntaxTheme(index, false),
+                    )),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("default-font")).control(widget::dropdown(
+                        &self.font_names,
+                        font_selected,
+                        Message::DefaultFont,
+                    )),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("default-font-size")).control(
+                        widget::dropdown(&self.font_size_names, font_size_selected, |index| {
+                            Message::DefaultFontSize(index)
+                        }),
+                    ),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("default-zoom-step")).control(
+                        widget::dropdown(&self.zoom_step_names, zoom_step_selected, |index| {
+                            Message::DefaultZoomStep(index)
+                        }),
+                    ),
+                )
+                .into(),
+            widget::settings::section()
+                .title(fl!("local-history"))
+                .add(
+                    widget::settings::item::builder(fl!("local-history-max-count")).control(
+                        widget::dropdown(
+                            &self.local_history_max_count_names,
+                            local_history_max_count_selected,
+                            Message::LocalHistoryMaxCount,
+                        ),
+                    ),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("local-history-max-days")).control(
+                        widget::dropdown(
+                            &self.local_history_max_days_names,
+                            local_history_max_days_selected,
+                            Message::LocalHistoryMaxDays,
+                        ),
+                    ),
+                )
+                .into(),
+            widget::settings::section()
+                .title(fl!("undo-history"))
+                .add(
+                    widget::settings::item::builder(fl!("undo-history-max-changes")).control(
+                        widget::dropdown(
+                            &self.undo_history_max_changes_names,
+                            undo_history_max_changes_selected,
+                            Message::UndoHistoryMaxChanges,
+                        ),
+                    ),
+                )
+                .add(
+                    widget::settings::item::builder(fl!("undo-history-max-file-size")).control(
+                        widget::dropdown(
+                            &self.max_file_size_names,
+                            undo_history_max_file_size_selected,
+                            Message::UndoHistoryMaxFileSize,
+                        ),
+                    ),
+                )
+                .into(),
+            widget::settings::section()
+                .title(fl!("formatting"))
+                .add(
+                    widget::settings::item::builder(fl!("format-on-save"))
+                        .toggler(self.config.format_on_save, Message::FormatOnSave),
+                )
+                .into(),
+            widget::settings::section()
+                .title(fl!("language-servers"))
+                .add(
+                    widget::settings::item::builder(fl!("enable-language-servers"))
+                        .toggler(self.config.lsp_enabled, Message::LspEnabled),
+                )
+                .into(),
+            widget::settings::section()
+                .title(fl!("keyboard-shortcuts"))
+                .add(
+                    widget::settings::item::builder(fl!("enable-vim-bindings"))
+                        .toggler(self.config.vim_bindings, Message::VimBindings),
+                )
+                .into(),
+        ])
+        .into()
+    }
+}
+
+/// Implement [`cosmic::Application`] to integrate with COSMIC.
+impl Application for App {
+    /// Default async executor to use with the app.
+    type Executor = executor::Default;
+
+    /// Argument received [`cosmic::Application::new`].
+    type Flags = Flags;
+
+    /// Message type specific to our [`App`].
+    type Message = Message;
+
+    /// The unique application ID to supply to the window manager.
+    const APP_ID: &'static str = "com.system76.CosmicEdit";
+
+    fn core(&self) -> &Core {
+        &self.core
+    }
+
+    fn core_mut(&mut self) -> &mut Core {
+        &mut self.core
+    }
+
+    /// Creates the application, and optionally emits command on initialize.
+    fn init(mut core: Core, flags: Self::Flags) -> (Self, Task<Self::Message>) {
+        core.window.context_is_overlay = false;
+
+        // Update font name from config
+        {
+            let mut font_system = font_system().write().unwrap();
+            font_system
+                .raw()
+                .db_mut()
+                .set_monospace_family(&flags.config.font_name);
+        }
+
+        let app_themes = vec![fl!("match-desktop"), fl!("dark"), fl!("light")];
+
+        let font_names = {
+            let mut font_names = Vec::new();
+            let mut font_system = font_system().write().unwrap();
+            for face in font_system.raw().db().faces() {
+                if face.monospaced {
+                    //TODO: get localized name if possible
+                    let font_name = face
+                        .families
+                        .first()
+                        .map_or_else(|| face.post_script_name.to_string(), |x| x.0.to_string());
+                    if !font_names.contains(&font_name) {
+                        font_names.push(font_name);
+                    }
+                }
+            }
+            font_names.sort();
+            font_names
+        };
+
+        let mut font_size_names = Vec::new();
+        let mut font_sizes = Vec::new();
+        for font_size in 4..=32 {
+            font_size_names.push(format!("{}px", font_size));
+            font_sizes.push(font_size);
+        }
+
+        let mut theme_names =
+            Vec::with_capacity(SYNTAX_SYSTEM.get().unwrap().theme_set.themes.len());
+        for (theme_name, _theme) in SYNTAX_SYSTEM.get().unwrap().theme_set.themes.iter() {
+            theme_names.push(theme_name.to_string());
+        }
+
+        let mut zoom_step_names = Vec::new();
+        let mut zoom_steps = Vec::new();
+        for zoom_step in [25, 50, 75, 100, 150, 200] {
+            zoom_step_names.push(format!("{}px", f32::from(zoom_step) / 100.0));
+            zoom_steps.push(zoom_step);
+        }
+
+        let mut max_file_size_names = Vec::new();
+        let mut max_file_sizes = Vec::new();
+        for max_file_size_mib in [1, 8, 64, 512] {
+            max_file_size_names.push(format!("{} MiB", max_file_size_mib));
+            max_file_sizes.push(max_file_size_mib * 1024 * 1024);
+        }
+        // Zero is used for no limit
+        max_file_size_names.push(fl!("unlimited"));
+        max_file_sizes.push(0);
+
+        let mut local_history_max_count_names = Vec::new();
+        let mut local_history_max_counts = Vec::new();
+        for max_count in [10, 50, 100, 500] {
+            local_history_max_count_names.push(max_count.to_string());
+            local_history_max_counts.push(max_count);
+        }
+        let mut local_history_max_days_names = Vec::new();
+        let mut local_history_max_days = Vec::new();
+        for max_days in [1, 7, 30, 90, 365] {
+            local_history_max_days_names.push(fl!("local-history-days", count = max_days));
+            local_history_max_days.push(max_days);
+        }
+        // Zero is used for no limit
+        local_history_max_count_names.push(fl!("unlimited"));
+        local_history_max_counts.push(0);
+        local_history_max_days_names.push(fl!("unlimited"));
+        local_history_max_days.push(0);
+
+        let mut undo_history_max_changes_names = Vec::new();
+        let mut undo_history_max_changes = Vec::new();
+        for max_changes in [10, 100, 1000] {
+            undo_history_max_changes_names.push(max_changes.to_string());
+            undo_history_max_changes.push(max_changes);
+        }
+        // Zero is used for no limit
+        undo_history_max_changes_names.push(fl!("unlimited"));
+        undo_history_max_changes.push(0);
+
+        let mut project_search_context_names = Vec::new();
+        for context_lines in 0..=10 {
+            project_search_context_names.push(context_lines.to_string());
+        }
+
+        let about = About::default()
+            .name(fl!("cosmic-text-editor"))
+            .icon(icon::from_name(Self::APP_ID))
+            .version(env!("CARGO_PKG_VERSION"))
+            .author("System76")
+            .comments(fl!("comment"))
+            .license("GPL-3.0-only")
+            .developers([("Jeremy Soller", "jeremy@system76.com")])
+            .links([
+                (fl!("repository"), "https://github.com/pop-os/cosmic-edit"),
+                (
+                    fl!("support"),
+                    "https://github.com/pop-os/cosmic-edit/issues",
+                ),
+            ]);
+
+        let mut app = App {
+            core,
+            about,
+            nav_model: nav_bar::Model::builder().build(),
+            tab_model: segmented_button::Model::builder().build(),
+            config_handler: flags.config_handler,
+            config: flags.config,
+            config_state_handler: flags.config_state_handler,
+            config_state: flags.config_state,
+            key_binds: key_binds(),
+            zoom_step_names,
+            zoom_steps,
+            max_file_size_names,
+            max_file_sizes,
+            app_themes,
+            font_names,
+            font_size_names,
+            font_sizes,
+            theme_names,
+            context_page: ContextPage::Settings,
+            text_box_id: widget::Id::unique(),
+            auto_scroll: None,
+            compare_selection_opt: None,
+            dialog_opt: None,
+            dialog_page_opt: None,
+            find_opt: None,
+            find_replace_id: widget::Id::unique(),
+            find_replace_value: String::new(),
+            find_search_id: widget::Id::unique(),
+            find_search_value: String::new(),
+            formatter_error_opt: None,
+            git_askpass_id: widget::Id::unique(),
+            git_askpass_prompts: VecDeque::new(),
+            git_blame_hover_opt: None,
+            git_branch_pickers: HashMap::new(),
+            git_diff_scroll_id: widget::Id::unique(),
+            git_error_opt: None,
+            git_file_history_opt: None,
+            git_project_status: None,
+            git_project_status_generation: 0,
+            git_project_status_update: 0,
+            git_project_status_full: false,
+            git_tree_status: ProjectGitStatuses::default(),
+            git_commit_editors: HashMap::new(),
+            git_stash_editors: HashMap::new(),
+            git_remotes: HashMap::new(),
+            local_history_opt: None,
+            local_history_max_count_names,
+            local_history_max_counts,
+            local_history_max_days_names,
+            local_history_max_days,
+            undo_history_max_changes_names,
+            undo_history_max_changes,
+            lsp_servers: BTreeMap::new(),
+            lsp_diagnostics: BTreeMap::new(),
+            lsp_completion_opt: None,
+            lsp_completion_generation: 0,
+            lsp_signature_help_opt: None,
+            lsp_signature_help_generation: 0,
+            lsp_code_actions_opt: None,
+            lsp_code_actions_generation: 0,
+            lsp_error_opt: None,
+            navigation_opt: None,
+            navigation_generation: 0,
+            tags_cache: TagsCache::default(),
+            projects: Vec::new(),
+            project_git_dirs: HashMap::new(),
+            project_search_id: widget::Id::unique(),
+            project_search_value: String::new(),
+            project_search_result: None,
+            project_search_has_focus: false,
+            project_search_include_value: String::new(),
+            project_search_exclude_value: String::new(),
+            project_search_context_names,
+            project_search_collapsed: HashSet::new(),
+            project_search_open_tabs_only: false,
+            project_search_generation: 0,
+            project_search_cancel_opt: None,
+            rename_id: widget::Id::unique(),
+            rename_opt: None,
+            rename_generation: 0,
+            symbol_search_id: widget::Id::unique(),
+            symbol_search_opt: None,
+            symbol_search_generation: 0,
+            watcher_opt: None,
+            modifiers: Modifiers::empty(),
+        };
+
+        // Do not show nav bar by default. Will be opened by open_project if needed
+        app.core.nav_bar_set_toggled(false);
+        let mut tasks = Vec::new();
+        for arg in env::args().skip(1) {
+            let path = PathBuf::from(arg);
+            if path.is_dir() {
+                tasks.push(app.open_project(path));
+            } else {
+                app.open_tab(Some(path));
+            }
+        }
+
+        app.update_nav_bar_placeholder();
+
+        // Open an empty file if no arguments provided
+        if app.tab_model.iter().next().is_none() {
+            app.open_tab(None);
+        }
+
+        //TODO: try update_config here? It breaks loading system theme by default
+        tasks.push(app.update_tab());
+        (app, Task::batch(tasks))
+    }
+
+    // The default nav_bar widget needs to be condensed for cosmic-edit
+    fn nav_bar(&self) -> Option<Element<'_, action::Action<Self::Message>>> {
+        if !self.core().nav_bar_active() {
+            return None;
+        }
+
+        let nav_model = self.nav_model()?;
+
+        let cosmic_theme::Spacing {
+            space_none,
+            space_s,
+            space_xxxs,
+            ..
+        } = self.core().system_theme().cosmic().spacing;
+
+        let mut nav = segmented_button::vertical(nav_model)
+            .button_height(space_xxxs + 20 /* line height */ + space_xxxs)
+            .button_padding([space_s, space_xxxs, space_s, space_xxxs])
+            .button_spacing(space_xxxs)
+            .on_activate(|entity| action::cosmic(cosmic::app::Action::NavBar(entity)))
+            .on_context(|entity| action::cosmic(cosmic::app::Action::NavBarContext(entity)))
+            .context_menu(self.nav_context_menu(self.core().nav_bar_context()))
+            .spacing(space_none)
+            .style(theme::SegmentedButton::FileNav)
+            .apply(widget::container)
+            .padding(space_s)
+            .width(Length::Shrink);
+
+        if !self.core().is_condensed() {
+            nav = nav.max_width(280);
+        }
+
+        Some(
+            nav.apply(widget::scrollable)
+                .apply(widget::container)
+                .height(Length::Fill)
+                .class(theme::Container::custom(nav_bar::nav_bar_style))
+                .into(),
+        )
+    }
+
+    fn nav_context_menu(
+        &self,
+        id: nav_bar::Id,
+    ) -> Option<Vec<widget::menu::Tree<action::Action<Self::Message>>>> {
+        // Formatters can be turned off for projects with their own formatting conventions
+        if let Some(ProjectNode::Folder {
+            path, root: true, ..
+        }) = self.nav_model.data::<ProjectNode>(id)
+        {
+            let enabled = !self.config.formatter_disabled_projects.contains(path);
+            return Some(widget::menu::items(
+                &HashMap::new(),
+                vec![widget::menu::Item::CheckBox(
+                    fl!("use-formatters"),
+                    None,
+                    enabled,
+                    NavMenuAction::ToggleFormatters(id),
+                )],
+            ));
+        }
+        // Only files can be compared
+        let Some(ProjectNode::File { .. }) = self.nav_model.data::<ProjectNode>(id) else {
+            return None;
+        };
+        let mut items = vec![widget::menu::Item::Button(
+            fl!("compare-select"),
+            None,
+            NavMenuAction::CompareSelect(id),
+        )];
+        if self.compare_selection_opt.is_some() {
+            items.push(widget::menu::Item::Button(
+                fl!("compare-with-selected"),
+                None,
+                NavMenuAction::CompareWithSelected(id),
+            ));
+        }
+        Some(widget::menu::items(&HashMap::new(), items))
+    }
+
+    fn nav_model(&self) -> Option<&nav_bar::Model> {
+        Some(&self.nav_model)
+    }
+
+    fn on_app_exit(&mut self) -> Option<Message> {
+        Some(Message::Quit)
+    }
+
+    fn on_context_drawer(&mut self) -> Task<Message> {
+        // Focus correct widget
+        self.update_focus()
+    }
+
+    //TODO: currently the first escape unfocuses, and the second calls this function
+    fn on_escape(&mut self) -> Task<Message> {
+        if self.core.window.show_context {
+            // Close context drawer if open
+            self.core.window.show_context = false;
+        } else if self.find_opt.is_some() {
+            // Close find if open
+            self.find_opt = None;
+        }
+
+        // Focus correct widget
+        self.update_focus()
+    }
+
+    fn on_nav_select(&mut self, id: nav_bar::Id) -> Task<Message> {
+        // Toggle open state and get clone of node data
+        let node_opt = match self.nav_model.data_mut::<ProjectNode>(id) {
+            Some(node) => {
+                if let ProjectNode::Folder { open, .. } = node {
+                    *open = !*open;
+                }
+                Some(node.clone())
+            }
+            None => None,
+        };
+
+        match node_opt {
+            Some(node) => {
+                // Update icon
+                let icon = self.project_node_icon(&node);
+                self.nav_model.icon_set(id, icon);
+
+                match node {
+                    ProjectNode::Folder { path, open, .. } => {
+                        let position = self.nav_model.position(id).unwrap_or(0);
+                        let indent = self.nav_model.indent(id).unwrap_or(0);
+                        if open {
+                            // Open folder
+                            self.open_folder(path, position + 1, indent + 1);
+                        } else {
+                            // Close folder
+                            while let Some(child_id) = self.nav_model.entity_at(position + 1) {
+                                if self.nav_model.indent(child_id).unwrap_or(0) > indent {
+                                    self.nav_model.remove(child_id);
+                                } else {
+                                    break;
+                                }
+                            }
+                        }
+
+                        // Prevent nav bar from closing when selecting a
+                        // folder in condensed mode.
+                        self.core_mut().nav_bar_set_toggled(true);
+
+                        Task::none()
+                    }
+                    ProjectNode::File { path, .. } => {
+                        //TODO: go to already open file if possible
+                        self.update(Message::OpenFile(path))
+                    }
+                }
+            }
+            None => {
+                // Open project
+                self.update(Message::OpenProjectDialog)
+            }
+        }
+    }
+
+    fn dialog(&self) -> Option<Element<'_, Self::Message>> {
+        let Some(ref dialog) = self.dialog_page_opt else {
+            return None;
+        };
+
+        let cosmic_theme::Spacing { space_xxs, .. } = self.core().system_theme().cosmic().spacing;
+
+        match dialog {
+            DialogPage::PromptGitAskpass => {
+                let prompt = self.git_askpass_prompts.front()?;
+                // Only usernames are shown, passwords and passphrases are hidden
+                let input = if prompt.prompt.starts_with("Username") {
+                    widget::text_input::text_input("", &prompt.value)
+                } else {
+                    widget::secure_input("", &prompt.value, None, true)
+                }
+                .id(self.git_askpass_id.clone())
+                .on_input(Message::GitAskpassInput)
+                .on_submit(|_| Message::GitAskpassSubmit);
+                let submit_button = widget::button::suggested(fl!("git-askpass-submit"))
+                    .on_press(Message::GitAskpassSubmit);
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let dialog = widget::dialog()
+                    .title(fl!("prompt-git-askpass-title"))
+                    .body(prompt.prompt.trim().to_string())
+                    .icon(icon::from_name("dialog-password-symbolic").size(64))
+                    .control(input)
+                    .primary_action(submit_button)
+                    .secondary_action(cancel_button);
+                Some(dialog.into())
+            }
+            DialogPage::PromptGitCheckout(project_path, name) => {
+                let mut column = widget::column::with_capacity(1).spacing(space_xxs);
+                for entity in self.git_unsaved_tabs(project_path) {
+                    if let Some(tab) = self.tab_model.data::<Tab>(entity) {
+                        column = column.push(widget::text(tab.title()));
+                    }
+                }
+                let save_button =
+                    widget::button::suggested(fl!("save-all")).on_press(Message::SaveAll);
+                let switch_button = widget::button::destructive(fl!("git-branch-switch"))
+                    .on_press(Message::GitBranchSwitch(project_path.clone(), name.clone()));
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let dialog = widget::dialog()
+                    .title(fl!("prompt-git-checkout-title", name = name.as_str()))
+                    .body(fl!("prompt-git-checkout-unsaved"))
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .control(column)
+                    .primary_action(save_button)
+                    .secondary_action(switch_button)
+                    .tertiary_action(cancel_button);
+                Some(dialog.into())
+            }
+            DialogPage::PromptGitConflictResolve(project_path, path, count) => {
+                let resolve_button =
+                    widget::button::destructive(fl!("git-conflict-mark-resolved")).on_press(
+                        Message::GitConflictMarkResolvedForce(project_path.clone(), path.clone()),
+                    );
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let dialog = widget::dialog()
+                    .title(fl!(
+                        "prompt-git-conflict-resolve-title",
+                        name = file_title(path)
+                    ))
+                    .body(fl!("prompt-git-conflict-resolve", count = *count))
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .primary_action(resolve_button)
+                    .secondary_action(cancel_button);
+                Some(dialog.into())
+            }
+            DialogPage::PromptGitStash(project_path, stash_action) => {
+                let (title, body, label) = match stash_action {
+                    GitStashAction::Push(..) => (
+                        fl!("prompt-git-stash-push-title"),
+                        fl!("prompt-git-stash-unsaved"),
+                        fl!("git-stash-push"),
+                    ),
+                    GitStashAction::Apply(_, pop) => (
+                        fl!("prompt-git-stash-apply-title"),
+                        fl!("prompt-git-stash-unsaved"),
+                        if *pop {
+                            fl!("git-stash-pop")
+                        } else {
+                            fl!("git-stash-apply")
+                        },
+                    ),
+                    GitStashAction::Drop(_) => (
+                        fl!("prompt-git-stash-drop-title"),
+                        fl!("prompt-git-stash-drop"),
+                        fl!("git-stash-drop"),
+                    ),
+                };
+                let mut column = widget::column::with_capacity(1).spacing(space_xxs);
+                if stash_action.changes_files() {
+                    for entity in self.git_unsaved_tabs(project_path) {
+                        if let Some(tab) = self.tab_model.data::<Tab>(entity) {
+                            column = column.push(widget::text(tab.title()));
+                        }
+                    }
+                }
+                let confirm_button = widget::button::destructive(label).on_press(
+                    Message::GitStashForce(project_path.clone(), stash_action.clone()),
+                );
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let mut dialog = widget::dialog()
+                    .title(title)
+                    .body(body)
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .control(column);
+                if stash_action.changes_files() {
+                    let save_button =
+                        widget::button::suggested(fl!("save-all")).on_press(Message::SaveAll);
+                    dialog = dialog
+                        .primary_action(save_button)
+                        .secondary_action(confirm_button)
+                        .tertiary_action(cancel_button);
+                } else {
+                    dialog = dialog
+                        .primary_action(confirm_button)
+                        .secondary_action(cancel_button);
+                }
+                Some(dialog.into())
+            }
+            DialogPage::PromptGitDiscard(project_path, path, untracked) => {
+                let discard_button = widget::button::destructive(fl!("discard")).on_press(
+                    Message::GitDiscardForce(project_path.clone(), path.clone(), *untracked),
+                );
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let mut dialog = widget::dialog()
+                    .title(fl!("prompt-git-discard-title", name = file_title(path)))
+                    .body(if *untracked {
+                        fl!("prompt-git-discard-untracked")
+                    } else {
+                        fl!("prompt-git-discard-file")
+                    })
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .primary_action(discard_button)
+                    .secondary_action(cancel_button);
+                // Trashed files do not reload their tabs
+                if !*untracked {
+                    dialog = dialog.control(self.git_discard_unsaved(path));
+                }
+                Some(dialog.into())
+            }
+            DialogPage::PromptGitDiscardHunk(entity, hunk_i) => {
+                let Some(Tab::GitDiff(tab)) = self.tab_model.data::<Tab>(*entity) else {
+                    return None;
+                };
+                let discard_button = widget::button::destructive(fl!("discard")).on_press(
+                    Message::GitDiffApply(*entity, *hunk_i, GitHunkAction::Discard),
+                );
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let lines = match &tab.selection_opt {
+                    Some((selection_hunk_i, lines)) if selection_hunk_i == hunk_i => lines.len(),
+                    _ => 0,
+                };
+                let body = if lines > 0 {
+                    fl!("prompt-git-discard-lines", count = lines)
+                } else {
+                    fl!("prompt-git-discard-hunk")
+                };
+                let dialog = widget::dialog()
+                    .title(fl!(
+                        "prompt-git-discard-title",
+                        name = file_title(&tab.diff.path)
+                    ))
+                    .body(body)
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .control(self.git_discard_unsaved(&tab.diff.path))
+                    .primary_action(discard_button)
+                    .secondary_action(cancel_button);
+                Some(dialog.into())
+            }
+            DialogPage::PromptSaveClose(entity) => {
+                let save_button =
+                    widget::button::suggested(fl!("save")).on_press(Message::Save(Some(*entity)));
+                let discard_button = widget::button::destructive(fl!("discard"))
+                    .on_press(Message::TabCloseForce(*entity));
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let dialog = widget::dialog()
+                    .title(fl!("prompt-save-changes-title"))
+                    .body(fl!("prompt-unsaved-changes"))
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .primary_action(save_button)
+                    .secondary_action(discard_button)
+                    .tertiary_action(cancel_button);
+                Some(dialog.into())
+            }
+            DialogPage::PromptSaveQuit(entities) => {
+                let mut can_save_all = true;
+                let mut column = widget::column::with_capacity(entities.len()).spacing(space_xxs);
+                for entity in entities.iter() {
+                    if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(*entity) {
+                        let mut row = widget::row::with_capacity(3).align_y(Alignment::Center);
+                        row = row.push(widget::text(tab.title()));
+                        row = row.push(widget::space::horizontal());
+                        if let Some(_path) = &tab.path_opt {
+                            row = row.push(
+                                widget::button::standard(fl!("save"))
+                                    .on_press(Message::Save(Some(*entity))),
+                            );
+                            //TODO row = row.push(widget::text(format!("{}", path.display())));
+                        } else {
+                            row = row.push(
+                                widget::button::standard(fl!("save-as"))
+                                    .on_press(Message::SaveAsDialog(Some(*entity))),
+                            );
+                            can_save_all = false;
+                        }
+
+                        column = column.push(row);
+                    }
+                }
+
+                let mut save_button = widget::button::suggested(fl!("save-all"));
+                if can_save_all {
+                    save_button = save_button.on_press(Message::SaveAll);
+                }
+                let discard_button =
+                    widget::button::destructive(fl!("discard")).on_press(Message::QuitForce);
+                let cancel_button =
+                    widget::button::text(fl!("cancel")).on_press(Message::DialogCancel);
+                let dialog = widget::dialog()
+                    .title(fl!("prompt-save-changes-title"))
+                    .body(fl!("prompt-unsaved-changes"))
+                    .icon(icon::from_name("dialog-warning-symbolic").size(64))
+                    .control(column)
+                    .primary_action(save_button)
+                    .secondary_action(discard_button)
+                    .tertiary_action(cancel_button);
+
+                Some(dialog.into())
+            }
+        }
+    }
+
+    fn update(&mut self, message: Message) -> Task<Message> {
+        // Helper for updating config values efficiently
+        macro_rules! config_set {
+            ($name: ident, $value: expr) => {
+                match &self.config_handler {
+                    Some(config_handler) => {
+                        if let Err(err) =
+                            paste::paste! { self.config.[<set_ $name>](config_handler, $value) }
+                        {
+                            log::warn!("failed to save config {:?}: {}", stringify!($name), err);
+                        }
+                    }
+                    None => {
+                        self.config.$name = $value;
+                        log::warn!(
+                            "failed to save config {:?}: no config handler",
+                            stringify!($name)
+                        );
+                    }
+                }
+            };
+        }
+        // Tabs showing past revisions of files are read-only
+        let edit_entity_opt = match &message {
+            Message::Cut
+            | Message::FindReplace
+            | Message::FindReplaceAll
+            | Message::PasteValue(_)
+            | Message::Redo
+            | Message::RevertAllChanges
+            | Message::Undo => Some(self.tab_model.active()),
+            Message::Save(entity_opt) => {
+                Some(entity_opt.unwrap_or_else(|| self.tab_model.active()))
+            }
+            _ => None,
+        };
+        if let Some(entity) = edit_entity_opt {
+            if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                if tab.read_only() {
+                    return Task::none();
+                }
+            }
+        }
+
+        match message {
+            Message::AppTheme(app_theme) => {
+                config_set!(app_theme, app_theme);
+                return self.update_config();
+            }
+            Message::AutoScroll(auto_scroll) => {
+                self.auto_scroll = auto_scroll.map(|new| {
+                    (
+                        new,
+                        self.auto_scroll
+                            .map(|old| old.1.wrapping_add(1))
+                            .unwrap_or_default(),
+                    )
+                });
+            }
+            Message::Config(config) => {
+                if config != self.config {
+                    log::info!("update config");
+                    //TODO: update syntax theme by clearing tabs, only if needed
+                    self.config = config;
+                    return self.update_config();
+                }
+            }
+            Message::ConfigState(config_state) => {
+                if config_state != self.config_state {
+                    log::info!("update config state");
+                    self.config_state = config_state;
+                }
+            }
+            Message::CloseFile => {
+                return self.update(Message::TabClose(self.tab_model.active()));
+            }
+            Message::CloseProject(project_i) => {
+                if project_i < self.projects.len() {
+                    let (_project_name, project_path) = self.projects.remove(project_i);
+                    self.project_git_dirs.remove(&project_path);
+                    self.update_watcher();
+                    self.forget_git_repositories();
+                    let mut position = 0;
+                    let mut closing = false;
+                    while let Some(id) = self.nav_model.entity_at(position) {
+                        match self.nav_model.data::<ProjectNode>(id) {
+                            Some(node) => {
+                                if let ProjectNode::Folder { path, root, .. } = node {
+                                    if path == &project_path {
+                                        // Found the project root node, closing
+                                        closing = true;
+                                    } else if *root && closing {
+                                        // Found another project root node after closing, breaking
+                                        break;
+                                    }
+                                }
+                            }
+                            None => {
+                                if closing {
+                                    break;
+                                }
+                            }
+                        }
+                        if closing {
+                            self.nav_model.remove(id);
+                        } else {
+                            position += 1;
+                        }
+                    }
+                    self.update_nav_bar_placeholder();
+                }
+            }
+            Message::CloseWindow(window_id) => {
+                if Some(window_id) == self.core.main_window_id() {
+                    return self.update(Message::Quit);
+                }
+            }
+            Message::Compare(old_side, new_side) => {
+                // Tabs and the clipboard are compared as they are now, files are read later
+                let mut sides = Vec::with_capacity(2);
+                for side in [old_side, new_side] {
+                    sides.push(match side {
+                        CompareSide::Tab(entity) => match self.tab_model.data::<Tab>(entity) {
+                            Some(Tab::Editor(tab)) => {
+                                (tab.title(), tab.path_opt.clone(), Ok(tab.text()))
+                            }
+                            _ => {
+                                log::warn!("failed to find tab {:?} to compare", entity);
+                                return Task::none();
+                            }
+                        },
+                        CompareSide::File(path) => {
+                            (file_title(&path), Some(path.clone()), Err(path))
+                        }
+                        CompareSide::Clipboard(text) => (fl!("clipboard"), None, Ok(text)),
+                        CompareSide::Snapshot(snapshot) => {
+                            (relative_time(snapshot.time()), None, Err(snapshot.path))
+                        }
+                    });
+                }
+                let (new_title, new_path_opt, new_text) = sides.pop().unwrap();
+                let (old_title, old_path_opt, old_text) = sides.pop().unwrap();
+                let title = fl!(
+                    "compare-title",
+                    old = old_title.as_str(),
+                    new = new_title.as_str()
+                );
+                // The path is used for syntax highlighting
+                let path = new_path_opt
+                    .or(old_path_opt)
+                    .unwrap_or_else(|| PathBuf::from(new_title));
+                return Task::perform(
+                    async move {
+                        let read = |text: Result<String, PathBuf>| {
+                            text.or_else(|path| {
+                                fs::read_to_string(&path)
+                                    .map_err(|err| format!("failed to read {:?}: {}", path, err))
+                            })
+                        };
+                        match (read(old_text), read(new_text)) {
+                            (Ok(old_text), Ok(new_text)) => {
+                                let diff = GitDiff::compare(
+                                    &path,
+                                    &old_text,
+                                    &new_text,
+                                    GIT_DIFF_CONTEXT_LINES,
+                                );
+                                action::app(Message::CompareOpen(title, diff, old_text, new_text))
+                            }
+                            (Err(err), _) | (_, Err(err)) => {
+                                log::error!("{}", err);
+                                action::app(Message::GitError(err))
+                            }
+                        }
+                    },
+                    |x| x,
+                );
+            }
+            Message::CompareOpen(title, diff, old_text, new_text) => {
+                let tab = GitDiffTab::new_compare(title, diff, old_text, new_text);
+                return self.open_diff_tab(tab);
+            }
+            Message::CompareSelect => {
+                let entity = self.tab_model.active();
+                if let Some(Tab::Editor(_)) = self.tab_model.data::<Tab>(entity) {
+                    self.compare_selection_opt = Some(CompareSide::Tab(entity));
+                }
+            }
+            Message::CompareWithClipboard => {
+                let entity = self.tab_model.active();
+                return clipboard::read().map(move |value_opt| match value_opt {
+                    Some(value) => action::app(Message::Compare(
+                        CompareSide::Clipboard(value),
+                        CompareSide::Tab(entity),
+                    )),
+                    None => action::none(),
+                });
+            }
+            Message::CompareWithSaved => {
+                let entity = self.tab_model.active();
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                    if let Some(path) = &tab.path_opt {
+                        return self.update(Message::Compare(
+                            CompareSide::File(path.clone()),
+                            CompareSide::Tab(entity),
+                        ));
+                    }
+                }
+            }
+            Message::CompareWithSelected => {
+                let entity = self.tab_model.active();
+                if let Some(old_side) = self.compare_selection_opt.clone() {
+                    return self.update(Message::Compare(old_side, CompareSide::Tab(entity)));
+                }
+            }
+            Message::Copy => {
+                if let Some(Tab::Editor(tab)) = self.active_tab() {
+                    let editor = tab.editor.lock().unwrap();
+                    let selection_opt = editor.copy_selection();
+                    if let Some(selection) = selection_opt {
+                        return clipboard::write(selection);
+                    }
+                }
+            }
+            Message::Cut => {
+                if let Some(Tab::Editor(tab)) = self.active_tab() {
+                    let selection_opt = {
+                        let mut editor = tab.editor.lock().unwrap();
+                        let selection_opt = editor.copy_selection();
+                        editor.start_change();
+                        editor.delete_selection();
+                        editor.finish_change();
+                        selection_opt
+                    };
+                    if let Some(selection) = selection_opt {
+                        return Task::batch([
+                            clipboard::write(selection),
+                            self.update(Message::TabChanged(self.tab_model.active())),
+                        ]);
+                    }
+                }
+            }
+            Message::DefaultFont(index) => {
+                match self.font_names.get(index) {
+                    Some(font_name) => {
+                        if font_name != &self.config.font_name {
+                            // Update font name from config
+                            {
+                                let mut font_system = font_system().write().unwrap();
+                                font_system.raw().db_mut().set_monospace_family(font_name);
+                            }
+
+                            // Reset line number cache
+                            {
+                                let mut line_number_cache =
+                                    LINE_NUMBER_CACHE.get().unwrap().lock().unwrap();
+                                line_number_cache.clear();
+                            }
+
+                            // This does a complete reset of shaping data!
+                            let entities: Vec<_> = self.tab_model.iter().collect();
+                            for entity in entities {
+                                if let Some(Tab::Editor(tab)) =
+                                    self.tab_model.data_mut::<Tab>(entity)
+                                {
+                                    let mut editor = tab.editor.lock().unwrap();
+                                    editor.with_buffer_mut(|buffer| {
+                                        for line in buffer.lines.iter_mut() {
+                                            line.reset();
+                                        }
+                                    });
+                                }
+                            }
+
+                            config_set!(font_name, font_name.to_string());
+                            return self.update_config();
+                        }
+                    }
+                    None => {
+                        log::warn!("failed to find font with index {}", index);
+                    }
+                }
+            }
+            Message::DefaultFontSize(index) => match self.font_sizes.get(index) {
+                Some(font_size) => {
+                    config_set!(font_size, *font_size);
+                    self.reset_tabs_zoom();
+                    return self.update_config();
+                }
+                None => {
+                    log::warn!("failed to find font with index {}", index);
+                }
+            },
+            Message::ZoomIn => {
+                return self.update_render_active_tab_zoom(message);
+            }
+            Message::ZoomOut => {
+                return self.update_render_active_tab_zoom(message);
+            }
+            Message::ZoomReset => {
+                self.reset_tabs_zoom();
+                return self.update_config();
+            }
+            Message::DefaultZoomStep(index) => match self.zoom_steps.get(index) {
+                Some(zoom_step) => {
+                    config_set!(font_size_zoom_step_mul_100, *zoom_step);
+                    self.reset_tabs_zoom(); // reset zoom
+                    return self.update_config();
+                }
+                None => {
+                    log::warn!("failed to find zoom step with index {}", index);
+                }
+            },
+
+            Message::DialogCancel => {
+                if self.dialog_page_opt == Some(DialogPage::PromptGitAskpass) {
+                    // Dropping the reply cancels the prompt
+                    self.git_askpass_prompts.pop_front();
+                    if !self.git_askpass_prompts.is_empty() {
+                        return Task::none();
+                    }
+                }
+                self.dialog_page_opt = None;
+            }
+            Message::DialogMessage(dialog_message) => {
+                if let Some(dialog) = &mut self.dialog_opt {
+                    return dialog.update(dialog_message);
+                }
+            }
+            Message::Find(find_opt) => {
+                self.find_opt = find_opt.map(|f| FindField {
+                    replace: f,
+                    has_focus: true,
+                });
+
+                // Focus correct input
+                return self.update_focus();
+            }
+            Message::FindCaseSensitive(find_case_sensitive) => {
+                config_set!(find_case_sensitive, find_case_sensitive);
+                return self.update_config();
+            }
+            Message::FindNext => {
+                if !self.find_search_value.is_empty() {
+                    if let Some(Tab::Editor(tab)) = self.active_tab() {
+                        //TODO: do not compile find regex on every search?
+                        match self.config.find_regex(&self.find_search_value) {
+                            Ok(regex) => {
+                                tab.search(&regex, true, self.config.find_wrap_around);
+                            }
+                            Err(err) => {
+                                //TODO: put regex error in find box
+                                log::warn!(
+                                    "failed to compile regex {:?}: {}",
+                                    self.find_search_value,
+                                    err
+                                );
+                            }
+                        }
+                    }
+                }
+
+                // Focus correct input
+                return self.update_focus();
+            }
+            Message::FindPrevious => {
+                if !self.find_search_value.is_empty() {
+                    if let Some(Tab::Editor(tab)) = self.active_tab() {
+                        //TODO: do not compile find regex on every search?
+                        match self.config.find_regex(&self.find_search_value) {
+                            Ok(regex) => {
+                                tab.search(&regex, false, self.config.find_wrap_around);
+                            }
+                            Err(err) => {
+                                //TODO: put regex error in find box
+                                log::warn!(
+                                    "failed to compile regex {:?}: {}",
+                                    self.find_search_value,
+                                    err
+                                );
+                            }
+                        }
+                    }
+                }
+
+                // Focus correct input
+                return self.update_focus();
+            }
+            Message::FindReplace => {
+                if !self.find_search_value.is_empty() {
+                    if let Some(Tab::Editor(tab)) = self.active_tab() {
+                        //TODO: do not compile find regex on every search?
+                        match self.config.find_regex(&self.find_search_value) {
+                            Ok(regex) => {
+                                //TODO: support captures
+                                tab.replace(
+                                    &regex,
+                                    &self.find_replace_value,
+                                    self.config.find_wrap_around,
+                                );
+                                return self.update(Message::TabChanged(self.tab_model.active()));
+                            }
+                            Err(err) => {
+                                //TODO: put regex error in find box
+                                log::warn!(
+                                    "failed to compile regex {:?}: {}",
+                                    self.find_search_value,
+                                    err
+                                );
+                            }
+                        }
+                    }
+                }
+
+                // Focus correct input
+                return self.update_focus();
+            }
+            Message::FindReplaceAll => {
+                if !self.find_search_value.is_empty() {
+                    if let Some(Tab::Editor(tab)) = self.active_tab() {
+                        //TODO: do not compile find regex on every search?
+                        match self.config.find_regex(&self.find_search_value) {
+                            Ok(regex) => {
+                                //TODO: support captures
+                                {
+                                    let mut editor = tab.editor.lock().unwrap();
+                                    editor.set_cursor(cosmic_text::Cursor::new(0, 0));
+                                }
+                                while tab.replace(&regex, &self.find_replace_value, false) {}
+                                return self.update(Message::TabChanged(self.tab_model.active()));
+                            }
+                            Err(err) => {
+                                //TODO: put regex error in find box
+                                log::warn!(
+                                    "failed to compile regex {:?}: {}",
+                                    self.find_search_value,
+                                    err
+                                );
+                            }
+                        }
+                    }
+                }
+
+                // Focus correct input
+                return self.update_focus();
+            }
+            Message::FindReplaceValueChanged(value) => {
+                self.find_replace_value = value;
+            }
+            Message::FindSearchValueChanged(value) => {
+                self.find_search_value = value;
+            }
+            Message::FindUseRegex(find_use_regex) => {
+                config_set!(find_use_regex, find_use_regex);
+                return self.update_config();
+            }
+            Message::FindWholeWord(find_whole_word) => {
+                config_set!(find_whole_word, find_whole_word);
+                return self.update_config();
+            }
+            Message::FindWrapAround(find_wrap_around) => {
+                config_set!(find_wrap_around, find_wrap_around);
+                return self.update_config();
+            }
+            Message::FindFocused(has_focus) => {
+                if let Some(f) = self.find_opt.as_mut() {
+                    *f = FindField {
+                        replace: f.replace,
+                        has_focus,
+                    };
+                }
+                if !has_focus {
+                    self.project_search_has_focus = false;
+                    return self.update_focus();
+                }
+            }
+            Message::GitAskpass(prompt, reply) => {
+                self.git_askpass_prompts.push_back(GitAskpassPrompt {
+                    prompt,
+                    value: String::new(),
+                    reply,
+                });
+                // The git command waits for an answer, so this is shown over other dialogs
+                self.dialog_page_opt = Some(DialogPage::PromptGitAskpass);
+                return widget::text_input::focus(self.git_askpass_id.clone());
+            }
+            Message::GitAskpassInput(value) => {
+                if let Some(prompt) = self.git_askpass_prompts.front_mut() {
+                    prompt.value = value;
+                }
+            }
+            Message::GitAskpassSubmit => {
+                if let Some(prompt) = self.git_askpass_prompts.pop_front() {
+                    prompt.reply.send(prompt.value);
+                }
+                if !self.git_askpass_prompts.is_empty() {
+                    return widget::text_input::focus(self.git_askpass_id.clone());
+                }
+                if self.dialog_page_opt == Some(DialogPage::PromptGitAskpass) {
+                    self.dialog_page_opt = None;
+                }
+            }
+            Message::GitBlame(entity, generation, head, blame) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    // Ignore blames of older versions of the buffer
+                    if tab.git_blame_generation != generation {
+                        return Task::none();
+                    }
+                    tab.git_blame_head_opt = Some(head);
+                    tab.git_blame_head_stale = false;
+                    tab.git_blame_labels = git_blame_labels(&blame);
+                    tab.git_blame_opt = Some(blame);
+                    tab.editor.lock().unwrap().set_redraw(true);
+                }
+            }
+            Message::GitBlameHover(entity, hover_opt) => {
+                self.git_blame_hover_opt = hover_opt.map(|(line_i, point)| (entity, line_i, point));
+            }
+            Message::GitBlameOpenCommit(entity, line_i) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                    let Some(path) = tab.path_opt.clone() else {
+                        return Task::none();
+                    };
+                    let Some(commit) = tab.git_blame_opt.as_ref().and_then(|blame| {
+                        blame
+                            .lines
+                            .get(line_i)
+                            .and_then(|commit_i| blame.commits.get(*commit_i))
+                    }) else {
+                        return Task::none();
+                    };
+                    if !commit.is_committed() {
+                        return Task::none();
+                    }
+                    let hash = commit.hash.clone();
+                    // The file may have had another name in the commit
+                    let commit_path = commit.path.clone();
+                    return git_task(path, move |repo| async move {
+                        match repo
+                            .commit_diff(&hash, &commit_path, GIT_DIFF_CONTEXT_LINES)
+                            .await
+                        {
+                            Ok(diff) => action::app(Message::OpenGitDiff(
+                                repo.path().to_path_buf(),
+                                diff,
+                                Some(hash),
+                            )),
+                            Err(err) => git_error(
+                                format!(
+                                    "failed to get diff of {:?} in commit {}",
+                                    commit_path, hash
+                                ),
+                                err,
+                            ),
+                        }
+                    });
+                }
+            }
+            Message::GitBranchCheckout(project_path, name) => {
+                if self.git_unsaved_tabs(&project_path).is_empty() {
+                    return self.update(Message::GitBranchSwitch(project_path, name));
+                }
+                self.dialog_page_opt = Some(DialogPage::PromptGitCheckout(project_path, name));
+            }
+            Message::GitBranchCreate(project_path) => {
+                if let Some(picker) = self.git_branch_pickers.get_mut(&project_path) {
+                    let name = picker.name.trim().to_string();
+                    if name.is_empty() || picker.in_progress {
+                        return Task::none();
+                    }
+                    picker.in_progress = true;
+                    picker.result_opt = None;
+                    return Task::perform(
+                        async move {
+                            // The new branch starts at HEAD, so files do not change
+                            let result = match GitRepository::new(&project_path).await {
+                                Ok(repo) => repo
+                                    .create_branch(&name)
+                                    .await
+                                    .map(|()| fl!("git-branch-created", name = name.as_str()))
+                                    .map_err(|err| err.to_string()),
+                                Err(err) => Err(err.to_string()),
+                            };
+                            action::app(Message::GitBranchFinished(project_path, result, false))
+                        },
+                        |x| x,
+                    );
+                }
+            }
+            Message::GitBranchDelete(project_path, name) => {
+                if let Some(picker) = self.git_branch_pickers.get_mut(&project_path) {
+                    if picker.in_progress {
+                        return Task::none();
+                    }
+                    picker.in_progress = true;
+                    picker.result_opt = None;
+                    return Task::perform(
+                        async move {
+                            let result = match GitRepository::new(&project_path).await {
+                                Ok(repo) => repo
+                                    .delete_branch(&name)
+                                    .await
+                                    .map(|()| fl!("git-branch-deleted", name = name.as_str()))
+                                    .map_err(|err| err.to_string()),
+                                Err(err) => Err(err.to_string()),
+                            };
+                            action::app(Message::GitBranchFinished(project_path, result, false))
+                        },
+                        |x| x,
+                    );
+                }
+            }
+            Message::GitBranchFinished(project_path, result, checkout) => {
+                let mut tasks = Vec::with_capacity(2);
+                if checkout && result.is_ok() {
+                    tasks.push(self.git_reload_tabs(&project_path));
+                }
+                let picker = self.git_branch_pickers.entry(project_path).or_default();
+                picker.in_progress = false;
+                if result.is_ok() {
+                    picker.name.clear();
+                }
+                picker.result_opt = Some(result);
+                tasks.push(self.update(Message::UpdateGitProjectStatus));
+                return Task::batch(tasks);
+            }
+            Message::GitBranchName(project_path, name) => {
+                if let Some(picker) = self.git_branch_pickers.get_mut(&project_path) {
+                    picker.name = name;
+                }
+            }
+            Message::GitBranchPicker(project_path) => {
+                if self.git_branch_pickers.remove(&project_path).is_none() {
+                    self.git_branch_pickers
+                        .insert(project_path, GitBranchPicker::default());
+                }
+            }
+            Message::GitBranchSwitch(project_path, name) => {
+                if matches!(
+                    self.dialog_page_opt,
+                    Some(DialogPage::PromptGitCheckout(..))
+                ) {
+                    self.dialog_page_opt = None;
+                }
+                let picker = self
+                    .git_branch_pickers
+                    .entry(project_path.clone())
+                    .or_default();
+                if picker.in_progress {
+                    return Task::none();
+                }
+                picker.in_progress = true;
+                picker.result_opt = None;
+                // Only unsaved tabs are confirmed, git refuses to overwrite changed files itself
+                return Task::perform(
+                    async move {
+                        let result = match GitRepository::new(&project_path).await {
+                            Ok(repo) => repo
+                                .checkout_branch(&name)
+                                .await
+                                .map(|()| fl!("git-branch-switched", name = name.as_str()))
+                                .map_err(|err| err.to_string()),
+                            Err(err) => Err(err.to_string()),
+                        };
+                        action::app(Message::GitBranchFinished(project_path, result, true))
+                    },
+                    |x| x,
+                );
+            }
+            Message::GitBufferDiff(entity, generation, diff) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    if tab.git_diff_generation == generation {
+                        if tab
+                            .git_hunk_opt
+                            .is_some_and(|hunk_i| hunk_i >= diff.hunks.len())
+                        {
+                            tab.git_hunk_opt = None;
+                        }
+                        tab.git_diff_opt = Some(diff);
+                        tab.editor.lock().unwrap().set_redraw(true);
+                    }
+                }
+            }
+            Message::GitBufferDiffUpdate(entity, generation) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                    if tab.git_diff_generation == generation {
+                        if let (Some(path), Some(index_text)) =
+                            (tab.path_opt.clone(), tab.git_index_opt.clone())
+                        {
+                            let text = tab.text();
+                            let git_blame_task = self.update_git_blame(entity);
+                            let git_diff_task = Task::perform(
+                                async move {
+                                    match tokio::task::spawn_blocking(move || {
+                                        GitDiff::from_text(path, &index_text, &text)
+                                    })
+                                    .await
+                                    {
+                                        Ok(diff) => action::app(Message::GitBufferDiff(
+                                            entity, generation, diff,
+                                        )),
+                                        Err(err) => {
+                                            log::error!("failed to diff buffer: {}", err);
+                                            action::none()
+                                        }
+                                    }
+                                },
+                                |x| x,
+                            );
+                            return Task::batch([git_diff_task, git_blame_task]);
+                        }
+                    }
+                }
+            }
+            Message::GitDiffApply(entity, hunk_i, hunk_action) => {
+                if matches!(
+                    self.dialog_page_opt,
+                    Some(DialogPage::PromptGitDiscardHunk(..))
+                ) {
+                    self.dialog_page_opt = None;
+                }
+                if let Some(Tab::GitDiff(tab)) = self.tab_model.data::<Tab>(entity) {
+                    let Some(hunk) = tab.diff.hunks.get(hunk_i).cloned() else {
+                        log::warn!("failed to find hunk {} in {:?}", hunk_i, tab.diff.path);
+                        return Task::none();
+                    };
+                    let lines_opt = match &tab.selection_opt {
+                        Some((selection_hunk_i, lines)) if *selection_hunk_i == hunk_i => {
+                            Some(lines.clone())
+                        }
+                        _ => None,
+                    };
+                    let project_path = tab.project_path.clone();
+                    let path = tab.diff.path.clone();
+                    let staged = tab.diff.staged;
+                    let context_lines = tab.context_lines;
+                    let apply = {
+                        let project_path = project_path.clone();
+                        let path = path.clone();
+                        git_task(project_path.clone(), move |repo| async move {
+                            match repo.apply_hunk(&path, &hunk, lines_opt, hunk_action).await {
+                                Ok(()) => action::none(),
+                                Err(err) => git_error(
+                                    format!(
+                                        "failed to {:?} hunk of {:?} in {:?}",
+                                        hunk_action, path, project_path
+                                    ),
+                                    err,
+                                ),
+                            }
+                        })
+                    };
+                    // Refresh the diff even on errors, it may be outdated
+                    let refresh = git_task(project_path.clone(), move |repo| async move {
+                        match repo.diff(&path, staged, context_lines).await {
+                            Ok(diff) => action::app(match hunk_action {
+                                // The file changed, open tabs must be reloaded
+                                GitHunkAction::Discard => Message::GitDiffDiscarded(entity, diff),
+                                GitHunkAction::Stage | GitHunkAction::Unstage => {
+                                    Message::GitDiffUpdate(entity, diff)
+                                }
+                            }),
+                            Err(err) => git_error(
+                                format!("failed to get diff of {:?} in {:?}", path, project_path),
+                                err,
+                            ),
+                        }
+                    });
+                    return apply.chain(refresh);
+                }
+            }
+            Message::GitDiffContext(entity) => {
+                if let Some(Tab::GitDiff(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    tab.context_lines = tab.context_lines.saturating_add(10);
+                    if let Some(compare) = &tab.compare_opt {
+                        let diff = GitDiff::compare(
+                            &tab.diff.path,
+                            &compare.old_text,
+                            &compare.new_text,
+                            tab.context_lines,
+                        );
+                        tab.set_diff(diff);
+                        return self.update_git_diff_spans(entity);
+                    }
+                    let project_path = tab.project_path.clone();
+                    let path = tab.diff.path.clone();
+                    let staged = tab.diff.staged;
+                    let commit_opt = tab.commit_opt.clone();
+                    let context_lines = tab.context_lines;
+                    return git_task(project_path.clone(), move |repo| async move {
+                        let result = match &commit_opt {
+                            Some(hash) => repo.commit_diff(hash, &path, context_lines).await,
+                            None => repo.diff(&path, staged, context_lines).await,
+                        };
+                        match result {
+                            Ok(diff) => action::app(Message::GitDiffUpdate(entity, diff)),
+                            Err(err) => git_error(
+                                format!("failed to get diff of {:?} in {:?}", path, project_path),
+                                err,
+                            ),
+                        }
+                    });
+                }
+            }
+            Message::GitDiffDiscard(entity, hunk_i) => {
+                self.dialog_page_opt = Some(DialogPage::PromptGitDiscardHunk(entity, hunk_i));
+            }
+            Message::GitDiffDiscarded(entity, diff) => {
+                let path = diff.path.clone();
+                return Task::batch([
+                    self.update(Message::GitDiffUpdate(entity, diff)),
+                    self.update(Message::GitDiscardFinished(path)),
+                ]);
+            }
+            Message::GitDiffHunk(entity, hunk_i) => {
+                let side_by_side = self.config.git_diff_side_by_side;
+                if let Some(Tab::GitDiff(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    if hunk_i >= tab.diff.hunks.len() {
+                        return Task::none();
+                    }
+                    tab.hunk_i = hunk_i;
+                    // Scroll by the fraction of rows before the hunk, which keeps it in view
+                    let row_count = |hunk: &GitDiffHunk| {
+                        let rows = if side_by_side {
+                            hunk.rows().len()
+                        } else {
+                            hunk.lines.len()
+                        };
+                        // Hunk header and collapsed context
+                        rows + 2
+                    };
+                    let rows_before: usize = tab.diff.hunks[..hunk_i].iter().map(row_count).sum();
+                    let rows_total: usize = tab.diff.hunks.iter().map(row_count).sum();
+                    let y = rows_before as f32 / rows_total.saturating_sub(1).max(1) as f32;
+                    return iced::widget::scrollable::snap_to(
+                        self.git_diff_scroll_id.clone(),
+                        iced::widget::scrollable::RelativeOffset { x: 0.0, y },
+                    );
+                }
+            }
+            Message::GitDir(project_path, git_dir) => {
+                // Ignore projects closed while finding the git folder
+                if self.projects.iter().any(|(_, path)| path == &project_path) {
+                    self.project_git_dirs.insert(project_path, git_dir);
+                    self.update_watcher();
+                    // Load the status shown in the project tree
+                    return self.update(Message::UpdateGitProjectStatus);
+                }
+            }
+            Message::GitDiffSideBySide(side_by_side) => {
+                config_set!(git_diff_side_by_side, side_by_side);
+                return self.update_config();
+            }
+            Message::GitDiffSelect(entity, hunk_i, line_i) => {
+                let extend = self.modifiers.contains(Modifiers::SHIFT);
+                if let Some(Tab::GitDiff(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    tab.selection_opt = match tab.selection_opt.take() {
+                        // Shift extends the selection within the same hunk
+                        Some((selection_hunk_i, lines)) if extend && selection_hunk_i == hunk_i => {
+                            Some((hunk_i, lines.start.min(line_i)..lines.end.max(line_i + 1)))
+                        }
+                        // Clicking the only selected line clears the selection
+                        Some((selection_hunk_i, lines))
+                            if selection_hunk_i == hunk_i && lines == (line_i..line_i + 1) =>
+                        {
+                            None
+                        }
+                        _ => Some((hunk_i, line_i..line_i + 1)),
+                    };
+                    tab.hunk_i = hunk_i;
+                }
+            }
+            Message::GitDiffSpans(entity, generation, spans) => {
+                if let Some(Tab::GitDiff(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    // Ignore spans of older diffs
+                    if tab.spans_generation == generation {
+                        tab.spans = spans;
+                    }
+                }
+            }
+            Message::GitDiffUpdate(entity, diff) => {
+                if let Some(Tab::GitDiff(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    tab.set_diff(diff);
+                }
+                return Task::batch([
+                    self.update_git_diff_spans(entity),
+                    self.update(Message::UpdateGitProjectStatus),
+                ]);
+            }
+            Message::GitDiscard(project_path, path, untracked) => {
+                self.dialog_page_opt =
+                    Some(DialogPage::PromptGitDiscard(project_path, path, untracked));
+            }
+            Message::GitDiscardFinished(path) => {
+                return Task::batch([
+                    self.git_reload_tabs(&path),
+                    self.update(Message::UpdateGitProjectStatus),
+                ]);
+            }
+            Message::GitDiscardForce(project_path, path, untracked) => {
+                if matches!(self.dialog_page_opt, Some(DialogPage::PromptGitDiscard(..))) {
+                    self.dialog_page_opt = None;
+                }
+                if untracked {
+                    // Untracked files cannot be restored by git, so they go to the trash
+                    return Task::perform(
+                        async move {
+                            if let Err(err) = trash::delete(&path) {
+                                return git_error(
+                                    format!("failed to move {:?} to trash", path),
+                                    err,
+                                );
+                            }
+                            // Tabs of a trashed file keep their text
+                            action::app(Message::UpdateGitProjectStatus)
+                        },
+                        |x| x,
+                    );
+                }
+                return git_task(project_path.clone(), move |repo| async move {
+                    match repo.discard(&path).await {
+                        Ok(()) => action::app(Message::GitDiscardFinished(path)),
+                        Err(err) => git_error(
+                            format!(
+                                "failed to discard changes of {:?} in {:?}",
+                                path, project_path
+                            ),
+                            err,
+                        ),
+                    }
+                });
+            }
+            Message::GitError(err) => {
+                self.git_error_opt = Some(err);
+            }
+            Message::GitErrorClose => {
+                self.git_error_opt = None;
+            }
+            Message::GitFileHistory(path, result) => {
+                if let Some(history) = &mut self.git_file_history_opt {
+                    if history.path == path {
+                        history.result_opt = Some(result);
+                    }
+                }
+            }
+            Message::GitFileHistoryDiff(revision_i) => {
+                if let Some((repo_path, revision)) = self.git_file_history_revision(revision_i) {
+                    return git_task(repo_path.clone(), move |repo| async move {
+                        match repo
+                            .commit_diff(
+                                &revision.commit.hash,
+                                &revision.path,
+                                GIT_DIFF_CONTEXT_LINES,
+                            )
+                            .await
+                        {
+                            Ok(diff) => action::app(Message::OpenGitDiff(
+                                repo_path,
+                                diff,
+                                Some(revision.commit.hash),
+                            )),
+                            Err(err) => git_error(
+                                format!(
+                                    "failed to get diff of {:?} in commit {}",
+                                    revision.path, revision.commit.hash
+                                ),
+                                err,
+                            ),
+                        }
+                    });
+                }
+            }
+            Message::GitFileHistoryOpen(revision_i) => {
+                if let Some((repo_path, revision)) = self.git_file_history_revision(revision_i) {
+                    return git_task(repo_path, move |repo| async move {
+                        match repo
+                            .revision_text(&revision.commit.hash, &revision.path)
+                            .await
+                        {
+                            Ok(text) => action::app(Message::OpenGitRevision(
+                                revision.path,
+                                revision.commit.short_hash,
+                                text,
+                            )),
+                            Err(err) => git_error(
+                                format!(
+                                    "failed to read {:?} in commit {}",
+                                    revision.path, revision.commit.hash
+                                ),
+                                err,
+                            ),
+                        }
+                    });
+                }
+            }
+            Message::GitHunkClose(entity) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    tab.git_hunk_opt = None;
+                }
+            }
+            Message::GitHunkRevert(entity, hunk_i) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    if tab.revert_git_hunk(hunk_i) {
+                        tab.git_hunk_opt = None;
+                        return self.update(Message::TabChanged(entity));
+                    }
+                }
+            }
+            Message::GitIndexText(entity, path, text_opt) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    if tab.path_opt.as_ref() == Some(&path) {
+                        if text_opt.is_none() {
+                            tab.git_diff_opt = None;
+                            tab.git_hunk_opt = None;
+                            tab.editor.lock().unwrap().set_redraw(true);
+                        }
+                        tab.git_index_opt = text_opt;
+                        return self.update_git_diff(entity);
+                    }
+                }
+            }
+            Message::GitMarker(entity, line_i) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    let hunk_i_opt = tab.git_diff_opt.as_ref().and_then(|diff| {
+                        diff.hunks.iter().position(|hunk| {
+                            let lines = hunk.new_lines();
+                            lines.contains(&line_i) || lines.start == line_i
+                        })
+                    });
+                    // Clicking the marker of the shown hunk hides it
+                    tab.git_hunk_opt = if tab.git_hunk_opt == hunk_i_opt {
+                        None
+                    } else {
+                        hunk_i_opt
+                    };
+                }
+            }
+            Message::GitCommit(project_path) => {
+                if let Some(editor) = self.git_commit_editors.get_mut(&project_path) {
+                    let message = editor.message();
+                    if message.is_empty() || editor.in_progress {
+                        return Task::none();
+                    }
+                    editor.in_progress = true;
+                    editor.result_opt = None;
+                    let amend = editor.amend;
+                    let signoff = editor.signoff;
+                    return Task::perform(
+                        async move {
+                            let result = match GitRepository::new(&project_path).await {
+                                Ok(repo) => repo
+                                    .commit(&message, amend, signoff)
+                                    .await
+                                    .map_err(|err| err.to_string()),
+                                Err(err) => Err(err.to_string()),
+                            };
+                            action::app(Message::GitCommitFinished(project_path, result))
+                        },
+                        |x| x,
+                    );
+                }
+            }
+            Message::GitCommitAction(project_path, text_editor_action) => {
+                if let Some(editor) = self.git_commit_editors.get_mut(&project_path) {
+                    editor.content.perform(text_editor_action);
+                }
+            }
+            Message::GitCommitAmend(project_path, amend) => {
+                if let Some(editor) = self.git_commit_editors.get_mut(&project_path) {
+                    editor.amend = amend;
+                    if amend {
+                        editor.message_before_amend = Some(editor.message());
+                        return Task::perform(
+                            async move {
+                                // Repositories without commits have nothing to amend
+                                let result = match GitRepository::new(&project_path).await {
+                                    Ok(repo) => repo
+                                        .last_commit_message()
+                                        .await
+                                        .map_err(|err| err.to_string()),
+                                    Err(err) => Err(err.to_string()),
+                                };
+                                action::app(Message::GitCommitAmendMessage(project_path, result))
+                            },
+                            |x| x,
+                        );
+                    } else if let Some(message) = editor.message_before_amend.take() {
+                        editor.content = text_editor::Content::with_text(&message);
+                    }
+                }
+            }
+            Message::GitCommitAmendMessage(project_path, result) => {
+                if let Some(editor) = self.git_commit_editors.get_mut(&project_path) {
+                    // Amend may have been disabled while loading
+                    if editor.amend {
+                        match result {
+                            Ok(message) => {
+                                editor.content = text_editor::Content::with_text(&message);
+                            }
+                            Err(err) => {
+                                log::error!(
+                                    "failed to get last commit message of {:?}: {}",
+                                    project_path,
+                                    err
+                                );
+                                editor.amend = false;
+                                editor.message_before_amend = None;
+                                editor.result_opt = Some(Err(err));
+                            }
+                        }
+                    }
+                }
+            }
+            Message::GitCommitFinished(project_path, result) => {
+                if let Some(editor) = self.git_commit_editors.get_mut(&project_path) {
+                    editor.in_progress = false;
+                    if result.is_ok() {
+                        editor.content = text_editor::Content::new();
+                        editor.amend = false;
+                        editor.message_before_amend = None;
+                    }
+                    editor.result_opt = Some(result);
+                }
+                return self.update(Message::UpdateGitProjectStatus);
+            }
+            Message::GitCommitSignoff(project_path, signoff) => {
+                if let Some(editor) = self.git_commit_editors.get_mut(&project_path) {
+                    editor.signoff = signoff;
+                }
+            }
+            Message::GitConflictAccept(entity, conflict_i, choice) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    if tab.resolve_git_conflict(conflict_i, choice) {
+                        // Indices of the remaining conflicts are needed right away for the next one
+                        tab.update_git_conflicts();
+                        return self.update(Message::TabChanged(entity));
+                    }
+                }
+            }
+            Message::GitConflictMarkResolved(project_path, path) => {
+                return Task::perform(
+                    async move {
+                        // The saved file is staged, so its conflict markers are checked
+                        match fs::read_to_string(&path) {
+                            Ok(text) => {
+                                let count = conflicts(text.lines()).len();
+                                action::app(Message::GitConflictMarkers(project_path, path, count))
+                            }
+                            Err(err) => git_error(format!("failed to read {:?}", path), err),
+                        }
+                    },
+                    |x| x,
+                );
+            }
+            Message::GitConflictMarkResolvedForce(project_path, path) => {
+                if matches!(
+                    self.dialog_page_opt,
+                    Some(DialogPage::PromptGitConflictResolve(..))
+                ) {
+                    self.dialog_page_opt = None;
+                }
+                return self.update(Message::GitStage(project_path, path));
+            }
+            Message::GitConflictMarkers(project_path, path, count) => {
+                if count == 0 {
+                    return self.update(Message::GitStage(project_path, path));
+                }
+                self.dialog_page_opt = Some(DialogPage::PromptGitConflictResolve(
+                    project_path,
+                    path,
+                    count,
+                ));
+            }
+            Message::GitConflictResolve(entity) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    // Conflicts may not be updated yet after the last edit
+                    tab.update_git_conflicts();
+                    // Only saved files without conflict markers can be marked resolved
+                    if tab.changed() || !tab.git_conflicts.is_empty() {
+                        return Task::none();
+                    }
+                    let Some(path) = tab.path_opt.clone() else {
+                        return Task::none();
+                    };
+                    return git_task(path.clone(), move |repo| async move {
+                        // Staging an unmerged file marks it resolved
+                        match repo.stage(&path).await {
+                            Ok(()) => action::app(Message::GitConflictResolved(entity)),
+                            Err(err) => git_error(format!("failed to stage {:?}", path), err),
+                        }
+                    });
+                }
+            }
+            Message::GitConflictResolved(entity) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    tab.git_conflicted = false;
+                }
+                return self.update(Message::UpdateGitProjectStatus);
+            }
+            Message::GitConflictsUpdate(entity, generation) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    if tab.git_conflicts_generation == generation {
+                        tab.update_git_conflicts();
+                    }
+                }
+            }
+            Message::GitProjectStatus(generation, project_status, tree_status, errors) => {
+                if generation != self.git_project_status_generation {
+                    return Task::none();
+                }
+                if !errors.is_empty() {
+                    self.git_error_opt = Some(errors.join("\n"));
                 }
-            }
-            Message::ConfigState(config_state) => {
-                if config_state != self.config_state {
-                    log::info!("update config state");
-                    self.config_state = config_state;
+                for project in project_status.iter() {
+                    self.git_commit_editors
+                        .entry(project.path.clone())
+                        .or_insert_with(GitCommitEditor::new);
+                    self.git_stash_editors
+                        .entry(project.path.clone())
+                        .or_default();
+                    self.git_remotes.entry(project.path.clone()).or_default();
                 }
+                self.git_project_status = Some(project_status);
+                self.git_tree_status = tree_status;
+                self.update_project_git_status();
             }
-            Message::CloseFile => {
-                return self.update(Message::TabClose(self.tab_model.active()));
+            Message::GitProjectStatusUpdate(update) => {
+                if update == self.git_project_status_update {
+                    if mem::take(&mut self.git_project_status_full) {
+                        return self.update(Message::UpdateGitProjectStatus);
+                    }
+                    return self.update_git_status();
+                }
             }
-            Message::CloseProject(project_i) => {
-                if project_i < self.projects.len() {
-                    let (_project_name, project_path) = self.projects.remove(project_i);
-                    self.update_watcher();
-                    let mut position = 0;
-                    let mut closing = false;
-                    while let Some(id) = self.nav_model.entity_at(position) {
-                        match self.nav_model.data::<ProjectNode>(id) {
-                            Some(node) => {
-                                if let ProjectNode::Folder { path, root, .. } = node {
-                                    if path == &project_path {
-                                        // Found the project root node, closing
-                                        closing = true;
-                                    } else if *root && closing {
-                                        // Found another project root node after closing, breaking
-                                        break;
+            Message::GitRemote(project_path, remote_action) => {
+                // Branches without upstream are pushed to a new remote branch set as upstream
+                let set_upstream = self
+                    .git_project_status
+                    .as_ref()
+                    .and_then(|projects| {
+                        projects.iter().find(|project| project.path == project_path)
+                    })
+                    .is_some_and(|project| project.ahead_behind_opt.is_none());
+                let remote = self.git_remotes.entry(project_path.clone()).or_default();
+                if remote.in_progress {
+                    return Task::none();
+                }
+                remote.in_progress = true;
+                remote.log.clear();
+                remote.log_progress = false;
+                remote.result_opt = None;
+                let rebase = remote.rebase;
+                return Task::stream(stream::channel(
+                    16,
+                    move |mut output: futures::channel::mpsc::Sender<cosmic::Action<Message>>| async move {
+                        // Credential prompts are answered in a dialog
+                        #[cfg(unix)]
+                        let (envs, server_opt) = match git_askpass::GitAskpass::new()
+                            .and_then(|askpass| Ok((askpass.envs()?, askpass)))
+                        {
+                            Ok((envs, askpass)) => {
+                                let output = output.clone();
+                                let server = tokio::spawn(async move {
+                                    let result = askpass
+                                        .serve(|prompt| {
+                                            let mut output = output.clone();
+                                            async move {
+                                                let (sender, receiver) =
+                                                    futures::channel::oneshot::channel();
+                                                let reply = GitAskpassReply(Arc::new(Mutex::new(
+                                                    Some(sender),
+                                                )));
+                                                output
+                                                    .send(action::app(Message::GitAskpass(
+                                                        prompt, reply,
+                                                    )))
+                                                    .await
+                                                    .ok()?;
+                                                receiver.await.ok()
+                                            }
+                                        })
+                                        .await;
+                                    if let Err(err) = result {
+                                        log::warn!(
+                                            "failed to answer git credential prompt: {}",
+                                            err
+                                        );
                                     }
-                                }
+                                });
+                                (envs, Some(server))
                             }
-                            None => {
-                                if closing {
-                                    break;
+                            Err(err) => {
+                                log::warn!("failed to create git askpass socket: {}", err);
+                                (Vec::new(), None)
+                            }
+                        };
+                        #[cfg(not(unix))]
+                        let (envs, server_opt): (
+                            Vec<(&str, std::ffi::OsString)>,
+                            Option<tokio::task::JoinHandle<()>>,
+                        ) = (Vec::new(), None);
+
+                        let (line_tx, mut line_rx) = futures::channel::mpsc::unbounded();
+                        let task = tokio::spawn({
+                            let project_path = project_path.clone();
+                            async move {
+                                let repo = GitRepository::new(&project_path).await?;
+                                let output = |line, progress| {
+                                    // Fails only if the operation was abandoned
+                                    let _ = line_tx.unbounded_send((line, progress));
+                                };
+                                match remote_action {
+                                    GitRemoteAction::Fetch => {
+                                        repo.fetch(&envs, output).await.map(|()| fl!("git-fetched"))
+                                    }
+                                    GitRemoteAction::Pull => repo
+                                        .pull(rebase, &envs, output)
+                                        .await
+                                        .map(|()| fl!("git-pulled")),
+                                    GitRemoteAction::Push => repo
+                                        .push(set_upstream, &envs, output)
+                                        .await
+                                        .map(|()| fl!("git-pushed")),
                                 }
                             }
+                        });
+
+                        // Stream output to the UI while the command runs
+                        while let Some((line, progress)) = line_rx.next().await {
+                            if let Err(err) = output
+                                .send(action::app(Message::GitRemoteOutput(
+                                    project_path.clone(),
+                                    line,
+                                    progress,
+                                )))
+                                .await
+                            {
+                                log::warn!("failed to send git output: {:?}", err);
+                                break;
+                            }
                         }
-                        if closing {
-                            self.nav_model.remove(id);
-                        } else {
-                            position += 1;
+
+                        let result = match task.await {
+                            Ok(result) => result.map_err(|err| err.to_string()),
+                            Err(err) => Err(format!("failed to run git task: {}", err)),
+                        };
+                        if let Some(server) = server_opt {
+                            server.abort();
+                        }
+                        if let Err(err) = output
+                            .send(action::app(Message::GitRemoteFinished(
+                                project_path,
+                                result,
+                            )))
+                            .await
+                        {
+                            log::warn!("failed to send git finished: {:?}", err);
                         }
+                    },
+                ));
+            }
+            Message::GitRemoteFinished(project_path, result) => {
+                // Pulled changes to files reload unchanged tabs through the watcher
+                let remote = self.git_remotes.entry(project_path).or_default();
+                remote.in_progress = false;
+                remote.result_opt = Some(result);
+                return self.update(Message::UpdateGitProjectStatus);
+            }
+            Message::GitRemoteOutput(project_path, line, progress) => {
+                if let Some(remote) = self.git_remotes.get_mut(&project_path) {
+                    if remote.log_progress {
+                        remote.log.pop();
                     }
-                    self.update_nav_bar_placeholder();
+                    remote.log.push(line);
+                    remote.log_progress = progress;
+                    return iced::widget::scrollable::snap_to(
+                        remote.log_scroll_id.clone(),
+                        iced::widget::scrollable::RelativeOffset::END,
+                    );
                 }
             }
-            Message::CloseWindow(window_id) => {
-                if Some(window_id) == self.core.main_window_id() {
-                    return self.update(Message::Quit);
+            Message::GitRemoteRebase(project_path, rebase) => {
+                if let Some(remote) = self.git_remotes.get_mut(&project_path) {
+                    remote.rebase = rebase;
                 }
             }
-            Message::Copy => {
-                if let Some(Tab::Editor(tab)) = self.active_tab() {
-                    let editor = tab.editor.lock().unwrap();
-                    let selection_opt = editor.copy_selection();
-                    if let Some(selection) = selection_opt {
-                        return clipboard::write(selection);
+            Message::GitStage(project_path, path) => {
+                return git_task(project_path.clone(), move |repo| async move {
+                    match repo.stage(&path).await {
+                        Ok(()) => action::app(Message::UpdateGitProjectStatus),
+                        Err(err) => git_error(
+                            format!("failed to stage {:?} in {:?}", path, project_path),
+                            err,
+                        ),
                     }
+                });
+            }
+            Message::GitStash(project_path, stash_action) => {
+                let confirm = match &stash_action {
+                    GitStashAction::Drop(_) => true,
+                    // Unsaved changes are not stashed, and tabs are reloaded afterwards
+                    _ => !self.git_unsaved_tabs(&project_path).is_empty(),
+                };
+                if !confirm {
+                    return self.update(Message::GitStashForce(project_path, stash_action));
                 }
+                self.dialog_page_opt = Some(DialogPage::PromptGitStash(project_path, stash_action));
             }
-            Message::Cut => {
-                if let Some(Tab::Editor(tab)) = self.active_tab() {
-                    let selection_opt = {
-                        let mut editor = tab.editor.lock().unwrap();
-                        let selection_opt = editor.copy_selection();
-                        editor.start_change();
-                        editor.delete_selection();
-                        editor.finish_change();
-                        selection_opt
-                    };
-                    if let Some(selection) = selection_opt {
-                        return Task::batch([
-                            clipboard::write(selection),
-                            self.update(Message::TabChanged(self.tab_model.active())),
-                        ]);
+            Message::GitStashDiff(project_path, hash) => {
+                return git_task(project_path.clone(), move |repo| async move {
+                    match repo.stash_diffs(&hash, GIT_DIFF_CONTEXT_LINES).await {
+                        Ok(diffs) => action::app(Message::GitStashDiffs(project_path, diffs)),
+                        Err(err) => git_error(format!("failed to get diff of stash {}", hash), err),
                     }
+                });
+            }
+            Message::GitStashDiffs(project_path, diffs) => {
+                let mut tasks = Vec::with_capacity(diffs.len());
+                for (hash, diff) in diffs {
+                    tasks.push(self.update(Message::OpenGitDiff(
+                        project_path.clone(),
+                        diff,
+                        Some(hash),
+                    )));
                 }
+                return Task::batch(tasks);
             }
-            Message::DefaultFont(index) => {
-                match self.font_names.get(index) {
-                    Some(font_name) => {
-                        if font_name != &self.config.font_name {
-                            // Update font name from config
-                            {
-                                let mut font_system = font_system().write().unwrap();
-                                font_system.raw().db_mut().set_monospace_family(font_name);
-                            }
-
-                            // Reset line number cache
-                            {
-                                let mut line_number_cache =
-                                    LINE_NUMBER_CACHE.get().unwrap().lock().unwrap();
-                                line_number_cache.clear();
-                            }
-
-                            // This does a complete reset of shaping data!
-                            let entities: Vec<_> = self.tab_model.iter().collect();
-                            for entity in entities {
-                                if let Some(Tab::Editor(tab)) =
-                                    self.tab_model.data_mut::<Tab>(entity)
-                                {
-                                    let mut editor = tab.editor.lock().unwrap();
-                                    editor.with_buffer_mut(|buffer| {
-                                        for line in buffer.lines.iter_mut() {
-                                            line.reset();
+            Message::GitStashFinished(project_path, stash_action, result) => {
+                let mut tasks = Vec::with_capacity(2);
+                // Applying a stash may fail with conflicts after changing files
+                if stash_action.changes_files() {
+                    tasks.push(self.git_reload_tabs(&project_path));
+                }
+                let editor = self.git_stash_editors.entry(project_path).or_default();
+                editor.in_progress = false;
+                if result.is_ok() && matches!(stash_action, GitStashAction::Push(..)) {
+                    editor.message.clear();
+                }
+                editor.result_opt = Some(result);
+                tasks.push(self.update(Message::UpdateGitProjectStatus));
+                return Task::batch(tasks);
+            }
+            Message::GitStashForce(project_path, stash_action) => {
+                if matches!(self.dialog_page_opt, Some(DialogPage::PromptGitStash(..))) {
+                    self.dialog_page_opt = None;
+                }
+                let editor = self
+                    .git_stash_editors
+                    .entry(project_path.clone())
+                    .or_default();
+                if editor.in_progress {
+                    return Task::none();
+                }
+                editor.in_progress = true;
+                editor.result_opt = None;
+                return Task::perform(
+                    async move {
+                        let result = match GitRepository::new(&project_path).await {
+                            Ok(repo) => match &stash_action {
+                                GitStashAction::Push(message, include_untracked) => repo
+                                    .stash_push(message, *include_untracked)
+                                    .await
+                                    .map(|()| fl!("git-stash-pushed")),
+                                GitStashAction::Apply(hash, pop) => {
+                                    repo.stash_apply(hash, *pop).await.map(|()| {
+                                        if *pop {
+                                            fl!("git-stash-popped")
+                                        } else {
+                                            fl!("git-stash-applied")
                                         }
-                                    });
+                                    })
                                 }
+                                GitStashAction::Drop(hash) => repo
+                                    .stash_drop(hash)
+                                    .await
+                                    .map(|()| fl!("git-stash-dropped")),
                             }
-
-                            config_set!(font_name, font_name.to_string());
-                            return self.update_config();
-                        }
+                            .map_err(|err| err.to_string()),
+                            Err(err) => Err(err.to_string()),
+                        };
+                        action::app(Message::GitStashFinished(
+                            project_path,
+                            stash_action,
+                            result,
+                        ))
+                    },
+                    |x| x,
+                );
+            }
+            Message::GitStashMessage(project_path, message) => {
+                if let Some(editor) = self.git_stash_editors.get_mut(&project_path) {
+                    editor.message = message;
+                }
+            }
+            Message::GitStashUntracked(project_path, include_untracked) => {
+                if let Some(editor) = self.git_stash_editors.get_mut(&project_path) {
+                    editor.include_untracked = include_untracked;
+                }
+            }
+            Message::GitUnstage(project_path, path) => {
+                return git_task(project_path.clone(), move |repo| async move {
+                    match repo.unstage(&path).await {
+                        Ok(()) => action::app(Message::UpdateGitProjectStatus),
+                        Err(err) => git_error(
+                            format!("failed to unstage {:?} in {:?}", path, project_path),
+                            err,
+                        ),
                     }
-                    None => {
-                        log::warn!("failed to find font with index {}", index);
+                });
+            }
+            Message::Key(modifiers, physical, key) => {
+                for (key_bind, action) in self.key_binds.iter() {
+                    if key_bind.matches(modifiers, &key, Some(&physical)) {
+                        return self.update(action.message(None));
                     }
                 }
             }
-            Message::DefaultFontSize(index) => match self.font_sizes.get(index) {
-                Some(font_size) => {
-                    config_set!(font_size, *font_size);
-                    self.reset_tabs_zoom();
-                    return self.update_config();
-                }
-                None => {
-                    log::warn!("failed to find font with index {}", index);
+            Message::LaunchUrl(url) => match open::that_detached(&url) {
+                Ok(()) => {}
+                Err(err) => {
+                    log::warn!("failed to open {:?}: {}", url, err);
                 }
             },
-            Message::ZoomIn => {
-                return self.update_render_active_tab_zoom(message);
+            Message::LocalHistory(path, result) => {
+                if let Some(history) = &mut self.local_history_opt {
+                    if history.path == path {
+                        history.result_opt = Some(result);
+                    }
+                }
             }
-            Message::ZoomOut => {
-                return self.update_render_active_tab_zoom(message);
+            Message::LocalHistoryDiff(snapshot_i) => {
+                if let Some(snapshot) = self.local_history_snapshot(snapshot_i) {
+                    let entity = self.tab_model.active();
+                    return self.update(Message::Compare(
+                        CompareSide::Snapshot(snapshot),
+                        CompareSide::Tab(entity),
+                    ));
+                }
             }
-            Message::ZoomReset => {
-                self.reset_tabs_zoom();
-                return self.update_config();
+            Message::LocalHistoryMaxCount(index) => {
+                match self.local_history_max_counts.get(index) {
+                    Some(max_count) => {
+                        config_set!(local_history_max_count, *max_count);
+                        return self.update_config();
+                    }
+                    None => {
+                        log::warn!(
+                            "failed to find local history max count with index {}",
+                            index
+                        );
+                    }
+                }
             }
-            Message::DefaultZoomStep(index) => match self.zoom_steps.get(index) {
-                Some(zoom_step) => {
-                    config_set!(font_size_zoom_step_mul_100, *zoom_step);
-                    self.reset_tabs_zoom(); // reset zoom
+            Message::LocalHistoryMaxDays(index) => match self.local_history_max_days.get(index) {
+                Some(max_days) => {
+                    config_set!(local_history_max_days, *max_days);
                     return self.update_config();
                 }
                 None => {
-                    log::warn!("failed to find zoom step with index {}", index);
+                    log::warn!("failed to find local history max days with index {}", index);
                 }
             },
-
-            Message::DialogCancel => {
-                self.dialog_page_opt = None;
-            }
-            Message::DialogMessage(dialog_message) => {
-                if let Some(dialog) = &mut self.dialog_opt {
-                    return dialog.update(dialog_message);
+            Message::LocalHistoryRestore(snapshot_i) => {
+                if let Some(snapshot) = self.local_history_snapshot(snapshot_i) {
+                    let entity = self.tab_model.active();
+                    return Task::perform(
+                        async move {
+                            match snapshot.read() {
+                                Ok(text) => {
+                                    action::app(Message::LocalHistoryRestoreText(entity, text))
+                                }
+                                Err(err) => {
+                                    let err = format!(
+                                        "failed to read snapshot {:?}: {}",
+                                        snapshot.path, err
+                                    );
+                                    log::error!("{}", err);
+                                    action::app(Message::GitError(err))
+                                }
+                            }
+                        },
+                        |x| x,
+                    );
                 }
             }
-            Message::Find(find_opt) => {
-                self.find_opt = find_opt.map(|f| FindField {
-                    replace: f,
-                    has_focus: true,
-                });
-
-                // Focus correct input
-                return self.update_focus();
+            Message::LocalHistoryRestoreText(entity, text) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                    // Restoring can be undone like any other change
+                    tab.restore_snapshot(&text);
+                    return self.update(Message::TabChanged(entity));
+                }
             }
-            Message::FindCaseSensitive(find_case_sensitive) => {
-                config_set!(find_case_sensitive, find_case_sensitive);
-                return self.update_config();
+            Message::LocalHistoryUpdate => {
+                return self.update_local_history();
             }
-            Message::FindNext => {
-                if !self.find_search_value.is_empty() {
-                    if let Some(Tab::Editor(tab)) = self.active_tab() {
-                        //TODO: do not compile find regex on every search?
-                        match self.config.find_regex(&self.find_search_value) {
-                            Ok(regex) => {
-                                tab.search(&regex, true, self.config.find_wrap_around);
-                            }
-                            Err(err) => {
-                                //TODO: put regex error in find box
-                                log::warn!(
-                                    "failed to compile regex {:?}: {}",
-                                    self.find_search_value,
-                                    err
-                                );
-                            }
-                        }
-                    }
+            Message::LspChange(entity, generation) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity)
+                    && tab.lsp_generation == generation
+                {
+                    self.lsp_sync(entity);
                 }
-
-                // Focus correct input
-                return self.update_focus();
             }
-            Message::FindPrevious => {
-                if !self.find_search_value.is_empty() {
-                    if let Some(Tab::Editor(tab)) = self.active_tab() {
-                        //TODO: do not compile find regex on every search?
-                        match self.config.find_regex(&self.find_search_value) {
-                            Ok(regex) => {
-                                tab.search(&regex, false, self.config.find_wrap_around);
-                            }
-                            Err(err) => {
-                                //TODO: put regex error in find box
-                                log::warn!(
-                                    "failed to compile regex {:?}: {}",
-                                    self.find_search_value,
-                                    err
-                                );
-                            }
-                        }
+            Message::LspCodeActionApply(action_i) => {
+                return self.lsp_apply_code_action(action_i);
+            }
+            Message::LspCodeActionKey(entity, key) => {
+                let Some(code_actions) = self
+                    .lsp_code_actions_opt
+                    .as_mut()
+                    .filter(|code_actions| code_actions.entity == entity)
+                else {
+                    return Task::none();
+                };
+                let len = match &code_actions.result_opt {
+                    Some(Ok(actions)) => actions.len() as isize,
+                    _ => 0,
+                };
+                let page = LSP_COMPLETION_ITEMS as isize;
+                let offset = match key {
+                    CompletionKey::Up | CompletionKey::BackTab => -1,
+                    CompletionKey::Down | CompletionKey::Tab => 1,
+                    CompletionKey::PageUp => -page,
+                    CompletionKey::PageDown => page,
+                    CompletionKey::Enter => {
+                        let selected = code_actions.selected;
+                        return self.lsp_apply_code_action(selected);
+                    }
+                    CompletionKey::Escape => {
+                        self.lsp_code_actions_opt = None;
+                        return Task::none();
+                    }
+                };
+                if len > 0 {
+                    code_actions.selected =
+                        (code_actions.selected as isize + offset).rem_euclid(len) as usize;
+                }
+            }
+            Message::LspCodeActionsOpen(entity, line_opt, point) => {
+                return self.lsp_code_actions(entity, line_opt, point);
+            }
+            Message::LspCodeActionsResult(generation, result) => {
+                if generation == self.lsp_code_actions_generation
+                    && let Some(code_actions) = &mut self.lsp_code_actions_opt
+                {
+                    code_actions.result_opt = Some(result);
+                }
+            }
+            Message::LspCompletionAccept(match_i) => {
+                if let Some(completion) = &mut self.lsp_completion_opt {
+                    completion.list.selected = match_i;
+                    return self.lsp_resolve_completion(true);
+                }
+            }
+            Message::LspCompletionKey(entity, key) => {
+                let Some(completion) = self
+                    .lsp_completion_opt
+                    .as_mut()
+                    .filter(|completion| completion.entity == entity)
+                else {
+                    return Task::none();
+                };
+                let page = LSP_COMPLETION_ITEMS as isize;
+                match key {
+                    CompletionKey::Up | CompletionKey::BackTab => completion.list.select(-1),
+                    CompletionKey::Down => completion.list.select(1),
+                    CompletionKey::PageUp => completion.list.select(-page),
+                    CompletionKey::PageDown => completion.list.select(page),
+                    CompletionKey::Enter | CompletionKey::Tab => {
+                        return self.lsp_resolve_completion(true);
+                    }
+                    CompletionKey::Escape => {
+                        self.lsp_completion_opt = None;
+                        return Task::none();
                     }
                 }
-
-                // Focus correct input
-                return self.update_focus();
+                return self.lsp_resolve_completion(false);
             }
-            Message::FindReplace => {
-                if !self.find_search_value.is_empty() {
-                    if let Some(Tab::Editor(tab)) = self.active_tab() {
-                        //TODO: do not compile find regex on every search?
-                        match self.config.find_regex(&self.find_search_value) {
-                            Ok(regex) => {
-                                //TODO: support captures
-                                tab.replace(
-                                    &regex,
-                                    &self.find_replace_value,
-                                    self.config.find_wrap_around,
-                                );
-                                return self.update(Message::TabChanged(self.tab_model.active()));
-                            }
-                            Err(err) => {
-                                //TODO: put regex error in find box
-                                log::warn!(
-                                    "failed to compile regex {:?}: {}",
-                                    self.find_search_value,
-                                    err
-                                );
-                            }
-                        }
+            Message::LspCompletionResolved(generation, item_i, item, insert) => {
+                if generation == self.lsp_completion_generation
+                    && let Some(completion) = &mut self.lsp_completion_opt
+                    && let Some(old_item) = completion.list.items.get_mut(item_i)
+                {
+                    *old_item = item;
+                    completion.resolved.insert(item_i);
+                    if insert {
+                        return self.lsp_insert_completion(item_i);
                     }
                 }
-
-                // Focus correct input
-                return self.update_focus();
             }
-            Message::FindReplaceAll => {
-                if !self.find_search_value.is_empty() {
-                    if let Some(Tab::Editor(tab)) = self.active_tab() {
-                        //TODO: do not compile find regex on every search?
-                        match self.config.find_regex(&self.find_search_value) {
-                            Ok(regex) => {
-                                //TODO: support captures
-                                {
-                                    let mut editor = tab.editor.lock().unwrap();
-                                    editor.set_cursor(cosmic_text::Cursor::new(0, 0));
-                                }
-                                while tab.replace(&regex, &self.find_replace_value, false) {}
-                                return self.update(Message::TabChanged(self.tab_model.active()));
+            Message::LspCompletionResult(generation, mut completion) => {
+                if generation != self.lsp_completion_generation {
+                    return Task::none();
+                }
+                let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(completion.entity) else {
+                    return Task::none();
+                };
+                // Filter by what was typed while waiting for the server
+                let (_, word_start, word) = tab.completion_word();
+                completion.list.filter(&word);
+                self.lsp_completion_opt = Some(completion).filter(|completion| {
+                    completion.word_start == word_start && !completion.list.matches.is_empty()
+                });
+                return self.lsp_resolve_completion(false);
+            }
+            Message::LspCompletionTrigger(entity, trigger, point) => {
+                return self.lsp_complete(entity, trigger, point);
+            }
+            Message::LspEdit(key, edit) => {
+                let changes = match edit.result {
+                    Ok(changes) => changes,
+                    Err(err) => {
+                        self.lsp_error_opt = Some(err);
+                        return Task::none();
+                    }
+                };
+                let mut tasks = vec![self.apply_file_changes(edit.encoding, changes)];
+                // The command runs after the edits, as it may send edits of its own
+                if let Some(command) = edit.command_opt
+                    && let Some(LspServer {
+                        state: LspServerState::Running(client),
+                        ..
+                    }) = self.lsp_servers.get(&key)
+                {
+                    let client = client.clone();
+                    tasks.push(Task::perform(
+                        async move {
+                            match client.execute_command(command).await {
+                                Ok(()) => action::none(),
+                                Err(err) => action::app(Message::LspError(err)),
                             }
-                            Err(err) => {
-                                //TODO: put regex error in find box
-                                log::warn!(
-                                    "failed to compile regex {:?}: {}",
-                                    self.find_search_value,
-                                    err
-                                );
+                        },
+                        |x| x,
+                    ));
+                }
+                return Task::batch(tasks);
+            }
+            Message::LspEnabled(lsp_enabled) => {
+                config_set!(lsp_enabled, lsp_enabled);
+                return self.update_config();
+            }
+            Message::LspError(err) => {
+                self.lsp_error_opt = Some(err);
+            }
+            Message::LspErrorClose => {
+                self.lsp_error_opt = None;
+            }
+            Message::LspEvent(key, generation, event) => {
+                let Some(LspServer {
+                    state: LspServerState::Running(client),
+                    generation: server_generation,
+                    ..
+                }) = self.lsp_servers.get(&key)
+                else {
+                    return Task::none();
+                };
+                if *server_generation != generation {
+                    return Task::none();
+                }
+                match event {
+                    LspEvent::Diagnostics(params) => {
+                        let Some(path) = lsp::uri_path(&params.uri) else {
+                            log::warn!("diagnostics for unsupported URI {:?}", params.uri);
+                            return Task::none();
+                        };
+                        let encoding = client.position_encoding();
+                        let entities: Vec<_> = self.tab_model.iter().collect();
+                        for entity in entities {
+                            if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity)
+                                && tab.lsp_document_opt.as_ref().is_some_and(|document| {
+                                    document.key == key && document.path == path
+                                })
+                            {
+                                tab.set_lsp_diagnostics(encoding, &params.diagnostics);
                             }
                         }
+                        if params.diagnostics.is_empty() {
+                            self.lsp_diagnostics.remove(&path);
+                        } else {
+                            self.lsp_diagnostics.insert(path, (key, params.diagnostics));
+                        }
+                    }
+                    LspEvent::ApplyEdit(edit) => {
+                        let encoding = client.position_encoding();
+                        let result = workspace_edit::load(edit, encoding, &self.open_texts());
+                        return self.update(Message::LspEdit(
+                            key,
+                            LspEdit {
+                                encoding,
+                                result,
+                                command_opt: None,
+                            },
+                        ));
                     }
                 }
-
-                // Focus correct input
-                return self.update_focus();
             }
-            Message::FindReplaceValueChanged(value) => {
-                self.find_replace_value = value;
-            }
-            Message::FindSearchValueChanged(value) => {
-                self.find_search_value = value;
+            Message::LspExited(key, generation) => {
+                let Some(server) = self.lsp_servers.get_mut(&key) else {
+                    return Task::none();
+                };
+                if server.generation != generation {
+                    return Task::none();
+                }
+                match server.state {
+                    LspServerState::Running(_) if server.restarts < LSP_MAX_RESTARTS => {
+                        server.restarts += 1;
+                        log::warn!(
+                            "{} in {:?} exited, restarting it ({}/{})",
+                            server.config.name,
+                            key.root,
+                            server.restarts,
+                            LSP_MAX_RESTARTS
+                        );
+                        // Wait longer after every crash
+                        let delay = time::Duration::from_secs(server.restarts.into());
+                        self.forget_lsp_server(&key);
+                        return self.start_lsp_server(key, delay);
+                    }
+                    LspServerState::Running(_) => {
+                        log::error!(
+                            "{} in {:?} exited too often, not restarting it",
+                            server.config.name,
+                            key.root
+                        );
+                        server.state = LspServerState::Failed;
+                        self.forget_lsp_server(&key);
+                    }
+                    // Servers that could not be started are tried again when the config changes
+                    LspServerState::Starting => {
+                        server.state = LspServerState::Failed;
+                    }
+                    LspServerState::Failed => {}
+                }
             }
-            Message::FindUseRegex(find_use_regex) => {
-                config_set!(find_use_regex, find_use_regex);
+            Message::FormatOnSave(format_on_save) => {
+                config_set!(format_on_save, format_on_save);
                 return self.update_config();
             }
-            Message::FindWrapAround(find_wrap_around) => {
-                config_set!(find_wrap_around, find_wrap_around);
-                return self.update_config();
+            Message::FormatterErrorClose => {
+                self.formatter_error_opt = None;
             }
-            Message::FindFocused(has_focus) => {
-                if let Some(f) = self.find_opt.as_mut() {
-                    *f = FindField {
-                        replace: f.replace,
-                        has_focus,
+            Message::FormatterResult(entity, text_hash, save, result) => {
+                let formatted_opt = match result {
+                    Ok(formatted) => {
+                        self.formatter_error_opt = None;
+                        Some(formatted)
+                    }
+                    // Missing formatters are only reported when formatting is asked for
+                    Err(FormatError::NotFound(command)) if save => {
+                        log::info!("formatter {} is not installed", command);
+                        None
+                    }
+                    Err(err) => {
+                        self.formatter_error_opt = Some(err.to_string());
+                        None
+                    }
+                };
+                let mut changed = false;
+                if let Some(formatted) = formatted_opt
+                    && let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity)
+                {
+                    let text = tab.text();
+                    // Formatting is dropped if the text was edited meanwhile
+                    let edits = if stable_hash(text.as_bytes()) == text_hash {
+                        formatter::minimal_edits(&text, &formatted)
+                    } else {
+                        Vec::new()
                     };
+                    if !edits.is_empty() {
+                        match tab.apply_lsp_edits(LspPositionEncoding::Utf8, &edits) {
+                            Ok(()) => changed = true,
+                            Err(err) => self.formatter_error_opt = Some(err),
+                        }
+                    }
                 }
-                if !has_focus {
-                    self.project_search_has_focus = false;
-                    return self.update_focus();
+                if !save {
+                    if !changed {
+                        return Task::none();
+                    }
+                    self.lsp_sync(entity);
+                    return self.update(Message::TabChanged(entity));
                 }
+                // The tab is written once, formatted or not when the formatter failed
+                let save_task = self.save_editor_tab(entity);
+                return Task::batch([
+                    self.update(Message::TabChanged(entity)),
+                    self.update_dialogs(),
+                    save_task,
+                ]);
             }
-            Message::GitProjectStatus(project_status) => {
-                self.git_project_status = Some(project_status);
+            Message::LspFormat(selection) => {
+                // Formatters are used for whole documents before language servers
+                let entity = self.tab_model.active();
+                if !selection && self.formatter(entity).is_some() {
+                    return self.run_formatter(entity, false);
+                }
+                return self.lsp_format(selection);
             }
-            Message::GitStage(project_path, path) => {
-                return Task::perform(
-                    async move {
-                        //TODO: send errors to UI
-                        match GitRepository::new(&project_path) {
-                            Ok(repo) => match repo.stage(&path).await {
-                                Ok(()) => {
-                                    return action::app(Message::UpdateGitProjectStatus);
-                                }
-                                Err(err) => {
-                                    log::error!(
-                                        "failed to stage {:?} in {:?}: {}",
-                                        path,
-                                        project_path,
-                                        err
-                                    );
-                                }
-                            },
-                            Err(err) => {
-                                log::error!(
-                                    "failed to open repository {:?}: {}",
-                                    project_path,
-                                    err
-                                );
-                            }
+            Message::LspFormatResult(entity, text_hash, encoding, result) => match result {
+                Ok(Some(edits)) => {
+                    if edits.is_empty() {
+                        return Task::none();
+                    }
+                    let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) else {
+                        return Task::none();
+                    };
+                    // Formatting is dropped if the text was edited meanwhile
+                    if stable_hash(tab.text().as_bytes()) != text_hash {
+                        return Task::none();
+                    }
+                    match tab.apply_lsp_edits(encoding, &edits) {
+                        Ok(()) => {
+                            self.lsp_sync(entity);
+                            return self.update(Message::TabChanged(entity));
                         }
-                        action::none()
-                    },
-                    |x| x,
-                );
+                        Err(err) => self.lsp_error_opt = Some(err),
+                    }
+                }
+                Ok(None) => self.lsp_error_opt = Some(fl!("format-unsupported")),
+                Err(err) => self.lsp_error_opt = Some(err),
+            },
+            Message::LspProblemOpen(path, position) => {
+                let encoding = match self
+                    .lsp_diagnostics
+                    .get(&path)
+                    .and_then(|(key, _)| self.lsp_servers.get(key))
+                {
+                    Some(LspServer {
+                        state: LspServerState::Running(client),
+                        ..
+                    }) => client.position_encoding(),
+                    _ => LspPositionEncoding::Utf16,
+                };
+                return self.open_lsp_position(path, encoding, position);
             }
-            Message::GitUnstage(project_path, path) => {
-                return Task::perform(
-                    async move {
-                        //TODO: send errors to UI
-                        match GitRepository::new(&project_path) {
-                            Ok(repo) => match repo.unstage(&path).await {
-                                Ok(()) => {
-                                    return action::app(Message::UpdateGitProjectStatus);
-                                }
-                                Err(err) => {
-                                    log::error!(
-                                        "failed to unstage {:?} in {:?}: {}",
-                                        path,
-                                        project_path,
-                                        err
-                                    );
-                                }
-                            },
-                            Err(err) => {
-                                log::error!(
-                                    "failed to open repository {:?}: {}",
-                                    project_path,
-                                    err
-                                );
-                            }
-                        }
-                        action::none()
-                    },
-                    |x| x,
-                );
+            Message::LspSignatureHelp(generation, signature_help_opt) => {
+                if generation == self.lsp_signature_help_generation {
+                    self.lsp_signature_help_opt = signature_help_opt
+                        .filter(|signature_help| !signature_help.help.signatures.is_empty());
+                }
             }
-            Message::Key(modifiers, physical, key) => {
-                for (key_bind, action) in self.key_binds.iter() {
-                    if key_bind.matches(modifiers, &key, Some(&physical)) {
-                        return self.update(action.message(None));
-                    }
+            Message::LspStable(key, generation) => {
+                if let Some(server) = self.lsp_servers.get_mut(&key)
+                    && server.generation == generation
+                    && matches!(server.state, LspServerState::Running(_))
+                {
+                    server.restarts = 0;
                 }
             }
-            Message::LaunchUrl(url) => match open::that_detached(&url) {
-                Ok(()) => {}
-                Err(err) => {
-                    log::warn!("failed to open {:?}: {}", url, err);
+            Message::LspStarted(key, generation, client) => {
+                match self.lsp_servers.get_mut(&key) {
+                    Some(server) if server.generation == generation => {
+                        server.state = LspServerState::Running(client);
+                        let restarted = server.restarts > 0;
+                        let mut tasks = vec![self.update_lsp()];
+                        if restarted {
+                            // Crashes long ago do not count towards giving up on the server
+                            tasks.push(Task::perform(
+                                async move {
+                                    time::sleep(LSP_STABLE_TIME).await;
+                                    action::app(Message::LspStable(key, generation))
+                                },
+                                |x| x,
+                            ));
+                        }
+                        return Task::batch(tasks);
+                    }
+                    // The server is no longer needed
+                    _ => {
+                        return Task::perform(
+                            async move {
+                                client.shutdown().await;
+                                action::none()
+                            },
+                            |x| x,
+                        );
+                    }
                 }
-            },
+            }
             Message::Modifiers(modifiers) => {
                 self.modifiers = modifiers;
             }
+            Message::Navigate(request) => {
+                return self.navigate(request);
+            }
+            Message::NavigationOpen(item_i) => {
+                if let Some(results) = &self.navigation_opt
+                    && let Ok(items) = &results.result
+                    && let Some(item) = items.get(item_i)
+                {
+                    let (path, encoding, position) =
+                        (item.path.clone(), results.encoding, item.position);
+                    return self.open_lsp_position(path, encoding, position);
+                }
+            }
+            Message::NavigationResult(generation, results) => {
+                if generation != self.navigation_generation {
+                    return Task::none();
+                }
+                // A single definition is opened right away
+                if results.request != NavRequest::References
+                    && let Ok(items) = &results.result
+                    && let [item] = items.as_slice()
+                {
+                    let (path, position) = (item.path.clone(), item.position);
+                    return self.open_lsp_position(path, results.encoding, position);
+                }
+                self.navigation_opt = Some(results);
+                self.context_page = ContextPage::Navigation;
+                self.core.window.show_context = true;
+                self.project_search_has_focus = false;
+                return self.update_focus();
+            }
             Message::NewFile => {
                 self.open_tab(None);
                 return self.update_tab();
             }
+            Message::NavMenuAction(nav_menu_action) => match nav_menu_action {
+                NavMenuAction::CompareSelect(id) => {
+                    if let Some(ProjectNode::File { path, .. }) =
+                        self.nav_model.data::<ProjectNode>(id)
+                    {
+                        self.compare_selection_opt = Some(CompareSide::File(path.clone()));
+                    }
+                }
+                NavMenuAction::CompareWithSelected(id) => {
+                    if let Some(ProjectNode::File { path, .. }) =
+                        self.nav_model.data::<ProjectNode>(id)
+                    {
+                        if let Some(old_side) = self.compare_selection_opt.clone() {
+                            let new_side = CompareSide::File(path.clone());
+                            return self.update(Message::Compare(old_side, new_side));
+                        }
+                    }
+                }
+                NavMenuAction::ToggleFormatters(id) => {
+                    if let Some(ProjectNode::Folder {
+                        path, root: true, ..
+                    }) = self.nav_model.data::<ProjectNode>(id)
+                    {
+                        let mut disabled_projects = self.config.formatter_disabled_projects.clone();
+                        match disabled_projects.iter().position(|project| project == path) {
+                            Some(project_i) => {
+                                disabled_projects.remove(project_i);
+                            }
+                            None => disabled_projects.push(path.clone()),
+                        }
+                        config_set!(formatter_disabled_projects, disabled_projects);
+                    }
+                }
+            },
             Message::NewWindow => {
                 //TODO: support multi-window in winit
                 match env::current_exe() {
@@ -2180,16 +8122,22 @@ impl Application for App {
                         }
                     }
                 }
+                let mut tasks = Vec::new();
+                let mut local_history_saves = Vec::new();
                 for entity in tab_reload {
                     match self.tab_model.data_mut::<Tab>(entity) {
                         Some(Tab::Editor(tab)) => {
-                            tab.reload();
+                            local_history_saves.extend(tab.reload());
                         }
                         _ => {
                             log::warn!("failed to find tab {:?} that needs reload", entity);
+                            continue;
                         }
                     }
+                    self.lsp_sync(entity);
+                    tasks.push(self.update_git_diff(entity));
                 }
+                tasks.push(self.write_local_history(local_history_saves));
 
                 // Reload folders that changed
                 let mut close_entities = Vec::new();
@@ -2258,7 +8206,8 @@ impl Application for App {
                         } else {
                             continue;
                         }
-                        node.icon(16)
+                        let node = node.clone();
+                        self.project_node_icon(&node)
                     } else {
                         continue;
                     };
@@ -2269,22 +8218,62 @@ impl Application for App {
                     self.open_folder(open_path, position + 1, indent + 1);
                 }
 
-                // Reload git status if necessary
-                if self.core.window.show_context && self.context_page == ContextPage::GitManagement
-                {
-                    for (_, project_path) in self.projects.iter() {
-                        for path in event.paths.iter() {
-                            if let Ok(prefix) = path.strip_prefix(&project_path) {
-                                // Manually ignore project .git folders
-                                //TODO: use logic from ignore crate somehow?
-                                if prefix.starts_with(".git") {
-                                    continue;
-                                }
-                                return self.update(Message::UpdateGitProjectStatus);
+                // Tags of projects are generated again once their files change
+                for path in event.paths.iter() {
+                    if !path
+                        .components()
+                        .any(|component| component.as_os_str() == ".git")
+                    {
+                        self.tags_cache.invalidate(path);
+                    }
+                }
+
+                // Reload indexed versions for git change markers if a git folder changed
+                let git_changed = event.paths.iter().any(|path| {
+                    self.project_git_dirs
+                        .values()
+                        .any(|git_dir| path.starts_with(git_dir))
+                        || self.projects.iter().any(|(_, project_path)| {
+                            path.strip_prefix(project_path)
+                                .is_ok_and(|prefix| prefix.starts_with(".git"))
+                        })
+                });
+                if git_changed {
+                    let entities: Vec<_> = self.tab_model.iter().collect();
+                    for entity in entities {
+                        if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                            tab.git_index_stale = true;
+                            tab.git_blame_head_stale = true;
+                        }
+                    }
+                    tasks.push(self.update_git_index(self.tab_model.active()));
+                    tasks.push(self.update_git_blame(self.tab_model.active()));
+                }
+
+                // Reload git status for git management and the project tree
+                let mut status_changed = git_changed;
+                'projects: for (_, project_path) in self.projects.iter() {
+                    for path in event.paths.iter() {
+                        if let Ok(prefix) = path.strip_prefix(&project_path) {
+                            // Manually ignore project .git folders
+                            //TODO: use logic from ignore crate somehow?
+                            if prefix.starts_with(".git") {
+                                continue;
                             }
+                            // Ignored files, like build output, do not change the status
+                            if self.git_tree_status.get(path) == Some(ProjectGitStatus::Ignored) {
+                                continue;
+                            }
+                            status_changed = true;
+                            break 'projects;
                         }
                     }
                 }
+                if status_changed {
+                    tasks.push(self.update_git_project_status(git_changed));
+                }
+
+                return Task::batch(tasks);
             }
             Message::NotifyWatcher(mut watcher_wrapper) => match watcher_wrapper.watcher_opt.take()
             {
@@ -2339,13 +8328,16 @@ impl Application for App {
                     }
                 }
             }
-            Message::OpenGitDiff(project_path, diff) => {
+            Message::OpenGitDiff(project_path, diff, commit_opt) => {
                 // Close any diff tabs with same path
                 {
                     let mut close = Vec::new();
                     for entity in self.tab_model.iter() {
                         if let Some(Tab::GitDiff(other_tab)) = self.tab_model.data::<Tab>(entity) {
-                            if other_tab.diff.path == diff.path {
+                            if other_tab.diff.path == diff.path
+                                && other_tab.commit_opt == commit_opt
+                                && other_tab.compare_opt.is_none()
+                            {
                                 close.push(entity);
                             }
                         }
@@ -2369,20 +8361,38 @@ impl Application for App {
                 };
                 let title = format!(
                     "{}: {}",
-                    if diff.staged {
-                        fl!("staged-changes")
-                    } else {
-                        fl!("unstaged-changes")
+                    match &commit_opt {
+                        Some(hash) => hash.chars().take(8).collect(),
+                        None if diff.staged => fl!("staged-changes"),
+                        None => fl!("unstaged-changes"),
                     },
                     relative_path.display()
                 );
-                let icon =
-                    icon::icon(mime_icon(mime_for_path(&diff.path, None, false), 16)).size(16);
-                let tab = Tab::GitDiff(GitDiffTab { title, diff });
+                let tab = GitDiffTab::new(title, project_path, diff, commit_opt);
+                return self.open_diff_tab(tab);
+            }
+            Message::OpenGitRevision(path, short_hash, text) => {
+                // Activate the tab if this revision is already open
+                for entity in self.tab_model.iter() {
+                    if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                        if let Some(revision) = &tab.revision_opt {
+                            if revision.path == path && revision.short_hash == short_hash {
+                                self.tab_model.activate(entity);
+                                return self.update_tab();
+                            }
+                        }
+                    }
+                }
+
+                let tab = Tab::Editor(EditorTab::new_revision(
+                    &self.config,
+                    EditorRevision { path, short_hash },
+                    &text,
+                ));
                 self.tab_model
                     .insert()
                     .text(tab.title())
-                    .icon(icon)
+                    .icon(tab.icon(16))
                     .data::<Tab>(tab)
                     .closable()
                     .activate();
@@ -2403,10 +8413,12 @@ impl Application for App {
                 self.dialog_opt = None;
                 match result {
                     DialogResult::Cancel => {}
-                    DialogResult::Open(paths) => {
-                        for path in paths {
-                            self.open_project(path);
-                        }
+                    DialogResult::Open(paths) => {
+                        let tasks: Vec<_> = paths
+                            .into_iter()
+                            .map(|path| self.open_project(path))
+                            .collect();
+                        return Task::batch(tasks);
                     }
                 }
             }
@@ -2418,18 +8430,23 @@ impl Application for App {
             }
             Message::OpenRecentProject(index) => {
                 if let Some(path) = self.config_state.recent_projects.get(index).cloned() {
-                    self.open_project(path);
+                    return self.open_project(path);
                 }
             }
-            Message::OpenSearchResult(file_i, line_i) => {
+            Message::OpenSearchResult(file_i, line_i, match_i) => {
                 let path_cursor_opt = match &self.project_search_result {
                     Some(project_search_result) => match project_search_result.files.get(file_i) {
                         Some(file_search_result) => match file_search_result.lines.get(line_i) {
                             Some(line_search_result) => Some((
+                                file_search_result.tab_opt,
                                 file_search_result.path.to_path_buf(),
                                 Cursor::new(
                                     line_search_result.number.saturating_sub(1),
-                                    line_search_result.first.start(),
+                                    // Context lines have no matches and open at the line start
+                                    line_search_result
+                                        .matches
+                                        .get(match_i)
+                                        .map_or(0, |m| m.start()),
                                 ),
                             )),
                             None => {
@@ -2445,9 +8462,17 @@ impl Application for App {
                     None => None,
                 };
 
-                if let Some((path, cursor)) = path_cursor_opt {
+                if let Some((tab_opt, path, cursor)) = path_cursor_opt {
                     self.project_search_has_focus = false;
-                    if let Some(entity) = self.open_tab(Some(path)) {
+                    // Prefer the searched tab, which may be unsaved or untitled
+                    let entity_opt = match tab_opt {
+                        Some(entity) if self.tab_model.data::<Tab>(entity).is_some() => {
+                            self.tab_model.activate(entity);
+                            Some(entity)
+                        }
+                        _ => self.open_tab(Some(path)),
+                    };
+                    if let Some(entity) = entity_opt {
                         return Task::batch([
                             //TODO: why must this be done in a command?
                             Task::perform(
@@ -2477,77 +8502,110 @@ impl Application for App {
                 }
             }
             Message::PrepareGitDiff(project_path, path, staged) => {
-                return Task::perform(
-                    async move {
-                        //TODO: send errors to UI
-                        match GitRepository::new(&project_path) {
-                            Ok(repo) => match repo.diff(&path, staged).await {
-                                Ok(diff) => {
-                                    return action::app(Message::OpenGitDiff(project_path, diff));
-                                }
-                                Err(err) => {
-                                    log::error!(
-                                        "failed to get diff of {:?} in {:?}: {}",
-                                        path,
-                                        project_path,
-                                        err
-                                    );
-                                }
-                            },
-                            Err(err) => {
-                                log::error!(
-                                    "failed to open repository {:?}: {}",
-                                    project_path,
-                                    err
-                                );
-                            }
-                        }
-                        action::none()
-                    },
-                    |x| x,
-                );
+                return git_task(project_path.clone(), move |repo| async move {
+                    match repo.diff(&path, staged, GIT_DIFF_CONTEXT_LINES).await {
+                        Ok(diff) => action::app(Message::OpenGitDiff(project_path, diff, None)),
+                        Err(err) => git_error(
+                            format!("failed to get diff of {:?} in {:?}", path, project_path),
+                            err,
+                        ),
+                    }
+                });
             }
-            Message::ProjectSearchResult(project_search_result) => {
-                self.project_search_result = Some(project_search_result);
-                self.project_search_has_focus = true;
+            Message::ProjectSearchCancel => {
+                if let Some(cancel) = self.project_search_cancel_opt.take() {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                if let Some(project_search_result) = &mut self.project_search_result {
+                    project_search_result.in_progress = false;
+                }
+            }
+            Message::ProjectSearchContextAfter(index) => match u16::try_from(index) {
+                Ok(context_lines) => {
+                    config_set!(project_search_context_after, context_lines);
+                }
+                Err(err) => {
+                    log::warn!("invalid context lines {}: {}", index, err);
+                }
+            },
+            Message::ProjectSearchContextBefore(index) => match u16::try_from(index) {
+                Ok(context_lines) => {
+                    config_set!(project_search_context_before, context_lines);
+                }
+                Err(err) => {
+                    log::warn!("invalid context lines {}: {}", index, err);
+                }
+            },
+            Message::ProjectSearchExclude(value) => {
+                self.project_search_exclude_value = value;
+            }
+            Message::ProjectSearchFile(id, file_search_result) => {
+                if let Some(project_search_result) = &mut self.project_search_result {
+                    if project_search_result.id == id {
+                        project_search_result.insert_file(file_search_result);
+                    }
+                }
+            }
+            Message::ProjectSearchFinished(id, error_opt) => {
+                if let Some(project_search_result) = &mut self.project_search_result {
+                    // Cancelled searches are no longer in progress and keep the focus where it is
+                    if project_search_result.id == id && project_search_result.in_progress {
+                        if let Some(err) = &error_opt {
+                            log::error!("failed to search projects: {}", err);
+                        }
+                        project_search_result.in_progress = false;
+                        project_search_result.error_opt = error_opt;
+                        self.project_search_cancel_opt = None;
+                        self.project_search_has_focus = true;
 
-                // Focus correct input
-                return self.update_focus();
+                        // Focus correct input
+                        return self.update_focus();
+                    }
+                }
+            }
+            Message::ProjectSearchInclude(value) => {
+                self.project_search_include_value = value;
+            }
+            Message::ProjectSearchMaxFileSize(index) => match self.max_file_sizes.get(index) {
+                Some(max_file_size) => {
+                    config_set!(project_search_max_file_size, *max_file_size);
+                }
+                None => {
+                    log::warn!("failed to find max file size with index {}", index);
+                }
+            },
+            Message::ProjectSearchOpenTabsOnly(open_tabs_only) => {
+                self.project_search_open_tabs_only = open_tabs_only;
             }
             Message::ProjectSearchSubmit => {
                 //TODO: Figure out length requirements?
                 if !self.project_search_value.is_empty() {
-                    let projects = self.projects.clone();
-                    let project_search_value = self.project_search_value.clone();
-                    let mut project_search_result = ProjectSearchResult {
-                        value: project_search_value.clone(),
-                        in_progress: true,
-                        files: Vec::new(),
-                    };
-                    self.project_search_result = Some(project_search_result.clone());
-                    return Task::perform(
-                        async move {
-                            let task_res = tokio::task::spawn_blocking(move || {
-                                project_search_result.search_projects(projects);
-                                action::app(Message::ProjectSearchResult(project_search_result))
-                            })
-                            .await;
-                            match task_res {
-                                Ok(message) => message,
-                                Err(err) => {
-                                    log::error!("failed to run search task: {}", err);
-                                    action::none()
-                                }
-                            }
-                        },
-                        |x| x,
-                    );
+                    return self.start_project_search(self.project_search_options());
+                }
+            }
+            Message::ProjectSearchToggleFile(key) => {
+                if !self.project_search_collapsed.remove(&key) {
+                    self.project_search_collapsed.insert(key);
                 }
             }
             Message::ProjectSearchValue(value) => {
                 self.project_search_has_focus = true;
                 self.project_search_value = value;
             }
+            Message::ProjectSearchWord(word) => {
+                // References are searched as whole words without a language server
+                self.project_search_value = word;
+                self.context_page = ContextPage::ProjectSearch;
+                self.core.window.show_context = true;
+                self.project_search_has_focus = true;
+                let options = ProjectSearchOptions {
+                    use_regex: false,
+                    case_sensitive: true,
+                    whole_word: true,
+                    ..self.project_search_options()
+                };
+                return Task::batch([self.start_project_search(options), self.update_focus()]);
+            }
             Message::PromptSaveChanges(entity) => {
                 self.dialog_page_opt = Some(DialogPage::PromptSaveClose(entity));
             }
@@ -2558,7 +8616,7 @@ impl Application for App {
                 return self.update_dialogs();
             }
             Message::QuitForce => {
-                process::exit(0);
+                return self.quit(Vec::new());
             }
             Message::Redo => {
                 if let Some(Tab::Editor(tab)) = self.active_tab() {
@@ -2570,6 +8628,88 @@ impl Application for App {
                     return self.update(Message::TabChanged(self.tab_model.active()));
                 }
             }
+            Message::RenameApply => {
+                // The rename is only applied once its preview is shown
+                if !matches!(
+                    self.rename_opt,
+                    Some(Rename {
+                        changes_opt: Some(Ok(_)),
+                        loading: false,
+                        ..
+                    })
+                ) {
+                    return Task::none();
+                }
+                let Some(Rename {
+                    encoding,
+                    changes_opt: Some(Ok(changes)),
+                    ..
+                }) = self.rename_opt.take()
+                else {
+                    return Task::none();
+                };
+                self.core.window.show_context = false;
+                return Task::batch([
+                    self.apply_file_changes(encoding, changes),
+                    self.update_focus(),
+                ]);
+            }
+            Message::RenameInput(name) => {
+                if let Some(rename) = &mut self.rename_opt {
+                    // The preview is of the previous name
+                    rename.name = name;
+                    rename.changes_opt = None;
+                    rename.loading = false;
+                    self.rename_generation = self.rename_generation.wrapping_add(1);
+                }
+            }
+            Message::RenameOpen => {
+                let entity = self.tab_model.active();
+                let Some((client, _)) = self.lsp_client(entity) else {
+                    return Task::none();
+                };
+                let encoding = client.position_encoding();
+                let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) else {
+                    return Task::none();
+                };
+                let old_name = tab.cursor_word();
+                if old_name.is_empty() {
+                    return Task::none();
+                }
+                self.rename_opt = Some(Rename {
+                    entity,
+                    encoding,
+                    position: tab.lsp_position(encoding),
+                    name: old_name.clone(),
+                    old_name,
+                    changes_opt: None,
+                    loading: false,
+                });
+                self.context_page = ContextPage::Rename;
+                self.core.window.show_context = true;
+                self.project_search_has_focus = false;
+                return self.update_focus();
+            }
+            Message::RenameResult(generation, result) => {
+                if generation == self.rename_generation
+                    && let Some(rename) = &mut self.rename_opt
+                {
+                    rename.changes_opt = Some(result);
+                    rename.loading = false;
+                }
+            }
+            Message::RenameSubmit => {
+                // Enter shows the preview, and applies it once it is shown
+                if let Some(Rename {
+                    changes_opt: Some(Ok(_)),
+                    loading: false,
+                    ..
+                }) = self.rename_opt
+                {
+                    return self.update(Message::RenameApply);
+                }
+                return self.update_rename();
+            }
             Message::ReorderTab(ReorderEvent {
                 dragged,
                 target,
@@ -2579,52 +8719,51 @@ impl Application for App {
             }
             Message::RevertAllChanges => {
                 if let Some(Tab::Editor(tab)) = self.active_tab_mut() {
-                    tab.reload();
+                    let local_history_save_opt = tab.reload();
 
-                    return self.update(Message::TabChanged(self.tab_model.active()));
+                    return Task::batch([
+                        self.update(Message::TabChanged(self.tab_model.active())),
+                        self.write_local_history(local_history_save_opt),
+                    ]);
                 }
             }
             Message::Save(entity_opt) => {
                 let mut title_opt = None;
+                let mut save_task = Task::none();
 
                 let entity = entity_opt.unwrap_or_else(|| self.tab_model.active());
-                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
-                    match tab.path_opt.clone() {
-                        Some(path) => {
-                            title_opt = Some(tab.title());
-                            tab.save();
-                            if let Ok(canonical) = fs::canonicalize(&path) {
-                                self.add_to_recents(&canonical);
-                            }
-                        }
-                        None => {
-                            return self.update(Message::SaveAsDialog(Some(entity)));
-                        }
+                if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                    if tab.path_opt.is_none() {
+                        return self.update(Message::SaveAsDialog(Some(entity)));
                     }
+                    title_opt = Some(tab.title());
+                    if self.format_on_save(entity) {
+                        // The tab is saved once the formatter is done
+                        return self.run_formatter(entity, true);
+                    }
+                    save_task = self.save_editor_tab(entity);
                 }
                 if let Some(title) = title_opt {
                     self.tab_model.text_set(self.tab_model.active(), title);
                 }
-                return self.update_dialogs();
+                return Task::batch([self.update_dialogs(), save_task]);
             }
             Message::SaveAll => {
+                let mut tasks = Vec::new();
                 let entities: Vec<_> = self.tab_model.iter().collect();
                 for entity in entities {
-                    if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
-                        match tab.path_opt.clone() {
-                            Some(path) => {
-                                tab.save();
-                                if let Ok(canonical) = fs::canonicalize(&path) {
-                                    self.add_to_recents(&canonical);
-                                }
-                            }
-                            None => {
-                                log::warn!("{} has no path when doing save all", tab.title());
-                            }
+                    if let Some(Tab::Editor(tab)) = self.tab_model.data::<Tab>(entity) {
+                        if tab.path_opt.is_none() {
+                            log::warn!("{} has no path when doing save all", tab.title());
+                        } else if self.format_on_save(entity) {
+                            tasks.push(self.run_formatter(entity, true));
+                        } else {
+                            tasks.push(self.save_editor_tab(entity));
                         }
                     }
                 }
-                return self.update_dialogs();
+                tasks.push(self.update_dialogs());
+                return Task::batch(tasks);
             }
             Message::SaveAsDialog(entity_opt) => {
                 if self.dialog_opt.is_none() {
@@ -2661,10 +8800,11 @@ impl Application for App {
                     DialogResult::Open(mut paths) => {
                         if !paths.is_empty() {
                             let mut title_opt = None;
+                            let mut local_history_save_opt = None;
                             if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
                                 tab.path_opt = Some(paths.remove(0));
                                 title_opt = Some(tab.title());
-                                tab.save();
+                                local_history_save_opt = tab.save();
                                 if let Some(path) = tab.path_opt.clone() {
                                     if let Ok(canonical) = fs::canonicalize(&path) {
                                         self.add_to_recents(&canonical);
@@ -2674,7 +8814,15 @@ impl Application for App {
                             if let Some(title) = title_opt {
                                 self.tab_model.text_set(entity, title);
                             }
-                            return self.update_dialogs();
+                            // The file is opened with the server of its new name first
+                            let lsp_task = self.update_lsp();
+                            self.lsp_did_save(entity);
+                            return Task::batch([
+                                self.update_dialogs(),
+                                self.update_local_history(),
+                                self.write_local_history(local_history_save_opt),
+                                lsp_task,
+                            ]);
                         }
                     }
                 }
@@ -2695,6 +8843,15 @@ impl Application for App {
                     editor.set_selection(selection);
                 }
             }
+            Message::SnippetKey(entity, key) => {
+                if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                    match key {
+                        CompletionKey::Tab => tab.snippet_tab_stop(false),
+                        CompletionKey::BackTab => tab.snippet_tab_stop(true),
+                        _ => {}
+                    }
+                }
+            }
             Message::Scroll(auto_scroll) => {
                 if let Some(Tab::Editor(tab)) = self.active_tab_mut() {
                     let mut editor = tab.editor.lock().unwrap();
@@ -2710,6 +8867,65 @@ impl Application for App {
                     cosmic::app::Action::Surface(a),
                 ));
             }
+            Message::SymbolSearchOpen(workspace) => {
+                let entity = self.tab_model.active();
+                if !matches!(self.tab_model.data::<Tab>(entity), Some(Tab::Editor(_))) {
+                    return Task::none();
+                }
+                let encoding = self
+                    .lsp_client(entity)
+                    .map_or(LspPositionEncoding::Utf16, |(client, _)| {
+                        client.position_encoding()
+                    });
+                self.symbol_search_opt = Some(SymbolSearch {
+                    entity,
+                    workspace,
+                    query: String::new(),
+                    encoding,
+                    from_server: false,
+                    result_opt: None,
+                    matches: Vec::new(),
+                });
+                self.context_page = ContextPage::Symbols;
+                self.core.window.show_context = true;
+                self.project_search_has_focus = false;
+                return Task::batch([self.update_symbol_search(), self.update_focus()]);
+            }
+            Message::SymbolSearchOpenItem(item_i) => {
+                return self.open_symbol(item_i);
+            }
+            Message::SymbolSearchQuery(query) => {
+                if let Some(search) = &mut self.symbol_search_opt {
+                    search.query = query;
+                    if search.workspace && search.from_server {
+                        return self.update_symbol_search();
+                    }
+                    if let Some(Ok(items)) = &search.result_opt {
+                        search.matches = navigation::filter_items(items, &search.query);
+                    }
+                }
+            }
+            Message::SymbolSearchResult(generation, from_server, result) => {
+                if generation == self.symbol_search_generation
+                    && let Some(search) = &mut self.symbol_search_opt
+                {
+                    search.from_server = from_server;
+                    search.matches = match &result {
+                        Ok(items) => navigation::filter_items(items, &search.query),
+                        Err(_) => Vec::new(),
+                    };
+                    search.result_opt = Some(result);
+                }
+            }
+            Message::SymbolSearchSubmit => {
+                if let Some(item_i) = self
+                    .symbol_search_opt
+                    .as_ref()
+                    .and_then(|search| search.matches.first().copied())
+                {
+                    return self.open_symbol(item_i);
+                }
+            }
             Message::SystemThemeModeChange(_theme_mode) => {
                 return self.update_config();
             }
@@ -2758,6 +8974,11 @@ impl Application for App {
                         title.push_str(" \u{2022}");
                     }
                     self.tab_model.text_set(entity, title);
+                    return Task::batch([
+                        self.update_git_conflicts(entity),
+                        self.update_git_diff(entity),
+                        self.update_lsp_change(entity),
+                    ]);
                 }
             }
             Message::TabClose(entity) => {
@@ -2796,12 +9017,18 @@ impl Application for App {
                 }
 
                 // Remove item
+                let undo_history_saves: Vec<_> = match self.tab_model.data_mut::<Tab>(entity) {
+                    Some(Tab::Editor(tab)) => tab.undo_history_save().into_iter().collect(),
+                    _ => Vec::new(),
+                };
+                self.lsp_close(entity);
                 self.tab_model.remove(entity);
                 self.update_watcher();
+                self.forget_git_repositories();
 
                 // If that was the last tab, exit the application
                 if self.tab_model.iter().next().is_none() {
-                    return self.update(Message::QuitForce);
+                    return self.quit(undo_history_saves);
                 }
 
                 // Close PromptSaveClose dialog if open for this entity
@@ -2809,7 +9036,16 @@ impl Application for App {
                     self.dialog_page_opt = None;
                 }
 
-                return self.update_tab();
+                return Task::batch([
+                    self.update_tab(),
+                    Task::perform(
+                        async move {
+                            write_undo_histories(undo_history_saves).await;
+                            action::none()
+                        },
+                        |x| x,
+                    ),
+                ]);
             }
             Message::TabContextAction(entity, action) => {
                 if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
@@ -2820,6 +9056,10 @@ impl Application for App {
                 }
             }
             Message::TabContextMenu(entity, position_opt) => {
+                // Clicking moves the cursor away from completions and code actions
+                self.lsp_completion_opt = None;
+                self.lsp_signature_help_opt = None;
+                self.lsp_code_actions_opt = None;
                 if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
                     // Update context menu
                     tab.context_menu = position_opt;
@@ -2890,10 +9130,34 @@ impl Application for App {
                 {
                     return self.update(Message::UpdateGitProjectStatus);
                 }
+                if self.context_page == ContextPage::GitFileHistory {
+                    // Commits may have been made since the history was loaded
+                    self.git_file_history_opt = None;
+                    return Task::batch([self.update_git_file_history(), self.update_focus()]);
+                }
+                if self.context_page == ContextPage::LocalHistory {
+                    return Task::batch([self.update_local_history(), self.update_focus()]);
+                }
 
                 // Ensure focus of correct input
                 return self.update_focus();
             }
+            Message::ToggleGitBlame => {
+                config_set!(git_blame, !self.config.git_blame);
+                self.git_blame_hover_opt = None;
+                // This forces a redraw of all buffers
+                let mut tasks = Vec::new();
+                let entities: Vec<_> = self.tab_model.iter().collect();
+                for entity in entities {
+                    tasks.push(self.update_git_blame(entity));
+                    if let Some(Tab::Editor(tab)) = self.tab_model.data_mut::<Tab>(entity) {
+                        let mut editor = tab.editor.lock().unwrap();
+                        editor.set_redraw(true);
+                    }
+                }
+                tasks.push(self.update_config());
+                return Task::batch(tasks);
+            }
             Message::ToggleHighlightCurrentLine => {
                 config_set!(highlight_current_line, !self.config.highlight_current_line);
                 // This forces a redraw of all buffers
@@ -2934,43 +9198,102 @@ impl Application for App {
                     return self.update(Message::TabChanged(self.tab_model.active()));
                 }
             }
+            Message::UndoHistoryMaxChanges(index) => {
+                match self.undo_history_max_changes.get(index) {
+                    Some(max_changes) => {
+                        config_set!(undo_history_max_changes, *max_changes);
+                        return self.update_config();
+                    }
+                    None => {
+                        log::warn!(
+                            "failed to find undo history max changes with index {}",
+                            index
+                        );
+                    }
+                }
+            }
+            Message::UndoHistoryMaxFileSize(index) => match self.max_file_sizes.get(index) {
+                Some(max_file_size) => {
+                    config_set!(undo_history_max_file_size, *max_file_size);
+                    return self.update_config();
+                }
+                None => {
+                    log::warn!("failed to find max file size with index {}", index);
+                }
+            },
             Message::UpdateGitProjectStatus => {
-                self.git_project_status = None;
+                // The previous status is shown until loading finishes
+                self.git_project_status_generation =
+                    self.git_project_status_generation.wrapping_add(1);
+                let generation = self.git_project_status_generation;
                 let projects = self.projects.clone();
                 return Task::perform(
                     async move {
                         let mut project_status = Vec::new();
+                        let mut errors = Vec::new();
+                        // Projects in the same repository are only shown once
+                        let mut repo_paths = HashSet::new();
                         for (project_name, project_path) in projects.iter() {
-                            //TODO: send errors to UI
-                            match GitRepository::new(project_path) {
-                                Ok(repo) => match repo.status().await {
-                                    Ok(status) => {
-                                        if !status.is_empty() {
-                                            project_status.push((
-                                                project_name.clone(),
-                                                project_path.clone(),
-                                                status,
-                                            ));
-                                        }
-                                    }
-                                    Err(err) => {
-                                        log::error!(
-                                            "failed to get status of {:?}: {}",
-                                            project_path,
-                                            err
-                                        );
-                                    }
-                                },
+                            // Projects that are not in a repository are not shown
+                            let repo = match GitRepository::new(project_path).await {
+                                Ok(ok) => ok,
                                 Err(err) => {
-                                    log::error!(
+                                    log::info!(
                                         "failed to open repository {:?}: {}",
                                         project_path,
                                         err
                                     );
+                                    continue;
+                                }
+                            };
+
+                            // Submodules and nested repositories follow the repository they are in
+                            let mut queue = VecDeque::new();
+                            queue.push_back((project_name.clone(), repo, GitProjectKind::Project));
+                            while let Some((name, repo, kind)) = queue.pop_front() {
+                                if !repo_paths.insert(repo.path().to_path_buf()) {
+                                    continue;
+                                }
+                                let project = match git_project(name, &repo, kind).await {
+                                    Ok(ok) => ok,
+                                    Err(err) => {
+                                        log::error!("{}", err);
+                                        errors.push(err);
+                                        continue;
+                                    }
+                                };
+                                for (nested_path, nested_kind) in repo.nested(&project.status).await
+                                {
+                                    let nested_repo = match GitRepository::new(&nested_path).await {
+                                        Ok(ok) => ok,
+                                        Err(err) => {
+                                            log::error!(
+                                                "failed to open repository {:?}: {}",
+                                                nested_path,
+                                                err
+                                            );
+                                            continue;
+                                        }
+                                    };
+                                    let nested_name = match nested_path.strip_prefix(&project.path)
+                                    {
+                                        Ok(relative_path) => {
+                                            format!("{}/{}", project.name, relative_path.display())
+                                        }
+                                        Err(_) => format!("{}", nested_path.display()),
+                                    };
+                                    queue.push_back((nested_name, nested_repo, nested_kind));
                                 }
+                                project_status.push(project);
                             }
                         }
-                        action::app(Message::GitProjectStatus(project_status))
+                        let tree_status = ProjectGitStatuses::new(&project_status);
+                        action::app(Message::GitProjectStatus(
+                            generation,
+                            project_status,
+                            tree_status,
+                            errors,
+                        ))
                     },
                     |x| x,
                 );
@@ -3008,21 +9331,67 @@ impl Application for App {
                 Message::ToggleContextPage(ContextPage::DocumentStatistics),
             )
             .title(fl!("document-statistics")),
+            ContextPage::GitFileHistory => context_drawer::context_drawer(
+                self.git_file_history(),
+                Message::ToggleContextPage(ContextPage::GitFileHistory),
+            )
+            .title(fl!("git-file-history")),
             ContextPage::GitManagement => context_drawer::context_drawer(
                 self.git_management(),
                 Message::ToggleContextPage(ContextPage::GitManagement),
             )
             .title(fl!("git-management")),
+            ContextPage::LocalHistory => context_drawer::context_drawer(
+                self.local_history(),
+                Message::ToggleContextPage(ContextPage::LocalHistory),
+            )
+            .title(fl!("local-history")),
+            ContextPage::Navigation => context_drawer::context_drawer(
+                self.navigation(),
+                Message::ToggleContextPage(ContextPage::Navigation),
+            )
+            .title(
+                match self.navigation_opt.as_ref().map(|results| results.request) {
+                    Some(NavRequest::References) => fl!("references"),
+                    Some(NavRequest::TypeDefinition) => fl!("type-definitions"),
+                    _ => fl!("definitions"),
+                },
+            ),
+            ContextPage::Problems => context_drawer::context_drawer(
+                self.problems(),
+                Message::ToggleContextPage(ContextPage::Problems),
+            )
+            .title(fl!("problems")),
             ContextPage::ProjectSearch => context_drawer::context_drawer(
                 self.project_search(),
                 Message::ToggleContextPage(ContextPage::ProjectSearch),
             )
             .title(fl!("project-search")),
+            ContextPage::Rename => context_drawer::context_drawer(
+                self.rename(),
+                Message::ToggleContextPage(ContextPage::Rename),
+            )
+            .title(fl!("rename-symbol")),
             ContextPage::Settings => context_drawer::context_drawer(
                 self.settings(),
                 Message::ToggleContextPage(ContextPage::Settings),
             )
             .title(fl!("settings")),
+            ContextPage::Symbols => context_drawer::context_drawer(
+                self.symbol_search(),
+                Message::ToggleContextPage(ContextPage::Symbols),
+            )
+            .title(
+                if self
+                    .symbol_search_opt
+                    .as_ref()
+                    .is_some_and(|search| search.workspace)
+                {
+                    fl!("workspace-symbols")
+                } else {
+                    fl!("document-symbols")
+                },
+            ),
         })
     }
 
@@ -3069,6 +9438,22 @@ impl Application for App {
                 ),
         );
 
+        if let Some(err) = &self.git_error_opt {
+            tab_column =
+                tab_column.push(self.error_bar(fl!("git-error"), err, Message::GitErrorClose));
+        }
+        if let Some(err) = &self.formatter_error_opt {
+            tab_column = tab_column.push(self.error_bar(
+                fl!("formatter-error"),
+                err,
+                Message::FormatterErrorClose,
+            ));
+        }
+        if let Some(err) = &self.lsp_error_opt {
+            tab_column =
+                tab_column.push(self.error_bar(fl!("lsp-error"), err, Message::LspErrorClose));
+        }
+
         let tab_id = self.tab_model.active();
         match self.tab_model.data::<Tab>(tab_id) {
             Some(Tab::Editor(tab)) => {
@@ -3080,20 +9465,109 @@ impl Application for App {
                     .has_context_menu(tab.context_menu.is_some())
                     .on_context_menu(move |position_opt| {
                         Message::TabContextMenu(tab_id, position_opt)
-                    });
+                    })
+                    .on_ctrl_click(Message::Navigate(NavRequest::Definition));
                 if self.config.highlight_current_line {
                     text_box = text_box.highlight_current_line();
                 }
+                if tab.read_only() {
+                    text_box = text_box.read_only();
+                }
                 if self.config.line_numbers {
                     text_box = text_box.line_numbers();
                 }
+                if let Some(diff) = &tab.git_diff_opt {
+                    text_box = text_box
+                        .git_hunks(&diff.hunks)
+                        .on_git_marker(move |line_i| Message::GitMarker(tab_id, line_i));
+                }
+                if !tab.git_conflicts.is_empty() {
+                    text_box = text_box.git_conflicts(&tab.git_conflicts);
+                }
+                if tab.lsp_document_opt.is_some() {
+                    text_box = text_box
+                        .lsp_diagnostics(&tab.lsp_diagnostics)
+                        .on_completion(move |trigger, point| {
+                            Message::LspCompletionTrigger(tab_id, trigger, point)
+                        })
+                        .on_code_actions(move |line_opt, point| {
+                            Message::LspCodeActionsOpen(tab_id, line_opt, point)
+                        });
+                }
+                let completion_opt = self
+                    .lsp_completion_opt
+                    .as_ref()
+                    .filter(|completion| completion.entity == tab_id);
+                let signature_help_opt = self
+                    .lsp_signature_help_opt
+                    .as_ref()
+                    .filter(|signature_help| signature_help.entity == tab_id);
+                let code_actions_opt = self
+                    .lsp_code_actions_opt
+                    .as_ref()
+                    .filter(|code_actions| code_actions.entity == tab_id);
+                if code_actions_opt.is_some() {
+                    text_box = text_box
+                        .on_completion_key(move |key| Message::LspCodeActionKey(tab_id, key));
+                } else if completion_opt.is_some() {
+                    text_box = text_box
+                        .on_completion_key(move |key| Message::LspCompletionKey(tab_id, key));
+                }
+                if tab.snippet_opt.is_some() {
+                    text_box = text_box.on_snippet_key(move |key| Message::SnippetKey(tab_id, key));
+                }
+                if self.config.git_blame {
+                    text_box = text_box
+                        .git_blame(&tab.git_blame_labels)
+                        .on_git_blame(move |line_i| Message::GitBlameOpenCommit(tab_id, line_i))
+                        .on_git_blame_hover(move |hover_opt| {
+                            Message::GitBlameHover(tab_id, hover_opt)
+                        });
+                }
+                if tab.git_conflicted {
+                    tab_column = tab_column.push(self.git_conflict_bar(tab_id, tab));
+                }
                 let mut popover = widget::popover(text_box);
                 if let Some(point) = tab.context_menu {
                     popover = popover
                         .popup(menu::context_menu(&self.key_binds, tab_id))
                         .position(widget::popover::Position::Point(point));
+                } else if let Some((_, line_i, point)) = self
+                    .git_blame_hover_opt
+                    .filter(|(entity, _, _)| *entity == tab_id)
+                {
+                    if let Some(commit) = tab.git_blame_opt.as_ref().and_then(|blame| {
+                        blame
+                            .lines
+                            .get(line_i)
+                            .and_then(|commit_i| blame.commits.get(*commit_i))
+                    }) {
+                        popover = popover
+                            .popup(self.git_blame_popup(commit))
+                            .position(widget::popover::Position::Point(point));
+                    }
+                } else if let Some(code_actions) = code_actions_opt {
+                    popover = popover
+                        .popup(self.lsp_code_actions_popup(code_actions))
+                        .position(widget::popover::Position::Point(code_actions.point));
+                } else if let Some(point) = completion_opt
+                    .map(|completion| completion.point)
+                    .or(signature_help_opt.map(|signature_help| signature_help.point))
+                {
+                    popover = popover
+                        .popup(self.lsp_popup(completion_opt, signature_help_opt))
+                        .position(widget::popover::Position::Point(point));
                 }
                 tab_column = tab_column.push(popover);
+                if let Some(hunk_i) = tab.git_hunk_opt {
+                    if let Some(hunk) = tab
+                        .git_diff_opt
+                        .as_ref()
+                        .and_then(|diff| diff.hunks.get(hunk_i))
+                    {
+                        tab_column = tab_column.push(self.git_hunk(tab_id, hunk_i, hunk));
+                    }
+                }
                 if self.config.vim_bindings {
                     let status = {
                         let editor = tab.editor.lock().unwrap();
@@ -3129,57 +9603,168 @@ impl Application for App {
                 }
             }
             Some(Tab::GitDiff(tab)) => {
-                let mut diff_widget = widget::column::with_capacity(tab.diff.hunks.len());
-                for hunk in tab.diff.hunks.iter() {
-                    let mut hunk_widget = widget::column::with_capacity(hunk.lines.len());
-                    for line in hunk.lines.iter() {
-                        let line_widget = match line {
-                            GitDiffLine::Context {
-                                old_line,
-                                new_line,
-                                text,
-                            } => widget::container(widget::text::monotext(format!(
-                                "{:4} {:4}   {}",
-                                old_line, new_line, text
-                            ))),
-                            GitDiffLine::Added { new_line, text } => {
-                                widget::container(widget::text::monotext(format!(
-                                    "{:4} {:4} + {}",
-                                    "", new_line, text
-                                )))
-                                .style(|_theme| {
-                                    //TODO: theme this color
-                                    widget::container::Style {
-                                        background: Some(Background::Color(Color::from_rgb8(
-                                            0x00, 0x40, 0x00,
-                                        ))),
-                                        ..Default::default()
-                                    }
-                                })
-                            }
-                            GitDiffLine::Deleted { old_line, text } => {
-                                widget::container(widget::text::monotext(format!(
-                                    "{:4} {:4} - {}",
-                                    old_line, "", text
+                let side_by_side = self.config.git_diff_side_by_side;
+                let hunk_count = tab.diff.hunks.len();
+                let hunk_position = if hunk_count > 0 {
+                    fl!(
+                        "git-diff-hunk-position",
+                        position = tab.hunk_i + 1,
+                        count = hunk_count
+                    )
+                } else {
+                    fl!("git-no-changes")
+                };
+                let toolbar = widget::row::with_capacity(6)
+                    .push(widget::tooltip(
+                        button::custom(icon_cache_get("go-up-symbolic", 16))
+                            .on_press_maybe(
+                                tab.hunk_i
+                                    .checked_sub(1)
+                                    .map(|hunk_i| Message::GitDiffHunk(tab_id, hunk_i)),
+                            )
+                            .padding(space_xxs)
+                            .class(style::Button::Icon),
+                        widget::text::body(fl!("git-diff-previous-hunk")),
+                        widget::tooltip::Position::Bottom,
+                    ))
+                    .push(widget::tooltip(
+                        button::custom(icon_cache_get("go-down-symbolic", 16))
+                            .on_press_maybe(
+                                Some(tab.hunk_i + 1)
+                                    .filter(|hunk_i| *hunk_i < hunk_count)
+                                    .map(|hunk_i| Message::GitDiffHunk(tab_id, hunk_i)),
+                            )
+                            .padding(space_xxs)
+                            .class(style::Button::Icon),
+                        widget::text::body(fl!("git-diff-next-hunk")),
+                        widget::tooltip::Position::Bottom,
+                    ))
+                    .push(widget::text::body(hunk_position))
+                    .push(widget::space::horizontal())
+                    .push(
+                        widget::button::text(fl!("git-diff-more-context"))
+                            .on_press(Message::GitDiffContext(tab_id)),
+                    )
+                    .push(
+                        widget::checkbox(side_by_side)
+                            .label(fl!("git-diff-side-by-side"))
+                            .on_toggle(Message::GitDiffSideBySide),
+                    )
+                    .align_y(Alignment::Center)
+                    .spacing(space_xxs)
+                    .padding(space_xxs);
+                tab_column = tab_column.push(toolbar);
+
+                let mut diff_widget = widget::column::with_capacity(tab.diff.hunks.len() * 2);
+                let mut old_end = 1;
+                for (hunk_i, hunk) in tab.diff.hunks.iter().enumerate() {
+                    // Unchanged lines hidden before this hunk, clicking them shows more context
+                    let hidden = hunk.old_range.start.saturating_sub(old_end);
+                    old_end = hunk.old_range.start + hunk.old_range.count;
+                    if hidden > 0 {
+                        diff_widget = diff_widget.push(
+                            button::custom(
+                                widget::container(widget::text::caption(fl!(
+                                    "git-diff-unchanged-lines",
+                                    count = hidden
                                 )))
-                                .style(|_theme| {
-                                    //TODO: theme this color
-                                    widget::container::Style {
-                                        background: Some(Background::Color(Color::from_rgb8(
-                                            0x40, 0x00, 0x00,
-                                        ))),
-                                        ..Default::default()
-                                    }
-                                })
-                            }
-                        };
-                        hunk_widget = hunk_widget.push(line_widget.width(Length::Fill));
+                                .center_x(Length::Fill),
+                            )
+                            .on_press(Message::GitDiffContext(tab_id))
+                            .padding(space_xxs)
+                            .class(theme::Button::Text)
+                            .width(Length::Fill),
+                        );
+                    }
+
+                    let selected = matches!(
+                        &tab.selection_opt,
+                        Some((selection_hunk_i, _)) if *selection_hunk_i == hunk_i
+                    );
+
+                    let mut hunk_widget = widget::column::with_capacity(hunk.lines.len() + 1);
+                    let mut header = widget::row::with_capacity(4)
+                        .push(widget::text::monotext(format!(
+                            "@@ -{},{} +{},{} @@",
+                            hunk.old_range.start,
+                            hunk.old_range.count,
+                            hunk.new_range.start,
+                            hunk.new_range.count
+                        )))
+                        .push(widget::space::horizontal())
+                        .align_y(Alignment::Center)
+                        .spacing(space_xxs);
+                    let (stage_label, discard_label) = if selected {
+                        (
+                            if tab.diff.staged {
+                                fl!("unstage-lines")
+                            } else {
+                                fl!("stage-lines")
+                            },
+                            fl!("discard-lines"),
+                        )
+                    } else {
+                        (
+                            if tab.diff.staged {
+                                fl!("unstage-hunk")
+                            } else {
+                                fl!("stage-hunk")
+                            },
+                            fl!("discard-hunk"),
+                        )
+                    };
+                    if tab.commit_opt.is_some() || tab.compare_opt.is_some() {
+                        // Committed and compared changes cannot be staged or discarded
+                    } else if tab.diff.staged {
+                        header = header.push(widget::button::standard(stage_label).on_press(
+                            Message::GitDiffApply(tab_id, hunk_i, GitHunkAction::Unstage),
+                        ));
+                    } else {
+                        header = header
+                            .push(
+                                widget::button::destructive(discard_label)
+                                    .on_press(Message::GitDiffDiscard(tab_id, hunk_i)),
+                            )
+                            .push(widget::button::standard(stage_label).on_press(
+                                Message::GitDiffApply(tab_id, hunk_i, GitHunkAction::Stage),
+                            ));
+                    }
+                    hunk_widget = hunk_widget.push(header);
+
+                    if side_by_side {
+                        for (old_i_opt, new_i_opt) in hunk.rows() {
+                            let cell = |line_i_opt: Option<usize>, side| match line_i_opt {
+                                Some(line_i) => {
+                                    self.git_diff_line(tab_id, tab, hunk_i, line_i, Some(side))
+                                }
+                                None => widget::space::horizontal().into(),
+                            };
+                            hunk_widget = hunk_widget.push(
+                                widget::row::with_capacity(2)
+                                    .push(
+                                        widget::container(cell(old_i_opt, false))
+                                            .width(Length::FillPortion(1)),
+                                    )
+                                    .push(
+                                        widget::container(cell(new_i_opt, true))
+                                            .width(Length::FillPortion(1)),
+                                    ),
+                            );
+                        }
+                    } else {
+                        for line_i in 0..hunk.lines.len() {
+                            hunk_widget = hunk_widget
+                                .push(self.git_diff_line(tab_id, tab, hunk_i, line_i, None));
+                        }
                     }
                     diff_widget = diff_widget.push(hunk_widget);
                 }
-                tab_column = tab_column.push(widget::scrollable(
-                    widget::layer_container(diff_widget).layer(cosmic_theme::Layer::Primary),
-                ));
+                tab_column = tab_column.push(
+                    widget::scrollable(
+                        widget::layer_container(diff_widget).layer(cosmic_theme::Layer::Primary),
+                    )
+                    .id(self.git_diff_scroll_id.clone()),
+                );
             }
             None => {}
         }
@@ -3293,6 +9878,10 @@ impl Application for App {
                         .label(fl!("use-regex"))
                         .on_toggle(Message::FindUseRegex)
                         .into(),
+                    widget::checkbox(self.config.find_whole_word)
+                        .label(fl!("whole-word"))
+                        .on_toggle(Message::FindWholeWord)
+                        .into(),
                     widget::checkbox(self.config.find_wrap_around)
                         .label(fl!("wrap-around"))
                         .on_toggle(Message::FindWrapAround)
@@ -157,6 +157,18 @@ pub fn context_menu<'a>(
             menu_item(fl!("copy"), Action::Copy).into(),
             menu_item(fl!("paste"), Action::Paste).into(),
             menu_item(fl!("select-all"), Action::SelectAll).into(),
+            divider::horizontal::light().into(),
+            menu_item(fl!("go-to-definition"), Action::GoToDefinition).into(),
+            menu_item(fl!("go-to-type-definition"), Action::GoToTypeDefinition).into(),
+            menu_item(fl!("find-references"), Action::FindReferences).into(),
+            menu_item(fl!("menu-rename"), Action::Rename).into(),
+            menu_item(fl!("format-document"), Action::FormatDocument).into(),
+            menu_item(fl!("format-selection"), Action::FormatSelection).into(),
+            divider::horizontal::light().into(),
+            menu_item(fl!("compare-select"), Action::CompareSelect).into(),
+            menu_item(fl!("compare-with-selected"), Action::CompareWithSelected).into(),
+            menu_item(fl!("compare-with-saved"), Action::CompareWithSaved).into(),
+            menu_item(fl!("compare-with-clipboard"), Action::CompareWithClipboard).into(),
         ]),
     )
     .padding(1)
@@ -254,6 +266,31 @@ pub fn menu_bar<'a>(
                         MenuItem::Button(fl!("save-as"), None, Action::SaveAsDialog),
                         MenuItem::Divider,
                         MenuItem::Button(fl!("revert-all-changes"), None, Action::RevertAllChanges),
+                        MenuItem::Folder(
+                            fl!("compare"),
+                            vec![
+                                MenuItem::Button(
+                                    fl!("compare-select"),
+                                    None,
+                                    Action::CompareSelect,
+                                ),
+                                MenuItem::Button(
+                                    fl!("compare-with-selected"),
+                                    None,
+                                    Action::CompareWithSelected,
+                                ),
+                                MenuItem::Button(
+                                    fl!("compare-with-saved"),
+                                    None,
+                                    Action::CompareWithSaved,
+                                ),
+                                MenuItem::Button(
+                                    fl!("compare-with-clipboard"),
+                                    None,
+                                    Action::CompareWithClipboard,
+                                ),
+                            ],
+                        ),
                         MenuItem::Divider,
                         MenuItem::Button(
                             fl!("menu-document-statistics"),
@@ -267,6 +304,17 @@ pub fn menu_bar<'a>(
                             None,
                             Action::ToggleGitManagement,
                         ),
+                        MenuItem::Button(
+                            fl!("menu-git-file-history"),
+                            None,
+                            Action::ToggleGitFileHistory,
+                        ),
+                        MenuItem::Button(
+                            fl!("menu-local-history"),
+                            None,
+                            Action::ToggleLocalHistory,
+                        ),
+                        MenuItem::Button(fl!("menu-problems"), None, Action::ToggleProblems),
                         //TODO MenuItem::Button(fl!("print"), Action::Todo),
                         MenuItem::Divider,
                         MenuItem::Button(fl!("quit"), None, Action::Quit),
@@ -286,6 +334,28 @@ pub fn menu_bar<'a>(
                         MenuItem::Button(fl!("find"), None, Action::Find),
                         MenuItem::Button(fl!("replace"), None, Action::FindAndReplace),
                         MenuItem::Button(fl!("find-in-project"), None, Action::ToggleProjectSearch),
+                        MenuItem::Divider,
+                        MenuItem::Button(fl!("go-to-definition"), None, Action::GoToDefinition),
+                        MenuItem::Button(
+                            fl!("go-to-type-definition"),
+                            None,
+                            Action::GoToTypeDefinition,
+                        ),
+                        MenuItem::Button(fl!("find-references"), None, Action::FindReferences),
+                        MenuItem::Button(
+                            fl!("menu-document-symbols"),
+                            None,
+                            Action::DocumentSymbols,
+                        ),
+                        MenuItem::Button(
+                            fl!("menu-workspace-symbols"),
+                            None,
+                            Action::WorkspaceSymbols,
+                        ),
+                        MenuItem::Divider,
+                        MenuItem::Button(fl!("menu-rename"), None, Action::Rename),
+                        MenuItem::Button(fl!("format-document"), None, Action::FormatDocument),
+                        MenuItem::Button(fl!("format-selection"), None, Action::FormatSelection),
                         /*TODO: implement spell-check
                         MenuItem::Divider,
                         MenuItem::Button(fl!("spell-check"), None, Action::Todo),
@@ -335,6 +405,12 @@ pub fn menu_bar<'a>(
                             config.line_numbers,
                             Action::ToggleLineNumbers,
                         ),
+                        MenuItem::CheckBox(
+                            fl!("show-git-blame"),
+                            None,
+                            config.git_blame,
+                            Action::ToggleGitBlame,
+                        ),
                         MenuItem::CheckBox(
                             fl!("highlight-current-line"),
                             None,
@@ -0,0 +1,551 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Targets of code navigation: definitions, references and symbols. They come from the language
+// server of a file when it has one, and from ctags otherwise, either read from a `tags` file in the
+// project or made by running Universal Ctags. Tags of projects are kept until files in them change.
+
+use lsp_types::{
+    DocumentSymbol, DocumentSymbolResponse, Location, OneOf, Position, SymbolKind,
+    WorkspaceSymbolResponse,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{process::Command, sync::OnceCell};
+
+use crate::{completion::fuzzy_score, lsp::uri_path};
+
+/// Time ctags may take to index a project
+const CTAGS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Definitions, references or symbols
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NavRequest {
+    Definition,
+    TypeDefinition,
+    References,
+}
+
+/// A place in a file to jump to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NavItem {
+    pub path: PathBuf,
+    /// Columns are counted in the position encoding of the language server the item came from
+    pub position: Position,
+    /// Name of a symbol, or the text of the line for references
+    pub text: String,
+    /// Kind and container of a symbol
+    pub detail: String,
+}
+
+impl NavItem {
+    fn new(path: PathBuf, position: Position, text: String, detail: String) -> Self {
+        Self {
+            path,
+            position,
+            text,
+            detail,
+        }
+    }
+}
+
+/// Name of a symbol kind, like `function` or `enum member`
+fn kind_name(kind: SymbolKind) -> String {
+    format!("{:?}", kind).to_lowercase().replace('_', " ")
+}
+
+/// Kind of a symbol with the symbol it is in
+fn symbol_detail(kind: &str, container_opt: Option<&str>) -> String {
+    match container_opt {
+        Some(container) if !container.is_empty() => format!("{} in {}", kind, container),
+        _ => kind.to_string(),
+    }
+}
+
+/// Items of locations sorted by file and position, showing the text of their lines
+pub fn location_items(locations: Vec<Location>) -> Vec<NavItem> {
+    let mut items: Vec<NavItem> = locations
+        .into_iter()
+        .filter_map(|location| {
+            Some(NavItem::new(
+                uri_path(&location.uri)?,
+                location.range.start,
+                String::new(),
+                String::new(),
+            ))
+        })
+        .collect();
+    items.sort_by(|a, b| {
+        (&a.path, a.position.line, a.position.character).cmp(&(
+            &b.path,
+            b.position.line,
+            b.position.character,
+        ))
+    });
+    items.dedup();
+
+    let mut texts = HashMap::new();
+    for item in items.iter_mut() {
+        let text: &String = texts.entry(item.path.clone()).or_insert_with(|| {
+            fs::read_to_string(&item.path).unwrap_or_else(|err| {
+                log::warn!("failed to read {:?}: {}", item.path, err);
+                String::new()
+            })
+        });
+        item.text = text
+            .lines()
+            .nth(item.position.line as usize)
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+    }
+    items
+}
+
+/// Items of the symbols of a document, with nested symbols after the symbols they are in
+pub fn document_symbol_items(path: &Path, response: DocumentSymbolResponse) -> Vec<NavItem> {
+    fn push_nested(
+        items: &mut Vec<NavItem>,
+        path: &Path,
+        symbols: Vec<DocumentSymbol>,
+        container_opt: Option<&str>,
+    ) {
+        for symbol in symbols {
+            items.push(NavItem::new(
+                path.to_path_buf(),
+                symbol.selection_range.start,
+                symbol.name.clone(),
+                symbol_detail(&kind_name(symbol.kind), container_opt),
+            ));
+            if let Some(children) = symbol.children {
+                push_nested(items, path, children, Some(&symbol.name));
+            }
+        }
+    }
+
+    let mut items = Vec::new();
+    match response {
+        DocumentSymbolResponse::Flat(symbols) => {
+            for symbol in symbols {
+                items.push(NavItem::new(
+                    path.to_path_buf(),
+                    symbol.location.range.start,
+                    symbol.name,
+                    symbol_detail(&kind_name(symbol.kind), symbol.container_name.as_deref()),
+                ));
+            }
+        }
+        DocumentSymbolResponse::Nested(symbols) => push_nested(&mut items, path, symbols, None),
+    }
+    items
+}
+
+/// Items of the symbols found in a workspace
+pub fn workspace_symbol_items(response: WorkspaceSymbolResponse) -> Vec<NavItem> {
+    match response {
+        WorkspaceSymbolResponse::Flat(symbols) => symbols
+            .into_iter()
+            .filter_map(|symbol| {
+                Some(NavItem::new(
+                    uri_path(&symbol.location.uri)?,
+                    symbol.location.range.start,
+                    symbol.name,
+                    symbol_detail(&kind_name(symbol.kind), symbol.container_name.as_deref()),
+                ))
+            })
+            .collect(),
+        WorkspaceSymbolResponse::Nested(symbols) => symbols
+            .into_iter()
+            .filter_map(|symbol| {
+                // Symbols without a range are at the start of their file
+                let (uri, position) = match symbol.location {
+                    OneOf::Left(location) => (location.uri, location.range.start),
+                    OneOf::Right(location) => (location.uri, Position::default()),
+                };
+                Some(NavItem::new(
+                    uri_path(&uri)?,
+                    position,
+                    symbol.name,
+                    symbol_detail(&kind_name(symbol.kind), symbol.container_name.as_deref()),
+                ))
+            })
+            .collect(),
+    }
+}
+
+/// Indexes of the items matching `query` fuzzily, best first. All items match an empty query
+/// and keep their order.
+pub fn filter_items(items: &[NavItem], query: &str) -> Vec<usize> {
+    let mut matches: Vec<(i64, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| Some((fuzzy_score(query, &item.text)?, i)))
+        .collect();
+    if !query.is_empty() {
+        matches.sort_by_key(|(score, i)| (-score, *i));
+    }
+    matches.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Definition found by ctags
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub path: PathBuf,
+    /// Line index, if the tag has a line number
+    pub line_opt: Option<u32>,
+    /// Search pattern for the line, if the tag has no line number
+    pub pattern_opt: Option<String>,
+    pub kind: String,
+    /// Symbol the definition is in
+    pub scope_opt: Option<String>,
+}
+
+impl Tag {
+    /// Kinds of definitions that are types
+    pub fn is_type(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "class" | "enum" | "interface" | "struct" | "trait" | "type" | "typedef" | "union"
+        )
+    }
+
+    /// Line index of the tag in `text`, the text of its file
+    fn line(&self, text_opt: Option<&str>) -> u32 {
+        if let Some(line) = self.line_opt {
+            return line;
+        }
+        let (Some(pattern), Some(text)) = (&self.pattern_opt, text_opt) else {
+            return 0;
+        };
+        // Patterns are anchored at the start of lines, and at the end unless they were cut off
+        let (pattern, whole_line) = match pattern.strip_suffix('$') {
+            Some(pattern) => (pattern, true),
+            None => (pattern.as_str(), false),
+        };
+        text.lines()
+            .position(|line| {
+                if whole_line {
+                    line == pattern
+                } else {
+                    line.starts_with(pattern)
+                }
+            })
+            .map_or(0, |line_i| line_i as u32)
+    }
+
+    fn item(&self, text_opt: Option<&str>) -> NavItem {
+        NavItem::new(
+            self.path.clone(),
+            Position::new(self.line(text_opt), 0),
+            self.name.clone(),
+            symbol_detail(&self.kind, self.scope_opt.as_deref()),
+        )
+    }
+}
+
+/// Items of tags, reading their files to find the lines of tags without line numbers
+pub fn tag_items<'a>(tags: impl IntoIterator<Item = &'a Tag>) -> Vec<NavItem> {
+    let mut texts: HashMap<&Path, Option<String>> = HashMap::new();
+    tags.into_iter()
+        .map(|tag| {
+            let text_opt = match tag.line_opt {
+                Some(_) => None,
+                None => texts
+                    .entry(&tag.path)
+                    .or_insert_with(|| fs::read_to_string(&tag.path).ok())
+                    .as_deref(),
+            };
+            tag.item(text_opt)
+        })
+        .collect()
+}
+
+/// Unescape a tag search pattern, without its delimiters
+fn unescape_pattern(pattern: &str) -> String {
+    let mut unescaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Parse a line of a tags file, with paths relative to `root`
+fn parse_tag(line: &str, root: &Path) -> Option<Tag> {
+    let mut parts = line.splitn(3, '\t');
+    let name = parts.next()?;
+    let file = parts.next()?;
+    let rest = parts.next()?;
+
+    // The address is a line number or a search pattern, followed by `;"` and fields
+    let (mut line_opt, mut pattern_opt) = (None, None);
+    let fields = match rest.chars().next()? {
+        delimiter @ ('/' | '?') => {
+            let mut escaped = false;
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| {
+                    let found = !escaped && *c == delimiter;
+                    escaped = !escaped && *c == '\\';
+                    found
+                })
+                .map(|(i, _)| i)?;
+            let pattern = unescape_pattern(&rest[1..end]);
+            pattern_opt = Some(pattern.strip_prefix('^').unwrap_or(&pattern).to_string());
+            &rest[end + 1..]
+        }
+        _ => {
+            let end = rest.find([';', '\t']).unwrap_or(rest.len());
+            line_opt = Some(rest[..end].parse::<u32>().ok()?.saturating_sub(1));
+            &rest[end..]
+        }
+    };
+
+    let mut kind = String::new();
+    let mut scope_opt = None;
+    for field in fields.trim_start_matches(";\"").split('\t') {
+        match field.split_once(':') {
+            Some(("line", value)) => {
+                if let Ok(line) = value.parse::<u32>() {
+                    line_opt = Some(line.saturating_sub(1));
+                }
+            }
+            Some(("kind", value)) => kind = value.to_string(),
+            Some((
+                "access" | "end" | "file" | "language" | "roles" | "signature" | "typeref",
+                _,
+            )) => {}
+            // Other fields name the scope, like `class:Name`
+            Some((_, value)) => scope_opt = Some(value.to_string()),
+            None if !field.is_empty() && kind.is_empty() => kind = field.to_string(),
+            None => {}
+        }
+    }
+
+    let path = Path::new(file);
+    Some(Tag {
+        name: name.to_string(),
+        path: root.join(path.strip_prefix("./").unwrap_or(path)),
+        line_opt,
+        pattern_opt,
+        kind,
+        scope_opt,
+    })
+}
+
+/// Parse a tags file, with paths relative to `root`
+pub fn parse_tags(data: &str, root: &Path) -> Vec<Tag> {
+    data.lines()
+        .filter(|line| !line.starts_with("!_"))
+        .filter_map(|line| parse_tag(line, root))
+        .collect()
+}
+
+/// Tags of a project, or only of the file at `path_opt`. A `tags` or `.tags` file in the project is
+/// used if there is one, otherwise ctags is run.
+pub async fn ctags(root: PathBuf, path_opt: Option<PathBuf>) -> Result<Vec<Tag>, String> {
+    for name in ["tags", ".tags"] {
+        let tags_path = root.join(name);
+        if let Ok(data) = fs::read_to_string(&tags_path) {
+            let mut tags = parse_tags(&data, &root);
+            if let Some(path) = &path_opt {
+                tags.retain(|tag| &tag.path == path);
+            }
+            return Ok(tags);
+        }
+    }
+
+    let mut command = Command::new("ctags");
+    command
+        .arg("--fields=+nKS")
+        .arg("-f")
+        .arg("-")
+        .current_dir(&root)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    match &path_opt {
+        Some(path) => command.arg(path),
+        None => command.arg("-R"),
+    };
+    let output = match tokio::time::timeout(CTAGS_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(err)) => return Err(format!("failed to run ctags: {}", err)),
+        Err(_) => return Err("ctags timed out".to_string()),
+    };
+    if !output.status.success() {
+        return Err(format!(
+            "ctags failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_tags(&String::from_utf8_lossy(&output.stdout), &root))
+}
+
+/// Tags of projects, generated once per project and dropped when files in it change
+#[derive(Clone, Debug, Default)]
+pub struct TagsCache {
+    projects: Arc<Mutex<HashMap<PathBuf, Arc<OnceCell<Arc<Vec<Tag>>>>>>>,
+}
+
+impl TagsCache {
+    /// Tags of the project at `root`, generated if they are not kept yet. Requests while they are
+    /// generated wait for the same ctags run.
+    async fn project(&self, root: PathBuf) -> Result<Arc<Vec<Tag>>, String> {
+        let cell = self
+            .projects
+            .lock()
+            .unwrap()
+            .entry(root.clone())
+            .or_default()
+            .clone();
+        cell.get_or_try_init(|| async { ctags(root, None).await.map(Arc::new) })
+            .await
+            .cloned()
+    }
+
+    /// Drop the tags of projects containing `path`
+    pub fn invalidate(&self, path: &Path) {
+        self.projects
+            .lock()
+            .unwrap()
+            .retain(|root, _| !path.starts_with(root));
+    }
+}
+
+/// Tags of the folder at `root`, or only of the file at `path_opt`. With a cache, `root` is a
+/// project whose tags are taken from the cache.
+pub async fn load_tags(
+    cache_opt: Option<TagsCache>,
+    root: PathBuf,
+    path_opt: Option<PathBuf>,
+) -> Result<Arc<Vec<Tag>>, String> {
+    let Some(cache) = cache_opt else {
+        return ctags(root, path_opt).await.map(Arc::new);
+    };
+    let tags = cache.project(root).await?;
+    Ok(match path_opt {
+        Some(path) => Arc::new(
+            tags.iter()
+                .filter(|tag| tag.path == path)
+                .cloned()
+                .collect(),
+        ),
+        None => tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::block_on;
+
+    #[test]
+    fn tags() {
+        let root = Path::new("/project");
+        let data = "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+            main\tsrc/main.rs\t/^fn main() {$/;\"\tfunction\tline:3\n\
+            Config\t./src/config.rs\t/^pub struct Config {$/;\"\tstruct\n\
+            new\tsrc/config.rs\t/^    pub fn new(path: \\/\\/) -> Self {$/;\"\tf\timpl:Config\tsignature:()\n\
+            VERSION\tsrc/lib.rs\t12;\"\tc\n";
+        let tags = parse_tags(data, root);
+        assert_eq!(tags.len(), 4);
+        assert_eq!(
+            tags[0],
+            Tag {
+                name: "main".to_string(),
+                path: PathBuf::from("/project/src/main.rs"),
+                line_opt: Some(2),
+                pattern_opt: Some("fn main() {$".to_string()),
+                kind: "function".to_string(),
+                scope_opt: None,
+            }
+        );
+        assert_eq!(tags[1].path, Path::new("/project/src/config.rs"));
+        assert!(tags[1].is_type());
+        assert_eq!(
+            tags[2].pattern_opt.as_deref(),
+            Some("    pub fn new(path: //) -> Self {$")
+        );
+        assert_eq!(tags[2].scope_opt.as_deref(), Some("Config"));
+        assert_eq!(tags[3].line_opt, Some(11));
+
+        let text = "use std;\n\npub struct Config {\n    pub fn new(path: //) -> Self {\n";
+        assert_eq!(tags[1].line(Some(text)), 2);
+        assert_eq!(tags[2].line(Some(text)), 3);
+        let item = tags[2].item(Some(text));
+        assert_eq!(item.position, Position::new(3, 0));
+        assert_eq!(item.detail, "f in Config");
+    }
+
+    #[test]
+    fn tags_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let tags_path = root.join("tags");
+        fs::write(&tags_path, "main\tsrc/main.rs\t1;\"\tf\n").unwrap();
+        block_on(async {
+            let cache = TagsCache::default();
+            let names = |tags: Arc<Vec<Tag>>| -> Vec<String> {
+                tags.iter().map(|tag| tag.name.clone()).collect()
+            };
+            let project_tags = || load_tags(Some(cache.clone()), root.clone(), None);
+            let file_tags =
+                |name: &str| load_tags(Some(cache.clone()), root.clone(), Some(root.join(name)));
+            assert_eq!(names(project_tags().await.unwrap()), ["main"]);
+
+            // Tags are kept until a file in the project changes
+            fs::write(&tags_path, "run\tsrc/lib.rs\t1;\"\tf\n").unwrap();
+            assert_eq!(names(project_tags().await.unwrap()), ["main"]);
+            cache.invalidate(Path::new("/elsewhere/tags"));
+            assert_eq!(names(project_tags().await.unwrap()), ["main"]);
+            cache.invalidate(&tags_path);
+            assert_eq!(names(project_tags().await.unwrap()), ["run"]);
+
+            // Tags of a file are taken from the tags of its project
+            assert_eq!(names(file_tags("src/lib.rs").await.unwrap()), ["run"]);
+            assert!(file_tags("src/main.rs").await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn symbols() {
+        let path = Path::new("/project/src/main.rs");
+        #[allow(deprecated)]
+        let symbol = |name: &str, kind, line, children| DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: lsp_types::Range::new(Position::new(line, 0), Position::new(line + 1, 0)),
+            selection_range: lsp_types::Range::new(Position::new(line, 4), Position::new(line, 8)),
+            children,
+        };
+        let response = DocumentSymbolResponse::Nested(vec![symbol(
+            "App",
+            SymbolKind::STRUCT,
+            1,
+            Some(vec![symbol("new_tab", SymbolKind::METHOD, 2, None)]),
+        )]);
+        let items = document_symbol_items(path, response);
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| (item.text.as_str(), item.detail.as_str(), item.position.line))
+                .collect::<Vec<_>>(),
+            [("App", "struct", 1), ("new_tab", "method in App", 2)]
+        );
+        assert_eq!(filter_items(&items, ""), [0, 1]);
+        assert_eq!(filter_items(&items, "nt"), [1]);
+    }
+}
@@ -4,11 +4,15 @@ use cosmic::widget::icon;
 use cosmic_files::mime_icon::{mime_for_path, mime_icon};
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     fs, io,
     path::{Path, PathBuf},
 };
 
-use crate::icon_cache_get;
+use crate::{
+    git::{GitProject, GitStatus, GitStatusKind},
+    icon_cache_get,
+};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ProjectNode {
@@ -73,6 +77,13 @@ impl ProjectNode {
             Self::File { name, .. } => name,
         }
     }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Folder { path, .. } => path,
+            Self::File { path, .. } => path,
+        }
+    }
 }
 
 impl Ord for ProjectNode {
@@ -100,3 +111,187 @@ impl PartialOrd for ProjectNode {
         Some(self.cmp(other))
     }
 }
+
+/// Git status of a node in the project tree, ordered by priority for the status of folders
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ProjectGitStatus {
+    Ignored,
+    Untracked,
+    Added,
+    Modified,
+    Conflicted,
+}
+
+impl ProjectGitStatus {
+    fn from_status(item: &GitStatus) -> Option<Self> {
+        if item.is_conflicted() {
+            return Some(Self::Conflicted);
+        }
+        if item.unstaged == GitStatusKind::Untracked {
+            return Some(Self::Untracked);
+        }
+        let mut status_opt = None;
+        for kind in [item.staged, item.unstaged] {
+            let status = match kind {
+                GitStatusKind::Unmodified => continue,
+                GitStatusKind::Added | GitStatusKind::Renamed | GitStatusKind::Copied => {
+                    Self::Added
+                }
+                _ => Self::Modified,
+            };
+            status_opt = status_opt.max(Some(status));
+        }
+        status_opt
+    }
+
+    /// Icon shown instead of the icon of the file type
+    pub fn icon_name(self) -> &'static str {
+        match self {
+            Self::Ignored => "action-unavailable-symbolic",
+            Self::Untracked => "document-new-symbolic",
+            Self::Added => "list-add-symbolic",
+            Self::Modified => "document-edit-symbolic",
+            Self::Conflicted => "dialog-warning-symbolic",
+        }
+    }
+}
+
+/// Git status of everything in the project tree
+#[derive(Clone, Debug, Default)]
+pub struct ProjectGitStatuses {
+    /// Changed and ignored files
+    paths: HashMap<PathBuf, ProjectGitStatus>,
+    /// Untracked and ignored folders, git does not list their contents
+    folders: Vec<(PathBuf, ProjectGitStatus)>,
+    /// Folders containing changes, with the highest status of their contents
+    contents: HashMap<PathBuf, ProjectGitStatus>,
+}
+
+impl ProjectGitStatuses {
+    /// Collect the status of repositories, this checks which paths are folders
+    pub fn new(projects: &[GitProject]) -> Self {
+        let mut statuses = Self::default();
+        for project in projects.iter() {
+            for item in project.status.iter() {
+                let Some(status) = ProjectGitStatus::from_status(item) else {
+                    continue;
+                };
+                if status == ProjectGitStatus::Untracked && item.path.is_dir() {
+                    statuses.folders.push((item.path.clone(), status));
+                } else {
+                    let entry = statuses.paths.entry(item.path.clone()).or_insert(status);
+                    *entry = (*entry).max(status);
+                }
+                for path in item.path.ancestors().skip(1) {
+                    if !path.starts_with(&project.path) {
+                        break;
+                    }
+                    let entry = statuses
+                        .contents
+                        .entry(path.to_path_buf())
+                        .or_insert(status);
+                    *entry = (*entry).max(status);
+                }
+            }
+            // Folders with ignored files are not ignored themselves
+            for path in project.ignored.iter() {
+                if path.is_dir() {
+                    statuses
+                        .folders
+                        .push((path.clone(), ProjectGitStatus::Ignored));
+                } else {
+                    statuses
+                        .paths
+                        .entry(path.clone())
+                        .or_insert(ProjectGitStatus::Ignored);
+                }
+            }
+        }
+        statuses
+    }
+
+    /// Status of a file or folder, folders listed by git keep their own status over the status
+    /// of their contents
+    pub fn get(&self, path: &Path) -> Option<ProjectGitStatus> {
+        if let Some(status) = self.paths.get(path) {
+            return Some(*status);
+        }
+        self.folders
+            .iter()
+            .filter(|(folder, _)| path.starts_with(folder))
+            .map(|(_, status)| *status)
+            .max()
+            .or_else(|| self.contents.get(path).copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::GitProjectKind;
+
+    #[test]
+    fn git_statuses() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for folder in ["src/nested", "new", "target"] {
+            fs::create_dir_all(root.join(folder)).unwrap();
+        }
+        let status = |path: &str, staged, unstaged| GitStatus {
+            path: root.join(path),
+            old_path: None,
+            staged,
+            unstaged,
+        };
+        let project = GitProject {
+            name: "project".to_string(),
+            path: root.to_path_buf(),
+            kind: GitProjectKind::Project,
+            status: vec![
+                status(
+                    "src/nested/main.rs",
+                    GitStatusKind::Unmodified,
+                    GitStatusKind::Modified,
+                ),
+                status(
+                    "src/lib.rs",
+                    GitStatusKind::Added,
+                    GitStatusKind::Unmodified,
+                ),
+                status("new", GitStatusKind::Untracked, GitStatusKind::Untracked),
+                // Tracked files in ignored folders can still change
+                status(
+                    "target/tracked.txt",
+                    GitStatusKind::Unmodified,
+                    GitStatusKind::Modified,
+                ),
+            ],
+            commits: Vec::new(),
+            head_opt: None,
+            branches: Vec::new(),
+            stashes: Vec::new(),
+            ignored: vec![root.join("target"), root.join("src/notes.txt")],
+            ahead_behind_opt: None,
+        };
+        let statuses = ProjectGitStatuses::new(&[project]);
+        let get = |path: &str| statuses.get(&root.join(path));
+
+        // Folders have the highest status of their contents
+        assert_eq!(get("src/nested/main.rs"), Some(ProjectGitStatus::Modified));
+        assert_eq!(get("src/nested"), Some(ProjectGitStatus::Modified));
+        assert_eq!(get("src/lib.rs"), Some(ProjectGitStatus::Added));
+        assert_eq!(get("src"), Some(ProjectGitStatus::Modified));
+        assert_eq!(get(""), Some(ProjectGitStatus::Modified));
+        assert_eq!(get("src/other.rs"), None);
+
+        // Contents of untracked folders are not listed by git
+        assert_eq!(get("new"), Some(ProjectGitStatus::Untracked));
+        assert_eq!(get("new/file.txt"), Some(ProjectGitStatus::Untracked));
+
+        // Ignored folders stay ignored with changed files in them
+        assert_eq!(get("target"), Some(ProjectGitStatus::Ignored));
+        assert_eq!(get("target/debug"), Some(ProjectGitStatus::Ignored));
+        assert_eq!(get("target/tracked.txt"), Some(ProjectGitStatus::Modified));
+        assert_eq!(get("src/notes.txt"), Some(ProjectGitStatus::Ignored));
+    }
+}
@@ -1,118 +1,617 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use cosmic::widget::segmented_button::Entity;
 use grep::matcher::{Match, Matcher};
-use grep::regex::RegexMatcher;
-use grep::searcher::{Searcher, sinks::UTF8};
-use std::path::PathBuf;
+use grep::regex::{RegexMatcher, RegexMatcherBuilder};
+use grep::searcher::{Searcher, SearcherBuilder, Sink, SinkContext, SinkError, SinkMatch};
+use ignore::{WalkState, overrides::OverrideBuilder};
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct LineSearchResult {
     pub number: usize,
     pub text: String,
-    pub first: Match,
+    /// Every match on this line, empty if this is a context line
+    pub matches: Vec<Match>,
+}
+
+impl LineSearchResult {
+    pub fn is_context(&self) -> bool {
+        self.matches.is_empty()
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct FileSearchResult {
+    /// Path of the file, or the tab title for tabs that were never saved
     pub path: PathBuf,
+    /// Tab that was searched instead of the file on disk
+    pub tab_opt: Option<Entity>,
+    /// The searched tab has changes that are not saved to disk
+    pub unsaved: bool,
     pub lines: Vec<LineSearchResult>,
 }
 
+impl FileSearchResult {
+    pub fn match_count(&self) -> usize {
+        self.lines.iter().map(|line| line.matches.len()).sum()
+    }
+
+    /// Identifies the file across searches, using the tab for results from buffers because
+    /// untitled tabs can share a title
+    pub fn key(&self) -> FileSearchKey {
+        match self.tab_opt {
+            Some(entity) => FileSearchKey::Tab(entity),
+            None => FileSearchKey::Path(self.path.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FileSearchKey {
+    Path(PathBuf),
+    Tab(Entity),
+}
+
+/// Text of an open tab, searched instead of its file on disk
+#[derive(Clone, Debug)]
+pub struct BufferSearchInput {
+    pub entity: Entity,
+    pub path_opt: Option<PathBuf>,
+    pub title: String,
+    pub text: String,
+    pub changed: bool,
+}
+
+/// Collects matching and context lines from a [`Searcher`]
+struct LineSink<'a> {
+    matcher: &'a RegexMatcher,
+    cancel: &'a AtomicBool,
+    lines: Vec<LineSearchResult>,
+}
+
+impl LineSink<'_> {
+    fn push(&mut self, number_opt: Option<u64>, bytes: &[u8], context: bool) -> io::Result<bool> {
+        let number = match number_opt.map(usize::try_from) {
+            Some(Ok(number)) => number,
+            Some(Err(err)) => return Err(io::Error::error_message(err)),
+            None => return Err(io::Error::error_message("line numbers not enabled")),
+        };
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text.trim_end(),
+            Err(err) => return Err(io::Error::error_message(err)),
+        };
+
+        let mut matches = Vec::new();
+        if !context {
+            self.matcher
+                .find_iter(text.as_bytes(), |m| {
+                    matches.push(m);
+                    true
+                })
+                .map_err(io::Error::error_message)?;
+            if matches.is_empty() {
+                log::warn!("match on line {} not found in {:?}", number, text);
+            }
+        }
+
+        self.lines.push(LineSearchResult {
+            number,
+            text: text.to_string(),
+            matches,
+        });
+        Ok(!self.cancel.load(Ordering::Relaxed))
+    }
+}
+
+impl Sink for LineSink<'_> {
+    type Error = io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        self.push(mat.line_number(), mat.bytes(), false)
+    }
+
+    fn context(&mut self, _searcher: &Searcher, context: &SinkContext<'_>) -> io::Result<bool> {
+        self.push(context.line_number(), context.bytes(), true)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProjectSearchOptions {
+    pub use_regex: bool,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    /// Comma separated globs, only matching files are searched if not empty
+    pub include: String,
+    /// Comma separated globs, matching files are never searched
+    pub exclude: String,
+    pub max_file_size: Option<u64>,
+    pub context_before: usize,
+    pub context_after: usize,
+    /// Only search open tabs instead of all project files
+    pub open_tabs_only: bool,
+}
+
+impl ProjectSearchOptions {
+    fn matcher(&self, value: &str) -> Result<RegexMatcher, grep::regex::Error> {
+        RegexMatcherBuilder::new()
+            .fixed_strings(!self.use_regex)
+            .case_insensitive(!self.case_sensitive)
+            .word(self.whole_word)
+            .build(value)
+    }
+
+    fn searcher(&self) -> Searcher {
+        SearcherBuilder::new()
+            .line_number(true)
+            .before_context(self.context_before)
+            .after_context(self.context_after)
+            .build()
+    }
+
+    fn globs(value: &str) -> impl Iterator<Item = &str> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|glob| !glob.is_empty())
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ProjectSearchResult {
+    /// Identifies the search that produced this result, used to drop stale updates
+    pub id: u64,
     //TODO: should this be included?
     pub value: String,
+    pub options: ProjectSearchOptions,
     pub in_progress: bool,
+    pub error_opt: Option<String>,
+    /// Files in the order they were found, so indexes stay valid while results stream in
     pub files: Vec<FileSearchResult>,
+    /// Indexes into `files`, sorted by path
+    sorted: Vec<usize>,
 }
 
 impl ProjectSearchResult {
-    pub fn search_projects(&mut self, projects: Vec<(String, PathBuf)>) {
-        //TODO: support literal search
-        //TODO: use ignore::WalkParallel?
-        match RegexMatcher::new(&self.value) {
-            Ok(matcher) => {
-                let mut searcher = Searcher::new();
-                let mut walk_builder_opt: Option<ignore::WalkBuilder> = None;
-                for (_, project_path) in projects.iter() {
-                    walk_builder_opt = match walk_builder_opt.take() {
-                        Some(mut walk_builder) => {
-                            walk_builder.add(project_path);
-                            Some(walk_builder)
-                        }
-                        None => Some(ignore::WalkBuilder::new(project_path)),
-                    };
+    pub fn new(id: u64, value: String, options: ProjectSearchOptions) -> Self {
+        Self {
+            id,
+            value,
+            options,
+            in_progress: true,
+            error_opt: None,
+            files: Vec::new(),
+            sorted: Vec::new(),
+        }
+    }
+
+    /// Add a file result as it streams in, keeping the indexes of earlier results
+    pub fn insert_file(&mut self, file: FileSearchResult) {
+        let index = match self
+            .sorted
+            .binary_search_by(|other_i| self.files[*other_i].path.cmp(&file.path))
+        {
+            Ok(index) | Err(index) => index,
+        };
+        self.sorted.insert(index, self.files.len());
+        self.files.push(file);
+    }
+
+    /// File results sorted by path, with their index in `files`
+    pub fn sorted_files(&self) -> impl Iterator<Item = (usize, &FileSearchResult)> {
+        self.sorted
+            .iter()
+            .filter_map(|file_i| Some((*file_i, self.files.get(*file_i)?)))
+    }
+
+    /// Search open tabs and then all projects in parallel, calling `on_file` for each file with
+    /// matches. The search stops early when `cancel` is set.
+    pub fn search_projects<F>(
+        &self,
+        projects: Vec<(String, PathBuf)>,
+        buffers: Vec<BufferSearchInput>,
+        cancel: &AtomicBool,
+        on_file: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(FileSearchResult) + Sync,
+    {
+        let matcher = self.options.matcher(&self.value).map_err(|err| {
+            format!(
+                "failed to create matcher with value {:?}: {}",
+                self.value, err
+            )
+        })?;
+
+        // Globs are matched relative to each project root
+        let mut project_overrides = Vec::with_capacity(projects.len());
+        for (_, project_path) in projects.iter() {
+            let mut override_builder = OverrideBuilder::new(project_path);
+            for glob in ProjectSearchOptions::globs(&self.options.include) {
+                override_builder
+                    .add(glob)
+                    .map_err(|err| format!("invalid include glob {:?}: {}", glob, err))?;
+            }
+            for glob in ProjectSearchOptions::globs(&self.options.exclude) {
+                override_builder
+                    .add(&format!("!{}", glob))
+                    .map_err(|err| format!("invalid exclude glob {:?}: {}", glob, err))?;
+            }
+            let overrides = override_builder
+                .build()
+                .map_err(|err| format!("failed to build globs: {}", err))?;
+            project_overrides.push((project_path.clone(), overrides));
+        }
+        let is_excluded = |path: &Path| {
+            project_overrides.iter().any(|(project_path, overrides)| {
+                path.starts_with(project_path) && overrides.matched(path, false).is_ignore()
+            })
+        };
+
+        // Search the text of open tabs, which may differ from the files on disk
+        let mut searcher = self.options.searcher();
+        let mut buffer_paths = HashSet::with_capacity(buffers.len());
+        for buffer in buffers {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            let path = match &buffer.path_opt {
+                Some(path) => {
+                    let in_project = projects
+                        .iter()
+                        .any(|(_, project_path)| path.starts_with(project_path));
+                    if !self.options.open_tabs_only && !in_project {
+                        continue;
+                    }
+                    buffer_paths.insert(path.clone());
+                    if is_excluded(path) {
+                        continue;
+                    }
+                    path.clone()
                 }
+                None => PathBuf::from(&buffer.title),
+            };
 
-                if let Some(walk_builder) = walk_builder_opt {
-                    for entry_res in walk_builder.build() {
-                        let entry = match entry_res {
-                            Ok(ok) => ok,
-                            Err(err) => {
-                                log::error!("failed to walk projects {:?}: {}", projects, err);
-                                continue;
-                            }
-                        };
+            let mut sink = LineSink {
+                matcher: &matcher,
+                cancel,
+                lines: Vec::new(),
+            };
+            match searcher.search_slice(&matcher, buffer.text.as_bytes(), &mut sink) {
+                Ok(()) => {
+                    if sink.lines.iter().any(|line| !line.is_context()) {
+                        on_file(FileSearchResult {
+                            path,
+                            tab_opt: Some(buffer.entity),
+                            unsaved: buffer.changed,
+                            lines: sink.lines,
+                        });
+                    }
+                }
+                Err(err) => {
+                    log::error!("failed to search tab {:?}: {}", buffer.title, err);
+                }
+            }
+        }
 
-                        if let Some(file_type) = entry.file_type() {
-                            if file_type.is_dir() {
-                                continue;
-                            }
+        if self.options.open_tabs_only {
+            return Ok(());
+        }
+
+        let on_file = &on_file;
+        let buffer_paths = &buffer_paths;
+        for (project_path, overrides) in project_overrides {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+
+            // Overrides let the walker skip excluded folders instead of filtering their files
+            let mut walk_builder = ignore::WalkBuilder::new(&project_path);
+            walk_builder
+                .max_filesize(self.options.max_file_size)
+                .overrides(overrides);
+            walk_builder.build_parallel().run(|| {
+                let matcher = matcher.clone();
+                let mut searcher = self.options.searcher();
+                Box::new(move |entry_res| {
+                    if cancel.load(Ordering::Relaxed) {
+                        return WalkState::Quit;
+                    }
+
+                    let entry = match entry_res {
+                        Ok(ok) => ok,
+                        Err(err) => {
+                            log::error!("failed to walk projects: {}", err);
+                            return WalkState::Continue;
                         }
+                    };
 
-                        let entry_path = entry.path();
-
-                        let mut lines = Vec::new();
-                        match searcher.search_path(
-                            &matcher,
-                            entry_path,
-                            UTF8(|number_u64, text| {
-                                match usize::try_from(number_u64) {
-                                    Ok(number) => match matcher.find(text.as_bytes()) {
-                                        Ok(Some(first)) => {
-                                            lines.push(LineSearchResult {
-                                                number,
-                                                text: text.trim_end().to_string(),
-                                                first,
-                                            });
-                                        },
-                                        Ok(None) => {
-                                            log::error!("first match in file {:?} line {} not found", entry_path, number);
-                                        }
-                                        Err(err) => {
-                                            log::error!("failed to find first match in file {:?} line {}: {}", entry_path, number, err);
-                                        }
-                                    },
-                                    Err(err) => {
-                                        log::error!("failed to convert file {:?} line {} to usize: {}", entry_path, number_u64, err);
-                                    }
-                                }
-                                Ok(true)
-                            }),
-                        ) {
-                            Ok(()) => {
-                                if !lines.is_empty() {
-                                    self.files.push(FileSearchResult {
-                                        path: entry_path.to_path_buf(),
-                                        lines,
-                                    });
-                                }
-                            }
-                            Err(err) => {
-                                log::error!("failed to search file {:?}: {}", entry_path, err);
+                    if let Some(file_type) = entry.file_type() {
+                        if file_type.is_dir() {
+                            return WalkState::Continue;
+                        }
+                    }
+
+                    let entry_path = entry.path();
+
+                    // Open tabs were already searched
+                    if buffer_paths.contains(entry_path) {
+                        return WalkState::Continue;
+                    }
+
+                    let mut sink = LineSink {
+                        matcher: &matcher,
+                        cancel,
+                        lines: Vec::new(),
+                    };
+                    match searcher.search_path(&matcher, entry_path, &mut sink) {
+                        Ok(()) => {
+                            if sink.lines.iter().any(|line| !line.is_context()) {
+                                on_file(FileSearchResult {
+                                    path: entry_path.to_path_buf(),
+                                    tab_opt: None,
+                                    unsaved: false,
+                                    lines: sink.lines,
+                                });
                             }
                         }
+                        Err(err) => {
+                            log::error!("failed to search file {:?}: {}", entry_path, err);
+                        }
                     }
-                }
-            }
-            Err(err) => {
-                log::error!(
-                    "failed to create regex matcher with value {:?}: {}",
-                    self.value,
-                    err
-                );
-            }
+
+                    WalkState::Continue
+                })
+            });
         }
-        self.in_progress = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::Mutex};
+
+    fn search(
+        value: &str,
+        options: ProjectSearchOptions,
+        projects: Vec<(String, PathBuf)>,
+        buffers: Vec<BufferSearchInput>,
+    ) -> Vec<FileSearchResult> {
+        let result = ProjectSearchResult::new(0, value.to_string(), options);
+        let files = Mutex::new(Vec::new());
+        result
+            .search_projects(projects, buffers, &AtomicBool::new(false), |file| {
+                files.lock().unwrap().push(file)
+            })
+            .unwrap();
+        let mut files = files.into_inner().unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    }
+
+    fn search_text(
+        value: &str,
+        options: ProjectSearchOptions,
+        text: &str,
+    ) -> Vec<LineSearchResult> {
+        let buffer = BufferSearchInput {
+            entity: Entity::default(),
+            path_opt: None,
+            title: "Untitled".to_string(),
+            text: text.to_string(),
+            changed: true,
+        };
+        let options = ProjectSearchOptions {
+            open_tabs_only: true,
+            ..options
+        };
+        search(value, options, Vec::new(), vec![buffer])
+            .pop()
+            .map(|file| file.lines)
+            .unwrap_or_default()
+    }
+
+    fn matched_lines(lines: &[LineSearchResult]) -> Vec<usize> {
+        lines
+            .iter()
+            .filter(|line| !line.is_context())
+            .map(|line| line.number)
+            .collect()
+    }
+
+    #[test]
+    fn literal_and_regex() {
+        let text = "a.c\nabc\n";
+        let literal = search_text("a.c", ProjectSearchOptions::default(), text);
+        assert_eq!(matched_lines(&literal), [1]);
+        let regex = ProjectSearchOptions {
+            use_regex: true,
+            ..Default::default()
+        };
+        assert_eq!(matched_lines(&search_text("a.c", regex, text)), [1, 2]);
+    }
+
+    #[test]
+    fn case_and_whole_word() {
+        let text = "Foo\nfoo\nfoobar\n";
+        assert_eq!(
+            matched_lines(&search_text("foo", ProjectSearchOptions::default(), text)),
+            [1, 2, 3]
+        );
+        let case_sensitive = ProjectSearchOptions {
+            case_sensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            matched_lines(&search_text("foo", case_sensitive, text)),
+            [2, 3]
+        );
+        let whole_word = ProjectSearchOptions {
+            whole_word: true,
+            ..Default::default()
+        };
+        assert_eq!(matched_lines(&search_text("foo", whole_word, text)), [1, 2]);
+    }
+
+    #[test]
+    fn every_match_and_context() {
+        let options = ProjectSearchOptions {
+            context_before: 1,
+            context_after: 1,
+            ..Default::default()
+        };
+        let lines = search_text("x", options, "a\nb\nx y x\nc\nd\n");
+        let numbers: Vec<usize> = lines.iter().map(|line| line.number).collect();
+        assert_eq!(numbers, [2, 3, 4]);
+        assert!(lines[0].is_context());
+        assert!(lines[2].is_context());
+        let spans: Vec<(usize, usize)> = lines[1]
+            .matches
+            .iter()
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        assert_eq!(spans, [(0, 1), (4, 5)]);
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let result = ProjectSearchResult::new(
+            0,
+            "(".to_string(),
+            ProjectSearchOptions {
+                use_regex: true,
+                ..Default::default()
+            },
+        );
+        assert!(
+            result
+                .search_projects(Vec::new(), Vec::new(), &AtomicBool::new(false), |_| {})
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn globs_and_max_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (path, text) in [
+            ("src/main.rs", "needle\n"),
+            ("src/lib.rs", "needle\n"),
+            ("README.md", "needle\n"),
+            ("target/debug/out.rs", "needle\n"),
+            ("big.rs", &format!("needle\n{}", "x".repeat(4096))),
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let projects = vec![("root".to_string(), root.to_path_buf())];
+        let paths = |options| {
+            search("needle", options, projects.clone(), Vec::new())
+                .into_iter()
+                .map(|file| file.path.strip_prefix(root).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(ProjectSearchOptions::default()).len(), 5);
+        assert_eq!(
+            paths(ProjectSearchOptions {
+                include: "*.rs".to_string(),
+                exclude: "target/**, big.rs".to_string(),
+                ..Default::default()
+            }),
+            [PathBuf::from("src/lib.rs"), PathBuf::from("src/main.rs")]
+        );
+        // Excluded folders are skipped with everything in them
+        assert_eq!(
+            paths(ProjectSearchOptions {
+                exclude: "target".to_string(),
+                max_file_size: Some(1024),
+                ..Default::default()
+            }),
+            [
+                PathBuf::from("README.md"),
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("src/main.rs")
+            ]
+        );
+    }
+
+    #[test]
+    fn buffers_replace_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "needle\n").unwrap();
+        let buffer = BufferSearchInput {
+            entity: Entity::default(),
+            path_opt: Some(path.clone()),
+            title: "file.txt".to_string(),
+            text: "other\nneedle\n".to_string(),
+            changed: true,
+        };
+        let files = search(
+            "needle",
+            ProjectSearchOptions::default(),
+            vec![("dir".to_string(), dir.path().to_path_buf())],
+            vec![buffer],
+        );
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].tab_opt, Some(Entity::default()));
+        assert!(files[0].unsaved);
+        assert_eq!(matched_lines(&files[0].lines), [2]);
+    }
+
+    #[test]
+    fn cancelled() {
+        let result = ProjectSearchResult::new(0, "a".to_string(), Default::default());
+        let buffer = BufferSearchInput {
+            entity: Entity::default(),
+            path_opt: None,
+            title: "Untitled".to_string(),
+            text: "a\n".to_string(),
+            changed: false,
+        };
+        let found = AtomicBool::new(false);
+        result
+            .search_projects(Vec::new(), vec![buffer], &AtomicBool::new(true), |_| {
+                found.store(true, Ordering::Relaxed)
+            })
+            .unwrap();
+        assert!(!found.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn stable_indexes() {
+        let file = |path: &str| FileSearchResult {
+            path: PathBuf::from(path),
+            tab_opt: None,
+            unsaved: false,
+            lines: Vec::new(),
+        };
+        let mut result = ProjectSearchResult::new(0, String::new(), Default::default());
+        result.insert_file(file("b"));
+        result.insert_file(file("c"));
+        result.insert_file(file("a"));
+        let sorted: Vec<(usize, &Path)> = result
+            .sorted_files()
+            .map(|(file_i, file)| (file_i, file.path.as_path()))
+            .collect();
+        assert_eq!(
+            sorted,
+            [
+                (2, Path::new("a")),
+                (0, Path::new("b")),
+                (1, Path::new("c"))
+            ]
+        );
     }
 }
@@ -1,21 +1,41 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use cosmic::{
-    iced::{Point, advanced::graphics::text::font_system},
+    iced::{Color, Point, advanced::graphics::text::font_system},
     widget::icon,
 };
 use cosmic_files::mime_icon::{FALLBACK_MIME_ICON, mime_for_path, mime_icon};
 use cosmic_text::{Attrs, Buffer, Cursor, Edit, Selection, Shaping, SyntaxEditor, ViEditor, Wrap};
 use regex::Regex;
 use std::{
+    cmp::Reverse,
+    ffi::OsStr,
     fs,
     io::{self, Write},
-    path::{self, PathBuf},
+    ops::Range,
+    path::{self, Path, PathBuf},
     process::{Command, Stdio},
     sync::{Arc, Mutex},
 };
 
-use crate::{Config, SYNTAX_SYSTEM, fl, git::GitDiff};
+use syntect::easy::HighlightLines;
+
+use crate::{
+    Config, SYNTAX_SYSTEM,
+    completion::{Snippet, SnippetSession, advance_cursor, is_word_char, shift_cursor},
+    fl,
+    git::{GitBlame, GitConflict, GitConflictChoice, GitDiff, GitDiffLine, conflicts, word_diff},
+    local_history::{LocalHistoryRetention, LocalHistorySave, stable_hash},
+    lsp::{LspDiagnostic, LspDocument, LspPositionEncoding},
+    undo_history::{UndoCache, UndoHistory, UndoHistoryLimits, UndoHistorySave, UndoStack},
+};
+
+/// Unchanged lines shown around changes in diff tabs
+pub const GIT_DIFF_CONTEXT_LINES: u32 = 3;
+
+fn editor_git_conflicts(editor: &ViEditor<'static, 'static>) -> Vec<GitConflict> {
+    editor.with_buffer(|buffer| conflicts(buffer.lines.iter().map(|line| line.text())))
+}
 
 fn editor_text(editor: &ViEditor<'static, 'static>) -> String {
     editor.with_buffer(|buffer| {
@@ -28,6 +48,171 @@ fn editor_text(editor: &ViEditor<'static, 'static>) -> String {
     })
 }
 
+/// Cursor at the byte `offset` of the text of the editor
+fn editor_cursor(editor: &ViEditor<'static, 'static>, mut offset: usize) -> Cursor {
+    editor.with_buffer(|buffer| {
+        for (line_i, line) in buffer.lines.iter().enumerate() {
+            let len = line.text().len();
+            if offset <= len {
+                return Cursor::new(line_i, offset);
+            }
+            offset = offset.saturating_sub(len + line.ending().as_str().len());
+        }
+        let last_line = buffer.lines.len().saturating_sub(1);
+        Cursor::new(
+            last_line,
+            buffer.lines.last().map_or(0, |line| line.text().len()),
+        )
+    })
+}
+
+/// Cursor at a position sent by a language server, clamped to the text of the editor
+fn editor_lsp_cursor(
+    editor: &ViEditor<'static, 'static>,
+    encoding: LspPositionEncoding,
+    position: lsp_types::Position,
+) -> Cursor {
+    editor.with_buffer(|buffer| {
+        let line_i = position.line as usize;
+        match buffer.lines.get(line_i) {
+            Some(line) => Cursor::new(line_i, encoding.index(line.text(), position.character)),
+            None => {
+                let last_line = buffer.lines.len().saturating_sub(1);
+                Cursor::new(
+                    last_line,
+                    buffer.lines.last().map_or(0, |line| line.text().len()),
+                )
+            }
+        }
+    })
+}
+
+/// Position of `cursor` as sent to a language server
+fn editor_lsp_position(
+    editor: &ViEditor<'static, 'static>,
+    encoding: LspPositionEncoding,
+    cursor: Cursor,
+) -> lsp_types::Position {
+    editor.with_buffer(|buffer| {
+        let character = buffer
+            .lines
+            .get(cursor.line)
+            .map_or(0, |line| encoding.column(line.text(), cursor.index));
+        lsp_types::Position::new(cursor.line as u32, character)
+    })
+}
+
+/// `cursor` moved into the text of the editor
+fn editor_clamp(editor: &ViEditor<'static, 'static>, cursor: Cursor) -> Cursor {
+    editor.with_buffer(|buffer| match buffer.lines.get(cursor.line) {
+        Some(line) => {
+            let text = line.text();
+            let mut index = cursor.index.min(text.len());
+            while !text.is_char_boundary(index) {
+                index -= 1;
+            }
+            Cursor::new(cursor.line, index)
+        }
+        None => Cursor::new(
+            buffer.lines.len().saturating_sub(1),
+            buffer.lines.last().map_or(0, |line| line.text().len()),
+        ),
+    })
+}
+
+/// Select the text from `start` to `end`, or place the cursor if they are equal
+fn editor_select(editor: &mut ViEditor<'static, 'static>, start: Cursor, end: Cursor) {
+    editor.set_cursor(end);
+    editor.set_selection(if start == end {
+        Selection::None
+    } else {
+        Selection::Normal(start)
+    });
+}
+
+/// Replace `len` bytes of the text of the editor at the byte `offset` with `text`
+fn editor_replace(editor: &mut ViEditor<'static, 'static>, offset: usize, len: usize, text: &str) {
+    let start = editor_cursor(editor, offset);
+    let end = editor_cursor(editor, offset + len);
+    editor.delete_range(start, end);
+    editor.insert_at(start, text, None);
+}
+
+/// Whether the undo history of a text is kept across sessions
+fn undo_history_allowed(text: &str, limits: UndoHistoryLimits) -> bool {
+    limits.max_file_size == 0 || text.len() as u64 <= limits.max_file_size
+}
+
+impl UndoStack for ViEditor<'static, 'static> {
+    fn text(&self) -> String {
+        editor_text(self)
+    }
+
+    fn undo_change(&mut self) {
+        self.undo();
+    }
+
+    fn redo_change(&mut self) {
+        self.redo();
+    }
+}
+
+/// Restore the undo history of the file at `path` with `text` kept from a previous session
+fn editor_restore_undo_history(
+    editor: &mut ViEditor<'static, 'static>,
+    path: &Path,
+    text: &str,
+    limits: UndoHistoryLimits,
+) {
+    let Some(undo_cache) = UndoCache::new() else {
+        return;
+    };
+    let mut history = match undo_cache.load(path, text) {
+        Ok(Some(history)) if history.applies_to(text) => history,
+        Ok(Some(_)) => {
+            log::warn!("undo history of {:?} does not lead to its text", path);
+            return;
+        }
+        Ok(None) => return,
+        Err(err) => {
+            log::warn!("failed to load undo history of {:?}: {}", path, err);
+            return;
+        }
+    };
+    history.limit(limits);
+
+    // Go back to the oldest text without recording changes, then redo every edit as a change
+    for edit in history.undo.iter().rev() {
+        editor_replace(editor, edit.offset, edit.inserted.len(), &edit.deleted);
+    }
+    for edit in history.undo.iter().chain(history.redo.iter()) {
+        editor.start_change();
+        editor_replace(editor, edit.offset, edit.deleted.len(), &edit.inserted);
+        editor.finish_change();
+    }
+    for _ in history.redo.iter() {
+        editor.undo();
+    }
+    let restored_text = editor_text(editor);
+    let restored = restored_text == text;
+    if !restored {
+        // The file must be shown as saved, even if undo does not work as expected
+        log::warn!("failed to restore undo history of {:?}", path);
+        editor_replace(editor, 0, restored_text.len(), text);
+    }
+    editor.set_cursor(Cursor::new(0, 0));
+    editor.set_selection(Selection::None);
+    editor.save_point();
+    if restored {
+        log::info!(
+            "restored {} undo and {} redo changes of {:?}",
+            history.undo.len(),
+            history.redo.len(),
+            path
+        );
+    }
+}
+
 pub enum Tab {
     Editor(EditorTab),
     GitDiff(GitDiffTab),
@@ -42,41 +227,365 @@ impl Tab {
     }
 }
 
+/// Part of a line in a [`GitDiffTab`] with the same syntax highlighting
+#[derive(Clone, Debug)]
+pub struct GitDiffSpan {
+    pub range: Range<usize>,
+    pub color: Option<Color>,
+    /// Part of the words that changed between a deleted and an added line
+    pub changed: bool,
+}
+
+/// Texts compared in process, for diffs that do not come from git
+pub struct DiffCompare {
+    pub old_text: String,
+    pub new_text: String,
+}
+
 pub struct GitDiffTab {
     pub title: String,
+    pub project_path: PathBuf,
     pub diff: GitDiff,
+    /// Hash of the commit the diff belongs to, `None` for working tree changes
+    pub commit_opt: Option<String>,
+    /// Compared texts, `None` for changes from git
+    pub compare_opt: Option<DiffCompare>,
+    /// Selected range of lines in a hunk, for partial staging
+    pub selection_opt: Option<(usize, Range<usize>)>,
+    /// Unchanged lines shown around changes
+    pub context_lines: u32,
+    /// Hunk used for navigating to the next or previous hunk
+    pub hunk_i: usize,
+    /// Spans of each line of each hunk, empty until highlighted in the background
+    pub spans: Vec<Vec<Vec<GitDiffSpan>>>,
+    /// Incremented when highlighting starts, to ignore spans of older diffs
+    pub spans_generation: u64,
+}
+
+impl GitDiffTab {
+    pub fn new(
+        title: String,
+        project_path: PathBuf,
+        diff: GitDiff,
+        commit_opt: Option<String>,
+    ) -> Self {
+        let mut tab = Self {
+            title,
+            project_path,
+            diff: GitDiff {
+                path: diff.path.clone(),
+                staged: diff.staged,
+                hunks: Vec::new(),
+            },
+            commit_opt,
+            compare_opt: None,
+            selection_opt: None,
+            context_lines: GIT_DIFF_CONTEXT_LINES,
+            hunk_i: 0,
+            spans: Vec::new(),
+            spans_generation: 0,
+        };
+        tab.set_diff(diff);
+        tab
+    }
+
+    /// Diff of two texts that do not come from git, like tabs or the clipboard. The path of the
+    /// diff is used for syntax highlighting.
+    pub fn new_compare(title: String, diff: GitDiff, old_text: String, new_text: String) -> Self {
+        let project_path = diff
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let mut tab = Self::new(title, project_path, diff, None);
+        tab.compare_opt = Some(DiffCompare { old_text, new_text });
+        tab
+    }
+
+    /// Replace the diff, spans have to be highlighted again
+    pub fn set_diff(&mut self, diff: GitDiff) {
+        self.diff = diff;
+        self.selection_opt = None;
+        self.hunk_i = self.hunk_i.min(self.diff.hunks.len().saturating_sub(1));
+        self.spans.clear();
+    }
+}
+
+/// Highlight syntax and changed words of all lines of `diff`. The full `old_text` and `new_text`
+/// of the file are highlighted up to each hunk, so syntax that started before it is kept.
+pub fn git_diff_spans(
+    diff: &GitDiff,
+    old_text: &str,
+    new_text: &str,
+    syntax_theme: &str,
+) -> Vec<Vec<Vec<GitDiffSpan>>> {
+    let syntax_system = SYNTAX_SYSTEM.get().unwrap();
+    let syntax_set = &syntax_system.syntax_set;
+    // Files without an extension may still be matched by name, like Makefile
+    let syntax = diff
+        .path
+        .extension()
+        .or_else(|| diff.path.file_name())
+        .and_then(OsStr::to_str)
+        .and_then(|extension| syntax_set.find_syntax_by_extension(extension))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+    let theme_opt = syntax_system.theme_set.themes.get(syntax_theme);
+
+    // The old and new side are highlighted separately to keep the parser state right
+    let mut highlighters_opt = theme_opt.map(|theme| {
+        (
+            HighlightLines::new(syntax, theme),
+            HighlightLines::new(syntax, theme),
+        )
+    });
+    let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+    // Zero based index of the next line of each side to highlight
+    let mut old_next = 0;
+    let mut new_next = 0;
+    let first_line = |range: &patch::Range| {
+        let start = range.start as usize;
+        if range.count == 0 {
+            start
+        } else {
+            start.saturating_sub(1)
+        }
+    };
+
+    let mut spans = Vec::with_capacity(diff.hunks.len());
+    for hunk in diff.hunks.iter() {
+        // Find changed words of deleted lines followed by added lines
+        let mut changed = vec![Vec::new(); hunk.lines.len()];
+        for (old_i_opt, new_i_opt) in hunk.rows() {
+            let (Some(old_i), Some(new_i)) = (old_i_opt, new_i_opt) else {
+                continue;
+            };
+            if let (
+                Some(old @ GitDiffLine::Deleted { .. }),
+                Some(new @ GitDiffLine::Added { .. }),
+            ) = (hunk.lines.get(old_i), hunk.lines.get(new_i))
+            {
+                if let Some((old_ranges, new_ranges)) = word_diff(old.text(), new.text()) {
+                    changed[old_i] = old_ranges;
+                    changed[new_i] = new_ranges;
+                }
+            }
+        }
+
+        // Highlight the lines between the previous hunk and this one
+        let old_start = first_line(&hunk.old_range);
+        let new_start = first_line(&hunk.new_range);
+        if let Some((old, new)) = &mut highlighters_opt {
+            for line in old_lines.get(old_next..old_start).into_iter().flatten() {
+                let _ = old.highlight_line(line, syntax_set);
+            }
+            for line in new_lines.get(new_next..new_start).into_iter().flatten() {
+                let _ = new.highlight_line(line, syntax_set);
+            }
+        }
+        old_next = old_start + hunk.old_range.count as usize;
+        new_next = new_start + hunk.new_range.count as usize;
+
+        let mut hunk_spans = Vec::with_capacity(hunk.lines.len());
+        for (line_i, line) in hunk.lines.iter().enumerate() {
+            // Lines are highlighted with their line endings, but shown without them
+            let text_len = line.text().len();
+            let highlighted = match (line, &mut highlighters_opt) {
+                (GitDiffLine::Context { text, .. }, Some((old, new))) => {
+                    let _ = new.highlight_line(text, syntax_set);
+                    old.highlight_line(text, syntax_set).ok()
+                }
+                (GitDiffLine::Deleted { text, .. }, Some((old, _))) => {
+                    old.highlight_line(text, syntax_set).ok()
+                }
+                (GitDiffLine::Added { text, .. }, Some((_, new))) => {
+                    new.highlight_line(text, syntax_set).ok()
+                }
+                (_, None) => None,
+            };
+
+            let mut syntax_spans = Vec::new();
+            match highlighted {
+                Some(highlighted) => {
+                    let mut start = 0;
+                    for (style, slice) in highlighted {
+                        let end = (start + slice.len()).min(text_len);
+                        if start >= end {
+                            break;
+                        }
+                        let color = style.foreground;
+                        syntax_spans.push((
+                            start..end,
+                            Some(Color::from_rgba8(
+                                color.r,
+                                color.g,
+                                color.b,
+                                f32::from(color.a) / 255.0,
+                            )),
+                        ));
+                        start = end;
+                    }
+                }
+                None => syntax_spans.push((0..text_len, None)),
+            }
+            hunk_spans.push(split_changed(syntax_spans, &changed[line_i]));
+        }
+        spans.push(hunk_spans);
+    }
+    spans
+}
+
+/// Split spans at the start and end of changed ranges
+fn split_changed(
+    syntax_spans: Vec<(Range<usize>, Option<Color>)>,
+    changed: &[Range<usize>],
+) -> Vec<GitDiffSpan> {
+    let mut spans = Vec::with_capacity(syntax_spans.len() + changed.len() * 2);
+    for (range, color) in syntax_spans {
+        let mut start = range.start;
+        while start < range.end {
+            // Find the changed range containing start, or the next one after it
+            let (end, is_changed) = match changed.iter().find(|changed| changed.end > start) {
+                Some(next) if next.start <= start => (next.end.min(range.end), true),
+                Some(next) => (next.start.min(range.end), false),
+                None => (range.end, false),
+            };
+            spans.push(GitDiffSpan {
+                range: start..end,
+                color,
+                changed: is_changed,
+            });
+            start = end;
+        }
+    }
+    spans
+}
+
+/// A file as it was in a past commit
+pub struct EditorRevision {
+    pub path: PathBuf,
+    pub short_hash: String,
 }
 
 pub struct EditorTab {
     pub path_opt: Option<PathBuf>,
+    /// Set for read-only tabs showing a past revision of a file
+    pub revision_opt: Option<EditorRevision>,
     attrs: Attrs<'static>,
     pub editor: Mutex<ViEditor<'static, 'static>>,
     pub context_menu: Option<Point>,
     pub zoom_adj: i8,
+    /// Indexed version of the file, `None` if not tracked by git
+    pub git_index_opt: Option<String>,
+    /// The indexed version must be loaded again
+    pub git_index_stale: bool,
+    /// Changes of the buffer compared to the indexed version
+    pub git_diff_opt: Option<GitDiff>,
+    /// Incremented on every edit to debounce updates of `git_diff_opt`
+    pub git_diff_generation: u64,
+    /// Hunk of `git_diff_opt` shown inline
+    pub git_hunk_opt: Option<usize>,
+    /// Blame of the buffer, loaded when the blame gutter is shown
+    pub git_blame_opt: Option<GitBlame>,
+    /// Hash of `HEAD` and the blame of the file committed there, reused for changes of the buffer
+    pub git_blame_head_opt: Option<(String, Arc<GitBlame>)>,
+    /// Files in the git folder changed, so `HEAD` may point to another commit
+    pub git_blame_head_stale: bool,
+    /// Incremented when blaming the buffer, to ignore results of older blames
+    pub git_blame_generation: u64,
+    /// Blame gutter label for each line of the buffer
+    pub git_blame_labels: Vec<String>,
+    /// Merge conflict regions in the buffer
+    pub git_conflicts: Vec<GitConflict>,
+    /// Incremented on every edit to debounce updates of `git_conflicts`
+    pub git_conflicts_generation: u64,
+    /// The buffer had merge conflicts since it was opened and is not marked resolved yet
+    pub git_conflicted: bool,
+    /// Snapshots of the file kept in the local history
+    local_history_retention: LocalHistoryRetention,
+    /// Undo history kept across sessions
+    undo_history_limits: UndoHistoryLimits,
+    /// The file as opened with a language server
+    pub lsp_document_opt: Option<LspDocument>,
+    /// Version of the text last sent to the language server
+    pub lsp_version: i32,
+    /// Hash of the text last sent to the language server
+    pub lsp_text_hash: u64,
+    /// Incremented on every edit to debounce sending changes to the language server
+    pub lsp_generation: u64,
+    /// Diagnostics published by the language server
+    pub lsp_diagnostics: Vec<LspDiagnostic>,
+    /// Tab stops of the snippet inserted last, until the final one is reached
+    pub snippet_opt: Option<SnippetSession>,
 }
 
 impl EditorTab {
     pub fn new(config: &Config) -> Self {
+        Self::with_text(config, None, "")
+    }
+
+    /// Create a read-only tab showing `text` from a past revision of a file
+    pub fn new_revision(config: &Config, revision: EditorRevision, text: &str) -> Self {
+        let mut tab = Self::with_text(config, Some(&revision.path), text);
+        tab.revision_opt = Some(revision);
+        // Vim bindings would edit the read-only buffer
+        tab.set_config(config);
+        tab
+    }
+
+    fn with_text(config: &Config, syntax_path_opt: Option<&Path>, text: &str) -> Self {
         let attrs = crate::monospace_attrs();
         let zoom_adj = Default::default();
         let mut buffer = Buffer::new_empty(config.metrics(zoom_adj));
         // Set a minimal size before it is updated by draw
         buffer.set_size(Some(0.0), Some(0.0));
-        buffer.set_text("", &attrs, Shaping::Advanced, None);
+        buffer.set_text(text, &attrs, Shaping::Advanced, None);
 
-        let editor = SyntaxEditor::new(
+        let mut editor = SyntaxEditor::new(
             Arc::new(buffer),
             SYNTAX_SYSTEM.get().unwrap(),
             config.syntax_theme(),
         )
         .unwrap();
+        if let Some(path) = syntax_path_opt {
+            // Files without an extension may still be matched by name, like Makefile
+            let extension_opt = path
+                .extension()
+                .or_else(|| path.file_name())
+                .and_then(|os_str| os_str.to_str());
+            if let Some(extension) = extension_opt {
+                editor.syntax_by_extension(extension);
+            }
+        }
 
         let mut tab = Self {
             path_opt: None,
+            revision_opt: None,
             attrs,
             editor: Mutex::new(ViEditor::new(editor)),
             context_menu: None,
             zoom_adj,
+            git_index_opt: None,
+            git_index_stale: true,
+            git_diff_opt: None,
+            git_diff_generation: 0,
+            git_hunk_opt: None,
+            git_blame_opt: None,
+            git_blame_head_opt: None,
+            git_blame_head_stale: false,
+            git_blame_generation: 0,
+            git_blame_labels: Vec::new(),
+            git_conflicts: Vec::new(),
+            git_conflicts_generation: 0,
+            git_conflicted: false,
+            local_history_retention: config.local_history_retention(),
+            undo_history_limits: config.undo_history_limits(),
+            lsp_document_opt: None,
+            lsp_version: 0,
+            lsp_text_hash: 0,
+            lsp_generation: 0,
+            lsp_diagnostics: Vec::new(),
+            snippet_opt: None,
         };
 
         // Update any other config settings
@@ -86,11 +595,13 @@ impl EditorTab {
     }
 
     pub fn set_config(&mut self, config: &Config) {
+        self.local_history_retention = config.local_history_retention();
+        self.undo_history_limits = config.undo_history_limits();
         let mut editor = self.editor.lock().unwrap();
         let mut font_system = font_system().write().unwrap();
         let mut editor = editor.borrow_with(font_system.raw());
         editor.set_auto_indent(config.auto_indent);
-        editor.set_passthrough(!config.vim_bindings);
+        editor.set_passthrough(!config.vim_bindings || self.revision_opt.is_some());
         editor.set_tab_width(config.tab_width);
         editor.with_buffer_mut(|buffer| {
             buffer.set_wrap(if config.word_wrap {
@@ -120,7 +631,14 @@ impl EditorTab {
         match editor.load_text(&absolute, self.attrs.clone()) {
             Ok(()) => {
                 log::info!("opened {:?}", absolute);
+                let text = editor_text(&editor);
+                let limits = self.undo_history_limits;
+                if undo_history_allowed(&text, limits) {
+                    editor_restore_undo_history(&mut editor, &absolute, &text, limits);
+                }
                 self.path_opt = Some(absolute);
+                self.git_conflicts = editor_git_conflicts(&editor);
+                self.git_conflicted = !self.git_conflicts.is_empty();
             }
             Err(err) => {
                 if err.kind() == io::ErrorKind::NotFound {
@@ -135,7 +653,9 @@ impl EditorTab {
         }
     }
 
-    pub fn reload(&mut self) {
+    /// Replace the buffer with the file, returning a snapshot of the replaced text to keep
+    pub fn reload(&mut self) -> Option<LocalHistorySave> {
+        let mut local_history_save_opt = None;
         let mut editor = self.editor.lock().unwrap();
         let mut font_system = font_system().write().unwrap();
         let mut editor = editor.borrow_with(font_system.raw());
@@ -149,10 +669,12 @@ impl EditorTab {
                     log::info!("reloaded {:?}", path);
 
                     //TODO: compare using line iterator to prevent allocations
-                    if file_content == editor_text(&editor) {
+                    let text = editor_text(&editor);
+                    if file_content == text {
                         log::info!("text not changed");
-                        return;
+                        return None;
                     }
+                    local_history_save_opt = Some(self.local_history_save(path, text));
 
                     // Store the entire operation as a single change for undo
                     editor.start_change();
@@ -201,6 +723,9 @@ impl EditorTab {
 
                     editor.finish_change();
                     editor.set_changed(false);
+
+                    self.git_conflicts = editor_git_conflicts(&editor);
+                    self.git_conflicted |= !self.git_conflicts.is_empty();
                 }
                 Err(err) => {
                     log::error!("failed to reload {:?}: {}", path, err);
@@ -212,9 +737,11 @@ impl EditorTab {
         } else {
             log::warn!("tried to reload with no path");
         }
+        local_history_save_opt
     }
 
-    pub fn save(&mut self) {
+    /// Write the buffer to the file, returning a snapshot of the saved text to keep
+    pub fn save(&mut self) -> Option<LocalHistorySave> {
         if let Some(path) = &self.path_opt {
             let mut editor = self.editor.lock().unwrap();
             let text = editor_text(&editor);
@@ -222,6 +749,7 @@ impl EditorTab {
                 Ok(()) => {
                     editor.save_point();
                     log::info!("saved {:?}", path);
+                    return Some(self.local_history_save(path, text));
                 }
                 Err(err) => {
                     if err.kind() == std::io::ErrorKind::PermissionDenied {
@@ -250,6 +778,7 @@ impl EditorTab {
                                         // Mark the editor's state as saved if the process succeeds
                                         editor.save_point();
                                         log::info!("File saved successfully with pkexec.");
+                                        return Some(self.local_history_save(path, text));
                                     } else {
                                         log::error!(
                                             "pkexec process exited with a non-zero status: {:?}",
@@ -272,6 +801,45 @@ impl EditorTab {
         } else {
             log::warn!("tab has no path yet");
         }
+        None
+    }
+
+    /// Undo history of an unchanged buffer to keep for the next time its file is opened
+    pub fn undo_history_save(&mut self) -> Option<UndoHistorySave> {
+        let path = self.path_opt.clone()?;
+        if self.changed() {
+            // The history would not lead to the saved text
+            return None;
+        }
+        let mut editor = self.editor.lock().unwrap();
+        let text = editor_text(&editor);
+        if !undo_history_allowed(&text, self.undo_history_limits) {
+            // Histories of files too large to keep them are removed
+            return Some(UndoHistorySave {
+                path,
+                text_hash: 0,
+                history: UndoHistory::default(),
+            });
+        }
+        Some(UndoHistorySave {
+            path,
+            text_hash: stable_hash(text.as_bytes()),
+            history: UndoHistory::from_stack(&mut *editor, self.undo_history_limits),
+        })
+    }
+
+    /// Snapshot of `text` to keep in the local history of the file at `path`
+    fn local_history_save(&self, path: &Path, text: String) -> LocalHistorySave {
+        LocalHistorySave {
+            path: path.to_path_buf(),
+            text,
+            retention: self.local_history_retention,
+        }
+    }
+
+    /// Replace the buffer with a snapshot from the local history as a single change for undo
+    pub fn restore_snapshot(&self, text: &str) {
+        self.replace_lines(0..usize::MAX, text);
     }
 
     pub fn changed(&self) -> bool {
@@ -279,31 +847,407 @@ impl EditorTab {
         editor.changed()
     }
 
+    pub fn text(&self) -> String {
+        let editor = self.editor.lock().unwrap();
+        editor_text(&editor)
+    }
+
+    /// Replace the lines of a hunk in `git_diff_opt` with their indexed version
+    pub fn revert_git_hunk(&self, hunk_i: usize) -> bool {
+        let Some(hunk) = self
+            .git_diff_opt
+            .as_ref()
+            .and_then(|diff| diff.hunks.get(hunk_i))
+        else {
+            return false;
+        };
+        let old_text: String = hunk.old_text().collect();
+        self.replace_lines(hunk.new_lines(), &old_text);
+        true
+    }
+
+    /// Find merge conflict regions after the buffer changed
+    pub fn update_git_conflicts(&mut self) {
+        let editor = self.editor.lock().unwrap();
+        self.git_conflicts = editor_git_conflicts(&editor);
+        self.git_conflicted |= !self.git_conflicts.is_empty();
+    }
+
+    /// Forget the language server the file was opened with and its diagnostics
+    pub fn clear_lsp(&mut self) {
+        self.lsp_document_opt = None;
+        self.lsp_diagnostics.clear();
+        self.editor.lock().unwrap().set_redraw(true);
+    }
+
+    /// Cursor at a position sent by the language server
+    pub fn lsp_cursor(
+        &self,
+        encoding: LspPositionEncoding,
+        position: lsp_types::Position,
+    ) -> Cursor {
+        let editor = self.editor.lock().unwrap();
+        editor_lsp_cursor(&editor, encoding, position)
+    }
+
+    /// Show diagnostics published by the language server, sorted by position
+    pub fn set_lsp_diagnostics(
+        &mut self,
+        encoding: LspPositionEncoding,
+        diagnostics: &[lsp_types::Diagnostic],
+    ) {
+        let mut editor = self.editor.lock().unwrap();
+        let mut lsp_diagnostics: Vec<LspDiagnostic> = diagnostics
+            .iter()
+            .map(|diagnostic| LspDiagnostic {
+                start: editor_lsp_cursor(&editor, encoding, diagnostic.range.start),
+                end: editor_lsp_cursor(&editor, encoding, diagnostic.range.end),
+                severity: diagnostic
+                    .severity
+                    .unwrap_or(lsp_types::DiagnosticSeverity::ERROR),
+                message: diagnostic.message.clone(),
+            })
+            .collect();
+        lsp_diagnostics.sort_by_key(|diagnostic| diagnostic.start);
+        self.lsp_diagnostics = lsp_diagnostics;
+        editor.set_redraw(true);
+    }
+
+    /// Position of the cursor as sent to the language server
+    pub fn lsp_position(&self, encoding: LspPositionEncoding) -> lsp_types::Position {
+        let editor = self.editor.lock().unwrap();
+        editor_lsp_position(&editor, encoding, editor.cursor())
+    }
+
+    /// Range of the selection as sent to the language server, empty at the cursor without a
+    /// selection
+    pub fn lsp_selection(&self, encoding: LspPositionEncoding) -> lsp_types::Range {
+        let editor = self.editor.lock().unwrap();
+        let (start, end) = editor
+            .selection_bounds()
+            .unwrap_or((editor.cursor(), editor.cursor()));
+        lsp_types::Range::new(
+            editor_lsp_position(&editor, encoding, start),
+            editor_lsp_position(&editor, encoding, end),
+        )
+    }
+
+    /// Range of the line `line_i` as sent to the language server
+    pub fn lsp_line_range(&self, encoding: LspPositionEncoding, line_i: usize) -> lsp_types::Range {
+        let editor = self.editor.lock().unwrap();
+        let end = editor_clamp(&editor, Cursor::new(line_i, usize::MAX));
+        lsp_types::Range::new(
+            editor_lsp_position(&editor, encoding, Cursor::new(end.line, 0)),
+            editor_lsp_position(&editor, encoding, end),
+        )
+    }
+
+    /// The cursor, the start of the word before it and the word, which is completed
+    pub fn completion_word(&self) -> (Cursor, Cursor, String) {
+        let editor = self.editor.lock().unwrap();
+        let cursor = editor.cursor();
+        editor.with_buffer(|buffer| {
+            let text = buffer
+                .lines
+                .get(cursor.line)
+                .and_then(|line| line.text().get(..cursor.index))
+                .unwrap_or_default();
+            let start = text
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| is_word_char(*c))
+                .last()
+                .map_or(text.len(), |(i, _)| i);
+            (
+                cursor,
+                Cursor::new(cursor.line, start),
+                text[start..].to_string(),
+            )
+        })
+    }
+
+    /// The word around the cursor, which is looked up when navigating without a language server
+    pub fn cursor_word(&self) -> String {
+        let editor = self.editor.lock().unwrap();
+        let cursor = editor.cursor();
+        editor.with_buffer(|buffer| {
+            let Some(text) = buffer.lines.get(cursor.line).map(|line| line.text()) else {
+                return String::new();
+            };
+            let index = cursor.index.min(text.len());
+            let start = text[..index]
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| is_word_char(*c))
+                .last()
+                .map_or(index, |(i, _)| i);
+            let end = text[index..]
+                .char_indices()
+                .find(|(_, c)| !is_word_char(*c))
+                .map_or(text.len(), |(i, _)| index + i);
+            text[start..end].to_string()
+        })
+    }
+
+    /// Insert a completion item of the language server as a single change for undo. Unless the
+    /// item has its own edit, the word from `word_start` to the cursor is replaced.
+    /// `request_cursor` is where the cursor was when completions were requested, text typed after
+    /// it is replaced as well.
+    pub fn insert_lsp_completion(
+        &mut self,
+        encoding: LspPositionEncoding,
+        item: &lsp_types::CompletionItem,
+        word_start: Cursor,
+        request_cursor: Cursor,
+    ) {
+        let mut editor = self.editor.lock().unwrap();
+        let cursor = editor.cursor();
+        let lsp_cursor = |position| editor_lsp_cursor(&editor, encoding, position);
+        let (start, end, new_text) = match &item.text_edit {
+            Some(lsp_types::CompletionTextEdit::Edit(edit)) => (
+                lsp_cursor(edit.range.start),
+                lsp_cursor(edit.range.end),
+                edit.new_text.as_str(),
+            ),
+            Some(lsp_types::CompletionTextEdit::InsertAndReplace(edit)) => (
+                lsp_cursor(edit.insert.start),
+                lsp_cursor(edit.insert.end),
+                edit.new_text.as_str(),
+            ),
+            None => (
+                word_start,
+                request_cursor,
+                item.insert_text.as_deref().unwrap_or(&item.label),
+            ),
+        };
+        let mut end = end;
+        if end.line == cursor.line
+            && request_cursor.line == cursor.line
+            && cursor.index >= request_cursor.index
+        {
+            end.index += cursor.index - request_cursor.index;
+        }
+        let mut snippet = if item.insert_text_format == Some(lsp_types::InsertTextFormat::SNIPPET) {
+            Snippet::parse(new_text)
+        } else {
+            Snippet::plain(new_text)
+        };
+        let indent: String = editor.with_buffer(|buffer| {
+            buffer.lines.get(start.line).map_or(String::new(), |line| {
+                line.text()
+                    .chars()
+                    .take_while(|c| c.is_whitespace())
+                    .collect()
+            })
+        });
+        snippet.indent(&indent);
+
+        // Edits like imports are applied from the end, so their positions stay valid. Edits
+        // overlapping the completion are invalid.
+        let mut edits: Vec<(Cursor, Cursor, &str, bool)> = item
+            .additional_text_edits
+            .iter()
+            .flatten()
+            .map(|edit| {
+                (
+                    lsp_cursor(edit.range.start),
+                    lsp_cursor(edit.range.end),
+                    edit.new_text.as_str(),
+                    false,
+                )
+            })
+            .filter(|(edit_start, edit_end, _, _)| *edit_end <= start || *edit_start >= end)
+            .collect();
+        edits.push((start, end, &snippet.text, true));
+        edits.sort_by_key(|(edit_start, _, _, _)| Reverse(*edit_start));
+
+        editor.start_change();
+        let mut snippet_start = start;
+        for (edit_start, edit_end, text, completion) in edits {
+            editor.delete_range(edit_start, edit_end);
+            editor.insert_at(edit_start, text, None);
+            if !completion && edit_end <= start {
+                snippet_start = shift_cursor(snippet_start, edit_start, edit_end, text);
+            }
+        }
+        self.snippet_opt = SnippetSession::new(&snippet, snippet_start);
+        match &mut self.snippet_opt {
+            Some(session) => {
+                let (tab_stop_start, tab_stop_end) = session.tab_stops[0];
+                editor_select(&mut editor, tab_stop_start, tab_stop_end);
+                editor.with_buffer(|buffer| {
+                    session.selected(buffer.lines.len(), |line_i| {
+                        buffer.lines.get(line_i).map_or(0, |line| line.text().len())
+                    })
+                });
+            }
+            None => {
+                let final_end = snippet.tab_stops.last().map_or(0, |range| range.end);
+                let cursor = advance_cursor(snippet_start, &snippet.text[..final_end]);
+                editor_select(&mut editor, cursor, cursor);
+            }
+        }
+        editor.finish_change();
+    }
+
+    /// Select the next or previous tab stop of the inserted snippet
+    pub fn snippet_tab_stop(&mut self, backwards: bool) {
+        let Some(session) = self.snippet_opt.as_mut() else {
+            return;
+        };
+        let mut editor = self.editor.lock().unwrap();
+        let tab_stop_opt = editor.with_buffer(|buffer| {
+            session.advance(backwards, buffer.lines.len(), |line_i| {
+                buffer.lines.get(line_i).map_or(0, |line| line.text().len())
+            })
+        });
+        match tab_stop_opt {
+            Some((start, end)) => {
+                // Positions may be off if the buffer was edited elsewhere
+                let start = editor_clamp(&editor, start);
+                let end = editor_clamp(&editor, end);
+                editor_select(&mut editor, start, end);
+                if session.is_final() {
+                    self.snippet_opt = None;
+                }
+            }
+            None => self.snippet_opt = None,
+        }
+    }
+
+    /// Apply edits of a language server as a single change for undo, keeping the cursor, the
+    /// selection and the scroll at the same place in the text. Edits must not overlap.
+    pub fn apply_lsp_edits(
+        &mut self,
+        encoding: LspPositionEncoding,
+        edits: &[lsp_types::TextEdit],
+    ) -> Result<(), String> {
+        let mut editor = self.editor.lock().unwrap();
+        let mut ranges: Vec<(Cursor, Cursor, &str)> = edits
+            .iter()
+            .map(|edit| {
+                let start = editor_lsp_cursor(&editor, encoding, edit.range.start);
+                let end = editor_lsp_cursor(&editor, encoding, edit.range.end).max(start);
+                (start, end, edit.new_text.as_str())
+            })
+            .collect();
+        // Edits at the same position are inserted in order
+        ranges.sort_by_key(|(start, _, _)| *start);
+        if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+            return Err("edits overlap".to_string());
+        }
+
+        // Edits are applied from the end, so the positions of the others stay valid
+        let shift = |cursor| {
+            ranges
+                .iter()
+                .rev()
+                .fold(cursor, |cursor, (start, end, text)| {
+                    shift_cursor(cursor, *start, *end, text)
+                })
+        };
+        let cursor = shift(editor.cursor());
+        let selection = match editor.selection() {
+            Selection::None => Selection::None,
+            Selection::Normal(anchor) => Selection::Normal(shift(anchor)),
+            Selection::Line(anchor) => Selection::Line(shift(anchor)),
+            Selection::Word(anchor) => Selection::Word(shift(anchor)),
+        };
+        let mut scroll = editor.with_buffer(|buffer| buffer.scroll());
+        scroll.line = shift(Cursor::new(scroll.line, 0)).line;
+        editor.start_change();
+        for (start, end, text) in ranges.iter().rev() {
+            editor.delete_range(*start, *end);
+            editor.insert_at(*start, text, None);
+        }
+        editor.set_cursor(cursor);
+        editor.set_selection(selection);
+        editor.finish_change();
+        editor.with_buffer_mut(|buffer| buffer.set_scroll(scroll));
+        self.snippet_opt = None;
+        Ok(())
+    }
+
+    /// Replace a conflict in `git_conflicts` with the sections of a choice
+    pub fn resolve_git_conflict(&self, conflict_i: usize, choice: GitConflictChoice) -> bool {
+        let Some(conflict) = self.git_conflicts.get(conflict_i) else {
+            return false;
+        };
+        let new_text: String = self.editor.lock().unwrap().with_buffer(|buffer| {
+            conflict
+                .resolution(choice)
+                .into_iter()
+                .flatten()
+                .filter_map(|line_i| buffer.lines.get(line_i))
+                .flat_map(|line| [line.text(), line.ending().as_str()])
+                .collect()
+        });
+        self.replace_lines(conflict.lines(), &new_text);
+        true
+    }
+
+    /// Replace whole lines of the buffer with `text`, which keeps the line endings of each line,
+    /// as a single change for undo
+    fn replace_lines(&self, lines: Range<usize>, text: &str) {
+        let mut editor = self.editor.lock().unwrap();
+        let (line_count, last_len) = editor.with_buffer(|buffer| {
+            let last_len = buffer.lines.last().map_or(0, |line| line.text().len());
+            (buffer.lines.len(), last_len)
+        });
+        let last_line = line_count.saturating_sub(1);
+        let (cursor_start, cursor_end, text) = if lines.start >= line_count {
+            // The last line has no line ending, so one is needed before appending lines
+            let cursor = Cursor::new(last_line, last_len);
+            (cursor, cursor, format!("\n{}", text))
+        } else if lines.end < line_count {
+            // Replace whole lines including their line endings
+            (
+                Cursor::new(lines.start, 0),
+                Cursor::new(lines.end, 0),
+                text.to_string(),
+            )
+        } else {
+            (
+                Cursor::new(lines.start, 0),
+                Cursor::new(last_line, last_len),
+                text.to_string(),
+            )
+        };
+
+        // Store the entire operation as a single change for undo
+        editor.start_change();
+        editor.delete_range(cursor_start, cursor_end);
+        let cursor = editor.insert_at(cursor_start, &text, None);
+        editor.set_cursor(cursor);
+        editor.set_selection(Selection::None);
+        editor.finish_change();
+    }
+
     pub fn icon(&self, size: u16) -> icon::Icon {
-        match &self.path_opt {
+        let revision_path_opt = self.revision_opt.as_ref().map(|revision| &revision.path);
+        match self.path_opt.as_ref().or(revision_path_opt) {
             Some(path) => icon::icon(mime_icon(mime_for_path(path, None, false), size)).size(size),
             None => icon::from_name(FALLBACK_MIME_ICON).size(size).icon(),
         }
     }
 
     pub fn title(&self) -> String {
+        if let Some(revision) = &self.revision_opt {
+            return format!("{} @ {}", file_title(&revision.path), revision.short_hash);
+        }
+
         //TODO: show full title when there is a conflict
         if let Some(path) = &self.path_opt {
-            match path.file_name() {
-                Some(file_name_os) => match file_name_os.to_str() {
-                    Some(file_name) => match file_name {
-                        "mod.rs" => title_with_parent(path, file_name),
-                        _ => file_name.to_string(),
-                    },
-                    None => format!("{}", path.display()),
-                },
-                None => format!("{}", path.display()),
-            }
+            file_title(path)
         } else {
             fl!("new-document")
         }
     }
 
+    pub fn read_only(&self) -> bool {
+        self.revision_opt.is_some()
+    }
+
     pub fn replace(&self, regex: &Regex, replace: &str, wrap_around: bool) -> bool {
         let mut editor = self.editor.lock().unwrap();
         let mut cursor = editor.cursor();
@@ -467,6 +1411,19 @@ impl EditorTab {
     }
 }
 
+pub fn file_title(path: &Path) -> String {
+    match path.file_name() {
+        Some(file_name_os) => match file_name_os.to_str() {
+            Some(file_name) => match file_name {
+                "mod.rs" => title_with_parent(path, file_name),
+                _ => file_name.to_string(),
+            },
+            None => format!("{}", path.display()),
+        },
+        None => format!("{}", path.display()),
+    }
+}
+
 /// Includes parent name in tab title
 ///
 /// Useful for distinguishing between Rust modules named `mod.rs`
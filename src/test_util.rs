@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Helpers shared by the tests of several modules
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+use tempfile::TempDir;
+
+/// Run `future` to completion on a runtime like the one of the application
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Temporary folder to set up git repositories in, removed when dropped
+pub struct GitFixture {
+    dir: TempDir,
+}
+
+impl GitFixture {
+    pub fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Path of `name` in the folder
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Run git in the folder and return its output, panicking if it fails
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = process::Command::new("git")
+            .arg("-C")
+            .arg(self.dir.path())
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?} {:?}", args, output);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Create a repository at `name` with branch `main` and an identity to commit with, which
+    /// git commands run by the tested code use too
+    pub fn init(&self, name: &str) {
+        self.git(&["init", "--quiet", "--initial-branch=main", name]);
+        self.git(&["-C", name, "config", "user.name", "Test"]);
+        self.git(&["-C", name, "config", "user.email", "test@example.com"]);
+    }
+
+    /// Write `text` to the file `name`, creating its parent folders
+    pub fn write(&self, name: &str, text: &str) {
+        let path = self.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+}
@@ -2,7 +2,7 @@
 
 use cosmic::{
     Renderer,
-    cosmic_theme::palette::{WithAlpha, blend::Compose},
+    cosmic_theme::palette::{Srgba, WithAlpha, blend::Compose},
     iced::core::{
         Border, Radians, Shell, Transformation,
         clipboard::Clipboard,
@@ -39,7 +39,41 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::{LINE_NUMBER_CACHE, SWASH_CACHE, line_number::LineNumberKey};
+use crate::{
+    LINE_NUMBER_CACHE, SWASH_CACHE,
+    git::{GitConflict, GitDiffHunk, GitLineChange},
+    line_number::{LineNumberKey, layout_monospace},
+    lsp::LspDiagnostic,
+};
+
+/// Width of the git blame gutter in characters
+pub const GIT_BLAME_CHARS: usize = 40;
+
+/// Why completions are asked for
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CompletionTrigger {
+    /// A character was typed
+    Typed(char),
+    /// Text before or after the cursor was deleted
+    Deleted,
+    /// Ctrl+Space was pressed
+    Invoked,
+    /// Escape was pressed
+    Cancelled,
+}
+
+/// Keys that pick completions or move between the tab stops of a snippet instead of editing
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CompletionKey {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    Tab,
+    BackTab,
+    Escape,
+}
 
 pub struct TextBox<'a, Message> {
     editor: &'a Mutex<ViEditor<'static, 'static>>,
@@ -53,7 +87,20 @@ pub struct TextBox<'a, Message> {
     has_context_menu: bool,
     on_context_menu: Option<Box<dyn Fn(Option<Point>) -> Message + 'a>>,
     highlight_current_line: bool,
+    read_only: bool,
     line_numbers: bool,
+    git_hunks: Option<&'a [GitDiffHunk]>,
+    on_git_marker: Option<Box<dyn Fn(usize) -> Message + 'a>>,
+    git_blame: Option<&'a [String]>,
+    on_git_blame: Option<Box<dyn Fn(usize) -> Message + 'a>>,
+    on_git_blame_hover: Option<Box<dyn Fn(Option<(usize, Point)>) -> Message + 'a>>,
+    git_conflicts: Option<&'a [GitConflict]>,
+    lsp_diagnostics: Option<&'a [LspDiagnostic]>,
+    on_completion: Option<Box<dyn Fn(CompletionTrigger, Point) -> Message + 'a>>,
+    on_completion_key: Option<Box<dyn Fn(CompletionKey) -> Message + 'a>>,
+    on_snippet_key: Option<Box<dyn Fn(CompletionKey) -> Message + 'a>>,
+    on_ctrl_click: Option<Message>,
+    on_code_actions: Option<Box<dyn Fn(Option<usize>, Point) -> Message + 'a>>,
 }
 
 impl<'a, Message> TextBox<'a, Message>
@@ -73,7 +120,20 @@ where
             has_context_menu: false,
             on_context_menu: None,
             highlight_current_line: false,
+            read_only: false,
             line_numbers: false,
+            git_hunks: None,
+            on_git_marker: None,
+            git_blame: None,
+            on_git_blame: None,
+            on_git_blame_hover: None,
+            git_conflicts: None,
+            lsp_diagnostics: None,
+            on_completion: None,
+            on_completion_key: None,
+            on_snippet_key: None,
+            on_ctrl_click: None,
+            on_code_actions: None,
         }
     }
 
@@ -92,6 +152,7 @@ where
         self
     }
 
+    /// Called when the changed flag or the vi mode changes, and after every edit
     pub fn on_changed(mut self, on_changed: Message) -> Self {
         self.on_changed = Some(on_changed);
         self
@@ -120,23 +181,138 @@ where
         self
     }
 
+    /// Allow moving the cursor and selecting, but not editing
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
     pub fn line_numbers(mut self) -> Self {
         self.line_numbers = true;
         self
     }
 
+    /// Draw change markers for `git_hunks` in the gutter
+    pub fn git_hunks(mut self, git_hunks: &'a [GitDiffHunk]) -> Self {
+        self.git_hunks = Some(git_hunks);
+        self
+    }
+
+    /// Called with the line index when the gutter is clicked
+    pub fn on_git_marker(mut self, on_git_marker: impl Fn(usize) -> Message + 'a) -> Self {
+        self.on_git_marker = Some(Box::new(on_git_marker));
+        self
+    }
+
+    /// Draw a git blame gutter with a label for each line, empty labels are skipped
+    pub fn git_blame(mut self, git_blame: &'a [String]) -> Self {
+        self.git_blame = Some(git_blame);
+        self
+    }
+
+    /// Called with the line index when the git blame gutter is clicked
+    pub fn on_git_blame(mut self, on_git_blame: impl Fn(usize) -> Message + 'a) -> Self {
+        self.on_git_blame = Some(Box::new(on_git_blame));
+        self
+    }
+
+    /// Called with the line index and cursor position when hovering over the git blame gutter
+    pub fn on_git_blame_hover(
+        mut self,
+        on_git_blame_hover: impl Fn(Option<(usize, Point)>) -> Message + 'a,
+    ) -> Self {
+        self.on_git_blame_hover = Some(Box::new(on_git_blame_hover));
+        self
+    }
+
+    /// Highlight the sections of merge conflicts, which must be sorted
+    pub fn git_conflicts(mut self, git_conflicts: &'a [GitConflict]) -> Self {
+        self.git_conflicts = Some(git_conflicts);
+        self
+    }
+
+    /// Underline diagnostics of a language server and mark their lines in the gutter, they must
+    /// be sorted
+    pub fn lsp_diagnostics(mut self, lsp_diagnostics: &'a [LspDiagnostic]) -> Self {
+        self.lsp_diagnostics = Some(lsp_diagnostics);
+        self
+    }
+
+    /// Called with the position below the cursor when characters are typed or deleted, when
+    /// completions are asked for with Ctrl+Space, and when Escape is pressed
+    pub fn on_completion(
+        mut self,
+        on_completion: impl Fn(CompletionTrigger, Point) -> Message + 'a,
+    ) -> Self {
+        self.on_completion = Some(Box::new(on_completion));
+        self
+    }
+
+    /// Send the keys that pick completions here instead of editing, while completions are shown
+    pub fn on_completion_key(
+        mut self,
+        on_completion_key: impl Fn(CompletionKey) -> Message + 'a,
+    ) -> Self {
+        self.on_completion_key = Some(Box::new(on_completion_key));
+        self
+    }
+
+    /// Send Tab and Shift+Tab here instead of indenting while a snippet is inserted
+    pub fn on_snippet_key(
+        mut self,
+        on_snippet_key: impl Fn(CompletionKey) -> Message + 'a,
+    ) -> Self {
+        self.on_snippet_key = Some(Box::new(on_snippet_key));
+        self
+    }
+
+    /// Called after Ctrl+click moves the cursor, instead of selecting by dragging
+    pub fn on_ctrl_click(mut self, on_ctrl_click: Message) -> Self {
+        self.on_ctrl_click = Some(on_ctrl_click);
+        self
+    }
+
+    /// Called with a position below the cursor when Ctrl+. is pressed, or with the line index and
+    /// a position below its marker when a diagnostic marker is clicked. The diagnostic marker at
+    /// the cursor is drawn as a lightbulb.
+    pub fn on_code_actions(
+        mut self,
+        on_code_actions: impl Fn(Option<usize>, Point) -> Message + 'a,
+    ) -> Self {
+        self.on_code_actions = Some(Box::new(on_code_actions));
+        self
+    }
+
     pub fn on_focus(mut self, on_focus: Message) -> Self {
         self.on_focus = Some(on_focus);
         self
     }
 
+    /// Position below the cursor relative to the widget, where popups about the cursor are shown
+    fn cursor_point(
+        &self,
+        editor: &mut BorrowedWithFontSystem<'_, ViEditor<'static, 'static>>,
+        state: &State,
+        scale_factor: f32,
+    ) -> Point {
+        editor.shape_as_needed(false);
+        let (caret_x, caret_y) = editor.cursor_position().unwrap_or(state.caret_position);
+        let scroll_x = editor.with_buffer(|buffer| buffer.scroll().horizontal);
+        let line_height = self.metrics.scale(scale_factor).line_height;
+        Point::new(
+            self.padding.left
+                + (caret_x as f32 + state.editor_offset_x.get() as f32 - scroll_x) / scale_factor,
+            self.padding.top + (caret_y as f32 + line_height) / scale_factor,
+        )
+    }
+
     fn input_method<'b>(
         &self,
         state: &'b State,
         scale_factor: f32,
         layout: Layout<'_>,
     ) -> InputMethod<&'b str> {
-        if !state.is_focused {
+        if !state.is_focused || self.read_only {
             return InputMethod::Disabled;
         };
 
@@ -263,6 +439,20 @@ fn draw_rect(
     }
 }
 
+/// Color of diagnostics with `severity`
+fn lsp_severity_color(
+    cosmic_theme: &cosmic::cosmic_theme::Theme,
+    severity: lsp_types::DiagnosticSeverity,
+) -> cosmic_text::Color {
+    let color = match severity {
+        lsp_types::DiagnosticSeverity::ERROR => cosmic_theme.destructive_color(),
+        lsp_types::DiagnosticSeverity::WARNING => cosmic_theme.warning_color(),
+        _ => cosmic_theme.accent_color(),
+    };
+    let color: Srgba<u8> = color.into_format();
+    cosmic_text::Color::rgba(color.red, color.green, color.blue, color.alpha)
+}
+
 struct CustomRenderer<'a> {
     renderer: &'a mut Renderer,
     pos: Point,
@@ -441,8 +631,19 @@ where
         // Lock font system (used throughout)
         let mut font_system = font_system().write().unwrap();
 
+        // Reserve space for git blame before the line numbers
+        let git_blame_w = if self.git_blame.is_some() {
+            let width = layout_monospace(font_system.raw(), " ".repeat(GIT_BLAME_CHARS))
+                .first()
+                .map_or(0.0, |layout_line| layout_line.w * metrics.font_size);
+            (width + 8.0).ceil() as i32
+        } else {
+            0
+        };
+        state.git_blame_w.set(git_blame_w);
+
         // Calculate line number information
-        let (line_number_chars, editor_offset_x) = if self.line_numbers {
+        let (line_number_chars, line_number_w) = if self.line_numbers {
             // Calculate number of characters needed in line number
             let mut line_number_chars = 1;
             let mut line_count = editor.with_buffer(|buffer| buffer.lines.len());
@@ -477,6 +678,26 @@ where
             (0, 0)
         };
 
+        // Reserve space for diagnostic markers after the line numbers
+        let lsp_marker_x = git_blame_w + line_number_w;
+        let lsp_marker_w = if self.lsp_diagnostics.is_some() {
+            (metrics.font_size / 2.0).ceil() as i32
+        } else {
+            0
+        };
+        state
+            .lsp_marker_x
+            .set((lsp_marker_x, lsp_marker_x + lsp_marker_w));
+
+        // Reserve space for git change markers after the diagnostic markers
+        let git_marker_x = lsp_marker_x + lsp_marker_w;
+        let git_marker_w = if self.git_hunks.is_some() {
+            (3.0 * scale_factor).ceil() as i32
+        } else {
+            0
+        };
+        let editor_offset_x = git_marker_x + git_marker_w * 2;
+
         // Save editor offset in state
         if state.editor_offset_x.replace(editor_offset_x) != editor_offset_x {
             // Mark buffer as needing redraw if editor offset has changed
@@ -514,7 +735,7 @@ where
                 };
 
                 //TODO: draw line numbers using iced functions for performance
-                if self.line_numbers {
+                if editor_offset_x > 0 {
                     let (gutter, gutter_foreground) = {
                         let convert_color = |color: syntect::highlighting::Color| {
                             cosmic_text::Color::rgba(color.r, color.g, color.b, color.a)
@@ -545,63 +766,285 @@ where
 
                     // Draw line numbers
                     //TODO: move to cosmic-text?
-                    editor.with_buffer(|buffer| {
-                        let mut line_number_cache =
-                            LINE_NUMBER_CACHE.get().unwrap().lock().unwrap();
-                        let mut last_line_number = 0;
-                        for run in buffer.layout_runs() {
-                            let line_number = run.line_i.saturating_add(1);
-                            if line_number == last_line_number {
-                                // Skip duplicate lines
-                                continue;
-                            } else {
-                                last_line_number = line_number;
+                    if self.line_numbers {
+                        editor.with_buffer(|buffer| {
+                            let mut line_number_cache =
+                                LINE_NUMBER_CACHE.get().unwrap().lock().unwrap();
+                            let mut last_line_number = 0;
+                            for run in buffer.layout_runs() {
+                                let line_number = run.line_i.saturating_add(1);
+                                if line_number == last_line_number {
+                                    // Skip duplicate lines
+                                    continue;
+                                } else {
+                                    last_line_number = line_number;
+                                }
+
+                                if let Some(layout_line) = line_number_cache
+                                    .get(
+                                        font_system.raw(),
+                                        LineNumberKey {
+                                            number: line_number,
+                                            width: line_number_chars,
+                                        },
+                                    )
+                                    .first()
+                                {
+                                    // These values must be scaled since layout is done at font size 1.0
+                                    let max_ascent = layout_line.max_ascent * metrics.font_size;
+                                    let max_descent = layout_line.max_descent * metrics.font_size;
+
+                                    // This code comes from cosmic_text::LayoutRunIter
+                                    let glyph_height = max_ascent + max_descent;
+                                    let centering_offset =
+                                        (metrics.line_height - glyph_height) / 2.0;
+                                    let line_y = run.line_top + centering_offset + max_ascent;
+
+                                    for layout_glyph in layout_line.glyphs.iter() {
+                                        let physical_glyph = layout_glyph.physical(
+                                            (git_blame_w as f32, line_y),
+                                            metrics.font_size,
+                                        );
+
+                                        swash_cache.with_pixels(
+                                            font_system.raw(),
+                                            physical_glyph.cache_key,
+                                            gutter_foreground,
+                                            |x, y, color| {
+                                                draw_rect(
+                                                    pixels,
+                                                    image_canvas,
+                                                    Canvas { w: 1, h: 1 },
+                                                    Offset {
+                                                        x: physical_glyph.x + x,
+                                                        y: physical_glyph.y + y,
+                                                    },
+                                                    color,
+                                                );
+                                            },
+                                        );
+                                    }
+                                }
                             }
+                        });
+                    }
 
-                            if let Some(layout_line) = line_number_cache
-                                .get(
-                                    font_system.raw(),
-                                    LineNumberKey {
-                                        number: line_number,
-                                        width: line_number_chars,
-                                    },
-                                )
-                                .first()
-                            {
-                                // These values must be scaled since layout is done at font size 1.0
-                                let max_ascent = layout_line.max_ascent * metrics.font_size;
-                                let max_descent = layout_line.max_descent * metrics.font_size;
+                    // Draw git blame, labels are drawn once for wrapped lines
+                    if let Some(git_blame) = self.git_blame {
+                        editor.with_buffer(|buffer| {
+                            let mut last_line_i_opt = None;
+                            for run in buffer.layout_runs() {
+                                if last_line_i_opt == Some(run.line_i) {
+                                    continue;
+                                }
+                                last_line_i_opt = Some(run.line_i);
 
-                                // This code comes from cosmic_text::LayoutRunIter
-                                let glyph_height = max_ascent + max_descent;
-                                let centering_offset = (metrics.line_height - glyph_height) / 2.0;
-                                let line_y = run.line_top + centering_offset + max_ascent;
+                                let Some(label) = git_blame.get(run.line_i) else {
+                                    continue;
+                                };
+                                if label.is_empty() {
+                                    continue;
+                                }
 
-                                for layout_glyph in layout_line.glyphs.iter() {
-                                    let physical_glyph =
-                                        layout_glyph.physical((0., line_y), metrics.font_size);
+                                for layout_line in
+                                    layout_monospace(font_system.raw(), label.clone()).iter()
+                                {
+                                    // These values must be scaled since layout is done at font size 1.0
+                                    let max_ascent = layout_line.max_ascent * metrics.font_size;
+                                    let max_descent = layout_line.max_descent * metrics.font_size;
+                                    let glyph_height = max_ascent + max_descent;
+                                    let centering_offset =
+                                        (metrics.line_height - glyph_height) / 2.0;
+                                    let line_y = run.line_top + centering_offset + max_ascent;
+
+                                    for layout_glyph in layout_line.glyphs.iter() {
+                                        let physical_glyph =
+                                            layout_glyph.physical((4.0, line_y), metrics.font_size);
+                                        swash_cache.with_pixels(
+                                            font_system.raw(),
+                                            physical_glyph.cache_key,
+                                            gutter_foreground,
+                                            |x, y, color| {
+                                                // Clip to the blame gutter
+                                                if physical_glyph.x + x < git_blame_w - 4 {
+                                                    draw_rect(
+                                                        pixels,
+                                                        image_canvas,
+                                                        Canvas { w: 1, h: 1 },
+                                                        Offset {
+                                                            x: physical_glyph.x + x,
+                                                            y: physical_glyph.y + y,
+                                                        },
+                                                        color,
+                                                    );
+                                                }
+                                            },
+                                        );
+                                    }
+                                }
+                            }
+                        });
+                    }
 
-                                    swash_cache.with_pixels(
-                                        font_system.raw(),
-                                        physical_glyph.cache_key,
+                    // Draw diagnostic markers with the color of the most severe diagnostic
+                    if let Some(lsp_diagnostics) = self.lsp_diagnostics {
+                        let lightbulb_line_opt =
+                            self.on_code_actions.as_ref().map(|_| editor.cursor().line);
+                        editor.with_buffer(|buffer| {
+                            let mut last_line_i_opt = None;
+                            for run in buffer.layout_runs() {
+                                if last_line_i_opt == Some(run.line_i) {
+                                    continue;
+                                }
+                                last_line_i_opt = Some(run.line_i);
+
+                                let Some(severity) = lsp_diagnostics
+                                    .iter()
+                                    .filter(|diagnostic| diagnostic.start.line == run.line_i)
+                                    .map(|diagnostic| diagnostic.severity)
+                                    .min()
+                                else {
+                                    continue;
+                                };
+                                let color = lsp_severity_color(cosmic_theme, severity);
+                                if lightbulb_line_opt == Some(run.line_i) {
+                                    // Bulb above a narrower base
+                                    let size = (lsp_marker_w * 2 / 3).max(2);
+                                    let base = Canvas {
+                                        w: (size / 2).max(1),
+                                        h: (size / 3).max(1),
+                                    };
+                                    let x = lsp_marker_x + (lsp_marker_w - size) / 2;
+                                    let y = (run.line_top
+                                        + (run.line_height - (size + 1 + base.h) as f32) / 2.0)
+                                        as i32;
+                                    draw_rect(
+                                        pixels,
+                                        image_canvas,
+                                        Canvas { w: size, h: size },
+                                        Offset { x, y },
+                                        color,
+                                    );
+                                    draw_rect(
+                                        pixels,
+                                        image_canvas,
+                                        base,
+                                        Offset {
+                                            x: x + (size - base.w) / 2,
+                                            y: y + size + 1,
+                                        },
                                         gutter_foreground,
-                                        |x, y, color| {
-                                            draw_rect(
-                                                pixels,
-                                                image_canvas,
-                                                Canvas { w: 1, h: 1 },
-                                                Offset {
-                                                    x: physical_glyph.x + x,
-                                                    y: physical_glyph.y + y,
-                                                },
-                                                color,
-                                            );
+                                    );
+                                    continue;
+                                }
+                                let size = lsp_marker_w / 2;
+                                draw_rect(
+                                    pixels,
+                                    image_canvas,
+                                    Canvas { w: size, h: size },
+                                    Offset {
+                                        x: lsp_marker_x + (lsp_marker_w - size) / 2,
+                                        y: (run.line_top + (run.line_height - size as f32) / 2.0)
+                                            as i32,
+                                    },
+                                    color,
+                                );
+                            }
+                        });
+                    }
+
+                    // Draw git change markers
+                    if let Some(git_hunks) = self.git_hunks {
+                        let convert_color = |color: Srgba| {
+                            let color: Srgba<u8> = color.into_format();
+                            cosmic_text::Color::rgba(
+                                color.red,
+                                color.green,
+                                color.blue,
+                                color.alpha,
+                            )
+                        };
+                        let added = convert_color(cosmic_theme.success_color());
+                        let modified = convert_color(cosmic_theme.accent_color());
+                        let deleted = convert_color(cosmic_theme.destructive_color());
+                        editor.with_buffer(|buffer| {
+                            let line_count = buffer.lines.len();
+                            let mut last_run_opt = None;
+                            for run in buffer.layout_runs() {
+                                // Hunks are sorted, find the first that ends after this line
+                                let hunk_i = git_hunks
+                                    .partition_point(|hunk| hunk.new_lines().end < run.line_i);
+                                for hunk in git_hunks[hunk_i..].iter() {
+                                    let lines = hunk.new_lines();
+                                    if lines.start > run.line_i {
+                                        break;
+                                    }
+                                    let (y, h, color) = match hunk.change() {
+                                        GitLineChange::Added | GitLineChange::Modified => {
+                                            if !lines.contains(&run.line_i) {
+                                                continue;
+                                            }
+                                            let color = if hunk.change() == GitLineChange::Added {
+                                                added
+                                            } else {
+                                                modified
+                                            };
+                                            (run.line_top, run.line_height, color)
+                                        }
+                                        GitLineChange::Deleted => {
+                                            if lines.start != run.line_i {
+                                                continue;
+                                            }
+                                            // Mark the boundary before this line
+                                            (
+                                                run.line_top - run.line_height / 4.0,
+                                                run.line_height / 2.0,
+                                                deleted,
+                                            )
+                                        }
+                                    };
+                                    draw_rect(
+                                        pixels,
+                                        image_canvas,
+                                        Canvas {
+                                            w: git_marker_w,
+                                            h: h.ceil() as i32,
                                         },
+                                        Offset {
+                                            x: git_marker_x,
+                                            y: y as i32,
+                                        },
+                                        color,
                                     );
                                 }
+                                last_run_opt = Some((run.line_i, run.line_top + run.line_height));
                             }
-                        }
-                    });
+
+                            // Deletions at the end of the buffer are marked after the last line
+                            if let Some((line_i, line_bottom)) = last_run_opt {
+                                if line_i + 1 == line_count
+                                    && git_hunks.last().is_some_and(|hunk| {
+                                        hunk.change() == GitLineChange::Deleted
+                                            && hunk.new_lines().start == line_count
+                                    })
+                                {
+                                    draw_rect(
+                                        pixels,
+                                        image_canvas,
+                                        Canvas {
+                                            w: git_marker_w,
+                                            h: (metrics.line_height / 2.0).ceil() as i32,
+                                        },
+                                        Offset {
+                                            x: git_marker_x,
+                                            y: (line_bottom - metrics.line_height / 4.0) as i32,
+                                        },
+                                        deleted,
+                                    );
+                                }
+                            }
+                        });
+                    }
                 }
 
                 // Calculate scrollbar
@@ -751,6 +1194,103 @@ where
                         });
                     }
 
+                    // Draw merge conflict sections, with stronger colors for the markers
+                    if let Some(git_conflicts) = self.git_conflicts {
+                        let convert_color = |color: Srgba, alpha: f32| {
+                            let color: Srgba<u8> =
+                                color.without_alpha().with_alpha(alpha).into_format();
+                            cosmic_text::Color::rgba(
+                                color.red,
+                                color.green,
+                                color.blue,
+                                color.alpha,
+                            )
+                        };
+                        let ours = cosmic_theme.success_color();
+                        let base = cosmic_theme.warning_color();
+                        let theirs = cosmic_theme.accent_color();
+                        editor.with_buffer(|buffer| {
+                            for run in buffer.layout_runs() {
+                                let line_i = run.line_i;
+                                let conflict_i =
+                                    git_conflicts.partition_point(|conflict| conflict.end < line_i);
+                                let Some(conflict) = git_conflicts
+                                    .get(conflict_i)
+                                    .filter(|conflict| conflict.start <= line_i)
+                                else {
+                                    continue;
+                                };
+                                let (color, marker) = if line_i < conflict.ours.end {
+                                    (ours, line_i == conflict.start)
+                                } else if line_i + 1 < conflict.theirs.start {
+                                    // Base section of the diff3 conflict style
+                                    (base, line_i == conflict.ours.end)
+                                } else {
+                                    (
+                                        theirs,
+                                        line_i + 1 == conflict.theirs.start
+                                            || line_i == conflict.end,
+                                    )
+                                };
+                                custom_renderer.rectangle(
+                                    0,
+                                    run.line_top as i32,
+                                    (image_w - editor_offset_x) as u32,
+                                    metrics.line_height as u32,
+                                    convert_color(color, if marker { 0.4 } else { 0.15 }),
+                                );
+                            }
+                        });
+                    }
+
+                    // Draw diagnostics as wavy underlines
+                    if let Some(lsp_diagnostics) = self.lsp_diagnostics {
+                        let wave = scale_factor.ceil() as i32;
+                        editor.with_buffer(|buffer| {
+                            for run in buffer.layout_runs() {
+                                let line_i = run.line_i;
+                                let start_i = lsp_diagnostics
+                                    .partition_point(|diagnostic| diagnostic.start.line < line_i);
+                                let y = (run.line_top + run.line_height) as i32 - wave * 2;
+                                // Diagnostics ending at this line may start on earlier lines
+                                for diagnostic in
+                                    lsp_diagnostics[..start_i]
+                                        .iter()
+                                        .filter(|diagnostic| diagnostic.end.line >= line_i)
+                                        .chain(lsp_diagnostics[start_i..].iter().take_while(
+                                            |diagnostic| diagnostic.start.line == line_i,
+                                        ))
+                                {
+                                    let mut spans: Vec<(f32, f32)> =
+                                        run.highlight(diagnostic.start, diagnostic.end).collect();
+                                    // Empty ranges are marked with a short underline
+                                    if spans.is_empty()
+                                        && diagnostic.start.line == line_i
+                                        && let Some(x) = run.cursor_position(&diagnostic.start)
+                                    {
+                                        spans.push((x, metrics.font_size / 2.0));
+                                    }
+                                    let color =
+                                        lsp_severity_color(cosmic_theme, diagnostic.severity);
+                                    for (x, w) in spans {
+                                        let (x, w) = (x as i32, (w as i32).max(wave * 2));
+                                        for (i, wave_x) in
+                                            (x..x + w).step_by(wave as usize).enumerate()
+                                        {
+                                            custom_renderer.rectangle(
+                                                wave_x,
+                                                y + if i % 2 == 0 { 0 } else { wave },
+                                                wave.min(x + w - wave_x) as u32,
+                                                wave as u32,
+                                                color,
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                        });
+                    }
+
                     // Draw editor selection, cursor, etc.
                     editor.render(&mut custom_renderer);
 
@@ -1033,6 +1573,42 @@ where
             }
         }
 
+        // Set when the text may have been edited
+        let mut edited = false;
+        // Set when completions may have to be updated
+        let mut completion_trigger_opt = None;
+        let mut code_actions_invoked = false;
+
+        // Keys that pick completions or move between tab stops are not passed to the editor
+        let completion_key_opt = match event {
+            Event::Keyboard(KeyEvent::KeyPressed {
+                modified_key: Key::Named(key),
+                modifiers,
+                ..
+            }) if state.is_focused && !modifiers.control() && !modifiers.alt() => {
+                let completion_key_opt = match key {
+                    Named::ArrowUp => Some(CompletionKey::Up),
+                    Named::ArrowDown => Some(CompletionKey::Down),
+                    Named::PageUp => Some(CompletionKey::PageUp),
+                    Named::PageDown => Some(CompletionKey::PageDown),
+                    Named::Enter => Some(CompletionKey::Enter),
+                    Named::Tab if modifiers.shift() => Some(CompletionKey::BackTab),
+                    Named::Tab => Some(CompletionKey::Tab),
+                    Named::Escape => Some(CompletionKey::Escape),
+                    _ => None,
+                };
+                let on_key_opt = match key {
+                    Named::Tab => self
+                        .on_completion_key
+                        .as_ref()
+                        .or(self.on_snippet_key.as_ref()),
+                    _ => self.on_completion_key.as_ref(),
+                };
+                on_key_opt.zip(completion_key_opt)
+            }
+            _ => None,
+        };
+
         if let Some(on_focus) = self.on_focus.as_ref()
             && state.emit_focus
         {
@@ -1041,6 +1617,42 @@ where
         }
 
         match event {
+            Event::Keyboard(KeyEvent::KeyPressed {
+                modified_key,
+                modifiers,
+                ..
+            }) if state.is_focused
+                && modifiers.control()
+                && self.on_completion.is_some()
+                && !self.read_only
+                && match modified_key {
+                    Key::Named(Named::Space) => true,
+                    Key::Character(c) => c == " ",
+                    _ => false,
+                } =>
+            {
+                completion_trigger_opt = Some(CompletionTrigger::Invoked);
+                shell.capture_event();
+            }
+            Event::Keyboard(KeyEvent::KeyPressed {
+                modified_key: Key::Character(c),
+                modifiers,
+                ..
+            }) if state.is_focused
+                && modifiers.control()
+                && self.on_code_actions.is_some()
+                && !self.read_only
+                && c == "." =>
+            {
+                code_actions_invoked = true;
+                shell.capture_event();
+            }
+            Event::Keyboard(KeyEvent::KeyPressed { .. }) if completion_key_opt.is_some() => {
+                if let Some((on_key, completion_key)) = completion_key_opt {
+                    shell.publish(on_key(completion_key));
+                    shell.capture_event();
+                }
+            }
             Event::Keyboard(KeyEvent::KeyPressed {
                 modified_key: Key::Named(key),
                 modifiers,
@@ -1080,20 +1692,27 @@ where
                 }
                 Named::Escape => {
                     editor.action(Action::Escape);
+                    completion_trigger_opt = Some(CompletionTrigger::Cancelled);
                     shell.capture_event();
                 }
+                Named::Enter | Named::Backspace | Named::Delete | Named::Tab if self.read_only => {}
                 Named::Enter => {
                     editor.action(Action::Enter);
+                    edited = true;
                     shell.capture_event();
                 }
                 Named::Backspace => {
                     delete_modifiers(&mut editor, Motion::LeftWord, *modifiers);
                     editor.action(Action::Backspace);
+                    edited = true;
+                    completion_trigger_opt = Some(CompletionTrigger::Deleted);
                     shell.capture_event();
                 }
                 Named::Delete => {
                     delete_modifiers(&mut editor, Motion::RightWord, *modifiers);
                     editor.action(Action::Delete);
+                    edited = true;
+                    completion_trigger_opt = Some(CompletionTrigger::Deleted);
                     shell.capture_event();
                 }
                 Named::Tab => {
@@ -1103,6 +1722,7 @@ where
                         } else {
                             editor.action(Action::Indent);
                         }
+                        edited = true;
                         shell.capture_event();
                     }
                 }
@@ -1117,8 +1737,30 @@ where
                     .unwrap_or_default();
                 // Only parse keys when Super, Ctrl, and Alt are not pressed
                 if !state.modifiers.logo() && !state.modifiers.control() && !state.modifiers.alt() {
-                    if !character.is_control() {
+                    if !character.is_control() && !self.read_only {
+                        let cursor = editor.cursor();
+                        let line_opt = self.on_completion.as_ref().and_then(|_| {
+                            editor.with_buffer(|buffer| {
+                                Some(buffer.lines.get(cursor.line)?.text().to_string())
+                            })
+                        });
                         editor.action(Action::Insert(character));
+                        edited = true;
+                        // Keys are commands in vi modes other than insert mode, so check that
+                        // the character was inserted
+                        if let Some(line) = line_opt
+                            && editor.cursor()
+                                == Cursor::new(cursor.line, cursor.index + character.len_utf8())
+                            && editor.with_buffer(|buffer| {
+                                buffer.lines.get(cursor.line).is_some_and(|new_line| {
+                                    let new_text = new_line.text();
+                                    new_text.len() == line.len() + character.len_utf8()
+                                        && new_text[cursor.index..].starts_with(character)
+                                })
+                            })
+                        {
+                            completion_trigger_opt = Some(CompletionTrigger::Typed(character));
+                        }
                     }
                     shell.capture_event();
                 }
@@ -1151,10 +1793,11 @@ where
                     }
                 }
                 InputMethodEvent::Commit(text) => {
-                    if state.is_focused {
+                    if state.is_focused && !self.read_only {
                         editor.start_change();
                         editor.insert_string(&text, None);
                         editor.finish_change();
+                        edited = true;
                         shell.capture_event();
                     }
                 }
@@ -1168,6 +1811,7 @@ where
                     }
 
                     // Handle left click drag
+                    let mut code_actions_clicked = false;
                     if let Button::Left = button {
                         let x_logical = p.x - self.padding.left;
                         let y_logical = p.y - self.padding.top;
@@ -1235,8 +1879,61 @@ where
                             if let Some(anchor) = maybe_anchor {
                                 editor.set_selection(Selection::Normal(anchor));
                             }
+                            let ctrl_click = matches!(click_kind, ClickKind::Single)
+                                && state.modifiers.control()
+                                && maybe_anchor.is_none();
                             state.click = Some((click_kind, Instant::now()));
-                            state.dragging = Some(Dragging::Buffer);
+                            match &self.on_ctrl_click {
+                                Some(on_ctrl_click) if ctrl_click => {
+                                    shell.publish(on_ctrl_click.clone());
+                                }
+                                _ => state.dragging = Some(Dragging::Buffer),
+                            }
+                        } else if x < 0.0 && y >= 0.0 && y < buffer_size.1.unwrap_or(0.0) {
+                            // Handle clicks on the gutter
+                            let run_opt = editor.with_buffer(|buffer| {
+                                buffer
+                                    .layout_runs()
+                                    .find(|run| {
+                                        y >= run.line_top && y < run.line_top + run.line_height
+                                    })
+                                    .map(|run| (run.line_i, run.line_top + run.line_height))
+                            });
+                            let gutter_x = x + editor_offset_x as f32;
+                            let (marker_start, marker_end) = state.lsp_marker_x.get();
+                            let on_marker = gutter_x >= marker_start as f32
+                                && gutter_x < marker_end as f32
+                                && run_opt.is_some_and(|(line_i, _)| {
+                                    self.lsp_diagnostics.is_some_and(|diagnostics| {
+                                        diagnostics
+                                            .iter()
+                                            .any(|diagnostic| diagnostic.start.line == line_i)
+                                    })
+                                });
+                            match (&self.on_code_actions, run_opt) {
+                                (Some(on_code_actions), Some((line_i, line_bottom)))
+                                    if on_marker =>
+                                {
+                                    let point = Point::new(
+                                        self.padding.left + marker_end as f32 / scale_factor,
+                                        self.padding.top + line_bottom / scale_factor,
+                                    );
+                                    shell.publish(on_code_actions(Some(line_i), point));
+                                    code_actions_clicked = true;
+                                }
+                                _ => {
+                                    let on_gutter = if gutter_x < state.git_blame_w.get() as f32 {
+                                        &self.on_git_blame
+                                    } else {
+                                        &self.on_git_marker
+                                    };
+                                    if let (Some(on_gutter), Some((line_i, _))) =
+                                        (on_gutter, run_opt)
+                                    {
+                                        shell.publish(on_gutter(line_i));
+                                    }
+                                }
+                            }
                         } else if let Some(scrollbar_v_rect) = scrollbar_v_rect {
                             if scrollbar_v_rect.contains(Point::new(x_logical, y_logical)) {
                                 state.dragging = Some(Dragging::ScrollbarV {
@@ -1263,8 +1960,10 @@ where
                         }
                     }
 
-                    // Update context menu state
-                    if let Some(on_context_menu) = &self.on_context_menu {
+                    // Update context menu state, code actions replace it
+                    if let Some(on_context_menu) = &self.on_context_menu
+                        && !code_actions_clicked
+                    {
                         shell.publish((on_context_menu)(if self.has_context_menu {
                             None
                         } else {
@@ -1290,6 +1989,37 @@ where
                 }
             }
             Event::Mouse(MouseEvent::CursorMoved { .. }) => {
+                // Track hovering over the git blame gutter
+                if let Some(on_git_blame_hover) = &self.on_git_blame_hover {
+                    let hover_opt = match cursor_position.position_in(layout.bounds()) {
+                        Some(p) if state.dragging.is_none() => {
+                            let x = (p.x - self.padding.left) * scale_factor;
+                            let y = (p.y - self.padding.top) * scale_factor;
+                            if x >= 0.0 && x < state.git_blame_w.get() as f32 {
+                                editor
+                                    .with_buffer(|buffer| {
+                                        buffer
+                                            .layout_runs()
+                                            .find(|run| {
+                                                y >= run.line_top
+                                                    && y < run.line_top + run.line_height
+                                            })
+                                            .map(|run| run.line_i)
+                                    })
+                                    .map(|line_i| (line_i, p))
+                            } else {
+                                None
+                            }
+                        }
+                        _ => None,
+                    };
+                    let line_i_opt = hover_opt.map(|(line_i, _)| line_i);
+                    if state.git_blame_hover != line_i_opt {
+                        state.git_blame_hover = line_i_opt;
+                        shell.publish(on_git_blame_hover(hover_opt));
+                    }
+                }
+
                 if let Some(dragging) = &state.dragging {
                     if let Some(p) = cursor_position.position() {
                         let x_logical = (p.x - layout.bounds().x) - self.padding.left;
@@ -1403,10 +2133,25 @@ where
             _ => (),
         }
 
+        if let Some(on_completion) = &self.on_completion
+            && let Some(trigger) = completion_trigger_opt
+        {
+            let point = self.cursor_point(&mut editor, state, scale_factor);
+            shell.publish(on_completion(trigger, point));
+        }
+
+        if let Some(on_code_actions) = &self.on_code_actions
+            && code_actions_invoked
+        {
+            let point = self.cursor_point(&mut editor, state, scale_factor);
+            shell.publish(on_code_actions(None, point));
+        }
+
         if let Some(on_changed) = &self.on_changed {
             //TODO: better handling of status line update
             let parser = editor.parser();
-            if editor.changed() != last_changed
+            if edited
+                || editor.changed() != last_changed
                 || (&parser.mode, &parser.cmd) != (&last_parser_mode, &last_parser_cmd)
             {
                 shell.publish(on_changed.clone());
@@ -1442,6 +2187,10 @@ pub struct State {
     click: Option<(ClickKind, Instant)>,
     dragging: Option<Dragging>,
     editor_offset_x: Cell<i32>,
+    git_blame_w: Cell<i32>,
+    /// Start and end of the diagnostic markers in the gutter
+    lsp_marker_x: Cell<(i32, i32)>,
+    git_blame_hover: Option<usize>,
     is_focused: bool,
     emit_focus: bool,
     scale_factor: Cell<f32>,
@@ -1461,6 +2210,9 @@ impl State {
             click: None,
             dragging: None,
             editor_offset_x: Cell::new(0),
+            git_blame_w: Cell::new(0),
+            lsp_marker_x: Cell::new((0, 0)),
+            git_blame_hover: None,
             is_focused: false,
             emit_focus: false,
             scale_factor: Cell::new(1.0),
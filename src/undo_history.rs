@@ -0,0 +1,489 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Undo and redo history of files kept across sessions. The history is read from the change stack
+// of the editor when a file is closed, and stored in a cache file per file, named after a hash of
+// its path.
+// The cache also has a hash of the text the edits lead to, so it is only used if the file was not
+// changed since.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::local_history::stable_hash;
+
+const MAGIC: &str = "cosmic-edit undo 1";
+
+/// Maximum size in bytes of the edits kept per file
+const MAX_HISTORY_SIZE: usize = 4 * 1024 * 1024;
+
+fn is_line_ending(byte: u8) -> bool {
+    byte == b'\n' || byte == b'\r'
+}
+
+/// Replacement of the text `deleted` at the byte `offset` with `inserted`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UndoEdit {
+    pub offset: usize,
+    pub deleted: String,
+    pub inserted: String,
+}
+
+impl UndoEdit {
+    /// The edit that turns `old` into `new`, `None` if they are equal
+    pub fn between(old: &str, new: &str) -> Option<Self> {
+        let (old_bytes, new_bytes) = (old.as_bytes(), new.as_bytes());
+        let mut prefix = old_bytes
+            .iter()
+            .zip(new_bytes)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if prefix == old.len() && prefix == new.len() {
+            return None;
+        }
+        // Edits must start at characters and not within line endings like `\r\n`
+        while !old.is_char_boundary(prefix)
+            || !new.is_char_boundary(prefix)
+            || (prefix > 0
+                && is_line_ending(old_bytes[prefix - 1])
+                && (old_bytes.get(prefix).is_some_and(|b| is_line_ending(*b))
+                    || new_bytes.get(prefix).is_some_and(|b| is_line_ending(*b))))
+        {
+            prefix -= 1;
+        }
+
+        let max_suffix = old.len().min(new.len()) - prefix;
+        let mut suffix = old_bytes
+            .iter()
+            .rev()
+            .zip(new_bytes.iter().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !old.is_char_boundary(old.len() - suffix)
+            || !new.is_char_boundary(new.len() - suffix)
+            || (suffix > 0
+                && is_line_ending(old_bytes[old.len() - suffix])
+                && ((old.len() - suffix > 0 && is_line_ending(old_bytes[old.len() - suffix - 1]))
+                    || (new.len() - suffix > 0
+                        && is_line_ending(new_bytes[new.len() - suffix - 1]))))
+        {
+            suffix -= 1;
+        }
+
+        Some(Self {
+            offset: prefix,
+            deleted: old[prefix..old.len() - suffix].to_string(),
+            inserted: new[prefix..new.len() - suffix].to_string(),
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.deleted.len() + self.inserted.len()
+    }
+}
+
+/// Undo and redo history of a file
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UndoHistory {
+    /// Edits leading to the current text, oldest first
+    pub undo: Vec<UndoEdit>,
+    /// Edits that can be redone from the current text, next first
+    pub redo: Vec<UndoEdit>,
+}
+
+impl UndoHistory {
+    /// Read the history from the change stack of an editor, by redoing and undoing its changes
+    /// one at a time. The stack is left at the change it was at. A change that does not alter the
+    /// text ends the history.
+    pub fn from_stack(stack: &mut impl UndoStack, limits: UndoHistoryLimits) -> Self {
+        let max_changes = match limits.max_changes {
+            0 => usize::MAX,
+            max_changes => max_changes,
+        };
+        let text = stack.text();
+        let mut history = Self::default();
+
+        let mut current = text.clone();
+        while history.redo.len() < max_changes {
+            stack.redo_change();
+            let next = stack.text();
+            let Some(edit) = UndoEdit::between(&current, &next) else {
+                break;
+            };
+            history.redo.push(edit);
+            current = next;
+        }
+        for _ in history.redo.iter() {
+            stack.undo_change();
+        }
+
+        let mut current = text;
+        let mut size = 0;
+        while history.undo.len() < max_changes && size <= MAX_HISTORY_SIZE {
+            stack.undo_change();
+            let previous = stack.text();
+            let Some(edit) = UndoEdit::between(&previous, &current) else {
+                break;
+            };
+            size += edit.size();
+            history.undo.push(edit);
+            current = previous;
+        }
+        for _ in history.undo.iter() {
+            stack.redo_change();
+        }
+        history.undo.reverse();
+
+        history.limit(limits);
+        history
+    }
+
+    /// Drop the oldest undo edits and the farthest redo edits beyond the limits
+    pub fn limit(&mut self, limits: UndoHistoryLimits) {
+        if limits.max_changes > 0 {
+            let excess = self.undo.len().saturating_sub(limits.max_changes);
+            self.undo.drain(..excess);
+            self.redo.truncate(limits.max_changes);
+        }
+        let mut size: usize = self
+            .undo
+            .iter()
+            .chain(self.redo.iter())
+            .map(UndoEdit::size)
+            .sum();
+        while size > MAX_HISTORY_SIZE {
+            let edit = if self.undo.is_empty() {
+                self.redo.pop()
+            } else {
+                Some(self.undo.remove(0))
+            };
+            match edit {
+                Some(edit) => size -= edit.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Check that the undo edits lead to `text` and the redo edits can be applied to it
+    pub fn applies_to(&self, text: &str) -> bool {
+        fn replace(text: &str, offset: usize, old: &str, new: &str) -> Option<String> {
+            let end = offset.checked_add(old.len())?;
+            if text.get(offset..end)? != old {
+                return None;
+            }
+            Some(format!("{}{}{}", &text[..offset], new, &text[end..]))
+        }
+
+        let mut undone_text = text.to_string();
+        for edit in self.undo.iter().rev() {
+            match replace(&undone_text, edit.offset, &edit.inserted, &edit.deleted) {
+                Some(some) => undone_text = some,
+                None => return false,
+            }
+        }
+        let mut redone_text = text.to_string();
+        for edit in self.redo.iter() {
+            match replace(&redone_text, edit.offset, &edit.deleted, &edit.inserted) {
+                Some(some) => redone_text = some,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Changes of an editor that can be undone and redone one at a time
+pub trait UndoStack {
+    /// The current text
+    fn text(&self) -> String;
+    /// Undo the last change, if any
+    fn undo_change(&mut self);
+    /// Redo the next undone change, if any
+    fn redo_change(&mut self);
+}
+
+/// Limits of the undo history kept per file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UndoHistoryLimits {
+    /// Maximum number of changes to undo and redo, zero for no limit
+    pub max_changes: usize,
+    /// Maximum size of files in bytes, zero for no limit
+    pub max_file_size: u64,
+}
+
+/// History of a file to store once its tab is closed
+pub struct UndoHistorySave {
+    pub path: PathBuf,
+    /// Hash of the text the history leads to
+    pub text_hash: u64,
+    pub history: UndoHistory,
+}
+
+impl UndoHistorySave {
+    /// Store the history, or remove the stored one if it is empty
+    pub fn write(&self) {
+        let Some(undo_cache) = UndoCache::new() else {
+            return;
+        };
+        let result = if self.history.undo.is_empty() && self.history.redo.is_empty() {
+            undo_cache.remove(&self.path)
+        } else {
+            undo_cache.save(&self.path, self.text_hash, &self.history)
+        };
+        if let Err(err) = result {
+            log::warn!("failed to save undo history of {:?}: {}", self.path, err);
+        }
+    }
+}
+
+pub struct UndoCache {
+    root: PathBuf,
+}
+
+impl UndoCache {
+    /// Cache in the cache folder of the user
+    pub fn new() -> Option<Self> {
+        let root = dirs::cache_dir()?.join("cosmic-edit").join("undo");
+        Some(Self { root })
+    }
+
+    fn file_path(&self, path: &Path) -> PathBuf {
+        self.root.join(format!(
+            "{:016x}",
+            stable_hash(path.as_os_str().as_encoded_bytes())
+        ))
+    }
+
+    /// Remove the history of the file at `path`
+    pub fn remove(&self, path: &Path) -> io::Result<()> {
+        match fs::remove_file(self.file_path(path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Store the history of the file at `path`, which leads to a text with `text_hash`
+    pub fn save(&self, path: &Path, text_hash: u64, history: &UndoHistory) -> io::Result<()> {
+        let mut data = format!(
+            "{}\n{:016x} {} {}\n",
+            MAGIC,
+            text_hash,
+            history.undo.len(),
+            history.redo.len()
+        );
+        for edit in history.undo.iter().chain(history.redo.iter()) {
+            data.push_str(&format!(
+                "{} {} {}\n",
+                edit.offset,
+                edit.deleted.len(),
+                edit.inserted.len()
+            ));
+            data.push_str(&edit.deleted);
+            data.push_str(&edit.inserted);
+        }
+        fs::create_dir_all(&self.root)?;
+        fs::write(self.file_path(path), data)
+    }
+
+    /// Load the history of the file at `path` if it leads to `text`. Histories of files that
+    /// changed since they were stored are removed.
+    pub fn load(&self, path: &Path, text: &str) -> io::Result<Option<UndoHistory>> {
+        let data = match fs::read_to_string(self.file_path(path)) {
+            Ok(ok) => ok,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        match parse(&data, stable_hash(text.as_bytes())) {
+            Some(history) => Ok(Some(history)),
+            None => {
+                self.remove(path)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Parse a stored history, `None` if it is invalid or does not lead to a text with `text_hash`
+fn parse(data: &str, text_hash: u64) -> Option<UndoHistory> {
+    fn line<'a>(data: &mut &'a str) -> Option<&'a str> {
+        let (line, rest) = data.split_once('\n')?;
+        *data = rest;
+        Some(line)
+    }
+    fn text(data: &mut &str, len: usize) -> Option<String> {
+        let text = data.get(..len)?.to_string();
+        *data = &data[len..];
+        Some(text)
+    }
+
+    let mut data = data;
+    if line(&mut data)? != MAGIC {
+        return None;
+    }
+    let mut header = line(&mut data)?.split(' ');
+    if u64::from_str_radix(header.next()?, 16).ok()? != text_hash {
+        return None;
+    }
+    let undo_len: usize = header.next()?.parse().ok()?;
+    let redo_len: usize = header.next()?.parse().ok()?;
+    let mut edits = Vec::new();
+    for _ in 0..undo_len.checked_add(redo_len)? {
+        let mut numbers = line(&mut data)?.split(' ');
+        let offset = numbers.next()?.parse().ok()?;
+        let deleted_len = numbers.next()?.parse().ok()?;
+        let inserted_len = numbers.next()?.parse().ok()?;
+        edits.push(UndoEdit {
+            offset,
+            deleted: text(&mut data, deleted_len)?,
+            inserted: text(&mut data, inserted_len)?,
+        });
+    }
+    let redo = edits.split_off(undo_len);
+    Some(UndoHistory { undo: edits, redo })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(text: &str, edit: &UndoEdit) -> String {
+        let end = edit.offset + edit.deleted.len();
+        assert_eq!(&text[edit.offset..end], edit.deleted);
+        format!("{}{}{}", &text[..edit.offset], edit.inserted, &text[end..])
+    }
+
+    #[test]
+    fn between() {
+        for (old, new) in [
+            ("hello world", "hello big world"),
+            ("hello world", "world"),
+            ("", "text"),
+            ("a\r\nb", "a\nb"),
+            ("a\nb", "a\r\nb"),
+            ("a\n\nb", "a\nb"),
+            ("caf\u{e9}", "caf\u{e8}"),
+            ("x\u{e9}x", "x\u{e8}\u{e9}x"),
+        ] {
+            let edit = UndoEdit::between(old, new).unwrap();
+            assert_eq!(apply(old, &edit), new, "{:?} -> {:?}", old, new);
+        }
+        assert_eq!(UndoEdit::between("same", "same"), None);
+
+        // Line endings like `\r\n` are not split
+        let edit = UndoEdit::between("a\r\nb", "a\nb").unwrap();
+        assert_eq!(
+            (edit.offset, edit.deleted.as_str(), edit.inserted.as_str()),
+            (1, "\r\n", "\n")
+        );
+    }
+
+    /// Stack of texts after each change
+    struct TestStack {
+        texts: Vec<&'static str>,
+        index: usize,
+    }
+
+    impl UndoStack for TestStack {
+        fn text(&self) -> String {
+            self.texts[self.index].to_string()
+        }
+        fn undo_change(&mut self) {
+            self.index = self.index.saturating_sub(1);
+        }
+        fn redo_change(&mut self) {
+            self.index = (self.index + 1).min(self.texts.len() - 1);
+        }
+    }
+
+    #[test]
+    fn from_stack() {
+        let unlimited = UndoHistoryLimits {
+            max_changes: 0,
+            max_file_size: 0,
+        };
+
+        // Typing and then deleting are two changes, not an undo
+        let mut stack = TestStack {
+            texts: vec!["a", "ax", "a"],
+            index: 2,
+        };
+        let history = UndoHistory::from_stack(&mut stack, unlimited);
+        assert_eq!(stack.index, 2);
+        assert_eq!(
+            history.undo,
+            vec![
+                UndoEdit::between("a", "ax").unwrap(),
+                UndoEdit::between("ax", "a").unwrap(),
+            ]
+        );
+        assert!(history.redo.is_empty());
+        assert!(history.applies_to("a"));
+
+        // Undone changes are kept as redo
+        let mut stack = TestStack {
+            texts: vec!["", "hello", "hello world", "hello big world"],
+            index: 1,
+        };
+        let history = UndoHistory::from_stack(&mut stack, unlimited);
+        assert_eq!(stack.index, 1);
+        assert_eq!(history.undo, vec![UndoEdit::between("", "hello").unwrap()]);
+        assert_eq!(
+            history.redo,
+            vec![
+                UndoEdit::between("hello", "hello world").unwrap(),
+                UndoEdit::between("hello world", "hello big world").unwrap(),
+            ]
+        );
+        assert!(history.applies_to("hello"));
+
+        // Only the changes closest to the current text are kept within the limits
+        stack.index = 3;
+        let limits = UndoHistoryLimits {
+            max_changes: 2,
+            max_file_size: 0,
+        };
+        let history = UndoHistory::from_stack(&mut stack, limits);
+        assert_eq!(stack.index, 3);
+        assert_eq!(history.undo.len(), 2);
+        assert!(history.applies_to("hello big world"));
+
+        let large: &'static str = "x".repeat(MAX_HISTORY_SIZE + 1).leak();
+        let mut stack = TestStack {
+            texts: vec!["", large],
+            index: 1,
+        };
+        let history = UndoHistory::from_stack(&mut stack, unlimited);
+        assert_eq!(stack.index, 1);
+        assert!(history.undo.is_empty());
+    }
+
+    #[test]
+    fn cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = UndoCache {
+            root: dir.path().join("undo"),
+        };
+        let path = Path::new("/home/user/notes.txt");
+        let history = UndoHistory {
+            undo: vec![
+                UndoEdit::between("", "one\n").unwrap(),
+                UndoEdit::between("one\n", "one\ntwo \u{2022}\n").unwrap(),
+            ],
+            redo: vec![UndoEdit::between("one\ntwo \u{2022}\n", "one\n").unwrap()],
+        };
+        let text = "one\ntwo \u{2022}\n";
+        assert!(history.applies_to(text));
+        assert!(!history.applies_to("one\ntwo\n"));
+        assert_eq!(cache.load(path, text).unwrap(), None);
+        cache
+            .save(path, stable_hash(text.as_bytes()), &history)
+            .unwrap();
+        assert_eq!(cache.load(path, text).unwrap(), Some(history));
+
+        // The history is dropped once the file changed
+        assert_eq!(cache.load(path, "one\n").unwrap(), None);
+        assert_eq!(cache.load(path, text).unwrap(), None);
+    }
+}
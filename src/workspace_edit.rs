@@ -0,0 +1,361 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Edits of language servers across files, made by renames and code actions. Edits of open files
+// are applied to their tabs, other files are edited on disk.
+
+use lsp_types::{DocumentChangeOperation, DocumentChanges, OneOf, TextEdit, Uri, WorkspaceEdit};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::lsp::{LspPositionEncoding, uri_path};
+
+/// Byte range of an edit with its new text
+type EditRange<'a> = (Range<usize>, &'a str);
+
+/// Lines changed by edits, shown before applying them
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChangedLines {
+    /// Index of the first changed line
+    pub line: u32,
+    /// Text of the changed lines before and after the edits, without the last line ending
+    pub old: String,
+    pub new: String,
+}
+
+/// Edits of a file with the text they were made for
+#[derive(Clone, Debug)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub edits: Vec<TextEdit>,
+    /// Text of the file, or of its tab if it is open
+    pub text: String,
+    pub lines: Vec<ChangedLines>,
+}
+
+/// Text edits of a workspace edit by file. Creating, renaming and deleting files is not supported.
+fn file_edits(workspace_edit: WorkspaceEdit) -> Result<BTreeMap<PathBuf, Vec<TextEdit>>, String> {
+    let mut files: BTreeMap<PathBuf, Vec<TextEdit>> = BTreeMap::new();
+    let mut add = |uri: &Uri, edits: Vec<TextEdit>| match uri_path(uri) {
+        Some(path) => {
+            files.entry(path).or_default().extend(edits);
+            Ok(())
+        }
+        None => Err(format!("cannot edit {}", uri.as_str())),
+    };
+    let annotated = |edits: Vec<OneOf<TextEdit, lsp_types::AnnotatedTextEdit>>| {
+        edits
+            .into_iter()
+            .map(|edit| match edit {
+                OneOf::Left(edit) => edit,
+                OneOf::Right(edit) => edit.text_edit,
+            })
+            .collect()
+    };
+
+    // Document changes are used instead of changes when a server sends both
+    match workspace_edit.document_changes {
+        Some(DocumentChanges::Edits(document_edits)) => {
+            for document_edit in document_edits {
+                add(
+                    &document_edit.text_document.uri,
+                    annotated(document_edit.edits),
+                )?;
+            }
+        }
+        Some(DocumentChanges::Operations(operations)) => {
+            for operation in operations {
+                match operation {
+                    DocumentChangeOperation::Edit(document_edit) => add(
+                        &document_edit.text_document.uri,
+                        annotated(document_edit.edits),
+                    )?,
+                    DocumentChangeOperation::Op(_) => {
+                        return Err(
+                            "creating, renaming and deleting files is not supported".to_string()
+                        );
+                    }
+                }
+            }
+        }
+        None => {
+            for (uri, edits) in workspace_edit.changes.into_iter().flatten() {
+                add(&uri, edits)?;
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Load the files changed by a workspace edit, taking the text of open files from `open_texts`
+pub fn load(
+    workspace_edit: WorkspaceEdit,
+    encoding: LspPositionEncoding,
+    open_texts: &HashMap<PathBuf, String>,
+) -> Result<Vec<FileChange>, String> {
+    let mut changes = Vec::new();
+    for (path, edits) in file_edits(workspace_edit)? {
+        if edits.is_empty() {
+            continue;
+        }
+        let text = match open_texts.get(&path) {
+            Some(text) => text.clone(),
+            None => fs::read_to_string(&path)
+                .map_err(|err| format!("failed to read {}: {}", path.display(), err))?,
+        };
+        let lines = changed_lines(&text, encoding, &edits)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        changes.push(FileChange {
+            path,
+            edits,
+            text,
+            lines,
+        });
+    }
+    Ok(changes)
+}
+
+/// Byte offsets of the starts of the lines of `text`, which end with `\n`, `\r\n` or `\r`
+pub fn line_starts(text: &str) -> Vec<usize> {
+    let bytes = text.as_bytes();
+    let mut starts = vec![0];
+    for (i, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'\n' => starts.push(i + 1),
+            b'\r' if bytes.get(i + 1) != Some(&b'\n') => starts.push(i + 1),
+            _ => {}
+        }
+    }
+    starts
+}
+
+/// Byte range of line `line_i` without its line ending
+fn line_range(text: &str, starts: &[usize], line_i: usize) -> Range<usize> {
+    let Some(start) = starts.get(line_i).copied() else {
+        return text.len()..text.len();
+    };
+    let end = starts.get(line_i + 1).copied().unwrap_or(text.len());
+    start..start + text[start..end].trim_end_matches(['\r', '\n']).len()
+}
+
+/// Byte offset of `position`, clamped to the text
+fn offset(
+    text: &str,
+    starts: &[usize],
+    encoding: LspPositionEncoding,
+    position: lsp_types::Position,
+) -> usize {
+    let range = line_range(text, starts, position.line as usize);
+    range.start + encoding.index(&text[range], position.character)
+}
+
+/// Byte ranges of edits sorted by their position, edits at the same position stay in order
+fn edit_ranges<'a>(
+    text: &str,
+    encoding: LspPositionEncoding,
+    edits: &'a [TextEdit],
+) -> Result<Vec<EditRange<'a>>, String> {
+    let starts = line_starts(text);
+    let mut ranges: Vec<EditRange> = edits
+        .iter()
+        .map(|edit| {
+            let start = offset(text, &starts, encoding, edit.range.start);
+            let end = offset(text, &starts, encoding, edit.range.end).max(start);
+            (start..end, edit.new_text.as_str())
+        })
+        .collect();
+    ranges.sort_by_key(|(range, _)| range.start);
+    if ranges
+        .windows(2)
+        .any(|pair| pair[0].0.end > pair[1].0.start)
+    {
+        return Err("edits overlap".to_string());
+    }
+    Ok(ranges)
+}
+
+/// Text of `span` with the edits of `ranges` in it applied
+fn splice(text: &str, span: Range<usize>, ranges: &[EditRange]) -> String {
+    let mut new_text = String::with_capacity(span.len());
+    let mut last_end = span.start;
+    for (range, new) in ranges {
+        new_text.push_str(&text[last_end..range.start]);
+        new_text.push_str(new);
+        last_end = range.end;
+    }
+    new_text.push_str(&text[last_end..span.end]);
+    new_text
+}
+
+/// Text after applying edits
+pub fn apply(
+    text: &str,
+    encoding: LspPositionEncoding,
+    edits: &[TextEdit],
+) -> Result<String, String> {
+    Ok(splice(
+        text,
+        0..text.len(),
+        &edit_ranges(text, encoding, edits)?,
+    ))
+}
+
+/// Lines changed by edits, edits touching the same lines are shown together
+pub fn changed_lines(
+    text: &str,
+    encoding: LspPositionEncoding,
+    edits: &[TextEdit],
+) -> Result<Vec<ChangedLines>, String> {
+    let starts = line_starts(text);
+    let line_of = |offset: usize| starts.partition_point(|start| *start <= offset) - 1;
+    let mut groups: Vec<(usize, Range<usize>, Vec<EditRange>)> = Vec::new();
+    for (range, new) in edit_ranges(text, encoding, edits)? {
+        let first_line = line_of(range.start);
+        let lines = line_range(text, &starts, first_line).start
+            ..line_range(text, &starts, line_of(range.end)).end;
+        match groups.last_mut() {
+            Some((_, group_lines, group_ranges)) if lines.start <= group_lines.end => {
+                group_lines.end = group_lines.end.max(lines.end);
+                group_ranges.push((range, new));
+            }
+            _ => groups.push((first_line, lines, vec![(range, new)])),
+        }
+    }
+    Ok(groups
+        .into_iter()
+        .map(|(line_i, lines, ranges)| ChangedLines {
+            line: line_i as u32,
+            new: splice(text, lines.clone(), &ranges),
+            old: text[lines].to_string(),
+        })
+        .collect())
+}
+
+/// Replace the text of the file at `path` if it still is `old_text`. The text is written to a
+/// temporary file next to it, which then takes the place of the file, so the file is never left
+/// partly written.
+pub fn write_file(path: &Path, old_text: &str, text: &str) -> io::Result<()> {
+    // Links are kept, the file they point to is replaced
+    let path = fs::canonicalize(path)?;
+    if fs::read_to_string(&path)? != old_text {
+        return Err(io::Error::other("the file was changed"));
+    }
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other("invalid file name"))?;
+    let temp_path =
+        path.with_file_name(format!(".{}.cosmic-edit-tmp", file_name.to_string_lossy()));
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::set_permissions(&temp_path, fs::metadata(&path)?.permissions())?;
+        fs::rename(&temp_path, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{Position, Range as LspRange};
+
+    fn edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEdit {
+        TextEdit::new(
+            LspRange::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            new_text.to_string(),
+        )
+    }
+
+    #[test]
+    fn apply_edits() {
+        let text = "let foo = 1;\r\nfoo + foo\n\u{e9}foo";
+        let edits = [
+            edit((1, 6), (1, 9), "bar"),
+            edit((0, 4), (0, 7), "bar"),
+            edit((1, 0), (1, 3), "bar"),
+            // Columns are counted in UTF-16
+            edit((2, 1), (2, 4), "bar"),
+        ];
+        let encoding = LspPositionEncoding::Utf16;
+        assert_eq!(
+            apply(text, encoding, &edits).unwrap(),
+            "let bar = 1;\r\nbar + bar\n\u{e9}bar"
+        );
+        assert_eq!(
+            changed_lines(text, encoding, &edits).unwrap(),
+            vec![
+                ChangedLines {
+                    line: 0,
+                    old: "let foo = 1;".to_string(),
+                    new: "let bar = 1;".to_string(),
+                },
+                ChangedLines {
+                    line: 1,
+                    old: "foo + foo".to_string(),
+                    new: "bar + bar".to_string(),
+                },
+                ChangedLines {
+                    line: 2,
+                    old: "\u{e9}foo".to_string(),
+                    new: "\u{e9}bar".to_string(),
+                },
+            ]
+        );
+
+        // Edits across lines are shown with every line they touch
+        let edits = [edit((0, 11), (1, 0), " "), edit((1, 4), (1, 5), "-")];
+        assert_eq!(
+            changed_lines(text, encoding, &edits).unwrap(),
+            vec![ChangedLines {
+                line: 0,
+                old: "let foo = 1;\r\nfoo + foo".to_string(),
+                new: "let foo = 1 foo - foo".to_string(),
+            }]
+        );
+
+        // Insertions at the same position keep their order, overlapping edits fail
+        let edits = [edit((0, 0), (0, 0), "a"), edit((0, 0), (0, 0), "b")];
+        assert_eq!(apply("c", encoding, &edits).unwrap(), "abc");
+        let edits = [edit((0, 0), (0, 2), "a"), edit((0, 1), (0, 3), "b")];
+        assert!(apply("cde", encoding, &edits).is_err());
+    }
+
+    #[test]
+    fn load_and_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = dir.path().join("open.rs");
+        let closed = dir.path().join("closed.rs");
+        fs::write(&closed, "use foo;\n").unwrap();
+        let uri = |path: &Path| crate::lsp::path_uri(path).unwrap();
+        let workspace_edit = WorkspaceEdit {
+            changes: Some(HashMap::from([
+                (uri(&open), vec![edit((0, 3), (0, 6), "bar")]),
+                (uri(&closed), vec![edit((0, 4), (0, 7), "bar")]),
+            ])),
+            ..Default::default()
+        };
+        let open_texts = HashMap::from([(open.clone(), "fn foo() {}".to_string())]);
+        let changes = load(workspace_edit, LspPositionEncoding::Utf8, &open_texts).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].path, closed);
+        assert_eq!(changes[0].text, "use foo;\n");
+        assert_eq!(changes[1].path, open);
+        assert_eq!(changes[1].lines[0].new, "fn bar() {}");
+
+        let change = &changes[0];
+        let text = apply(&change.text, LspPositionEncoding::Utf8, &change.edits).unwrap();
+        write_file(&change.path, &change.text, &text).unwrap();
+        assert_eq!(fs::read_to_string(&closed).unwrap(), "use bar;\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        // Files changed since the edits were made are left alone
+        assert!(write_file(&change.path, &change.text, &text).is_err());
+    }
+}